@@ -0,0 +1,257 @@
+use std::io::BufWriter;
+use std::path::Path;
+use std::sync::Arc;
+use chrono::NaiveDate;
+use crate::config::connections::{BITCOIN_NETWORK, DB_URL, REST_URL, RPC_URL};
+use crate::config::settings::{API_AUTH_ENABLED, EVENT_SINK, FULL_INDEX_ENABLED};
+use crate::services::api_auth::{generate_api_key, hash_api_key};
+use crate::services::bitcoin_rpc::BitcoinRpcService;
+use crate::services::block_files::BlockFiles;
+use crate::services::export::{self, ExportFormat, ExportSeries, Partition, PartitionedExport};
+use crate::services::ingestion::{backfill_blocks, import_block_files};
+use crate::services::storage::{self, Storage};
+
+const KEYS_USAGE: &str = "Usage:
+  project-rust keys create <name> [--rate <requests per minute>]
+  project-rust keys list
+  project-rust keys revoke <name>";
+
+const AGGREGATES_USAGE: &str = "Usage:
+  project-rust aggregates rebuild [--from <YYYY-MM-DD>]";
+
+const INDEX_USAGE: &str = "Usage:
+  project-rust index build";
+
+const BACKFILL_USAGE: &str = "Usage:
+  project-rust backfill --from <height> --to <height>";
+
+const IMPORT_USAGE: &str = "Usage:
+  project-rust import --blocks-dir <dir> [--from <height>] [--to <height>]
+
+Reads Bitcoin Core's blk*.dat files, and with the full index its rev*.dat files, e.g. ~/.bitcoin/blocks;
+by default the whole best chain they hold.";
+
+const EXPORT_USAGE: &str = "Usage:
+  project-rust export <series> [--from <YYYY-MM-DD>] [--to <YYYY-MM-DD>] [--format csv|ndjson|parquet]
+                               [--out-dir <dir> [--partition day|month|year]]
+
+Series: daily_tx, dma, fee_history, block_stats. Without --out-dir the rows go to stdout; with it
+they are split into one file per month, or per --partition, named like fee_history_2024-04.parquet.";
+
+// Manage API keys from the command line, e.g. `project-rust keys create dashboard --rate 120`
+pub fn run_keys_command(
+    storage: Arc<dyn Storage>,
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    storage.ensure_api_keys_table()?;
+
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["create", name, rest @ ..] => {
+            let rate_limit_per_minute = match rest {
+                [] => None,
+                ["--rate", rate] => Some(rate.parse::<u32>()?),
+                _ => return Err(KEYS_USAGE.into()),
+            };
+            let api_key = generate_api_key();
+            storage.create_api_key(name, &hash_api_key(&api_key), rate_limit_per_minute)?;
+            // The plain key is shown once and never stored
+            println!("Created API key '{}': {}", name, api_key);
+        }
+        ["list"] => {
+            for key in storage.list_api_keys()? {
+                let rate = key.rate_limit_per_minute
+                    .map(|rate| format!("{}/min", rate))
+                    .unwrap_or_else(|| "default".to_string());
+                let status = if key.revoked { "revoked" } else { "active" };
+                println!("{}\t{}\t{}\t{}", key.id, key.name, rate, status);
+            }
+        }
+        ["revoke", name] => {
+            if storage.revoke_api_key(name)? {
+                println!("Revoked API key '{}'", name);
+            } else {
+                return Err(format!("No API key named '{}'", name).into());
+            }
+        }
+        _ => return Err(KEYS_USAGE.into()),
+    }
+
+    Ok(())
+}
+
+// Recompute daily_transactions and seven_day_dma from the stored blocks, without touching the node
+pub fn run_aggregates_command(
+    storage: Arc<dyn Storage>,
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let from_date = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["rebuild"] => None,
+        ["rebuild", "--from", date] => Some(NaiveDate::parse_from_str(date, "%Y-%m-%d")?),
+        _ => return Err(AGGREGATES_USAGE.into()),
+    };
+    storage.recompute_daily_aggregates(from_date)?;
+    println!("Daily aggregates rebuilt");
+    Ok(())
+}
+
+// Create the secondary indexes of the full index once the initial load is done
+pub fn run_index_command(
+    storage: Arc<dyn Storage>,
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["build"] => {
+            storage.ensure_full_index_tables()?;
+            storage.build_full_index_secondary_indexes()?;
+            println!("Full index secondary indexes built");
+            Ok(())
+        }
+        _ => Err(INDEX_USAGE.into()),
+    }
+}
+
+type EnsureTables = fn(&dyn Storage) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+// Create every table the configured features use; safe to run again
+pub fn run_migrate_command(storage: &dyn Storage) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let steps: [(&str, bool, EnsureTables); 9] = [
+        ("core", true, |storage| storage.ensure_core_tables()),
+        ("blocks", true, |storage| storage.ensure_blocks_table()),
+        ("webhook", true, |storage| storage.ensure_webhook_tables()),
+        ("alert", true, |storage| storage.ensure_alert_tables()),
+        ("metric history", true, |storage| storage.ensure_metric_tables()),
+        ("watch-only wallet", true, |storage| storage.ensure_watch_tables()),
+        ("full index", FULL_INDEX_ENABLED, |storage| storage.ensure_full_index_tables()),
+        ("API key", API_AUTH_ENABLED, |storage| storage.ensure_api_keys_table()),
+        ("event outbox", EVENT_SINK.is_some(), |storage| storage.ensure_outbox_table()),
+    ];
+    for (name, enabled, ensure) in steps {
+        if enabled {
+            ensure(storage).map_err(|e| format!("Error preparing {} tables: {:?}", name, e))?;
+        }
+    }
+    Ok(())
+}
+
+// Store blocks below the ingested range, e.g. `project-rust backfill --from 800000 --to 830000`
+pub fn run_backfill_command(
+    storage: Arc<dyn Storage>,
+    bitcoin_service: Arc<BitcoinRpcService>,
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (from_height, to_height) = match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["--from", from, "--to", to] => (from.parse::<u64>()?, to.parse::<u64>()?),
+        _ => return Err(BACKFILL_USAGE.into()),
+    };
+    storage.ensure_blocks_table()?;
+    let stored = backfill_blocks(storage.as_ref(), &bitcoin_service, from_height, to_height)?;
+    println!("Backfilled {} blocks between heights {} and {}", stored, from_height, to_height);
+    Ok(())
+}
+
+// Store blocks from a local node's block files without going through RPC, e.g.
+// `project-rust import --blocks-dir /var/lib/bitcoind/blocks --to 840000`
+pub fn run_import_command(
+    storage: Arc<dyn Storage>,
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (mut blocks_dir, mut from_height, mut to_height) = (None, 0, None);
+    for option in args.iter().map(String::as_str).collect::<Vec<_>>().chunks(2) {
+        match option {
+            ["--blocks-dir", dir] => blocks_dir = Some(Path::new(*dir)),
+            ["--from", height] => from_height = height.parse::<u64>()?,
+            ["--to", height] => to_height = Some(height.parse::<u64>()?),
+            _ => return Err(IMPORT_USAGE.into()),
+        }
+    }
+    let blocks_dir = blocks_dir.ok_or(IMPORT_USAGE)?;
+    storage.ensure_blocks_table()?;
+
+    let mut block_files = BlockFiles::open(blocks_dir, BITCOIN_NETWORK)?;
+    let to_height = to_height.unwrap_or(block_files.tip_height());
+    let stored = import_block_files(storage.as_ref(), &mut block_files, from_height, to_height)?;
+    println!("Imported {} blocks between heights {} and {} from {}", stored, from_height, to_height, blocks_dir.display());
+    Ok(())
+}
+
+// Write a stored series as CSV, NDJSON or Parquet, to stdout or as date-partitioned files in a
+// directory, e.g. `project-rust export fee_history --format parquet --out-dir exports`
+pub fn run_export_command(
+    storage: Arc<dyn Storage>,
+    args: &[String],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        [series, rest @ ..] => {
+            let series = ExportSeries::parse(series).ok_or(EXPORT_USAGE)?;
+            let (mut from_date, mut to_date, mut format, mut out_dir, mut partition) = (None, None, ExportFormat::Csv, None, None);
+            for option in rest.chunks(2) {
+                match option {
+                    ["--from", date] => from_date = Some(NaiveDate::parse_from_str(date, "%Y-%m-%d")?),
+                    ["--to", date] => to_date = Some(NaiveDate::parse_from_str(date, "%Y-%m-%d")?),
+                    ["--format", name] => format = ExportFormat::parse(name).ok_or(EXPORT_USAGE)?,
+                    ["--out-dir", dir] => out_dir = Some(Path::new(*dir)),
+                    ["--partition", name] => partition = Some(Partition::parse(name).ok_or(EXPORT_USAGE)?),
+                    _ => return Err(EXPORT_USAGE.into()),
+                }
+            }
+            match out_dir {
+                Some(dir) => {
+                    let mut files = PartitionedExport::new(dir, series, format, partition.unwrap_or(Partition::Month))?;
+                    let rows = storage.export_series(series, from_date, to_date, &mut |row| files.write_row(row))?;
+                    let written = files.finish()?;
+                    println!("Exported {} {} rows to {} files in {}", rows, series.name(), written.len(), dir.display());
+                }
+                None if partition.is_some() => return Err(EXPORT_USAGE.into()),
+                None => {
+                    let mut encoder = export::encoder(format, series, BufWriter::new(std::io::stdout()))?;
+                    storage.export_series(series, from_date, to_date, &mut |row| encoder.write_row(row))?;
+                    encoder.finish()?;
+                }
+            }
+        }
+        _ => return Err(EXPORT_USAGE.into()),
+    }
+    Ok(())
+}
+
+// Report whether the node and the database are reachable; fails if either is not
+pub fn run_check_command(bitcoin_service: Arc<BitcoinRpcService>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut healthy = true;
+
+    match bitcoin_service.get_blockchain_info() {
+        Ok(info) if info.chain != BITCOIN_NETWORK => {
+            println!("Node:     FAIL {} is on {}, expected {}", RPC_URL, info.chain, BITCOIN_NETWORK);
+            healthy = false;
+        }
+        Ok(info) => println!("Node:     ok   {} on {} at height {}", RPC_URL, info.chain, info.blocks),
+        Err(e) => {
+            println!("Node:     FAIL {}: {}", RPC_URL, e);
+            healthy = false;
+        }
+    }
+    // Blocks are fetched over REST when it is configured, so it has to answer too
+    if let Some(rest_url) = REST_URL {
+        match bitcoin_service.get_block_hash(0) {
+            Ok(_) => println!("REST:     ok   {}", rest_url),
+            Err(e) => {
+                println!("REST:     FAIL {}: {}", rest_url, e);
+                healthy = false;
+            }
+        }
+    }
+
+    let database = storage::connect(DB_URL).and_then(|storage| {
+        storage.ping()?;
+        storage.get_stored_tip()
+    });
+    match database {
+        Ok(Some((height, _))) => println!("Database: ok   stored tip at height {}", height),
+        Ok(None) => println!("Database: ok   no blocks stored yet"),
+        Err(e) => {
+            println!("Database: FAIL {}", e);
+            healthy = false;
+        }
+    }
+
+    if healthy { Ok(()) } else { Err("Connectivity check failed".into()) }
+}
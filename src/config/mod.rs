@@ -1 +1,2 @@
-pub mod connections;
\ No newline at end of file
+pub mod connections;
+pub mod settings;
\ No newline at end of file
@@ -0,0 +1,165 @@
+use crate::services::alerts::{AlertMetric, AlertRule, Comparison};
+use crate::services::publisher::EventSink;
+use crate::services::telemetry::LogFormat;
+
+// Ingestion scheduler:
+pub const INGESTION_INTERVAL_SECS: u64 = 30;
+pub const FEE_BLOCK_TARGETS: [u16; 5] = [1, 3, 6, 12, 24];
+
+// Push feed:
+pub const EVENT_BUS_CAPACITY: usize = 256;
+pub const WS_CLIENT_BUFFER: usize = 32;
+pub const EVENT_REPLAY_CAPACITY: usize = 512;
+pub const SSE_KEEP_ALIVE_SECS: u64 = 15;
+
+// Response cache:
+pub const CACHE_MAX_AGE_SECS: u64 = 30;
+// Least recently used responses are evicted beyond this
+pub const RESPONSE_CACHE_MAX_ENTRIES: usize = 256;
+
+// API authentication:
+pub const API_AUTH_ENABLED: bool = false;
+// Path prefixes reachable without an API key when authentication is enabled
+pub const PUBLIC_ROUTES: &[&str] = &[
+    "/api/v1/block_info/block_height", "/api/block_info/block_height", "/api/openapi.json", "/docs",
+];
+pub const DEFAULT_RATE_LIMIT_PER_MINUTE: u32 = 60;
+pub const RATE_LIMIT_BURST: u32 = 20;
+pub const API_KEY_CACHE_TTL_SECS: u64 = 60;
+// Database lookups of keys not in the cache, per client address, so unknown keys can't flood the
+// database. Known keys are cached after their first lookup and rarely count against this.
+pub const KEY_LOOKUPS_PER_MINUTE: u32 = 30;
+pub const KEY_LOOKUP_BURST: u32 = 10;
+// Client addresses tracked for that before idle ones are dropped
+pub const KEY_LOOKUP_MAX_CLIENTS: usize = 10_000;
+
+// Block explorer:
+pub const EXPLORER_BLOCK_CACHE_SIZE: usize = 32;
+pub const EXPLORER_TX_CACHE_SIZE: usize = 1024;
+pub const EXPLORER_CACHE_MIN_CONFIRMATIONS: u64 = 6;
+// Spent outputs looked up for one transaction's input values, and in flight across all requests
+pub const EXPLORER_MAX_PREVOUT_LOOKUPS: usize = 25;
+pub const EXPLORER_PREVOUT_LOOKUP_SLOTS: usize = 50;
+pub const EXPLORER_DEFAULT_PAGE_SIZE: usize = 25;
+pub const EXPLORER_MAX_PAGE_SIZE: usize = 100;
+
+// Database pool, for either backend:
+pub const DB_POOL_MIN_CONNECTIONS: usize = 2;
+pub const DB_POOL_MAX_CONNECTIONS: usize = 16;
+pub const DB_CONNECT_TIMEOUT_SECS: u64 = 5;
+// How long a query waits for a free connection before failing
+pub const DB_ACQUIRE_TIMEOUT_SECS: u64 = 10;
+// Socket read/write timeout, which bounds how long a single query can run
+pub const DB_QUERY_TIMEOUT_SECS: u64 = 60;
+// Prepared statements kept per MySQL connection
+pub const DB_STMT_CACHE_SIZE: usize = 64;
+
+// PostgreSQL:
+// Store fee estimates and block stats in TimescaleDB hypertables and serve the daily rollups from a
+// continuous aggregate. Needs the timescaledb extension on the server; ignored for MySQL.
+pub const TIMESCALEDB_ENABLED: bool = false;
+
+// Export:
+// Rows buffered per Parquet row group, which bounds the memory an export needs
+pub const EXPORT_PARQUET_ROW_GROUP_ROWS: usize = 65_536;
+// Rows fetched from PostgreSQL per round trip while an export streams
+pub const EXPORT_FETCH_ROWS: i32 = 10_000;
+// Bytes sent to an HTTP export client at a time, and how many such chunks may wait for it
+pub const EXPORT_CHUNK_BYTES: usize = 64 * 1024;
+pub const EXPORT_HTTP_BUFFER_CHUNKS: usize = 16;
+// HTTP exports streaming at once; each holds a pooled database connection until it is done
+pub const EXPORT_MAX_CONCURRENT: usize = 2;
+
+// Metric history:
+// Fee and mempool samples are stored every ingestion tick and rolled up into hourly and daily
+// min/max/avg/percentile buckets as each bucket closes. Raw samples are deleted after
+// METRIC_RAW_RETENTION_DAYS and hourly rollups after METRIC_HOURLY_RETENTION_DAYS; daily rollups
+// are kept for good while METRIC_DAILY_RETENTION_DAYS is None.
+pub const METRIC_RAW_RETENTION_DAYS: i64 = 7;
+pub const METRIC_HOURLY_RETENTION_DAYS: i64 = 90;
+pub const METRIC_DAILY_RETENTION_DAYS: Option<i64> = None;
+pub const METRIC_ROLLUP_INTERVAL_SECS: u64 = 5 * 60;
+// Most points the history API returns for a range; it picks the finest resolution within this
+pub const METRIC_MAX_POINTS: i64 = 1500;
+
+// Block ingestion:
+// Blocks mined on an average day
+pub const BLOCKS_PER_DAY: u64 = 144;
+// How far below the tip to start when the blocks table is empty (about 7 days), moved back to
+// the first block of that UTC day so the first stored day is complete
+pub const BLOCK_BOOTSTRAP_DEPTH: u64 = 7 * BLOCKS_PER_DAY;
+pub const MAX_BLOCKS_PER_TICK: u64 = 50;
+pub const MAX_REORG_DEPTH: u64 = 100;
+// Blocks fetched by `backfill` before they are written in one transaction
+pub const BACKFILL_BATCH_BLOCKS: u64 = 200;
+
+// Node REST interface, when REST_URL is set:
+pub const REST_TIMEOUT_SECS: u64 = 30;
+// Most headers the node returns per request
+pub const REST_MAX_HEADERS: usize = 2000;
+
+// Full transaction index:
+pub const FULL_INDEX_ENABLED: bool = false;
+pub const BULK_INSERT_ROWS: usize = 500;
+
+// Watch-only wallets:
+// Unused addresses kept derived past the last used one on each descriptor
+pub const WATCH_DEFAULT_GAP_LIMIT: u32 = 20;
+pub const WATCH_MAX_GAP_LIMIT: u32 = 1000;
+// New mempool transactions fetched per tick; the rest are picked up on later ticks
+pub const WATCH_MAX_MEMPOOL_TXS_PER_TICK: usize = 2000;
+pub const WATCH_RECENT_EVENTS: usize = 50;
+
+// Webhooks:
+pub const WEBHOOK_MAX_ATTEMPTS: u32 = 8;
+// Doubled after every failed attempt, up to WEBHOOK_MAX_BACKOFF_SECS
+pub const WEBHOOK_INITIAL_BACKOFF_SECS: u64 = 5;
+pub const WEBHOOK_MAX_BACKOFF_SECS: u64 = 15 * 60;
+pub const WEBHOOK_TIMEOUT_SECS: u64 = 10;
+pub const WEBHOOK_DELIVERY_LOG_LIMIT: usize = 50;
+
+// Alert rules, evaluated on every fee estimate and mempool snapshot:
+pub const ALERT_RULES: &[AlertRule] = &[
+    AlertRule {
+        name: "next_block_fee_high",
+        metric: AlertMetric::FeeRate { block_target: 1 },
+        comparison: Comparison::Above,
+        threshold: 100.0,
+        clear_threshold: 80.0,
+        for_secs: 10 * 60,
+    },
+    AlertRule {
+        name: "mempool_large",
+        metric: AlertMetric::MempoolVsizeBytes,
+        comparison: Comparison::Above,
+        threshold: 300_000_000.0,
+        clear_threshold: 250_000_000.0,
+        for_secs: 0,
+    },
+];
+
+// Event publishing:
+// Destination for block, daily rollup, fee and reorg events, e.g. `Some(EventSink::Stdout)`.
+// While None no outbox rows are written.
+pub const EVENT_SINK: Option<EventSink> = None;
+pub const OUTBOX_POLL_INTERVAL_MS: u64 = 1000;
+pub const OUTBOX_BATCH_SIZE: usize = 500;
+// Published rows are kept this long for inspection, then deleted
+pub const OUTBOX_RETENTION_SECS: i64 = 7 * 24 * 60 * 60;
+pub const OUTBOX_PRUNE_INTERVAL_SECS: i64 = 60 * 60;
+
+// Shutdown:
+// Time allowed after SIGTERM/SIGINT to drain HTTP requests and commit the current ingestion batch.
+// Keep it below the container's stop timeout.
+pub const SHUTDOWN_DEADLINE_SECS: u64 = 25;
+
+// Logging and tracing:
+// Logs go to stderr, so `export` output and the stdout event sink stay clean
+pub const LOG_FORMAT: LogFormat = LogFormat::Text;
+// Default filter in `tracing_subscriber::EnvFilter` syntax; RUST_LOG overrides it.
+// warp's own request events are left out since every request is logged with its latency.
+pub const LOG_FILTER: &str = "info,warp=warn";
+// OTLP/HTTP traces endpoint of a local collector, e.g. `Some("http://localhost:4318/v1/traces")`.
+// Needs the `otel` feature.
+pub const OTEL_ENDPOINT: Option<&str> = None;
+pub const OTEL_SERVICE_NAME: &str = "project-rust";
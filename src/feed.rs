@@ -0,0 +1,205 @@
+use std::collections::HashSet;
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use futures_util::{stream, Stream, SinkExt, StreamExt};
+use serde::Deserialize;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{debug, error, warn};
+use warp::sse;
+use warp::ws::{Message, WebSocket};
+
+use crate::config::settings::{SSE_KEEP_ALIVE_SECS, WS_CLIENT_BUFFER};
+use crate::services::events::{EventBus, EventId, IngestionEvent, Resume, SequencedEvent, Topic};
+use crate::services::shutdown::Shutdown;
+
+// Query string accepted on connect, e.g. `/api/v1/ws?topics=blocks,fees`
+#[derive(Deserialize)]
+pub struct FeedQuery {
+    topics: Option<String>,
+}
+
+// Messages a client may send after connecting to change its subscriptions
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum ClientMessage {
+    Subscribe { topics: Vec<String> },
+    Unsubscribe { topics: Vec<String> },
+}
+
+// Wallet activity is only for clients presenting the admin key
+fn visible(topic: Topic, admin: bool) -> bool {
+    admin || topic != Topic::Wallets
+}
+
+fn parse_topics<'a>(topics: impl Iterator<Item = &'a str>, admin: bool) -> HashSet<Topic> {
+    topics
+        .filter_map(|topic| topic.parse().ok())
+        .filter(|topic| visible(*topic, admin))
+        .collect()
+}
+
+// Drive one WebSocket connection until the client leaves, falls behind or the server shuts down
+pub async fn handle_ws_client(
+    socket: WebSocket,
+    query: FeedQuery,
+    admin: bool,
+    event_bus: Arc<EventBus>,
+    shutdown: Arc<Shutdown>,
+) {
+    let (mut ws_tx, mut ws_rx) = socket.split();
+    let mut events = event_bus.subscribe();
+    let mut topics = parse_topics(query.topics.as_deref().unwrap_or("").split(','), admin);
+
+    // Writes go through a bounded queue so a slow socket cannot stall the event fan-out
+    let (out_tx, mut out_rx) = mpsc::channel::<Message>(WS_CLIENT_BUFFER);
+    let writer = tokio::spawn(async move {
+        while let Some(message) = out_rx.recv().await {
+            if ws_tx.send(message).await.is_err() {
+                break;
+            }
+        }
+        let _ = ws_tx.close().await;
+    });
+
+    loop {
+        tokio::select! {
+            _ = shutdown.wait() => {
+                // Dropping the queue lets the writer flush it and send a close frame
+                drop(out_tx);
+                let _ = tokio::time::timeout(Duration::from_secs(1), writer).await;
+                return;
+            }
+            event = events.recv() => {
+                let SequencedEvent { event, .. } = match event {
+                    Ok(sequenced) => sequenced,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!(skipped, "Dropping WebSocket client lagging behind");
+                        break;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if !topics.contains(&event.topic()) {
+                    continue;
+                }
+                let payload = match serde_json::to_string(&event) {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        error!(error = %e, "Failed to serialize event");
+                        continue;
+                    }
+                };
+                if out_tx.try_send(Message::text(payload)).is_err() {
+                    warn!("Dropping slow WebSocket client");
+                    break;
+                }
+            }
+            incoming = ws_rx.next() => {
+                let message = match incoming {
+                    Some(Ok(message)) => message,
+                    _ => break,
+                };
+                if message.is_close() {
+                    break;
+                }
+                let Ok(text) = message.to_str() else { continue };
+                match serde_json::from_str::<ClientMessage>(text) {
+                    Ok(ClientMessage::Subscribe { topics: added }) => {
+                        topics.extend(parse_topics(added.iter().map(String::as_str), admin));
+                    }
+                    Ok(ClientMessage::Unsubscribe { topics: removed }) => {
+                        for topic in parse_topics(removed.iter().map(String::as_str), admin) {
+                            topics.remove(&topic);
+                        }
+                    }
+                    Err(e) => debug!(error = %e, "Ignoring malformed WebSocket message"),
+                }
+            }
+        }
+    }
+
+    // The writer may be blocked on a stalled socket, so don't wait for it to drain
+    writer.abort();
+}
+
+// Tells a resuming client that events it missed are gone and it should reload its state
+fn reset_event(last_id: EventId) -> sse::Event {
+    sse::Event::default().id(last_id.to_string()).event("reset").data("{}")
+}
+
+fn to_sse_event(sequenced: &SequencedEvent) -> sse::Event {
+    sse::Event::default()
+        .id(sequenced.id.to_string())
+        .event(sequenced.event.kind())
+        .json_data(&sequenced.event)
+        .unwrap_or_else(|_| sse::Event::default().comment("unserializable event"))
+}
+
+// Build the SSE stream for one client: replay what it missed, or send a reset when that is no
+// longer buffered, then follow the live feed until shutdown. Without `?topics=` every topic is
+// sent, since SSE clients cannot subscribe later.
+pub fn sse_event_stream(
+    query: FeedQuery,
+    last_event_id: Option<String>,
+    admin: bool,
+    event_bus: Arc<EventBus>,
+    shutdown: Arc<Shutdown>,
+) -> impl Stream<Item = Result<sse::Event, Infallible>> + Send + 'static {
+    let topics = query.topics.map(|topics| parse_topics(topics.split(','), admin));
+
+    let (resume, receiver) = match last_event_id {
+        Some(last_event_id) => event_bus.subscribe_from(&last_event_id),
+        None => (Resume::Replay(Vec::new()), event_bus.subscribe()),
+    };
+    let (reset, missed) = match resume {
+        Resume::Replay(missed) => (None, missed),
+        Resume::Reset { last_id } => (Some(Ok(reset_event(last_id))), Vec::new()),
+    };
+
+    let replay = stream::iter(missed);
+    // A lagging client is disconnected; it resumes from the replay buffer via `Last-Event-ID`
+    let live = stream::unfold(receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(sequenced) => Some((sequenced, receiver)),
+            Err(_) => None,
+        }
+    });
+
+    let events = replay
+        .chain(live)
+        .filter(move |sequenced| {
+            let topic = sequenced.event.topic();
+            let wanted = match &topics {
+                Some(topics) => topics.contains(&topic),
+                None => visible(topic, admin),
+            };
+            futures_util::future::ready(wanted)
+        })
+        .map(|sequenced| Ok(to_sse_event(&sequenced)));
+    stream::iter(reset)
+        .chain(events)
+        .take_until(async move { shutdown.wait().await })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/events",
+    params(
+        ("topics" = Option<String>, Query, description = "Comma-separated topics: blocks, fees, mempool, daily_tx, alerts, wallets"),
+        ("Last-Event-ID" = Option<String>, Header, description = "Resume after this event id; a reset event is sent when that is no longer possible"),
+        ("X-Admin-Key" = Option<String>, Header, description = "Admin key, required for the wallets topic")
+    ),
+    responses(
+        (status = 200, description = "Server-Sent Events stream of ingestion events", body = IngestionEvent, content_type = "text/event-stream")
+    )
+)]
+pub fn sse_reply(
+    query: FeedQuery,
+    last_event_id: Option<String>,
+    admin: bool,
+    event_bus: Arc<EventBus>,
+    shutdown: Arc<Shutdown>,
+) -> impl warp::Reply {
+    let events = sse_event_stream(query, last_event_id, admin, event_bus, shutdown);
+    sse::reply(sse::keep_alive().interval(Duration::from_secs(SSE_KEEP_ALIVE_SECS)).stream(events))
+}
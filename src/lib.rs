@@ -0,0 +1,5 @@
+pub mod services;
+pub mod server;
+pub mod config;
+pub mod feed;
+pub mod admin;
@@ -1,34 +1,220 @@
-mod services;
-mod server;
-mod config;
-
-use config::connections::{RPC_URL,RPC_PWD,RPC_USER,DB_URL};
-use services::mysql_connection::MySqlService;
-use services::bitcoin_rpc::BitcoinRpcService;
-use services::ingestion::retrieve_and_store_data;
-use server::run_server;
+use project_rust::admin;
+use project_rust::config::connections::{RPC_URL,RPC_PWD,RPC_USER,REST_URL,DB_URL};
+use project_rust::config::settings::{
+    API_AUTH_ENABLED, EVENT_BUS_CAPACITY, EVENT_REPLAY_CAPACITY, EVENT_SINK, SHUTDOWN_DEADLINE_SECS,
+};
+use project_rust::services::storage;
+use project_rust::services::bitcoin_rpc::BitcoinRpcService;
+use project_rust::services::events::EventBus;
+use project_rust::services::tip_cache::TipCache;
+use project_rust::services::response_cache::{run_cache_invalidation, ResponseCache};
+use project_rust::services::api_auth::ApiAuth;
+use project_rust::services::explorer::ExplorerService;
+use project_rust::services::watch_wallets::WatchService;
+use project_rust::services::webhooks::{run_webhook_dispatcher, WebhookService};
+use project_rust::services::alerts::{run_alert_engine, run_alert_state_refresh, AlertEngine};
+use project_rust::services::publisher::{connect_publisher, run_outbox_relay};
+use project_rust::services::retention::run_retention_loop;
+use project_rust::services::ingestion::{retrieve_and_store_data, run_follower_loop, run_ingestion_loop};
+use project_rust::services::shutdown::{handle_signals, with_deadline, Shutdown};
+use project_rust::services::telemetry::init_tracing;
+use project_rust::server::{run_server, ApiServices};
+use std::time::Duration;
 use tokio::main;
+use tracing::{error, info, warn};
+
+const USAGE: &str = "Usage: project-rust [command]
+
+Commands:
+  run         Ingest from the node and serve the API (default)
+  serve       Serve the API only, reading what an `ingest` process stores
+  ingest      Ingest from the node only
+  backfill    Store older blocks: backfill --from <height> --to <height>
+  import      Store blocks from Bitcoin Core's block files: import --blocks-dir <dir>
+  migrate     Create the database tables
+  export      Write a stored series as CSV, NDJSON or Parquet
+  check       Verify the node and the database are reachable
+  keys        Manage API keys
+  aggregates  Rebuild the daily aggregates
+  index       Build the full index's secondary indexes";
+
+// Which long-running parts a process starts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Run,
+    Serve,
+    Ingest,
+}
+
+impl Mode {
+    fn ingests(self) -> bool {
+        self != Mode::Serve
+    }
+
+    fn serves(self) -> bool {
+        self != Mode::Ingest
+    }
+}
 
 #[main]
 async fn main() {
-    // Set up MySQL connection
-    let mysql_service = MySqlService::new(DB_URL);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = args.first().map(String::as_str).unwrap_or("run");
+    let bitcoin_service = || BitcoinRpcService::new(RPC_URL, RPC_USER, RPC_PWD, REST_URL);
+    // Held until exit so exported spans are flushed
+    let telemetry = match init_tracing() {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("Error setting up logging: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let result = match command {
+        "run" => start(Mode::Run).await,
+        "serve" => start(Mode::Serve).await,
+        "ingest" => start(Mode::Ingest).await,
+        "check" => admin::run_check_command(bitcoin_service()),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        // Admin commands run against the database and exit
+        "migrate" | "backfill" | "import" | "export" | "keys" | "aggregates" | "index" => match storage::connect(DB_URL) {
+            Ok(storage) => match command {
+                "migrate" => admin::run_migrate_command(storage.as_ref()),
+                "backfill" => admin::run_backfill_command(storage, bitcoin_service(), &args[1..]),
+                "import" => admin::run_import_command(storage, &args[1..]),
+                "export" => admin::run_export_command(storage, &args[1..]),
+                "keys" => admin::run_keys_command(storage, &args[1..]),
+                "aggregates" => admin::run_aggregates_command(storage, &args[1..]),
+                _ => admin::run_index_command(storage, &args[1..]),
+            },
+            Err(e) => Err(format!("Error connecting to the database: {}", e).into()),
+        },
+        other => Err(format!("Unknown command '{}'\n\n{}", other, USAGE).into()),
+    };
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        drop(telemetry);
+        std::process::exit(1);
+    }
+}
+
+async fn start(mode: Mode) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let shutdown = Shutdown::new();
+    tokio::spawn(handle_signals(shutdown.clone()));
+
+    // Set up the database connection
+    let storage = storage::connect(DB_URL).map_err(|e| format!("Error connecting to the database: {}", e))?;
+    admin::run_migrate_command(storage.as_ref())?;
 
     // Set up Bitcoin RPC connection
     let bitcoin_service = BitcoinRpcService::new(
         RPC_URL,
         RPC_USER,
-        RPC_PWD
+        RPC_PWD,
+        REST_URL,
     );
 
-    // Step 1: Retrieve the latest data before starting the server
-    if let Err(e) = retrieve_and_store_data(mysql_service.clone(), bitcoin_service.clone()).await {
-        eprintln!("Error retrieving and storing initial data: {:?}", e);
-        return;
+    if mode.ingests() {
+        // Publish stored changes from the outbox to the configured sink
+        if let Some(sink) = EVENT_SINK {
+            let publisher = connect_publisher(sink).await
+                .map_err(|e| format!("Error connecting to event sink {:?}: {:?}", sink, e))?;
+            tokio::spawn(run_outbox_relay(storage.clone(), publisher));
+        }
+
+        // Step 1: Retrieve the latest data before starting the server
+        retrieve_and_store_data(storage.clone(), bitcoin_service.clone()).await
+            .map_err(|e| format!("Error retrieving and storing initial data: {:?}", e))?;
     }
 
-    // Step 2: Run the Warp server
-    run_server(mysql_service, bitcoin_service).await;
-}
+    // Step 2: Keep ingesting in the background and push updates to feed clients
+    let event_bus = EventBus::new(EVENT_BUS_CAPACITY, EVENT_REPLAY_CAPACITY);
+    let tip_cache = TipCache::new();
+    let watch_service = WatchService::new(storage.clone(), bitcoin_service.clone(), event_bus.clone())
+        .map_err(|e| format!("Error loading watch-only wallets: {:?}", e))?;
+    // Alert rules over fee estimates and the mempool, resuming the states stored before a restart
+    let alert_engine = AlertEngine::new(storage.clone(), event_bus.clone())
+        .map_err(|e| format!("Error loading alert states: {:?}", e))?;
+    let webhook_service = WebhookService::new(storage.clone());
+
+    let ingestion = if mode.ingests() {
+        // Webhook deliveries for ingestion events, including ones interrupted by the last shutdown
+        if let Err(e) = webhook_service.resume_pending() {
+            error!(error = %e, "Error resuming pending webhook deliveries");
+        }
+        tokio::spawn(run_webhook_dispatcher(webhook_service.clone(), event_bus.clone()));
+        tokio::spawn(run_alert_engine(alert_engine.clone(), event_bus.clone()));
+        // Roll fee and mempool samples up into hourly and daily history and prune them
+        tokio::spawn(run_retention_loop(storage.clone()));
+
+        Some(tokio::spawn(run_ingestion_loop(
+            storage.clone(),
+            bitcoin_service.clone(),
+            event_bus.clone(),
+            tip_cache.clone(),
+            watch_service.clone(),
+            shutdown.clone(),
+        )))
+    } else {
+        // Another process ingests; follow what it stores
+        tokio::spawn(run_follower_loop(
+            storage.clone(),
+            bitcoin_service.clone(),
+            event_bus.clone(),
+            tip_cache.clone(),
+        ));
+        tokio::spawn(run_alert_state_refresh(alert_engine.clone()));
+        None
+    };
 
-// #TODO Get transactions has severe bug
\ No newline at end of file
+    let server = if mode.serves() {
+        // Drop cached API responses whenever ingestion publishes newer data
+        let response_cache = ResponseCache::new();
+        tokio::spawn(run_cache_invalidation(response_cache.clone(), event_bus.clone()));
+
+        // Optional API key authentication
+        let api_auth = API_AUTH_ENABLED.then(|| ApiAuth::new(storage.clone()));
+
+        let explorer_service = ExplorerService::new(bitcoin_service, tip_cache.clone());
+
+        // Step 3: Run the Warp server
+        Some(run_server(ApiServices {
+            storage,
+            tip_cache,
+            event_bus,
+            response_cache,
+            api_auth,
+            explorer_service,
+            watch_service,
+            webhook_service,
+            alert_engine,
+            shutdown: shutdown.clone(),
+        }))
+    } else {
+        None
+    };
+
+    // Both only return after shutdown: the server once requests are drained, ingestion once
+    // its current batch and the aggregates checkpoint are committed
+    let stopped = async {
+        if let Some(server) = server {
+            server.await;
+        }
+        if let Some(ingestion) = ingestion {
+            let _ = ingestion.await;
+        }
+    };
+    match with_deadline(&shutdown, Duration::from_secs(SHUTDOWN_DEADLINE_SECS), stopped).await {
+        Some(()) => info!("Shutdown complete"),
+        None => {
+            warn!(deadline_secs = SHUTDOWN_DEADLINE_SECS, "Shutdown deadline passed with work still in flight");
+            // Exit right away: dropping the runtime would wait for blocking calls still running,
+            // such as a query under its statement timeout
+            return Err("Stopped at the shutdown deadline".into());
+        }
+    }
+    Ok(())
+}
@@ -1,12 +1,40 @@
 use warp::Filter;
+use std::net::SocketAddr;
 use std::sync::Arc;
-use crate::services::mysql_connection::MySqlService;
-use crate::services::bitcoin_rpc::BitcoinRpcService;
-use serde::Serialize;
-use chrono::NaiveDate;
+use crate::services::storage::{spawn_blocking_in_span, PoolStats, Storage};
+use crate::services::export::{self, ChannelWriter, ExportFormat, ExportSeries};
+use crate::services::tip_cache::TipCache;
+use crate::services::response_cache::{cached_request, CachedRequest, ResponseCache};
+use crate::services::api_auth::{hash_api_key, ApiAuth, AuthError};
+use crate::services::explorer::{
+    BlockInfo, BlockTxSummary, BlockTxsPage, ExplorerError, ExplorerService, TransactionInfo, TxInput,
+    TxOutput, TxStatus,
+};
+use crate::services::watch_wallets::{WalletDetail, WalletEvent, WalletSummary, WatchError, WatchService};
+use crate::services::webhooks::{DeliveryInfo, WebhookError, WebhookInfo, WebhookService};
+use crate::services::alerts::{AlertEngine, AlertInfo, AlertStatus, Comparison};
+use crate::services::retention::{choose_resolution, metric_history, metric_names, MetricPoint, Resolution};
+use crate::services::shutdown::Shutdown;
+use crate::config::settings::{
+    EXPLORER_DEFAULT_PAGE_SIZE, EXPLORER_MAX_PAGE_SIZE, EXPORT_HTTP_BUFFER_CHUNKS, EXPORT_MAX_CONCURRENT,
+    FULL_INDEX_ENABLED,
+};
+use crate::config::connections::{ADMIN_API_KEY, BITCOIN_NETWORK};
+use bitcoincore_rpc::bitcoin::address::{Address, NetworkUnchecked};
+use crate::services::events::EventBus;
+use crate::feed::{handle_ws_client, sse_reply, FeedQuery};
+use crate::services::events::{FeeEstimate, IngestionEvent};
+use serde::{Deserialize, Serialize};
+use utoipa::openapi::path::{HttpMethod, OperationBuilder};
+use utoipa::openapi::{Deprecated, ResponseBuilder};
+use utoipa::{Modify, OpenApi, ToSchema};
+use chrono::{DateTime, NaiveDate, Utc};
+use warp::http::{header, StatusCode};
 
 use warp::reject::Reject;
 use std::fmt;
+use tracing::{error, info, info_span, warn, Instrument, Span};
+use tokio::sync::{mpsc, Semaphore};
 
 
 //////////////////////////////////////////
@@ -16,17 +44,17 @@ struct CustomError {
     message: String,
 }
 //////////////////////////////////////////
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 struct TxData {
     date: NaiveDate,
     tx_count: usize,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 struct FeeRateData {
     block_target: u16,
     fee_rate: f64,
-    estimated_at: String,
+    estimated_at: DateTime<Utc>,
 }
 
 
@@ -42,94 +70,1180 @@ impl fmt::Display for CustomError {
 
 impl std::error::Error for CustomError {}
 
+// Rejections raised by API key authentication and rate limiting
+#[derive(Debug)]
+struct AuthRejection(AuthError);
+
+impl Reject for AuthRejection {}
+
+// Client-side errors such as malformed ids or unknown resources
+#[derive(Debug)]
+struct RequestError {
+    status: StatusCode,
+    message: String,
+}
+
+impl Reject for RequestError {}
+
+impl From<ExplorerError> for warp::Rejection {
+    fn from(error: ExplorerError) -> Self {
+        match error {
+            ExplorerError::InvalidId(message) => warp::reject::custom(RequestError { status: StatusCode::BAD_REQUEST, message }),
+            ExplorerError::NotFound => warp::reject::custom(RequestError {
+                status: StatusCode::NOT_FOUND,
+                message: "Not found".to_string(),
+            }),
+            ExplorerError::Rpc(message) => {
+                error!(error = %message, "Explorer RPC error");
+                warp::reject::custom(CustomError { message: format!("Bitcoin RPC error: {}", message) })
+            }
+        }
+    }
+}
+
+impl From<WatchError> for warp::Rejection {
+    fn from(error: WatchError) -> Self {
+        match error {
+            WatchError::Invalid(message) => warp::reject::custom(RequestError { status: StatusCode::BAD_REQUEST, message }),
+            WatchError::Conflict(message) => warp::reject::custom(RequestError { status: StatusCode::CONFLICT, message }),
+            WatchError::NotFound => warp::reject::custom(RequestError {
+                status: StatusCode::NOT_FOUND,
+                message: "Not found".to_string(),
+            }),
+            WatchError::Backend(message) => {
+                error!(error = %message, "Watch-only wallet error");
+                warp::reject::custom(CustomError { message: format!("Watch-only wallet error: {}", message) })
+            }
+        }
+    }
+}
+
+impl From<WebhookError> for warp::Rejection {
+    fn from(error: WebhookError) -> Self {
+        match error {
+            WebhookError::Invalid(message) => warp::reject::custom(RequestError { status: StatusCode::BAD_REQUEST, message }),
+            WebhookError::NotFound => warp::reject::custom(RequestError {
+                status: StatusCode::NOT_FOUND,
+                message: "Not found".to_string(),
+            }),
+            WebhookError::Storage(message) => {
+                error!(error = %message, "Webhook storage error");
+                warp::reject::custom(CustomError { message: format!("Webhook storage error: {}", message) })
+            }
+        }
+    }
+}
+
 //////////////////////////////////////////
 
-#[derive(Serialize)]
+// OpenAPI document generated from the route handlers and response types below
+#[derive(OpenApi)]
+#[openapi(
+    modifiers(&LegacyPaths),
+    info(title = "Bitcoin ingestion API", description = "Chain data ingested from a Bitcoin Core node. \
+        A WebSocket feed of the same events as `/api/v1/events` is available at `/api/v1/ws?topics=...`."),
+    paths(
+        handle_get_block_height, handle_get_last_7_days, handle_get_fee_estimations, handle_export, crate::feed::sse_reply,
+        handle_get_block, handle_get_block_txs, handle_get_transaction,
+        handle_get_address, handle_get_address_txs,
+        handle_register_wallet, handle_list_wallets, handle_get_wallet,
+        handle_register_webhook, handle_list_webhooks, handle_delete_webhook, handle_list_webhook_deliveries,
+        handle_replay_webhook_delivery, handle_get_alerts, handle_get_metric_history, handle_get_db_pool
+    ),
+    components(schemas(
+        BlockHeightResponse, TxData, FeeRateData, ErrorResponse, IngestionEvent, FeeEstimate,
+        BlockInfo, BlockTxSummary, BlockTxsPage, TransactionInfo, TxInput, TxOutput, TxStatus,
+        AddressResponse, AddressTxData, AddressTxsResponse,
+        RegisterWalletRequest, WalletSummary, WalletDetail, WalletEvent,
+        RegisterWebhookRequest, WebhookInfo, DeliveryInfo,
+        AlertInfo, AlertStatus, Comparison, MetricHistoryResponse, MetricPoint, Resolution, PoolStats
+    ))
+)]
+struct ApiDoc;
+
+// Routes from before /api/v1, below /api, redirected to their /api/v1 path for one more release
+const LEGACY_ROUTES: [&str; 3] = ["block_info/block_height", "7d_tx", "fee_estimations"];
+
+// Lists the legacy routes in the OpenAPI document as deprecated
+struct LegacyPaths;
+
+impl Modify for LegacyPaths {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for route in LEGACY_ROUTES {
+            let operation = OperationBuilder::new()
+                .description(Some(format!("Moved to `/api/v1/{}`", route)))
+                .deprecated(Some(Deprecated::True))
+                .response("308", ResponseBuilder::new().description(format!("Redirect to /api/v1/{}", route)))
+                .build();
+            openapi.paths.add_path_operation(format!("/api/{}", route), vec![HttpMethod::Get], operation);
+        }
+    }
+}
+
+// The OpenAPI document served at /api/openapi.json
+pub fn openapi_document() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+// Swagger UI page pointed at our generated document
+const DOCS_HTML: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <title>Bitcoin ingestion API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>SwaggerUIBundle({ url: "/api/openapi.json", dom_id: "#swagger-ui" });</script>
+</body>
+</html>"##;
+
+#[derive(Serialize, ToSchema)]
 struct BlockHeightResponse {
     block_height: u64,
+    block_hash: String,
+    block_time: DateTime<Utc>,
+    // When this tip was first seen, which data_age_secs counts from
+    observed_at: DateTime<Utc>,
+    // When the node last reported it still
+    checked_at: DateTime<Utc>,
+    data_age_secs: i64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct ErrorResponse {
+    error: String,
+}
+
+#[derive(Deserialize)]
+struct PageQuery {
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    series: String,
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    format: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MetricHistoryQuery {
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    resolution: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct MetricHistoryResponse {
+    metric: String,
+    resolution: Resolution,
+    points: Vec<MetricPoint>,
+}
+
+#[derive(Deserialize)]
+struct CursorQuery {
+    cursor: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AddressResponse {
+    address: String,
+    balance_sats: u64,
+    total_received_sats: u64,
+    tx_count: u64,
+    first_seen_height: Option<u64>,
+    last_seen_height: Option<u64>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AddressTxData {
+    txid: String,
+    block_height: u64,
+    received_sats: u64,
+    sent_sats: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AddressTxsResponse {
+    address: String,
+    txs: Vec<AddressTxData>,
+    // Pass as `cursor` to get the next page; absent on the last page
+    next_cursor: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct RegisterWalletRequest {
+    name: String,
+    // Ranged output descriptors, or bare xpubs watched as wpkh on their /0/* and /1/* chains
+    descriptors: Vec<String>,
+    gap_limit: Option<u32>,
+}
+
+#[derive(Deserialize, ToSchema)]
+struct RegisterWebhookRequest {
+    url: String,
+    // Any of new_block, fee_threshold, reorg, daily_tx, wallet_activity, alert
+    events: Vec<String>,
+    // Required with fee_threshold: the estimate to watch and its threshold in sat/vB
+    fee_block_target: Option<u16>,
+    fee_threshold: Option<f64>,
+}
+
+// Everything the API handlers read from
+pub struct ApiServices {
+    pub storage: Arc<dyn Storage>,
+    pub tip_cache: Arc<TipCache>,
+    pub event_bus: Arc<EventBus>,
+    pub response_cache: Arc<ResponseCache>,
+    pub api_auth: Option<Arc<ApiAuth>>,
+    pub explorer_service: Arc<ExplorerService>,
+    pub watch_service: Arc<WatchService>,
+    pub webhook_service: Arc<WebhookService>,
+    pub alert_engine: Arc<AlertEngine>,
+    pub shutdown: Arc<Shutdown>,
 }
 
 // Function to create the Warp REST API server
-pub async fn run_server(mysql_service: Arc<MySqlService>, bitcoin_service: Arc<BitcoinRpcService>) {
-    // Define a route to fetch the latest block height
-    let get_block_height_route = warp::path!("api" / "block_info" / "block_height")
-        .and(warp::get())
-        .and(with_services(mysql_service.clone(), bitcoin_service.clone()))
-        .and_then(handle_get_block_height);
+pub async fn run_server(services: ApiServices) {
+    let ApiServices {
+        storage,
+        tip_cache,
+        event_bus,
+        response_cache,
+        api_auth,
+        explorer_service,
+        watch_service,
+        webhook_service,
+        alert_engine,
+        shutdown,
+    } = services;
 
-    let tx_data_route = warp::path!("api" / "7d_tx")
+    let get_block_height_route = block_height_route(tip_cache.clone());
+
+    let tx_data_route = warp::path!("7d_tx")
         .and(warp::get())
-        .and(with_mysql_service(mysql_service.clone()))
+        .and(cached_request(response_cache.clone(), api_auth.is_some(), &[]))
+        .and(with_storage(storage.clone()))
         .and_then(handle_get_last_7_days);
 
-    let fee_estimations_route = warp::path!("api" / "fee_estimations")
+    let fee_estimations_route = warp::path!("fee_estimations")
         .and(warp::get())
-        .and(with_mysql_service(mysql_service.clone()))
+        .and(cached_request(response_cache.clone(), api_auth.is_some(), &[]))
+        .and(with_storage(storage.clone()))
         .and_then(handle_get_fee_estimations);
 
-    let routes = 
-                get_block_height_route
-                .or(tx_data_route)
-                .or(fee_estimations_route);
+    let export_route = export_route(storage.clone(), Arc::new(Semaphore::new(EXPORT_MAX_CONCURRENT)));
+
+    // State of every configured alert rule
+    let alerts_route = warp::path!("alerts")
+        .and(warp::get())
+        .and(with_alert_engine(alert_engine.clone()))
+        .and_then(handle_get_alerts);
+
+    // Fee and mempool history at a resolution that suits the range
+    let metric_history_route = warp::path!("metrics" / String)
+        .and(warp::get())
+        .and(warp::query::<MetricHistoryQuery>())
+        .and(with_storage(storage.clone()))
+        .and_then(handle_get_metric_history);
+
+    // Explorer routes backed by the node
+    let block_route = warp::path!("blocks" / String)
+        .and(warp::get())
+        .and(with_explorer_service(explorer_service.clone()))
+        .and_then(handle_get_block);
+
+    let block_txs_route = warp::path!("blocks" / String / "txs")
+        .and(warp::get())
+        .and(warp::query::<PageQuery>())
+        .and(with_explorer_service(explorer_service.clone()))
+        .and_then(handle_get_block_txs);
+
+    let transaction_route = warp::path!("tx" / String)
+        .and(warp::get())
+        .and(with_explorer_service(explorer_service.clone()))
+        .and_then(handle_get_transaction);
+
+    // Address lookups, served from the full index
+    let address_route = warp::path!("address" / String)
+        .and(warp::get())
+        .and(with_storage(storage.clone()))
+        .and_then(handle_get_address);
+
+    let address_txs_route = warp::path!("address" / String / "txs")
+        .and(warp::get())
+        .and(warp::query::<CursorQuery>())
+        .and(with_storage(storage.clone()))
+        .and_then(handle_get_address_txs);
+
+    // Watch-only wallets, behind the admin key
+    let register_wallet_route = warp::path!("admin" / "wallets")
+        .and(warp::post())
+        .and(with_admin())
+        .and(warp::body::content_length_limit(64 * 1024))
+        .and(warp::body::json())
+        .and(with_watch_service(watch_service.clone()))
+        .and_then(handle_register_wallet);
+
+    let list_wallets_route = warp::path!("admin" / "wallets")
+        .and(warp::get())
+        .and(with_admin())
+        .and(with_watch_service(watch_service.clone()))
+        .and_then(handle_list_wallets);
+
+    let wallet_route = warp::path!("admin" / "wallets" / String)
+        .and(warp::get())
+        .and(with_admin())
+        .and(with_watch_service(watch_service.clone()))
+        .and_then(handle_get_wallet);
+
+    // Webhooks and their delivery log, behind the admin key
+    let register_webhook_route = warp::path!("admin" / "webhooks")
+        .and(warp::post())
+        .and(with_admin())
+        .and(warp::body::content_length_limit(16 * 1024))
+        .and(warp::body::json())
+        .and(with_webhook_service(webhook_service.clone()))
+        .and_then(handle_register_webhook);
+
+    let list_webhooks_route = warp::path!("admin" / "webhooks")
+        .and(warp::get())
+        .and(with_admin())
+        .and(with_webhook_service(webhook_service.clone()))
+        .and_then(handle_list_webhooks);
+
+    let delete_webhook_route = warp::path!("admin" / "webhooks" / u64)
+        .and(warp::delete())
+        .and(with_admin())
+        .and(with_webhook_service(webhook_service.clone()))
+        .and_then(handle_delete_webhook);
+
+    let webhook_deliveries_route = warp::path!("admin" / "webhooks" / u64 / "deliveries")
+        .and(warp::get())
+        .and(with_admin())
+        .and(with_webhook_service(webhook_service.clone()))
+        .and_then(handle_list_webhook_deliveries);
+
+    let replay_delivery_route = warp::path!("admin" / "webhook_deliveries" / u64 / "replay")
+        .and(warp::post())
+        .and(with_admin())
+        .and(with_webhook_service(webhook_service.clone()))
+        .and_then(handle_replay_webhook_delivery);
+
+    // Database connection pool usage, behind the admin key
+    let db_pool_route = warp::path!("admin" / "db_pool")
+        .and(warp::get())
+        .and(with_admin())
+        .and(with_storage(storage.clone()))
+        .and_then(handle_get_db_pool);
+
+    // Push feed of ingestion events, e.g. `/api/v1/ws?topics=blocks,fees`
+    let ws_route = warp::path!("ws")
+        .and(warp::ws())
+        .and(warp::query::<FeedQuery>())
+        .and(with_admin_flag())
+        .and(with_event_bus(event_bus.clone()))
+        .and(with_shutdown(shutdown.clone()))
+        .map(|ws: warp::ws::Ws, query: FeedQuery, admin: bool, event_bus: Arc<EventBus>, shutdown: Arc<Shutdown>| {
+            ws.on_upgrade(move |socket| handle_ws_client(socket, query, admin, event_bus, shutdown))
+        });
+
+    // Same feed over Server-Sent Events, resumable with `Last-Event-ID`
+    let sse_route = warp::path!("events")
+        .and(warp::get())
+        .and(warp::query::<FeedQuery>())
+        .and(warp::header::optional::<String>("last-event-id"))
+        .and(with_admin_flag())
+        .and(with_event_bus(event_bus.clone()))
+        .and(with_shutdown(shutdown.clone()))
+        .map(sse_reply);
+
+    // Version 1 of the API; breaking changes go into a new prefix
+    let api_v1 = warp::path!("api" / "v1" / ..)
+        .and(
+            get_block_height_route
+            .or(tx_data_route)
+            .or(fee_estimations_route)
+            .or(export_route)
+            .or(alerts_route)
+            .or(metric_history_route)
+            .or(block_route)
+            .or(block_txs_route)
+            .or(transaction_route)
+            .or(address_route)
+            .or(address_txs_route)
+            .or(register_wallet_route)
+            .or(list_wallets_route)
+            .or(wallet_route)
+            .or(register_webhook_route)
+            .or(list_webhooks_route)
+            .or(delete_webhook_route)
+            .or(webhook_deliveries_route)
+            .or(replay_delivery_route)
+            .or(db_pool_route)
+            .or(ws_route)
+            .or(sse_route)
+        );
+
+    let openapi_route = warp::path!("api" / "openapi.json")
+        .and(warp::get())
+        .map(|| warp::reply::json(&openapi_document()));
+
+    let docs_route = warp::path!("docs")
+        .and(warp::get())
+        .map(|| warp::reply::html(DOCS_HTML));
 
-    // Start the warp server
-    warp::serve(routes)
-        .run(([0, 0, 0, 0], 3030))
-        .await;
+    let routes = with_auth(api_auth)
+                .and(
+                    api_v1
+                    .or(legacy_route())
+                    .or(openapi_route)
+                    .or(docs_route)
+                )
+                .recover(handle_rejection)
+                .with(warp::log::custom(log_request))
+                .with(warp::trace(request_span));
+
+    // Start the warp server. On shutdown it stops accepting connections and returns once the
+    // requests in flight are answered; the push feeds end themselves.
+    let (_, server) = warp::serve(routes)
+        .bind_with_graceful_shutdown(([0, 0, 0, 0], 3030), async move { shutdown.wait().await });
+    server.await;
+    info!("HTTP server stopped");
 }
 
-// Helper function to inject services into the route handler
-fn with_services(
-    mysql_service: Arc<MySqlService>,
-    bitcoin_service: Arc<BitcoinRpcService>
-) -> impl Filter<Extract = ((Arc<MySqlService>, Arc<BitcoinRpcService>),), Error = warp::Rejection> + Clone {
-    warp::any()
-        .map(move || (mysql_service.clone(), bitcoin_service.clone()))
-        .boxed()
+// A span around each request, so everything it logs or calls carries the request id. The id is taken
+// from an `X-Request-Id` header when the client or a proxy sent one.
+fn request_span(info: warp::trace::Info) -> tracing::Span {
+    let request_id = info.request_headers()
+        .get("x-request-id")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()));
+    info_span!("http_request", request_id = %request_id, method = %info.method(), route = %info.path())
 }
 
-fn with_mysql_service(
-    mysql_service: Arc<MySqlService>,
-) -> impl Filter<Extract = (Arc<MySqlService>,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || mysql_service.clone())
+// Runs inside the request span once the reply is ready
+fn log_request(info: warp::log::Info) {
+    let status = info.status().as_u16();
+    let latency_ms = info.elapsed().as_millis() as u64;
+    if info.status().is_server_error() {
+        warn!(status, latency_ms, "Request failed");
+    } else {
+        info!(status, latency_ms, "Request completed");
+    }
 }
 
-// Route handler to fetch and return the block height
-async fn handle_get_block_height(
-    services: (Arc<MySqlService>, Arc<BitcoinRpcService>)
+// Require a valid API key on non-public routes; a no-op when authentication is disabled.
+// The key is read from `X-API-Key` or an `Authorization: Bearer` header.
+fn with_auth(
+    api_auth: Option<Arc<ApiAuth>>,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::path::full()
+        .and(warp::header::optional::<String>("x-api-key"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::addr::remote())
+        .and_then(move |path: warp::path::FullPath, api_key: Option<String>, authorization: Option<String>, remote: Option<SocketAddr>| {
+            let api_auth = api_auth.clone();
+            async move {
+                let Some(api_auth) = api_auth else {
+                    return Ok(());
+                };
+                let bearer = authorization
+                    .as_deref()
+                    .and_then(|value| value.strip_prefix("Bearer "))
+                    .map(str::to_string);
+                let api_key = api_key.or(bearer);
+                let client = remote.map(|remote| remote.ip());
+                if let Some(result) = api_auth.authorize_cached(path.as_str(), api_key.as_deref(), client) {
+                    return result.map_err(|e| warp::reject::custom(AuthRejection(e)));
+                }
+                // A key missing from the cache is looked up in the database
+                let api_key = api_key.unwrap_or_default();
+                run_blocking(api_auth, move |auth: &ApiAuth| {
+                    auth.authorize_stored(&api_key).map_err(|e| warp::reject::custom(AuthRejection(e)))
+                }).await
+            }
+        })
+        .untuple_one()
+}
+
+fn is_admin_key(admin_key: Option<&str>) -> bool {
+    match (ADMIN_API_KEY, admin_key) {
+        // Compare digests so the comparison time does not depend on the configured key
+        (Some(expected), Some(admin_key)) => hash_api_key(admin_key) == hash_api_key(expected),
+        _ => false,
+    }
+}
+
+// Whether the request carries the admin key in `X-Admin-Key`
+fn with_admin_flag() -> impl Filter<Extract = (bool,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("x-admin-key")
+        .map(|admin_key: Option<String>| is_admin_key(admin_key.as_deref()))
+}
+
+// Require the admin key; admin routes are refused outright while ADMIN_API_KEY is unset
+fn with_admin() -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    with_admin_flag()
+        .and_then(|admin: bool| async move {
+            if ADMIN_API_KEY.is_none() {
+                Err(warp::reject::custom(RequestError {
+                    status: StatusCode::FORBIDDEN,
+                    message: "Admin API is disabled".to_string(),
+                }))
+            } else if !admin {
+                Err(warp::reject::custom(RequestError {
+                    status: StatusCode::UNAUTHORIZED,
+                    message: "Missing or invalid admin key".to_string(),
+                }))
+            } else {
+                Ok(())
+            }
+        })
+        .untuple_one()
+}
+
+// Turn our own rejections into JSON error responses; anything else keeps warp's default handling
+pub async fn handle_rejection(err: warp::Rejection) -> Result<warp::reply::Response, warp::Rejection> {
+    use warp::Reply;
+
+    if let Some(AuthRejection(auth_error)) = err.find::<AuthRejection>() {
+        let (status, message) = match auth_error {
+            AuthError::MissingKey => (StatusCode::UNAUTHORIZED, "Missing API key".to_string()),
+            AuthError::InvalidKey => (StatusCode::UNAUTHORIZED, "Invalid API key".to_string()),
+            AuthError::RateLimited { .. } => (StatusCode::TOO_MANY_REQUESTS, "Rate limit exceeded".to_string()),
+            AuthError::Storage(e) => {
+                error!(error = %e, "Failed to look up API key");
+                (StatusCode::SERVICE_UNAVAILABLE, "Authentication unavailable".to_string())
+            }
+        };
+        let reply = warp::reply::with_status(warp::reply::json(&ErrorResponse { error: message }), status);
+        return Ok(match auth_error {
+            AuthError::RateLimited { retry_after_secs } => {
+                warp::reply::with_header(reply, header::RETRY_AFTER, retry_after_secs.to_string()).into_response()
+            }
+            _ => reply.into_response(),
+        });
+    }
+
+    if let Some(request_error) = err.find::<RequestError>() {
+        let response = ErrorResponse { error: request_error.message.clone() };
+        return Ok(warp::reply::with_status(warp::reply::json(&response), request_error.status).into_response());
+    }
+
+    if let Some(custom_error) = err.find::<CustomError>() {
+        let response = ErrorResponse { error: custom_error.to_string() };
+        return Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::INTERNAL_SERVER_ERROR).into_response());
+    }
+
+    Err(err)
+}
+
+fn with_storage(
+    storage: Arc<dyn Storage>,
+) -> impl Filter<Extract = (Arc<dyn Storage>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || storage.clone())
+}
+
+fn with_event_bus(
+    event_bus: Arc<EventBus>,
+) -> impl Filter<Extract = (Arc<EventBus>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || event_bus.clone())
+}
+
+fn with_explorer_service(
+    explorer_service: Arc<ExplorerService>,
+) -> impl Filter<Extract = (Arc<ExplorerService>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || explorer_service.clone())
+}
+
+fn with_watch_service(
+    watch_service: Arc<WatchService>,
+) -> impl Filter<Extract = (Arc<WatchService>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || watch_service.clone())
+}
+
+fn with_shutdown(
+    shutdown: Arc<Shutdown>,
+) -> impl Filter<Extract = (Arc<Shutdown>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || shutdown.clone())
+}
+
+fn with_alert_engine(
+    alert_engine: Arc<AlertEngine>,
+) -> impl Filter<Extract = (Arc<AlertEngine>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || alert_engine.clone())
+}
+
+fn with_webhook_service(
+    webhook_service: Arc<WebhookService>,
+) -> impl Filter<Extract = (Arc<WebhookService>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || webhook_service.clone())
+}
+
+// Explorer lookups, wallet registration and the services' database queries block, so run them off the
+// async workers
+async fn run_blocking<S, T, E, F>(service: Arc<S>, call: F) -> Result<T, warp::Rejection>
+where
+    S: Send + Sync + 'static,
+    T: Send + 'static,
+    E: Into<warp::Rejection> + Send + 'static,
+    F: FnOnce(&S) -> Result<T, E> + Send + 'static,
+{
+    tokio::task::spawn_blocking(move || call(&service))
+        .await
+        .map_err(|e| warp::reject::custom(CustomError { message: format!("Background task failed: {:?}", e) }))?
+        .map_err(Into::into)
+}
+
+// Route handler for a block by height or hash
+#[utoipa::path(
+    get,
+    path = "/api/v1/blocks/{id}",
+    params(("id" = String, Path, description = "Block height or block hash")),
+    responses(
+        (status = 200, description = "Block header fields and totals", body = BlockInfo),
+        (status = 400, description = "Malformed height or hash", body = ErrorResponse),
+        (status = 404, description = "Unknown block", body = ErrorResponse)
+    )
+)]
+async fn handle_get_block(
+    id: String,
+    explorer_service: Arc<ExplorerService>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let block = run_blocking(explorer_service, move |explorer: &ExplorerService| explorer.get_block(&id)).await?;
+    Ok(warp::reply::json(&block))
+}
+
+// Route handler for one page of a block's transactions
+#[utoipa::path(
+    get,
+    path = "/api/v1/blocks/{hash}/txs",
+    params(
+        ("hash" = String, Path, description = "Block hash"),
+        ("offset" = Option<usize>, Query, description = "Index of the first transaction, default 0"),
+        ("limit" = Option<usize>, Query, description = "Page size, default 25, at most 100")
+    ),
+    responses(
+        (status = 200, description = "Page of transaction summaries", body = BlockTxsPage),
+        (status = 400, description = "Malformed hash", body = ErrorResponse),
+        (status = 404, description = "Unknown block", body = ErrorResponse)
+    )
+)]
+async fn handle_get_block_txs(
+    hash: String,
+    page: PageQuery,
+    explorer_service: Arc<ExplorerService>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let (mysql_service, bitcoin_service) = services;
+    let offset = page.offset.unwrap_or(0);
+    let limit = page.limit.unwrap_or(EXPLORER_DEFAULT_PAGE_SIZE).clamp(1, EXPLORER_MAX_PAGE_SIZE);
+    let txs = run_blocking(explorer_service, move |explorer: &ExplorerService| explorer.get_block_txs(&hash, offset, limit)).await?;
+    Ok(warp::reply::json(&txs))
+}
+
+// Route handler for a transaction by txid
+#[utoipa::path(
+    get,
+    path = "/api/v1/tx/{txid}",
+    params(("txid" = String, Path, description = "Transaction id")),
+    responses(
+        (status = 200, description = "Inputs, outputs, fee and confirmation status", body = TransactionInfo),
+        (status = 400, description = "Malformed txid", body = ErrorResponse),
+        (status = 404, description = "Unknown transaction", body = ErrorResponse)
+    )
+)]
+async fn handle_get_transaction(
+    txid: String,
+    explorer_service: Arc<ExplorerService>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let transaction = run_blocking(explorer_service, move |explorer: &ExplorerService| explorer.get_transaction(&txid)).await?;
+    Ok(warp::reply::json(&transaction))
+}
+
+// Route handler to register a watch-only wallet
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/wallets",
+    request_body = RegisterWalletRequest,
+    params(("X-Admin-Key" = String, Header, description = "Admin key")),
+    responses(
+        (status = 201, description = "Wallet registered with its current confirmed balance", body = WalletDetail),
+        (status = 400, description = "Invalid name, descriptor or gap limit", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid admin key", body = ErrorResponse),
+        (status = 409, description = "A wallet with this name exists", body = ErrorResponse)
+    )
+)]
+async fn handle_register_wallet(
+    request: RegisterWalletRequest,
+    watch_service: Arc<WatchService>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // Imports the current balance with `scantxoutset`, which can take minutes on mainnet
+    let wallet = run_blocking(watch_service, move |watch: &WatchService| {
+        watch.register_wallet(&request.name, &request.descriptors, request.gap_limit)
+    }).await?;
+    Ok(warp::reply::with_status(warp::reply::json(&wallet), StatusCode::CREATED))
+}
 
-    match bitcoin_service.get_block_height() {
-        Ok(block_height) => {
-            // Update the block height in MySQL
-            if let Err(e) = mysql_service.update_block_height(block_height) {
-                eprintln!("Failed to update block height in MySQL: {:?}", e);
+// Route handler for every watched wallet with its balance
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/wallets",
+    params(("X-Admin-Key" = String, Header, description = "Admin key")),
+    responses(
+        (status = 200, description = "Watched wallets and their confirmed balances", body = Vec<WalletSummary>),
+        (status = 401, description = "Missing or invalid admin key", body = ErrorResponse)
+    )
+)]
+async fn handle_list_wallets(
+    watch_service: Arc<WatchService>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let wallets = run_blocking(watch_service, |watch: &WatchService| watch.list_wallets()).await?;
+    Ok(warp::reply::json(&wallets))
+}
+
+// Route handler for one watched wallet and its latest activity
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/wallets/{name}",
+    params(
+        ("name" = String, Path, description = "Wallet name"),
+        ("X-Admin-Key" = String, Header, description = "Admin key")
+    ),
+    responses(
+        (status = 200, description = "Balance and recent incoming and outgoing transactions", body = WalletDetail),
+        (status = 401, description = "Missing or invalid admin key", body = ErrorResponse),
+        (status = 404, description = "Unknown wallet", body = ErrorResponse)
+    )
+)]
+async fn handle_get_wallet(
+    name: String,
+    watch_service: Arc<WatchService>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let wallet = run_blocking(watch_service, move |watch: &WatchService| watch.get_wallet(&name)).await?;
+    Ok(warp::reply::json(&wallet))
+}
+
+// Route handler to register a webhook; the response carries the signing secret, shown only once
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/webhooks",
+    request_body = RegisterWebhookRequest,
+    params(("X-Admin-Key" = String, Header, description = "Admin key")),
+    responses(
+        (status = 201, description = "Webhook registered", body = WebhookInfo),
+        (status = 400, description = "Invalid URL, event or fee threshold", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid admin key", body = ErrorResponse)
+    )
+)]
+async fn handle_register_webhook(
+    request: RegisterWebhookRequest,
+    webhook_service: Arc<WebhookService>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let webhook = run_blocking(webhook_service, move |webhooks: &WebhookService| {
+        webhooks.register(&request.url, &request.events, request.fee_block_target, request.fee_threshold)
+    }).await?;
+    Ok(warp::reply::with_status(warp::reply::json(&webhook), StatusCode::CREATED))
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/webhooks",
+    params(("X-Admin-Key" = String, Header, description = "Admin key")),
+    responses(
+        (status = 200, description = "Registered webhooks, without their secrets", body = Vec<WebhookInfo>),
+        (status = 401, description = "Missing or invalid admin key", body = ErrorResponse)
+    )
+)]
+async fn handle_list_webhooks(
+    webhook_service: Arc<WebhookService>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let webhooks = run_blocking(webhook_service, |webhooks: &WebhookService| webhooks.list()).await?;
+    Ok(warp::reply::json(&webhooks))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/webhooks/{id}",
+    params(
+        ("id" = u64, Path, description = "Webhook id"),
+        ("X-Admin-Key" = String, Header, description = "Admin key")
+    ),
+    responses(
+        (status = 204, description = "Webhook deleted; its delivery log is kept"),
+        (status = 401, description = "Missing or invalid admin key", body = ErrorResponse),
+        (status = 404, description = "Unknown webhook", body = ErrorResponse)
+    )
+)]
+async fn handle_delete_webhook(
+    id: u64,
+    webhook_service: Arc<WebhookService>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    run_blocking(webhook_service, move |webhooks: &WebhookService| webhooks.delete(id)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/webhooks/{id}/deliveries",
+    params(
+        ("id" = u64, Path, description = "Webhook id"),
+        ("X-Admin-Key" = String, Header, description = "Admin key")
+    ),
+    responses(
+        (status = 200, description = "Most recent deliveries, newest first", body = Vec<DeliveryInfo>),
+        (status = 401, description = "Missing or invalid admin key", body = ErrorResponse)
+    )
+)]
+async fn handle_list_webhook_deliveries(
+    id: u64,
+    webhook_service: Arc<WebhookService>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let deliveries = run_blocking(webhook_service, move |webhooks: &WebhookService| webhooks.deliveries(id)).await?;
+    Ok(warp::reply::json(&deliveries))
+}
+
+// Route handler to send a logged delivery again, e.g. after fixing the receiver
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/webhook_deliveries/{id}/replay",
+    params(
+        ("id" = u64, Path, description = "Delivery id"),
+        ("X-Admin-Key" = String, Header, description = "Admin key")
+    ),
+    responses(
+        (status = 202, description = "New delivery of the same payload, sent in the background", body = DeliveryInfo),
+        (status = 400, description = "The delivery's webhook was deleted", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid admin key", body = ErrorResponse),
+        (status = 404, description = "Unknown delivery", body = ErrorResponse)
+    )
+)]
+async fn handle_replay_webhook_delivery(
+    id: u64,
+    webhook_service: Arc<WebhookService>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let replayed = spawn_blocking_in_span(move || webhook_service.replay(id)).await.map_err(warp::Rejection::from)?;
+    Ok(warp::reply::with_status(warp::reply::json(&replayed), StatusCode::ACCEPTED))
+}
+
+// Route handler for database connection pool usage since startup
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/db_pool",
+    params(("X-Admin-Key" = String, Header, description = "Admin key")),
+    responses(
+        (status = 200, description = "Connection pool counters", body = PoolStats),
+        (status = 401, description = "Missing or invalid admin key", body = ErrorResponse)
+    )
+)]
+async fn handle_get_db_pool(
+    storage: Arc<dyn Storage>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&storage.pool_stats()))
+}
+
+// Route handler for the configured alert rules and whether each is firing
+#[utoipa::path(
+    get,
+    path = "/api/v1/alerts",
+    responses(
+        (status = 200, description = "Alert rules with their current state", body = Vec<AlertInfo>)
+    )
+)]
+async fn handle_get_alerts(
+    alert_engine: Arc<AlertEngine>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&alert_engine.list()))
+}
+
+// Route handler for a metric's history. Without `resolution` the finest one still stored for the
+// whole range that keeps it within METRIC_MAX_POINTS points is used.
+#[utoipa::path(
+    get,
+    path = "/api/v1/metrics/{metric}",
+    params(
+        ("metric" = String, Path, description = "fee_rate_<target>_blocks for a configured block target, \
+            mempool_tx_count, mempool_vsize_bytes or mempool_usage_bytes"),
+        ("from" = Option<DateTime<Utc>>, Query, description = "Start of the range, RFC 3339; defaults to 24 hours before `to`"),
+        ("to" = Option<DateTime<Utc>>, Query, description = "End of the range, exclusive; defaults to now"),
+        ("resolution" = Option<String>, Query, description = "raw, hour or day")
+    ),
+    responses(
+        (status = 200, description = "Points oldest first; hourly and daily points cover closed buckets only", body = MetricHistoryResponse),
+        (status = 400, description = "Unknown resolution or an empty range", body = ErrorResponse),
+        (status = 404, description = "Unknown metric", body = ErrorResponse),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+async fn handle_get_metric_history(
+    metric: String,
+    query: MetricHistoryQuery,
+    storage: Arc<dyn Storage>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if !metric_names().contains(&metric) {
+        return Err(warp::reject::custom(RequestError {
+            status: StatusCode::NOT_FOUND,
+            message: format!("Unknown metric '{}'", metric),
+        }));
+    }
+    let now = Utc::now();
+    let to = query.to.unwrap_or(now);
+    let from = query.from.unwrap_or(to - chrono::TimeDelta::days(1));
+    if from >= to {
+        return Err(warp::reject::custom(RequestError {
+            status: StatusCode::BAD_REQUEST,
+            message: "`from` must be before `to`".to_string(),
+        }));
+    }
+    let resolution = match query.resolution.as_deref() {
+        None => choose_resolution(from, to, now),
+        Some(name) => Resolution::parse(name).ok_or_else(|| warp::reject::custom(RequestError {
+            status: StatusCode::BAD_REQUEST,
+            message: format!("Unknown resolution '{}'", name),
+        }))?,
+    };
+
+    let name = metric.clone();
+    let points = storage.run(move |db| metric_history(db, &name, resolution, from, to)).await
+        .map_err(database_error)?;
+    Ok(warp::reply::json(&MetricHistoryResponse { metric, resolution, points }))
+}
+
+// Check the address parses and belongs to the configured network; returns its canonical form
+fn validate_address(address: &str) -> Result<String, warp::Rejection> {
+    if !FULL_INDEX_ENABLED {
+        return Err(warp::reject::custom(RequestError {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            message: "Address lookups need the full index".to_string(),
+        }));
+    }
+    address.parse::<Address<NetworkUnchecked>>()
+        .ok()
+        .and_then(|address| address.require_network(BITCOIN_NETWORK).ok())
+        .map(|address| address.to_string())
+        .ok_or_else(|| warp::reject::custom(RequestError {
+            status: StatusCode::BAD_REQUEST,
+            message: format!("Not a valid {} address: {}", BITCOIN_NETWORK, address),
+        }))
+}
+
+fn database_error(e: Box<dyn std::error::Error + Send + Sync>) -> warp::Rejection {
+    error!(error = %e, "Failed to fetch data");
+    warp::reject::custom(CustomError {
+        message: format!("Failed to fetch data: {:?}", e),
+    })
+}
+
+fn serialization_error(e: serde_json::Error) -> warp::Rejection {
+    warp::reject::custom(CustomError {
+        message: format!("Failed to serialize response: {:?}", e),
+    })
+}
+
+// Route handler for an address's balance and activity range
+#[utoipa::path(
+    get,
+    path = "/api/v1/address/{address}",
+    params(("address" = String, Path, description = "Address on the configured network")),
+    responses(
+        (status = 200, description = "Confirmed balance and activity of the address", body = AddressResponse),
+        (status = 400, description = "Invalid address or wrong network", body = ErrorResponse),
+        (status = 503, description = "Full index disabled", body = ErrorResponse)
+    )
+)]
+async fn handle_get_address(
+    address: String,
+    storage: Arc<dyn Storage>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let address = validate_address(&address)?;
+    let summary = {
+        let address = address.clone();
+        storage.run(move |db| db.get_address_summary(&address)).await.map_err(database_error)?
+    };
+    Ok(warp::reply::json(&AddressResponse {
+        address,
+        balance_sats: summary.balance,
+        total_received_sats: summary.total_received,
+        tx_count: summary.tx_count,
+        first_seen_height: summary.first_seen_height,
+        last_seen_height: summary.last_seen_height,
+    }))
+}
+
+// Route handler for an address's transactions, newest first
+#[utoipa::path(
+    get,
+    path = "/api/v1/address/{address}/txs",
+    params(
+        ("address" = String, Path, description = "Address on the configured network"),
+        ("cursor" = Option<String>, Query, description = "`next_cursor` from the previous page"),
+        ("limit" = Option<usize>, Query, description = "Page size, default 25, at most 100")
+    ),
+    responses(
+        (status = 200, description = "Page of transactions touching the address", body = AddressTxsResponse),
+        (status = 400, description = "Invalid address, wrong network or malformed cursor", body = ErrorResponse),
+        (status = 503, description = "Full index disabled", body = ErrorResponse)
+    )
+)]
+async fn handle_get_address_txs(
+    address: String,
+    query: CursorQuery,
+    storage: Arc<dyn Storage>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let address = validate_address(&address)?;
+    let limit = query.limit.unwrap_or(EXPLORER_DEFAULT_PAGE_SIZE).clamp(1, EXPLORER_MAX_PAGE_SIZE);
+
+    // Cursors are `<height>:<txid>` of the last transaction already returned
+    let before = match query.cursor.as_deref() {
+        None => None,
+        Some(cursor) => {
+            let parsed = cursor.split_once(':')
+                .and_then(|(height, txid)| Some((height.parse::<u64>().ok()?, txid.to_string())));
+            match parsed {
+                Some(before) => Some(before),
+                None => return Err(warp::reject::custom(RequestError {
+                    status: StatusCode::BAD_REQUEST,
+                    message: format!("Malformed cursor: {}", cursor),
+                })),
+            }
+        }
+    };
+
+    let history = {
+        let address = address.clone();
+        storage.run(move |db| db.get_address_history(&address, before, limit)).await.map_err(database_error)?
+    };
+    let next_cursor = if history.len() == limit {
+        history.last().map(|tx| format!("{}:{}", tx.block_height, tx.txid))
+    } else {
+        None
+    };
+    let txs = history.into_iter()
+        .map(|tx| AddressTxData {
+            txid: tx.txid,
+            block_height: tx.block_height,
+            received_sats: tx.received,
+            sent_sats: tx.sent,
+        })
+        .collect();
+    Ok(warp::reply::json(&AddressTxsResponse { address, txs, next_cursor }))
+}
+
+// Route answering the legacy routes with a permanent redirect to /api/v1, keeping the query string
+pub fn legacy_route() -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    use warp::Reply;
+
+    warp::path("api")
+        .and(warp::path::tail())
+        .and(warp::get())
+        .and(warp::query::raw().or(warp::any().map(String::new)).unify())
+        .and_then(|tail: warp::path::Tail, query: String| async move {
+            if !LEGACY_ROUTES.contains(&tail.as_str()) {
+                return Err(warp::reject::not_found());
             }
+            let mut location = format!("/api/v1/{}", tail.as_str());
+            if !query.is_empty() {
+                location.push('?');
+                location.push_str(&query);
+            }
+            let location: warp::http::Uri = location.parse().map_err(|_| warp::reject::not_found())?;
+            Ok(warp::reply::with_header(warp::redirect::permanent(location), "deprecation", "true").into_response())
+        })
+}
+
+// Route streaming a stored series for a date range, below /api/v1. At most as many exports as
+// `export_slots` has permits run at once.
+pub fn export_route(
+    storage: Arc<dyn Storage>,
+    export_slots: Arc<Semaphore>,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    warp::path!("export")
+        .and(warp::get())
+        .and(warp::query::<ExportQuery>())
+        .and(with_storage(storage))
+        .and(warp::any().map(move || export_slots.clone()))
+        .and_then(handle_export)
+}
+
+// Route to fetch the latest block height, served from the ingestion-maintained tip, below /api/v1
+pub fn block_height_route(
+    tip_cache: Arc<TipCache>,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    warp::path!("block_info" / "block_height")
+        .and(warp::get())
+        .and(with_tip_cache(tip_cache))
+        .and_then(handle_get_block_height)
+}
 
-            // Return the block height as JSON response
-            let response = BlockHeightResponse { block_height };
-            Ok(warp::reply::json(&response))
+fn with_tip_cache(
+    tip_cache: Arc<TipCache>,
+) -> impl Filter<Extract = (Arc<TipCache>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || tip_cache.clone())
+}
+
+// Route handler to return the cached chain tip; never touches the node or the database
+#[utoipa::path(
+    get,
+    path = "/api/v1/block_info/block_height",
+    responses(
+        (status = 200, description = "Latest chain tip seen by ingestion", body = BlockHeightResponse),
+        (status = 503, description = "No tip ingested yet", body = ErrorResponse)
+    )
+)]
+async fn handle_get_block_height(
+    tip_cache: Arc<TipCache>
+) -> Result<warp::reply::Response, warp::Rejection> {
+    use warp::Reply;
+
+    match tip_cache.get() {
+        Some(cached) => {
+            let response = BlockHeightResponse {
+                block_height: cached.tip.block_height,
+                block_hash: cached.tip.block_hash,
+                block_time: cached.tip.block_time,
+                observed_at: cached.observed_at,
+                checked_at: cached.checked_at,
+                data_age_secs: (Utc::now() - cached.observed_at).num_seconds(),
+            };
+            Ok(warp::reply::json(&response).into_response())
         }
-        Err(e) => {
-            eprintln!("Failed to retrieve block height: {:?}", e);
-            let custom_error = CustomError {
-                message: format!("Bitcoin RPC error: {:?}", e),
+        None => {
+            // Ingestion has not seen a tip yet
+            let response = ErrorResponse {
+                error: "Chain tip not available yet".to_string(),
             };
-            Err(warp::reject::custom(custom_error))
+            Ok(warp::reply::with_status(warp::reply::json(&response), StatusCode::SERVICE_UNAVAILABLE).into_response())
         }
     }
 }
 
 // Route handler for getting the last 7 days' data
+#[utoipa::path(
+    get,
+    path = "/api/v1/7d_tx",
+    responses(
+        (status = 200, description = "Daily transaction counts, newest first", body = Vec<TxData>),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag"),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
 async fn handle_get_last_7_days(
-    mysql_service: Arc<MySqlService>, // We pass MySqlService via Arc
-) -> Result<impl warp::Reply, warp::Rejection> {
-    // Step 1: Fetch the last 7 days of transaction data from MySQL
-    let last_7_days_data = match mysql_service.get_all_days_tx().await {
+    request: CachedRequest,
+    storage: Arc<dyn Storage>, // We pass the storage backend via Arc
+) -> Result<warp::reply::Response, warp::Rejection> {
+    if let Some(response) = request.lookup() {
+        return Ok(response);
+    }
+
+    // Step 1: Fetch the last 7 days of transaction data from the database
+    let last_7_days_data = match storage.run(|db| db.get_all_days_tx()).await {
         Ok(data) => data,
         Err(err) => {
-            eprintln!("Failed to fetch data: {}", err);
+            error!(error = %err, "Failed to fetch data");
             let custom_error = CustomError {
                 message: format!("Failed to fetch data: {:?}", err),
             };
@@ -142,15 +1256,101 @@ async fn handle_get_last_7_days(
         .map(|(date, tx_count)| TxData { date, tx_count })
         .collect();
 
-    // Step 3: Return the data as a JSON response
-    Ok(warp::reply::json(&response_data))
+    // Step 3: Cache the data and return it as a JSON response
+    request.store(&response_data).map_err(serialization_error)
+}
+
+// Route handler streaming a stored series. Rows are encoded on a blocking thread and handed over in
+// chunks: a client that stops reading fills the channel and pauses the query, and one that
+// disconnects ends it.
+#[utoipa::path(
+    get,
+    path = "/api/v1/export",
+    params(
+        ("series" = String, Query, description = "daily_tx, dma, fee_history or block_stats"),
+        ("from" = Option<NaiveDate>, Query, description = "First day to include, UTC"),
+        ("to" = Option<NaiveDate>, Query, description = "Last day to include, UTC"),
+        ("format" = Option<String>, Query, description = "csv (default), ndjson or parquet")
+    ),
+    responses(
+        (status = 200, description = "The series oldest first, as text/csv, application/x-ndjson or \
+            application/vnd.apache.parquet", body = String, content_type = "text/csv"),
+        (status = 400, description = "Unknown series or format", body = ErrorResponse),
+        (status = 503, description = "Too many exports in progress", body = ErrorResponse)
+    )
+)]
+async fn handle_export(
+    query: ExportQuery,
+    storage: Arc<dyn Storage>,
+    export_slots: Arc<Semaphore>,
+) -> Result<warp::reply::Response, warp::Rejection> {
+    let series = ExportSeries::parse(&query.series).ok_or_else(|| warp::reject::custom(RequestError {
+        status: StatusCode::BAD_REQUEST,
+        message: format!("Unknown series '{}'", query.series),
+    }))?;
+    let format = match query.format.as_deref() {
+        None => ExportFormat::Csv,
+        Some(name) => ExportFormat::parse(name).ok_or_else(|| warp::reject::custom(RequestError {
+            status: StatusCode::BAD_REQUEST,
+            message: format!("Unknown format '{}'", name),
+        }))?,
+    };
+    let (from_date, to_date) = (query.from, query.to);
+    // Refuse rather than queue, since a stalled client holds its slot for as long as it stalls
+    let slot = export_slots.try_acquire_owned().map_err(|_| warp::reject::custom(RequestError {
+        status: StatusCode::SERVICE_UNAVAILABLE,
+        message: "Too many exports in progress, try again later".to_string(),
+    }))?;
+
+    let (sender, receiver) = mpsc::channel(EXPORT_HTTP_BUFFER_CHUNKS);
+    tokio::spawn(async move {
+        let writer = ChannelWriter::new(sender.clone());
+        let result = storage.run(move |db| {
+            // Held until the export stops writing, even once the client is gone
+            let _slot = slot;
+            let mut encoder = export::encoder(format, series, writer)?;
+            db.export_series(series, from_date, to_date, &mut |row| encoder.write_row(row))?;
+            encoder.finish()
+        }).await;
+        if let Err(e) = result {
+            warn!(error = %e, series = series.name(), "Export stopped");
+            // Fails the response body, so the client sees a broken transfer rather than a short file
+            let _ = sender.send(Err(std::io::Error::other(e.to_string()))).await;
+        }
+    }.instrument(Span::current()));
+
+    let chunks = futures_util::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    });
+    let mut response = warp::reply::Response::new(warp::hyper::Body::wrap_stream(chunks));
+    let headers = response.headers_mut();
+    headers.insert(header::CONTENT_TYPE, header::HeaderValue::from_static(format.content_type()));
+    let disposition = format!("attachment; filename=\"{}.{}\"", series.name(), format.extension());
+    if let Ok(value) = header::HeaderValue::from_str(&disposition) {
+        headers.insert(header::CONTENT_DISPOSITION, value);
+    }
+    Ok(response)
 }
 
 // Route handler to return fee estimation data as JSON
-pub async fn handle_get_fee_estimations(
-    mysql_service: Arc<MySqlService>
-) -> Result<impl warp::Reply, warp::Rejection> {
-    match mysql_service.get_fee_estimations().await {
+#[utoipa::path(
+    get,
+    path = "/api/v1/fee_estimations",
+    responses(
+        (status = 200, description = "Latest fee estimate per block target", body = Vec<FeeRateData>),
+        (status = 304, description = "Unchanged since the `If-None-Match` ETag"),
+        (status = 500, description = "Database error", body = ErrorResponse)
+    )
+)]
+async fn handle_get_fee_estimations(
+    request: CachedRequest,
+    storage: Arc<dyn Storage>
+) -> Result<warp::reply::Response, warp::Rejection> {
+    if let Some(response) = request.lookup() {
+        return Ok(response);
+    }
+
+    match storage.run(|db| db.get_fee_estimations()).await {
         Ok(fee_estimations) => {
             let response_data: Vec<FeeRateData> = fee_estimations
                 .into_iter()
@@ -161,14 +1361,14 @@ pub async fn handle_get_fee_estimations(
                 })
                 .collect();
 
-            Ok(warp::reply::json(&response_data))
+            request.store(&response_data).map_err(serialization_error)
         }
         Err(e) => {
-            eprintln!("Failed to fetch data: {}", e);
+            error!(error = %e, "Failed to fetch data");
             let custom_error = CustomError {
                 message: format!("Failed to fetch data: {:?}", e),
             };
-            return Err(warp::reject::custom(custom_error)); // Handle database errors gracefully
+            Err(warp::reject::custom(custom_error)) // Handle database errors gracefully
         } 
     }
 }
\ No newline at end of file
@@ -0,0 +1,332 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tracing::{error, info};
+use utoipa::ToSchema;
+use crate::config::settings::{ALERT_RULES, INGESTION_INTERVAL_SECS};
+use crate::services::events::{EventBus, IngestionEvent};
+use crate::services::storage::{spawn_blocking_in_span, AlertStateRecord, Storage};
+
+// What a rule watches
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlertMetric {
+    // Fee estimate for the block target, in sat/vB
+    FeeRate { block_target: u16 },
+    MempoolTxCount,
+    // Total virtual size of mempool transactions
+    MempoolVsizeBytes,
+    // Memory used by the node's mempool
+    MempoolUsageBytes,
+}
+
+impl fmt::Display for AlertMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertMetric::FeeRate { block_target } => write!(f, "fee_rate_{}_blocks", block_target),
+            AlertMetric::MempoolTxCount => write!(f, "mempool_tx_count"),
+            AlertMetric::MempoolVsizeBytes => write!(f, "mempool_vsize_bytes"),
+            AlertMetric::MempoolUsageBytes => write!(f, "mempool_usage_bytes"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+// A rule fires once the metric has been past `threshold` for `for_secs`, and resolves once it
+// is back past `clear_threshold`. Keeping `clear_threshold` short of `threshold` stops a value
+// hovering around the threshold from flapping between firing and resolved.
+#[derive(Debug, Clone, Copy)]
+pub struct AlertRule {
+    pub name: &'static str,
+    pub metric: AlertMetric,
+    pub comparison: Comparison,
+    pub threshold: f64,
+    pub clear_threshold: f64,
+    pub for_secs: i64,
+}
+
+impl AlertRule {
+    fn breached(&self, value: f64) -> bool {
+        match self.comparison {
+            Comparison::Above => value > self.threshold,
+            Comparison::Below => value < self.threshold,
+        }
+    }
+
+    fn cleared(&self, value: f64) -> bool {
+        match self.comparison {
+            Comparison::Above => value < self.clear_threshold,
+            Comparison::Below => value > self.clear_threshold,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertStatus {
+    Inactive,
+    // Breached, but not yet for the rule's full duration
+    Pending,
+    Firing,
+    // Was firing and has cleared; behaves like inactive
+    Resolved,
+}
+
+impl AlertStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlertStatus::Inactive => "inactive",
+            AlertStatus::Pending => "pending",
+            AlertStatus::Firing => "firing",
+            AlertStatus::Resolved => "resolved",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "inactive" => Some(AlertStatus::Inactive),
+            "pending" => Some(AlertStatus::Pending),
+            "firing" => Some(AlertStatus::Firing),
+            "resolved" => Some(AlertStatus::Resolved),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleState {
+    pub status: AlertStatus,
+    pub value: Option<f64>,
+    pub pending_since: Option<DateTime<Utc>>,
+    pub fired_at: Option<DateTime<Utc>>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+impl Default for RuleState {
+    fn default() -> Self {
+        Self {
+            status: AlertStatus::Inactive,
+            value: None,
+            pending_since: None,
+            fired_at: None,
+            resolved_at: None,
+            updated_at: None,
+        }
+    }
+}
+
+// Advance a rule's state with a new observation of its metric
+pub fn evaluate(rule: &AlertRule, state: &RuleState, value: f64, now: DateTime<Utc>) -> RuleState {
+    let mut next = RuleState { value: Some(value), updated_at: Some(now), ..state.clone() };
+    let breached = rule.breached(value);
+    match state.status {
+        AlertStatus::Inactive | AlertStatus::Resolved if breached => {
+            next.status = AlertStatus::Pending;
+            next.pending_since = Some(now);
+        }
+        AlertStatus::Pending if !breached => {
+            next.status = if state.fired_at.is_some() { AlertStatus::Resolved } else { AlertStatus::Inactive };
+            next.pending_since = None;
+        }
+        AlertStatus::Firing if rule.cleared(value) => {
+            next.status = AlertStatus::Resolved;
+            next.resolved_at = Some(now);
+        }
+        _ => {}
+    }
+    // A rule without a duration goes from breached to firing in one observation
+    if next.status == AlertStatus::Pending {
+        let since = next.pending_since.unwrap_or(now);
+        if (now - since).num_seconds() >= rule.for_secs {
+            next.status = AlertStatus::Firing;
+            next.fired_at = Some(now);
+            next.pending_since = None;
+        }
+    }
+    next
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AlertInfo {
+    pub name: String,
+    pub metric: String,
+    pub comparison: Comparison,
+    pub threshold: f64,
+    pub clear_threshold: f64,
+    pub for_secs: i64,
+    pub status: AlertStatus,
+    // Last observed value of the metric
+    pub value: Option<f64>,
+    pub pending_since: Option<DateTime<Utc>>,
+    pub fired_at: Option<DateTime<Utc>>,
+    pub resolved_at: Option<DateTime<Utc>>,
+    pub updated_at: Option<DateTime<Utc>>,
+}
+
+fn timestamp_to_utc(timestamp: Option<i64>) -> Option<DateTime<Utc>> {
+    timestamp.and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+}
+
+impl From<AlertStateRecord> for RuleState {
+    fn from(record: AlertStateRecord) -> Self {
+        RuleState {
+            status: AlertStatus::parse(&record.status).unwrap_or(AlertStatus::Inactive),
+            value: record.value,
+            pending_since: timestamp_to_utc(record.pending_since),
+            fired_at: timestamp_to_utc(record.fired_at),
+            resolved_at: timestamp_to_utc(record.resolved_at),
+            updated_at: timestamp_to_utc(record.updated_at),
+        }
+    }
+}
+
+// Evaluates ALERT_RULES against fee estimates and mempool snapshots from the event bus.
+// States are written through to the database, so a firing alert stays firing across restarts.
+pub struct AlertEngine {
+    storage: Arc<dyn Storage>,
+    event_bus: Arc<EventBus>,
+    rules: &'static [AlertRule],
+    states: Mutex<HashMap<&'static str, RuleState>>,
+}
+
+impl AlertEngine {
+    pub fn new(
+        storage: Arc<dyn Storage>,
+        event_bus: Arc<EventBus>,
+    ) -> Result<Arc<Self>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut stored: HashMap<String, AlertStateRecord> = storage.list_alert_states()?
+            .into_iter()
+            .map(|record| (record.rule_name.clone(), record))
+            .collect();
+        // States of rules no longer in the config are left alone in the table
+        let states = ALERT_RULES.iter()
+            .map(|rule| (rule.name, stored.remove(rule.name).map(RuleState::from).unwrap_or_default()))
+            .collect();
+        Ok(Arc::new(Self { storage, event_bus, rules: ALERT_RULES, states: Mutex::new(states) }))
+    }
+
+    // Take the states stored by the process evaluating the rules
+    pub fn reload(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut stored: HashMap<String, AlertStateRecord> = self.storage.list_alert_states()?
+            .into_iter()
+            .map(|record| (record.rule_name.clone(), record))
+            .collect();
+        let mut states = self.states.lock().unwrap();
+        for rule in self.rules {
+            if let Some(record) = stored.remove(rule.name) {
+                states.insert(rule.name, RuleState::from(record));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn observe(&self, event: &IngestionEvent) {
+        let now = Utc::now();
+        for rule in self.rules {
+            let value = match (rule.metric, event) {
+                (AlertMetric::FeeRate { block_target }, IngestionEvent::FeeEstimates { estimates }) => estimates.iter()
+                    .find(|estimate| estimate.block_target == block_target)
+                    .map(|estimate| estimate.fee_rate),
+                (AlertMetric::MempoolTxCount, IngestionEvent::Mempool { tx_count, .. }) => Some(*tx_count as f64),
+                (AlertMetric::MempoolVsizeBytes, IngestionEvent::Mempool { vsize_bytes, .. }) => Some(*vsize_bytes as f64),
+                (AlertMetric::MempoolUsageBytes, IngestionEvent::Mempool { usage_bytes, .. }) => Some(*usage_bytes as f64),
+                _ => None,
+            };
+            if let Some(value) = value {
+                self.update(rule, value, now);
+            }
+        }
+    }
+
+    fn update(&self, rule: &AlertRule, value: f64, now: DateTime<Utc>) {
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(rule.name).or_default();
+        let next = evaluate(rule, state, value, now);
+        let changed = next.status != state.status;
+        *state = next.clone();
+        drop(states);
+
+        let record = AlertStateRecord {
+            rule_name: rule.name.to_string(),
+            status: next.status.as_str().to_string(),
+            value: next.value,
+            pending_since: next.pending_since.map(|at| at.timestamp()),
+            fired_at: next.fired_at.map(|at| at.timestamp()),
+            resolved_at: next.resolved_at.map(|at| at.timestamp()),
+            updated_at: next.updated_at.map(|at| at.timestamp()),
+        };
+        if let Err(e) = self.storage.save_alert_state(&record, changed) {
+            error!(alert = rule.name, error = %e, "Failed to store alert state");
+        }
+        if changed && matches!(next.status, AlertStatus::Firing | AlertStatus::Resolved) {
+            info!(alert = rule.name, status = next.status.as_str(), value, "Alert changed status");
+            self.event_bus.publish(IngestionEvent::Alert {
+                rule: rule.name.to_string(),
+                status: next.status,
+                value,
+            });
+        }
+    }
+
+    pub fn list(&self) -> Vec<AlertInfo> {
+        let states = self.states.lock().unwrap();
+        self.rules.iter()
+            .map(|rule| {
+                let state = states.get(rule.name).cloned().unwrap_or_default();
+                AlertInfo {
+                    name: rule.name.to_string(),
+                    metric: rule.metric.to_string(),
+                    comparison: rule.comparison,
+                    threshold: rule.threshold,
+                    clear_threshold: rule.clear_threshold,
+                    for_secs: rule.for_secs,
+                    status: state.status,
+                    value: state.value,
+                    pending_since: state.pending_since,
+                    fired_at: state.fired_at,
+                    resolved_at: state.resolved_at,
+                    updated_at: state.updated_at,
+                }
+            })
+            .collect()
+    }
+}
+
+// Feed fee estimates and mempool snapshots into the alert rules
+pub async fn run_alert_engine(alert_engine: Arc<AlertEngine>, event_bus: Arc<EventBus>) {
+    let mut events = event_bus.subscribe();
+    loop {
+        match events.recv().await {
+            // Transitions are stored in the database, so observe on a blocking thread
+            Ok(sequenced) => {
+                let engine = alert_engine.clone();
+                spawn_blocking_in_span(move || engine.observe(&sequenced.event)).await;
+            }
+            // Missed samples only delay a transition until the next one
+            Err(broadcast::error::RecvError::Lagged(_)) => {}
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+// For an API-only process: rules are evaluated by the ingesting process, so follow its stored states
+pub async fn run_alert_state_refresh(alert_engine: Arc<AlertEngine>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(INGESTION_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        let engine = alert_engine.clone();
+        if let Err(e) = spawn_blocking_in_span(move || engine.reload()).await {
+            error!(error = %e, "Error reloading alert states");
+        }
+    }
+}
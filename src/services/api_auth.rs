@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use crate::config::settings::{
+    API_KEY_CACHE_TTL_SECS, DEFAULT_RATE_LIMIT_PER_MINUTE, KEY_LOOKUPS_PER_MINUTE, KEY_LOOKUP_BURST,
+    KEY_LOOKUP_MAX_CLIENTS, PUBLIC_ROUTES, RATE_LIMIT_BURST,
+};
+use crate::services::storage::{ApiKeyRecord, Storage};
+
+#[derive(Debug)]
+pub enum AuthError {
+    MissingKey,
+    InvalidKey,
+    RateLimited { retry_after_secs: u64 },
+    Storage(String),
+}
+
+// Generate a new random API key to hand out once to its owner
+pub fn generate_api_key() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub fn hash_api_key(api_key: &str) -> String {
+    format!("{:x}", Sha256::digest(api_key.as_bytes()))
+}
+
+pub struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(capacity: f64) -> Self {
+        Self { tokens: capacity, last_refill: Instant::now() }
+    }
+
+    // Take one token, or return how long until one is available
+    pub fn take(&mut self, capacity: f64, per_second: f64) -> Result<(), Duration> {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * per_second).min(capacity);
+        self.last_refill = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / per_second))
+        }
+    }
+}
+
+// API key checks and per-key token-bucket rate limiting.
+// Known keys are cached, so a revoked key stops working within API_KEY_CACHE_TTL_SECS. Unknown
+// keys are not, so the cache only ever holds keys that exist; instead each client address gets a
+// bucket of database lookups, which is what an unknown key costs.
+pub struct ApiAuth {
+    storage: Arc<dyn Storage>,
+    keys: Mutex<HashMap<String, (ApiKeyRecord, Instant)>>,
+    buckets: Mutex<HashMap<u64, TokenBucket>>,
+    lookups: Mutex<HashMap<IpAddr, TokenBucket>>,
+}
+
+impl ApiAuth {
+    pub fn new(storage: Arc<dyn Storage>) -> Arc<Self> {
+        Arc::new(Self {
+            storage,
+            keys: Mutex::new(HashMap::new()),
+            buckets: Mutex::new(HashMap::new()),
+            lookups: Mutex::new(HashMap::new()),
+        })
+    }
+
+    // A public route matches itself and the paths below it, whole segments only
+    pub fn is_public(path: &str) -> bool {
+        PUBLIC_ROUTES.iter().any(|route| {
+            path.strip_prefix(route).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        })
+    }
+
+    // Decide from the key cache alone where possible. `None` means the key has to be looked up with
+    // `authorize_stored`, a lookup already counted against `client`, the address the request came from.
+    pub fn authorize_cached(&self, path: &str, api_key: Option<&str>, client: Option<IpAddr>) -> Option<Result<(), AuthError>> {
+        if Self::is_public(path) {
+            return Some(Ok(()));
+        }
+        let Some(api_key) = api_key else {
+            return Some(Err(AuthError::MissingKey));
+        };
+        match self.cached(&hash_api_key(api_key)) {
+            Some(record) => Some(self.check_record(Some(record))),
+            None => client.and_then(|client| self.check_lookup_limit(client).err()).map(Err),
+        }
+    }
+
+    // Look the key up in the database and cache what is found
+    pub fn authorize_stored(&self, api_key: &str) -> Result<(), AuthError> {
+        let key_hash = hash_api_key(api_key);
+        let record = self.storage.find_api_key(&key_hash)
+            .map_err(|e| AuthError::Storage(e.to_string()))?;
+        let mut keys = self.keys.lock().unwrap();
+        match &record {
+            Some(record) => keys.insert(key_hash, (record.clone(), Instant::now())),
+            None => keys.remove(&key_hash),
+        };
+        drop(keys);
+        self.check_record(record)
+    }
+
+    fn cached(&self, key_hash: &str) -> Option<ApiKeyRecord> {
+        let ttl = Duration::from_secs(API_KEY_CACHE_TTL_SECS);
+        self.keys.lock().unwrap().get(key_hash)
+            .filter(|(_, fetched_at)| fetched_at.elapsed() < ttl)
+            .map(|(record, _)| record.clone())
+    }
+
+    fn check_record(&self, record: Option<ApiKeyRecord>) -> Result<(), AuthError> {
+        match record {
+            Some(record) if !record.revoked => self.check_rate_limit(&record),
+            _ => Err(AuthError::InvalidKey),
+        }
+    }
+
+    fn check_lookup_limit(&self, client: IpAddr) -> Result<(), AuthError> {
+        let per_second = KEY_LOOKUPS_PER_MINUTE.max(1) as f64 / 60.0;
+        let capacity = KEY_LOOKUP_BURST.max(1) as f64;
+
+        let mut lookups = self.lookups.lock().unwrap();
+        if lookups.len() >= KEY_LOOKUP_MAX_CLIENTS && !lookups.contains_key(&client) {
+            // A bucket idle this long has refilled, so dropping it changes nothing
+            let refill = Duration::from_secs_f64(capacity / per_second);
+            lookups.retain(|_, bucket| bucket.last_refill.elapsed() < refill);
+        }
+        let bucket = lookups.entry(client).or_insert_with(|| TokenBucket::new(capacity));
+        bucket.take(capacity, per_second).map_err(|wait| AuthError::RateLimited {
+            retry_after_secs: wait.as_secs_f64().ceil() as u64,
+        })
+    }
+
+    fn check_rate_limit(&self, record: &ApiKeyRecord) -> Result<(), AuthError> {
+        let per_minute = record.rate_limit_per_minute.unwrap_or(DEFAULT_RATE_LIMIT_PER_MINUTE).max(1);
+        let per_second = per_minute as f64 / 60.0;
+        let capacity = RATE_LIMIT_BURST.max(1) as f64;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(record.id).or_insert_with(|| TokenBucket::new(capacity));
+        bucket.take(capacity, per_second).map_err(|wait| AuthError::RateLimited {
+            retry_after_secs: wait.as_secs_f64().ceil() as u64,
+        })
+    }
+}
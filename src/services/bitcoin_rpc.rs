@@ -1,103 +1,284 @@
 use bitcoincore_rpc::{Auth, Client, RpcApi};
+use bitcoincore_rpc::bitcoin::{Address, Block, BlockHash, Transaction, Txid};
+use bitcoincore_rpc::bitcoin::address::NetworkUnchecked;
+use bitcoincore_rpc::bitcoin::consensus::deserialize;
+use std::fmt;
 use std::sync::Arc;
-use bitcoincore_rpc_json::EstimateMode; // Correct import for EstimateMode
-use std::error::Error;
-use chrono::{NaiveDateTime, NaiveDate};
+use chrono::{DateTime, Utc};
+use tracing::instrument;
+use bitcoincore_rpc_json::{EstimateMode, GetMempoolInfoResult}; // Correct import for EstimateMode
+use bitcoincore_rpc_json::{BlockStatsFields, GetBlockHeaderResult, GetBlockResult, GetRawTransactionResult};
+use bitcoincore_rpc_json::{GetBlockchainInfoResult, GetDescriptorInfoResult, ScanTxOutRequest, ScanTxOutResult};
+use crate::config::settings::{REST_MAX_HEADERS, REST_TIMEOUT_SECS};
 
 
 pub struct BitcoinRpcService {
     rpc_client: Client,
+    // Set when blocks, headers and block hashes are fetched over the node's REST interface
+    rest_client: Option<RestClient>,
 }
 
+// An error status from the REST interface, with the node's plain text message
 #[derive(Debug)]
-pub struct DailyTxData {
-    pub date: NaiveDate,
-    pub tx_count: usize,
+pub struct RestError {
+    pub status: i32,
+    pub message: String,
+}
+
+impl fmt::Display for RestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Paths the node has no handler for get an empty 404, as all of them do without `-rest`
+        if self.status == 404 && self.message.is_empty() {
+            write!(f, "REST interface not available, is the node running with -rest?")
+        } else {
+            write!(f, "REST request failed with status {}: {}", self.status, self.message)
+        }
+    }
+}
+
+impl std::error::Error for RestError {}
+
+// Bitcoin Core's REST interface: no authentication, and blocks come back as raw bytes rather than
+// the hex JSON-RPC wraps them in
+struct RestClient {
+    base_url: String,
+}
+
+impl RestClient {
+    fn get(&self, path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+        let response = minreq::get(format!("{}/rest/{}", self.base_url, path))
+            .with_timeout(REST_TIMEOUT_SECS)
+            .send()?;
+        if response.status_code != 200 {
+            return Err(Box::new(RestError {
+                status: response.status_code,
+                message: response.as_str().unwrap_or_default().trim().to_string(),
+            }));
+        }
+        Ok(response.into_bytes())
+    }
+
+    #[instrument(name = "rest", skip_all, fields(endpoint = "blockhashbyheight", block_height), err(level = "debug"))]
+    fn block_hash(&self, block_height: u64) -> Result<BlockHash, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(deserialize(&self.get(&format!("blockhashbyheight/{}.bin", block_height))?)?)
+    }
+
+    #[instrument(name = "rest", skip_all, fields(endpoint = "block", block_hash = %block_hash), err(level = "debug"))]
+    fn block(&self, block_hash: &BlockHash) -> Result<Block, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(deserialize(&self.get(&format!("block/{}.bin", block_hash))?)?)
+    }
+
+    // An unknown hash gets an empty list from the node rather than an error
+    #[instrument(name = "rest", skip_all, fields(endpoint = "headers", block_hash = %block_hash, count), err(level = "debug"))]
+    fn headers(&self, block_hash: &BlockHash, count: usize) -> Result<Vec<GetBlockHeaderResult>, Box<dyn std::error::Error + Send + Sync>> {
+        let headers: Vec<GetBlockHeaderResult> =
+            serde_json::from_slice(&self.get(&format!("headers/{}.json?count={}", block_hash, count))?)?;
+        if headers.is_empty() {
+            return Err(Box::new(RestError { status: 404, message: format!("{} not found", block_hash) }));
+        }
+        Ok(headers)
+    }
+}
+
+// RPC_INVALID_ADDRESS_OR_KEY and RPC_INVALID_PARAMETER, returned for unknown blocks and transactions
+const RPC_NOT_FOUND_CODES: [i32; 2] = [-5, -8];
+
+// Whether an error returned by this service means the requested block or transaction does not exist
+pub fn is_not_found(error: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    let rpc_not_found = matches!(
+        error.downcast_ref::<bitcoincore_rpc::Error>(),
+        Some(bitcoincore_rpc::Error::JsonRpc(bitcoincore_rpc::jsonrpc::error::Error::Rpc(rpc_error)))
+            if RPC_NOT_FOUND_CODES.contains(&rpc_error.code)
+    );
+    rpc_not_found || matches!(
+        error.downcast_ref::<RestError>(),
+        Some(rest_error) if rest_error.status == 404 && !rest_error.message.is_empty()
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct ChainTip {
+    pub block_height: u64,
+    pub block_hash: String,
+    pub block_time: DateTime<Utc>,
 }
 
 impl BitcoinRpcService {
-    pub fn new(rpc_url: &str, rpc_user: &str, rpc_password: &str) -> Arc<Self> {
+    // With `rest_url`, blocks, headers and block hashes are fetched over REST and the rest over RPC
+    pub fn new(rpc_url: &str, rpc_user: &str, rpc_password: &str, rest_url: Option<&str>) -> Arc<Self> {
         let rpc_client = Client::new(
             rpc_url,
             Auth::UserPass(rpc_user.to_string(), rpc_password.to_string())
         ).expect("Failed to create Bitcoin RPC client");
-        Arc::new(Self { rpc_client })
+        let rest_client = rest_url.map(|url| RestClient { base_url: url.trim_end_matches('/').to_string() });
+        Arc::new(Self { rpc_client, rest_client })
     }
 
-    pub fn get_block_height(&self) -> Result<u64, Box<dyn std::error::Error>> {
+    pub fn uses_rest(&self) -> bool {
+        self.rest_client.is_some()
+    }
+
+    #[instrument(name = "rpc", skip_all, fields(method = "getblockchaininfo"), err(level = "debug"))]
+    pub fn get_blockchain_info(&self) -> Result<GetBlockchainInfoResult, Box<dyn std::error::Error + Send + Sync>> {
+        let info = self.rpc_client.get_blockchain_info()?;
+        Ok(info)
+    }
+
+    #[instrument(name = "rpc", skip_all, fields(method = "getblockcount"), err(level = "debug"))]
+    pub fn get_block_height(&self) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
         let block_height = self.rpc_client.get_block_count()?;
         Ok(block_height)
     }
 
-    pub async fn get_last_7_days_tx_data(&self) -> Result<Vec<DailyTxData>, Box<dyn Error>> {
-        let mut daily_tx_counts = Vec::new();
-        let current_block = match self.rpc_client.get_block_count() {
-            Ok(block_count) => block_count,
-            Err(e) => {
-                eprintln!("Error retrieving block count: {:?}", e);
-                return Err(Box::new(e));
-            }
-        }; // Current block height
-        let blocks_per_day = 144;  // Estimate of blocks per day on Bitcoin network
-
-        // Iterate over the last 7 days
-        for day in 0..1 {
-            // Calculate the block range for the day
-            let start_block = current_block - (day + 1) * blocks_per_day;
-            let end_block = current_block - day * blocks_per_day;
-
-            let mut daily_tx_count = 0;
-            let mut block_date = None;
-
-            // Iterate over the blocks in the day
-            for block_height in start_block..=end_block {
-                let block_hash = match self.rpc_client.get_block_hash(block_height) {
-                    Ok(hash) => hash,
-                    Err(e) => {
-                        eprintln!("Error retrieving block hash at height {}: {:?}", block_height, e);
-                        continue; // Skip this block and continue with others
-                    }
-                };
-                let block = match self.rpc_client.get_block(&block_hash) {
-                    Ok(block) => block,
-                    Err(e) => {
-                        eprintln!("Error retrieving block data for hash {}: {:?}", block_hash, e);
-                        continue; // Skip this block and continue with others
-                    }
-                };
-
-                // Only retrieve the block date once for the first block
-                let block_header = match self.rpc_client.get_block_header(&block_hash) {
-                    Ok(header) => header,
-                    Err(e) => {
-                        eprintln!("Error retrieving block header for hash {}: {:?}", block_hash, e);
-                        continue; // Skip this block and continue with others
-                    }
-                };
-
-                if block_date.is_none() {
-                    let block_time = block_header.time;  // Block time in Unix timestamp
-                    let date = NaiveDateTime::from_timestamp(block_time as i64, 0).date();
-                    block_date = Some(date);
-                }
-
-                // Increment the transaction count for the current block
-                daily_tx_count += block.txdata.len();
-            }
-
-            // Push the result if the date was successfully set
-            if let Some(date) = block_date {
-                daily_tx_counts.push(DailyTxData {
-                    date,
-                    tx_count: daily_tx_count,
-                });
-            }
+    #[instrument(name = "rpc", skip_all, fields(method = "getbestblockhash"), err(level = "debug"))]
+    pub fn get_best_block_hash(&self) -> Result<BlockHash, Box<dyn std::error::Error + Send + Sync>> {
+        let block_hash = self.rpc_client.get_best_block_hash()?;
+        Ok(block_hash)
+    }
+
+    // The tip at `block_hash`, with its height and time from the one header so they agree
+    #[instrument(name = "rpc", skip_all, fields(method = "getblockheader"), err(level = "debug"))]
+    pub fn get_chain_tip(&self, block_hash: &BlockHash) -> Result<ChainTip, Box<dyn std::error::Error + Send + Sync>> {
+        let header = self.rpc_client.get_block_header_info(block_hash)?;
+        Ok(ChainTip {
+            block_height: header.height as u64,
+            block_hash: block_hash.to_string(),
+            block_time: DateTime::from_timestamp(header.time as i64, 0).ok_or("Block time out of range")?,
+        })
+    }
+
+    // Over REST when it is configured, as are blocks and headers
+    pub fn get_block_hash(&self, block_height: u64) -> Result<BlockHash, Box<dyn std::error::Error + Send + Sync>> {
+        match &self.rest_client {
+            Some(rest_client) => rest_client.block_hash(block_height),
+            None => self.rpc_block_hash(block_height),
         }
+    }
+
+    #[instrument(name = "rpc", skip_all, fields(method = "getblockhash", block_height), err(level = "debug"))]
+    fn rpc_block_hash(&self, block_height: u64) -> Result<BlockHash, Box<dyn std::error::Error + Send + Sync>> {
+        let block_hash = self.rpc_client.get_block_hash(block_height)?;
+        Ok(block_hash)
+    }
+
+    pub fn get_block(&self, block_hash: &BlockHash) -> Result<Block, Box<dyn std::error::Error + Send + Sync>> {
+        match &self.rest_client {
+            Some(rest_client) => rest_client.block(block_hash),
+            None => self.rpc_block(block_hash),
+        }
+    }
+
+    #[instrument(name = "rpc", skip_all, fields(method = "getblock", block_hash = %block_hash), err(level = "debug"))]
+    fn rpc_block(&self, block_hash: &BlockHash) -> Result<Block, Box<dyn std::error::Error + Send + Sync>> {
+        let block = self.rpc_client.get_block(block_hash)?;
+        Ok(block)
+    }
+
+    // Block fields without transaction data, including size and weight
+    #[instrument(name = "rpc", skip_all, fields(method = "getblock", block_hash = %block_hash), err(level = "debug"))]
+    pub fn get_block_info(&self, block_hash: &BlockHash) -> Result<GetBlockResult, Box<dyn std::error::Error + Send + Sync>> {
+        let block_info = self.rpc_client.get_block_info(block_hash)?;
+        Ok(block_info)
+    }
+
+    pub fn get_block_header_info(&self, block_hash: &BlockHash) -> Result<GetBlockHeaderResult, Box<dyn std::error::Error + Send + Sync>> {
+        match &self.rest_client {
+            Some(rest_client) => rest_client.headers(block_hash, 1)?.pop().ok_or_else(|| "Block header not returned".into()),
+            None => self.rpc_block_header_info(block_hash),
+        }
+    }
+
+    #[instrument(name = "rpc", skip_all, fields(method = "getblockheader", block_hash = %block_hash), err(level = "debug"))]
+    fn rpc_block_header_info(&self, block_hash: &BlockHash) -> Result<GetBlockHeaderResult, Box<dyn std::error::Error + Send + Sync>> {
+        let header = self.rpc_client.get_block_header_info(block_hash)?;
+        Ok(header)
+    }
+
+    // Up to `count` headers of the active chain, starting with `block_hash`; fewer when the tip is
+    // reached first. REST returns up to REST_MAX_HEADERS per request, RPC one, and each request
+    // continues from the last header's successor.
+    pub fn get_block_headers(
+        &self,
+        block_hash: &BlockHash,
+        count: usize,
+    ) -> Result<Vec<GetBlockHeaderResult>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut headers: Vec<GetBlockHeaderResult> = Vec::with_capacity(count);
+        let mut next = Some(*block_hash);
+        while let Some(block_hash) = next.filter(|_| headers.len() < count) {
+            let page = match &self.rest_client {
+                Some(rest_client) => rest_client.headers(&block_hash, (count - headers.len()).min(REST_MAX_HEADERS))?,
+                None => vec![self.rpc_block_header_info(&block_hash)?],
+            };
+            next = page.last().and_then(|last| last.next_block_hash);
+            headers.extend(page);
+        }
+        Ok(headers)
+    }
+
+    // Total fees paid in the block, in satoshis
+    #[instrument(name = "rpc", skip_all, fields(method = "getblockstats", block_height), err(level = "debug"))]
+    pub fn get_block_total_fee(&self, block_height: u64) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let stats = self.rpc_client.get_block_stats_fields(block_height, &[BlockStatsFields::TotalFee])?;
+        stats.total_fee.map(|fee| fee.to_sat()).ok_or_else(|| "Total fee not available".into())
+    }
+
+    // Needs `-txindex` on the node for confirmed transactions outside the wallet
+    #[instrument(name = "rpc", skip_all, fields(method = "getrawtransaction", txid = %txid), err(level = "debug"))]
+    pub fn get_raw_transaction_info(
+        &self,
+        txid: &Txid,
+        block_hash: Option<&BlockHash>,
+    ) -> Result<GetRawTransactionResult, Box<dyn std::error::Error + Send + Sync>> {
+        let transaction = self.rpc_client.get_raw_transaction_info(txid, block_hash)?;
+        Ok(transaction)
+    }
+
+    #[instrument(name = "rpc", skip_all, fields(method = "getmempoolinfo"), err(level = "debug"))]
+    pub fn get_mempool_info(&self) -> Result<GetMempoolInfoResult, Box<dyn std::error::Error + Send + Sync>> {
+        let mempool_info = self.rpc_client.get_mempool_info()?;
+        Ok(mempool_info)
+    }
+
+    #[instrument(name = "rpc", skip_all, fields(method = "getrawmempool"), err(level = "debug"))]
+    pub fn get_raw_mempool(&self) -> Result<Vec<Txid>, Box<dyn std::error::Error + Send + Sync>> {
+        let txids = self.rpc_client.get_raw_mempool()?;
+        Ok(txids)
+    }
+
+    #[instrument(name = "rpc", skip_all, fields(method = "getrawtransaction", txid = %txid), err(level = "debug"))]
+    pub fn get_raw_transaction(&self, txid: &Txid) -> Result<Transaction, Box<dyn std::error::Error + Send + Sync>> {
+        let transaction = self.rpc_client.get_raw_transaction(txid, None)?;
+        Ok(transaction)
+    }
+
+    // Normalized form of an output descriptor, with its checksum
+    #[instrument(name = "rpc", skip_all, fields(method = "getdescriptorinfo"), err(level = "debug"))]
+    pub fn get_descriptor_info(&self, descriptor: &str) -> Result<GetDescriptorInfoResult, Box<dyn std::error::Error + Send + Sync>> {
+        let info = self.rpc_client.get_descriptor_info(descriptor)?;
+        Ok(info)
+    }
+
+    // Addresses of a ranged descriptor for derivation indexes `start..=end`
+    #[instrument(name = "rpc", skip_all, fields(method = "deriveaddresses", start, end), err(level = "debug"))]
+    pub fn derive_addresses(
+        &self,
+        descriptor: &str,
+        start: u32,
+        end: u32,
+    ) -> Result<Vec<Address<NetworkUnchecked>>, Box<dyn std::error::Error + Send + Sync>> {
+        let addresses = self.rpc_client.derive_addresses(descriptor, Some([start, end]))?;
+        Ok(addresses)
+    }
 
-        Ok(daily_tx_counts)
+    // Scans the whole UTXO set, so this can take minutes on mainnet
+    #[instrument(name = "rpc", skip_all, fields(method = "scantxoutset"), err(level = "debug"))]
+    pub fn scan_tx_out_set(&self, requests: &[ScanTxOutRequest]) -> Result<ScanTxOutResult, Box<dyn std::error::Error + Send + Sync>> {
+        let result = self.rpc_client.scan_tx_out_set_blocking(requests)?;
+        Ok(result)
     }
 
-    pub async fn get_fee_estimation(&self, block_target: u16) -> Result<f64, Box<dyn std::error::Error>> {
+    #[instrument(name = "rpc", skip_all, fields(method = "estimatesmartfee", block_target), err(level = "debug"))]
+    pub fn get_fee_estimation(&self, block_target: u16) -> Result<f64, Box<dyn std::error::Error + Send + Sync>> {
         let fee_estimate = self.rpc_client.estimate_smart_fee(block_target, Some(EstimateMode::Conservative))?;
         if let Some(fee_rate) = fee_estimate.fee_rate {
             Ok(fee_rate.to_sat() as f64 / 1000.0)  // Convert to satoshis per byte if necessary
@@ -0,0 +1,471 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use bitcoincore_rpc::bitcoin::block::Header;
+use bitcoincore_rpc::bitcoin::consensus::deserialize;
+use bitcoincore_rpc::bitcoin::constants::genesis_block;
+use bitcoincore_rpc::bitcoin::hashes::{sha256d, Hash, HashEngine};
+use bitcoincore_rpc::bitcoin::pow::Work;
+use bitcoincore_rpc::bitcoin::secp256k1::PublicKey;
+use bitcoincore_rpc::bitcoin::{Amount, Block, BlockHash, Network, PubkeyHash, ScriptBuf, ScriptHash, TxOut};
+use tracing::{debug, info};
+use crate::services::block_index::SpentOutput;
+use crate::services::storage::BlockRecord;
+
+// Blocks read straight from Bitcoin Core's blocks directory, for imports that would take days over
+// RPC. Each blk*.dat file is a run of records: the network magic, the block's size as u32 LE and
+// the serialized block. Since Core 28 every file is XORed with the 8-byte key in xor.dat.
+// Blocks are stored in the order they arrived, so the best chain is found by following
+// prev_blockhash up from the genesis block to the tip with the most work.
+//
+// The rev*.dat file with the same number holds each block's undo data, the outputs its inputs
+// spent, framed the same way and followed by a checksum: the double SHA-256 of the parent's hash
+// and the undo data. Records are written as blocks are connected, so the checksum is what ties
+// one to its block. Spent outputs are compressed as in Core's coins database.
+
+const XOR_KEY_FILE: &str = "xor.dat";
+// Blocks the median time is taken over, this one included
+const MEDIAN_TIME_SPAN: usize = 11;
+const UNDO_CHECKSUM_SIZE: usize = 32;
+// Core stores longer scripts, which can't be spent, as a bare OP_RETURN
+const MAX_SCRIPT_SIZE: u64 = 10_000;
+
+// Where a block's serialized bytes are
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    file: u32,
+    offset: u64,
+    size: u32,
+}
+
+// Where a block's undo data is, and the checksum that names its block
+#[derive(Debug, Clone, Copy)]
+struct UndoRecord {
+    offset: u64,
+    size: u32,
+    checksum: [u8; UNDO_CHECKSUM_SIZE],
+}
+
+// The undo file being read, with the record after the last one matched to a block
+struct UndoFile {
+    file: u32,
+    reader: XorReader,
+    records: Vec<UndoRecord>,
+    next: usize,
+}
+
+// A block file, de-obfuscated as it is read
+struct XorReader {
+    inner: BufReader<File>,
+    key: [u8; 8],
+    position: u64,
+}
+
+impl XorReader {
+    fn open(path: &Path, key: [u8; 8]) -> std::io::Result<Self> {
+        Ok(XorReader { inner: BufReader::new(File::open(path)?), key, position: 0 })
+    }
+
+    fn seek_to(&mut self, position: u64) -> std::io::Result<()> {
+        self.inner.seek(SeekFrom::Start(position))?;
+        self.position = position;
+        Ok(())
+    }
+}
+
+impl Read for XorReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        for (index, byte) in buf[..read].iter_mut().enumerate() {
+            *byte ^= self.key[((self.position + index as u64) % 8) as usize];
+        }
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+fn block_file_path(dir: &Path, file: u32) -> PathBuf {
+    dir.join(format!("blk{:05}.dat", file))
+}
+
+fn undo_file_path(dir: &Path, file: u32) -> PathBuf {
+    dir.join(format!("rev{:05}.dat", file))
+}
+
+// Numbers of the blk*.dat files in `dir`, lowest first
+fn block_file_numbers(dir: &Path) -> Result<Vec<u32>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut numbers = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let number = name.to_str()
+            .and_then(|name| name.strip_prefix("blk"))
+            .and_then(|name| name.strip_suffix(".dat"))
+            .filter(|digits| digits.len() == 5)
+            .and_then(|digits| digits.parse::<u32>().ok());
+        numbers.extend(number);
+    }
+    numbers.sort_unstable();
+    Ok(numbers)
+}
+
+// All zeros, which leaves the data as is, when the directory predates obfuscation
+fn read_xor_key(dir: &Path) -> Result<[u8; 8], Box<dyn std::error::Error + Send + Sync>> {
+    match std::fs::read(dir.join(XOR_KEY_FILE)) {
+        Ok(key) => Ok(key.as_slice().try_into().map_err(|_| format!("{} is not 8 bytes long", XOR_KEY_FILE))?),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok([0; 8]),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// The header and location of every block record in one file. Core preallocates files, so reading
+// stops at the first record without the network magic, and at a record still being written.
+fn scan_block_file(
+    path: &Path,
+    file: u32,
+    key: [u8; 8],
+    magic: [u8; 4],
+    headers: &mut Vec<(Header, Location)>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let length = std::fs::metadata(path)?.len();
+    let mut reader = XorReader::open(path, key)?;
+    loop {
+        let mut frame = [0; 8];
+        match reader.read_exact(&mut frame) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            result => result?,
+        }
+        let size = u32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]);
+        let offset = reader.position;
+        if frame[..4] != magic || size < 80 || offset + u64::from(size) > length {
+            break;
+        }
+        let mut header = [0; 80];
+        reader.read_exact(&mut header)?;
+        headers.push((deserialize(&header)?, Location { file, offset, size }));
+        reader.seek_to(offset + u64::from(size))?;
+    }
+    Ok(())
+}
+
+// The location and checksum of every undo record in one file, which is preallocated like the
+// block files
+fn scan_undo_file(path: &Path, key: [u8; 8], magic: [u8; 4]) -> Result<Vec<UndoRecord>, Box<dyn std::error::Error + Send + Sync>> {
+    let length = std::fs::metadata(path)?.len();
+    let mut reader = XorReader::open(path, key)?;
+    let mut records = Vec::new();
+    loop {
+        let mut frame = [0; 8];
+        match reader.read_exact(&mut frame) {
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            result => result?,
+        }
+        let size = u32::from_le_bytes([frame[4], frame[5], frame[6], frame[7]]);
+        let offset = reader.position;
+        if frame[..4] != magic || offset + u64::from(size) + UNDO_CHECKSUM_SIZE as u64 > length {
+            break;
+        }
+        reader.seek_to(offset + u64::from(size))?;
+        let mut checksum = [0; UNDO_CHECKSUM_SIZE];
+        reader.read_exact(&mut checksum)?;
+        records.push(UndoRecord { offset, size, checksum });
+    }
+    Ok(records)
+}
+
+// Reads the fields of Core's undo serialization from one record
+struct UndoReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> UndoReader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], Box<dyn std::error::Error + Send + Sync>> {
+        if count > self.bytes.len() {
+            return Err("Undo data ends early".into());
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn byte(&mut self) -> Result<u8, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.take(1)?[0])
+    }
+
+    // The length prefix of vectors, as in the P2P protocol
+    fn compact_size(&mut self) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(match self.byte()? {
+            0xfd => u64::from(u16::from_le_bytes(self.take(2)?.try_into()?)),
+            0xfe => u64::from(u32::from_le_bytes(self.take(4)?.try_into()?)),
+            0xff => u64::from_le_bytes(self.take(8)?.try_into()?),
+            byte => u64::from(byte),
+        })
+    }
+
+    // Core's VARINT: base 128, most significant group first, each continued group one less
+    fn varint(&mut self) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut value: u64 = 0;
+        loop {
+            let byte = self.byte()?;
+            value = value.checked_mul(128).ok_or("Undo VARINT overflows")? | u64::from(byte & 0x7f);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+            value = value.checked_add(1).ok_or("Undo VARINT overflows")?;
+        }
+    }
+
+    // The special forms stand for P2PKH, P2SH and P2PK scripts, the rest are stored as is
+    fn script(&mut self) -> Result<ScriptBuf, Box<dyn std::error::Error + Send + Sync>> {
+        let kind = self.varint()?;
+        Ok(match kind {
+            0 => ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(self.take(20)?.try_into()?)),
+            1 => ScriptBuf::new_p2sh(&ScriptHash::from_byte_array(self.take(20)?.try_into()?)),
+            2..=5 => {
+                let mut compressed = [0; 33];
+                compressed[0] = if kind < 4 { kind as u8 } else { kind as u8 - 2 };
+                compressed[1..].copy_from_slice(self.take(32)?);
+                let key = if kind < 4 { compressed.to_vec() } else { PublicKey::from_slice(&compressed)?.serialize_uncompressed().to_vec() };
+                let mut script = vec![key.len() as u8];
+                script.extend(key);
+                script.push(0xac);
+                ScriptBuf::from_bytes(script)
+            }
+            _ if kind - 6 > MAX_SCRIPT_SIZE => {
+                self.take(usize::try_from(kind - 6)?)?;
+                ScriptBuf::from_bytes(vec![0x6a])
+            }
+            _ => ScriptBuf::from_bytes(self.take(usize::try_from(kind - 6)?)?.to_vec()),
+        })
+    }
+}
+
+// Undo of an amount compressed by dropping trailing zeros, stored as their count
+fn decompress_amount(compressed: u64) -> u64 {
+    if compressed == 0 {
+        return 0;
+    }
+    let mut x = compressed - 1;
+    let exponent = x % 10;
+    x /= 10;
+    let mut amount = if exponent < 9 {
+        let digit = x % 9 + 1;
+        x /= 9;
+        x * 10 + digit
+    } else {
+        x + 1
+    };
+    for _ in 0..exponent {
+        amount *= 10;
+    }
+    amount
+}
+
+// A block's undo data: for each transaction after the coinbase, the outputs its inputs spent
+fn parse_block_undo(bytes: &[u8]) -> Result<Vec<Vec<SpentOutput>>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut reader = UndoReader { bytes };
+    let mut transactions = Vec::new();
+    for _ in 0..reader.compact_size()? {
+        let mut spent = Vec::new();
+        for _ in 0..reader.compact_size()? {
+            // The height, doubled, plus one for coinbase outputs
+            let height = reader.varint()? / 2;
+            if height > 0 {
+                // Unused, kept from an older format
+                reader.varint()?;
+            }
+            let value = Amount::from_sat(decompress_amount(reader.varint()?));
+            spent.push(SpentOutput { height, output: TxOut { value, script_pubkey: reader.script()? } });
+        }
+        transactions.push(spent);
+    }
+    if !reader.bytes.is_empty() {
+        return Err("Undo data is longer than its transactions".into());
+    }
+    Ok(transactions)
+}
+
+// Cumulative work of a block and every ancestor, or that its ancestry never reaches genesis
+#[derive(Debug, Clone, Copy)]
+enum Ancestry {
+    Unvisited,
+    Orphaned,
+    ChainWork(Work),
+}
+
+// The chain from genesis to the tip with the most work; on equal work the tip stored first wins,
+// as it does in Core
+fn best_chain(headers: Vec<(Header, Location)>, genesis: BlockHash) -> Vec<(Header, Location)> {
+    let mut hashes = Vec::with_capacity(headers.len());
+    let mut by_hash: HashMap<BlockHash, usize> = HashMap::with_capacity(headers.len());
+    for (index, (header, _)) in headers.iter().enumerate() {
+        let hash = header.block_hash();
+        by_hash.entry(hash).or_insert(index);
+        hashes.push(hash);
+    }
+
+    let mut ancestry = vec![Ancestry::Unvisited; headers.len()];
+    let mut best: Option<(Work, usize)> = None;
+    for start in 0..headers.len() {
+        // Walk back to a block whose ancestry is known, then fill it in on the way forward
+        let mut path = Vec::new();
+        let mut index = start;
+        let mut chain_work = loop {
+            match ancestry[index] {
+                Ancestry::ChainWork(work) => break Some(work),
+                Ancestry::Orphaned => break None,
+                Ancestry::Unvisited => {}
+            }
+            if hashes[index] == genesis {
+                let work = headers[index].0.work();
+                ancestry[index] = Ancestry::ChainWork(work);
+                break Some(work);
+            }
+            path.push(index);
+            match by_hash.get(&headers[index].0.prev_blockhash) {
+                Some(&prev) => index = prev,
+                None => break None,
+            }
+        };
+        for &index in path.iter().rev() {
+            chain_work = chain_work.map(|work| work + headers[index].0.work());
+            ancestry[index] = chain_work.map_or(Ancestry::Orphaned, Ancestry::ChainWork);
+        }
+        if let Ancestry::ChainWork(work) = ancestry[start] {
+            if best.is_none_or(|(best_work, _)| work > best_work) {
+                best = Some((work, start));
+            }
+        }
+    }
+
+    let mut chain = Vec::new();
+    let mut next = best.map(|(_, index)| index);
+    while let Some(index) = next {
+        chain.push(headers[index]);
+        next = (hashes[index] != genesis).then(|| by_hash[&headers[index].0.prev_blockhash]);
+    }
+    chain.reverse();
+    chain
+}
+
+pub struct BlockFiles {
+    dir: PathBuf,
+    xor_key: [u8; 8],
+    magic: [u8; 4],
+    // Best chain, indexed by height
+    chain: Vec<(Header, Location)>,
+    open_file: Option<(u32, XorReader)>,
+    open_undo_file: Option<UndoFile>,
+}
+
+impl BlockFiles {
+    // Scan the headers of every block file in `dir` and find the best chain. Only the headers are
+    // kept in memory, about 100 bytes per block.
+    pub fn open(dir: &Path, network: Network) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let xor_key = read_xor_key(dir)?;
+        let magic = network.magic().to_bytes();
+        let files = block_file_numbers(dir)?;
+        let mut headers = Vec::new();
+        for &file in &files {
+            scan_block_file(&block_file_path(dir, file), file, xor_key, magic, &mut headers)?;
+            debug!(file, blocks = headers.len(), "Scanned block file");
+        }
+        let stored = headers.len();
+        let chain = best_chain(headers, genesis_block(network).block_hash());
+        if chain.is_empty() {
+            return Err(format!("No {} genesis block in the block files of {}", network, dir.display()).into());
+        }
+        info!(files = files.len(), stored, best_chain = chain.len(), "Read block file headers");
+        Ok(BlockFiles { dir: dir.to_path_buf(), xor_key, magic, chain, open_file: None, open_undo_file: None })
+    }
+
+    // Height of the best chain's tip
+    pub fn tip_height(&self) -> u64 {
+        self.chain.len() as u64 - 1
+    }
+
+    pub fn block_hash(&self, height: u64) -> Option<BlockHash> {
+        self.chain.get(height as usize).map(|(header, _)| header.block_hash())
+    }
+
+    // The best chain's block at `height`, with its row for the blocks table. Reads are fastest in
+    // height order, which mostly follows the order of the files.
+    pub fn read_block(&mut self, height: u64) -> Result<(BlockRecord, Block), Box<dyn std::error::Error + Send + Sync>> {
+        let (_, location) = *self.chain.get(height as usize)
+            .ok_or_else(|| format!("Height {} is above the block files' tip at {}", height, self.tip_height()))?;
+        let reader = match &mut self.open_file {
+            Some((file, reader)) if *file == location.file => reader,
+            open_file => {
+                let reader = XorReader::open(&block_file_path(&self.dir, location.file), self.xor_key)?;
+                &mut open_file.insert((location.file, reader)).1
+            }
+        };
+        reader.seek_to(location.offset)?;
+        let mut bytes = vec![0; location.size as usize];
+        reader.read_exact(&mut bytes)?;
+        let block: Block = deserialize(&bytes)?;
+        Ok((self.block_record(height, &block), block))
+    }
+
+    // The outputs spent by the best chain's block at `height`, one list per transaction after the
+    // coinbase, from the undo file numbered like its block file. Records are tried from the one
+    // after the last match, which in height order is almost always the right one.
+    pub fn read_spent_outputs(&mut self, height: u64) -> Result<Vec<Vec<SpentOutput>>, Box<dyn std::error::Error + Send + Sync>> {
+        let (header, location) = *self.chain.get(height as usize)
+            .ok_or_else(|| format!("Height {} is above the block files' tip at {}", height, self.tip_height()))?;
+        // The genesis block spends nothing and has no undo data
+        if height == 0 {
+            return Ok(Vec::new());
+        }
+        let undo_file = match &mut self.open_undo_file {
+            Some(undo_file) if undo_file.file == location.file => undo_file,
+            open_undo_file => {
+                let path = undo_file_path(&self.dir, location.file);
+                let records = scan_undo_file(&path, self.xor_key, self.magic)?;
+                let reader = XorReader::open(&path, self.xor_key)?;
+                open_undo_file.insert(UndoFile { file: location.file, reader, records, next: 0 })
+            }
+        };
+
+        let count = undo_file.records.len();
+        for index in (undo_file.next..count).chain(0..undo_file.next) {
+            let record = undo_file.records[index];
+            undo_file.reader.seek_to(record.offset)?;
+            let mut bytes = vec![0; record.size as usize];
+            undo_file.reader.read_exact(&mut bytes)?;
+            let mut engine = sha256d::Hash::engine();
+            engine.input(header.prev_blockhash.as_byte_array());
+            engine.input(&bytes);
+            if sha256d::Hash::from_engine(engine).to_byte_array() == record.checksum {
+                undo_file.next = index + 1;
+                return parse_block_undo(&bytes);
+            }
+        }
+        Err(format!("No undo data for block {} in {}", header.block_hash(), undo_file_path(&self.dir, location.file).display()).into())
+    }
+
+    // The fields RPC's getblock reports, computed from the block and the headers before it
+    fn block_record(&self, height: u64, block: &Block) -> BlockRecord {
+        let header = &block.header;
+        let last = height as usize;
+        let mut times: Vec<u32> = self.chain[(last + 1).saturating_sub(MEDIAN_TIME_SPAN)..=last]
+            .iter()
+            .map(|(header, _)| header.time)
+            .collect();
+        times.sort_unstable();
+        BlockRecord {
+            height,
+            hash: block.block_hash().to_string(),
+            prev_hash: (height > 0).then(|| header.prev_blockhash.to_string()),
+            time: i64::from(header.time),
+            median_time: Some(i64::from(times[times.len() / 2])),
+            version: header.version.to_consensus(),
+            bits: format!("{:08x}", header.bits.to_consensus()),
+            difficulty: header.difficulty_float(),
+            nonce: header.nonce,
+            size: block.total_size() as u32,
+            weight: block.weight().to_wu() as u32,
+            tx_count: block.txdata.len() as u32,
+        }
+    }
+}
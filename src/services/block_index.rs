@@ -0,0 +1,160 @@
+use bitcoincore_rpc::bitcoin::{Address, Block, Network, Script, TxOut};
+
+// Rows written to the full index tables for one block
+
+#[derive(Debug, Clone)]
+pub struct TxRow {
+    pub txid: String,
+    pub block_height: u64,
+    pub block_index: u32,
+    pub version: i32,
+    pub locktime: u32,
+    pub size: u32,
+    pub vsize: u32,
+    pub weight: u32,
+    pub input_count: u32,
+    pub output_count: u32,
+    pub is_coinbase: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct InputRow {
+    pub txid: String,
+    pub input_index: u32,
+    pub block_height: u64,
+    // None for coinbase inputs
+    pub prev_txid: Option<String>,
+    pub prev_vout: Option<u32>,
+    pub sequence: u32,
+    pub witness_size: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct OutputRow {
+    pub txid: String,
+    pub output_index: u32,
+    pub block_height: u64,
+    pub value: u64,
+    pub script_type: &'static str,
+    pub address: Option<String>,
+}
+
+// An output spent by a block, as Core's undo data keeps it: the height of the block that
+// created it, and the output
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpentOutput {
+    pub height: u64,
+    pub output: TxOut,
+}
+
+#[derive(Debug, Default)]
+pub struct IndexedBlock {
+    pub transactions: Vec<TxRow>,
+    pub inputs: Vec<InputRow>,
+    pub outputs: Vec<OutputRow>,
+}
+
+// Same names Bitcoin Core uses for `scriptPubKey.type`
+pub fn script_type(script: &Script) -> &'static str {
+    if script.is_p2pkh() {
+        "pubkeyhash"
+    } else if script.is_p2sh() {
+        "scripthash"
+    } else if script.is_p2wpkh() {
+        "witness_v0_keyhash"
+    } else if script.is_p2wsh() {
+        "witness_v0_scripthash"
+    } else if script.is_p2tr() {
+        "witness_v1_taproot"
+    } else if script.is_witness_program() {
+        "witness_unknown"
+    } else if script.is_op_return() {
+        "nulldata"
+    } else if script.is_p2pk() {
+        "pubkey"
+    } else if script.is_multisig() {
+        "multisig"
+    } else {
+        "nonstandard"
+    }
+}
+
+// Flatten a block into transaction, input and output rows
+pub fn index_block(block: &Block, block_height: u64, network: Network) -> IndexedBlock {
+    let mut indexed = IndexedBlock::default();
+
+    for (block_index, tx) in block.txdata.iter().enumerate() {
+        let txid = tx.compute_txid().to_string();
+        let is_coinbase = tx.is_coinbase();
+
+        indexed.transactions.push(TxRow {
+            txid: txid.clone(),
+            block_height,
+            block_index: block_index as u32,
+            version: tx.version.0,
+            locktime: tx.lock_time.to_consensus_u32(),
+            size: tx.total_size() as u32,
+            vsize: tx.vsize() as u32,
+            weight: tx.weight().to_wu() as u32,
+            input_count: tx.input.len() as u32,
+            output_count: tx.output.len() as u32,
+            is_coinbase,
+        });
+
+        for (input_index, input) in tx.input.iter().enumerate() {
+            let (prev_txid, prev_vout) = if is_coinbase {
+                (None, None)
+            } else {
+                (Some(input.previous_output.txid.to_string()), Some(input.previous_output.vout))
+            };
+            indexed.inputs.push(InputRow {
+                txid: txid.clone(),
+                input_index: input_index as u32,
+                block_height,
+                prev_txid,
+                prev_vout,
+                sequence: input.sequence.0,
+                witness_size: input.witness.size() as u32,
+            });
+        }
+
+        for (output_index, output) in tx.output.iter().enumerate() {
+            indexed.outputs.push(output_row(txid.clone(), output_index as u32, block_height, output, network));
+        }
+    }
+
+    indexed
+}
+
+// Output rows for what a block's inputs spent, from its undo data: one list per transaction after
+// the coinbase, with one output per input. Spends of outputs from blocks that were never indexed
+// are linked through these.
+pub fn index_spent_outputs(
+    block: &Block,
+    spent: &[Vec<SpentOutput>],
+    network: Network,
+) -> Result<Vec<OutputRow>, Box<dyn std::error::Error + Send + Sync>> {
+    let spending = block.txdata.iter().skip(1);
+    if spent.len() != spending.len() || spending.clone().zip(spent).any(|(tx, outputs)| tx.input.len() != outputs.len()) {
+        return Err(format!("Undo data of block {} does not match its inputs", block.block_hash()).into());
+    }
+    Ok(spending
+        .flat_map(|tx| &tx.input)
+        .zip(spent.iter().flatten())
+        .map(|(input, spent)| {
+            let prevout = input.previous_output;
+            output_row(prevout.txid.to_string(), prevout.vout, spent.height, &spent.output, network)
+        })
+        .collect())
+}
+
+fn output_row(txid: String, output_index: u32, block_height: u64, output: &TxOut, network: Network) -> OutputRow {
+    OutputRow {
+        txid,
+        output_index,
+        block_height,
+        value: output.value.to_sat(),
+        script_type: script_type(&output.script_pubkey),
+        address: Address::from_script(&output.script_pubkey, network).ok().map(|address| address.to_string()),
+    }
+}
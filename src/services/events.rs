@@ -0,0 +1,217 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+use tokio::sync::broadcast;
+use crate::services::alerts::AlertStatus;
+
+// Topics a feed client can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topic {
+    Blocks,
+    Fees,
+    Mempool,
+    DailyTx,
+    // Watch-only wallet activity, only delivered to admin clients
+    Wallets,
+    Alerts,
+}
+
+impl FromStr for Topic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "blocks" => Ok(Topic::Blocks),
+            "fees" => Ok(Topic::Fees),
+            "mempool" => Ok(Topic::Mempool),
+            "daily_tx" => Ok(Topic::DailyTx),
+            "wallets" => Ok(Topic::Wallets),
+            "alerts" => Ok(Topic::Alerts),
+            other => Err(format!("Unknown topic: {}", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct FeeEstimate {
+    pub block_target: u16,
+    pub fee_rate: f64,
+}
+
+// Events produced by the ingestion side and pushed to API clients
+#[derive(Debug, Clone, Serialize, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IngestionEvent {
+    NewBlock {
+        block_height: u64,
+        block_hash: String,
+        block_time: DateTime<Utc>,
+    },
+    // Stored blocks above `fork_height` were replaced by a competing chain
+    Reorg {
+        fork_height: u64,
+        orphaned_blocks: u64,
+    },
+    FeeEstimates {
+        estimates: Vec<FeeEstimate>,
+    },
+    Mempool {
+        tx_count: usize,
+        vsize_bytes: usize,
+        usage_bytes: usize,
+    },
+    DailyTx {
+        date: NaiveDate,
+        tx_count: usize,
+        dma_value: f64,
+    },
+    // A transaction paying to or spending from a watched wallet; `block_height` is None in the mempool
+    WalletActivity {
+        wallet: String,
+        txid: String,
+        received_sats: u64,
+        sent_sats: u64,
+        block_height: Option<u64>,
+    },
+    // An alert rule started firing or resolved
+    Alert {
+        rule: String,
+        status: AlertStatus,
+        value: f64,
+    },
+}
+
+impl IngestionEvent {
+    // Name used as the SSE `event:` field, matches the serialized `type`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            IngestionEvent::NewBlock { .. } => "new_block",
+            IngestionEvent::Reorg { .. } => "reorg",
+            IngestionEvent::FeeEstimates { .. } => "fee_estimates",
+            IngestionEvent::Mempool { .. } => "mempool",
+            IngestionEvent::DailyTx { .. } => "daily_tx",
+            IngestionEvent::WalletActivity { .. } => "wallet_activity",
+            IngestionEvent::Alert { .. } => "alert",
+        }
+    }
+
+    pub fn topic(&self) -> Topic {
+        match self {
+            IngestionEvent::NewBlock { .. } | IngestionEvent::Reorg { .. } => Topic::Blocks,
+            IngestionEvent::FeeEstimates { .. } => Topic::Fees,
+            IngestionEvent::Mempool { .. } => Topic::Mempool,
+            IngestionEvent::DailyTx { .. } => Topic::DailyTx,
+            IngestionEvent::WalletActivity { .. } => Topic::Wallets,
+            IngestionEvent::Alert { .. } => Topic::Alerts,
+        }
+    }
+}
+
+// Position of an event in the feed, sent as its SSE id, e.g. `1713571767123456-42`. The epoch is
+// when the bus was created, so ids from before a restart are told apart from current ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventId {
+    pub epoch: u64,
+    pub sequence: u64,
+}
+
+impl fmt::Display for EventId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.epoch, self.sequence)
+    }
+}
+
+impl FromStr for EventId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (epoch, sequence) = s.trim().split_once('-').ok_or_else(|| format!("Not an event id: {}", s))?;
+        Ok(EventId {
+            epoch: epoch.parse().map_err(|_| format!("Not an event id: {}", s))?,
+            sequence: sequence.parse().map_err(|_| format!("Not an event id: {}", s))?,
+        })
+    }
+}
+
+// An event together with its position in the feed, used for `Last-Event-ID` resume
+#[derive(Debug, Clone)]
+pub struct SequencedEvent {
+    pub id: EventId,
+    pub event: IngestionEvent,
+}
+
+// What a client resuming after `Last-Event-ID` gets ahead of the live feed
+#[derive(Debug)]
+pub enum Resume {
+    // Every event after the client's last one, possibly none
+    Replay(Vec<SequencedEvent>),
+    // Events after the client's last one are gone, to a restart or out of the buffer, so it has to
+    // reload its state; `last_id` is the last event published
+    Reset { last_id: EventId },
+}
+
+struct ReplayBuffer {
+    next_sequence: u64,
+    events: VecDeque<SequencedEvent>,
+}
+
+// In-process fan-out of ingestion events to any number of subscribers,
+// keeping the most recent ones around so reconnecting clients can catch up
+pub struct EventBus {
+    sender: broadcast::Sender<SequencedEvent>,
+    epoch: u64,
+    replay: Mutex<ReplayBuffer>,
+    replay_capacity: usize,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize, replay_capacity: usize) -> Arc<Self> {
+        let (sender, _) = broadcast::channel(capacity);
+        let replay = Mutex::new(ReplayBuffer {
+            next_sequence: 1,
+            events: VecDeque::with_capacity(replay_capacity),
+        });
+        let epoch = Utc::now().timestamp_micros() as u64;
+        Arc::new(Self { sender, epoch, replay, replay_capacity })
+    }
+
+    // Publish an event; having no subscribers is not an error
+    pub fn publish(&self, event: IngestionEvent) {
+        // Hold the lock while sending so ids reach subscribers in order
+        let mut replay = self.replay.lock().unwrap();
+        let sequenced = SequencedEvent { id: EventId { epoch: self.epoch, sequence: replay.next_sequence }, event };
+        replay.next_sequence += 1;
+        if self.replay_capacity > 0 {
+            if replay.events.len() == self.replay_capacity {
+                replay.events.pop_front();
+            }
+            replay.events.push_back(sequenced.clone());
+        }
+        let _ = self.sender.send(sequenced);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<SequencedEvent> {
+        self.sender.subscribe()
+    }
+
+    // Subscribe and return the buffered events after `last_event_id`, atomically so nothing
+    // published in between is missed or duplicated. An id this bus did not hand out, or one whose
+    // successors are no longer all buffered, gets a reset instead.
+    pub fn subscribe_from(&self, last_event_id: &str) -> (Resume, broadcast::Receiver<SequencedEvent>) {
+        let replay = self.replay.lock().unwrap();
+        let receiver = self.sender.subscribe();
+        let last_id = EventId { epoch: self.epoch, sequence: replay.next_sequence - 1 };
+        let oldest = replay.events.front().map_or(replay.next_sequence, |sequenced| sequenced.id.sequence);
+        let resume = match last_event_id.parse::<EventId>() {
+            Ok(id) if id.epoch == self.epoch && id.sequence >= oldest.saturating_sub(1) && id.sequence <= last_id.sequence => {
+                Resume::Replay(replay.events.iter().filter(|sequenced| sequenced.id.sequence > id.sequence).cloned().collect())
+            }
+            _ => Resume::Reset { last_id },
+        };
+        (resume, receiver)
+    }
+}
@@ -0,0 +1,388 @@
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use bitcoincore_rpc::bitcoin::{Block, BlockHash, Txid};
+use bitcoincore_rpc::json::GetRawTransactionResultVout;
+use serde::Serialize;
+use tokio::sync::Semaphore;
+use utoipa::ToSchema;
+use crate::config::settings::{
+    EXPLORER_BLOCK_CACHE_SIZE, EXPLORER_CACHE_MIN_CONFIRMATIONS, EXPLORER_MAX_PREVOUT_LOOKUPS,
+    EXPLORER_PREVOUT_LOOKUP_SLOTS, EXPLORER_TX_CACHE_SIZE,
+};
+use crate::services::bitcoin_rpc::{is_not_found, BitcoinRpcService};
+use crate::services::tip_cache::TipCache;
+
+#[derive(Debug)]
+pub enum ExplorerError {
+    InvalidId(String),
+    NotFound,
+    Rpc(String),
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for ExplorerError {
+    fn from(error: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        if is_not_found(error.as_ref()) {
+            ExplorerError::NotFound
+        } else {
+            ExplorerError::Rpc(error.to_string())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BlockInfo {
+    pub height: u64,
+    pub hash: String,
+    pub previous_block_hash: Option<String>,
+    pub time: u32,
+    pub median_time: Option<u64>,
+    pub version: i32,
+    pub bits: String,
+    pub nonce: u32,
+    pub difficulty: f64,
+    pub merkle_root: String,
+    pub tx_count: usize,
+    pub size: usize,
+    pub weight: u64,
+    pub total_fee_sats: Option<u64>,
+    pub miner_tag: Option<String>,
+    pub confirmations: u64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct BlockTxSummary {
+    pub txid: String,
+    pub size: usize,
+    pub vsize: usize,
+    pub weight: u64,
+    pub input_count: usize,
+    pub output_count: usize,
+    pub output_value_sats: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BlockTxsPage {
+    pub block_hash: String,
+    pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+    pub txs: Vec<BlockTxSummary>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TxInput {
+    pub coinbase: bool,
+    pub prev_txid: Option<String>,
+    pub prev_vout: Option<u32>,
+    pub sequence: u32,
+    pub witness_items: usize,
+    pub value_sats: Option<u64>,
+    pub address: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TxOutput {
+    pub n: u32,
+    pub value_sats: u64,
+    pub script_type: Option<String>,
+    pub address: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TxStatus {
+    pub confirmed: bool,
+    pub block_hash: Option<String>,
+    pub block_height: Option<u64>,
+    pub block_time: Option<u64>,
+    pub confirmations: u64,
+}
+
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct TransactionInfo {
+    pub txid: String,
+    pub wtxid: String,
+    pub version: u32,
+    pub locktime: u32,
+    pub size: usize,
+    pub vsize: usize,
+    pub fee_sats: Option<u64>,
+    pub inputs: Vec<TxInput>,
+    pub outputs: Vec<TxOutput>,
+    pub status: TxStatus,
+}
+
+// Everything we keep about a block once it is deep enough to cache
+struct BlockDetails {
+    info: BlockInfo,
+    txs: Vec<BlockTxSummary>,
+}
+
+// Fixed-size cache evicting the oldest insertion first
+struct BoundedCache<K, V> {
+    capacity: usize,
+    entries: HashMap<K, V>,
+    order: VecDeque<K>,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> BoundedCache<K, V> {
+    fn new(capacity: usize) -> Self {
+        Self { capacity, entries: HashMap::new(), order: VecDeque::new() }
+    }
+
+    fn get(&self, key: &K) -> Option<V> {
+        self.entries.get(key).cloned()
+    }
+
+    fn insert(&mut self, key: K, value: V) {
+        if self.entries.insert(key.clone(), value).is_none() {
+            self.order.push_back(key);
+        }
+        while self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+    }
+}
+
+// Printable ASCII runs from the coinbase scriptSig, where pools put their tag
+fn extract_miner_tag(script_sig: &[u8]) -> Option<String> {
+    let tag = script_sig
+        .split(|byte| !(0x20..0x7f).contains(byte))
+        .filter(|run| run.len() >= 4)
+        .map(|run| String::from_utf8_lossy(run).trim().to_string())
+        .filter(|run| !run.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    (!tag.is_empty()).then_some(tag)
+}
+
+// Minimal block explorer on top of the node's RPC interface.
+// Only results with EXPLORER_CACHE_MIN_CONFIRMATIONS are cached, so reorgs near the tip are never served stale.
+pub struct ExplorerService {
+    bitcoin_service: Arc<BitcoinRpcService>,
+    tip_cache: Arc<TipCache>,
+    blocks: Mutex<BoundedCache<BlockHash, Arc<BlockDetails>>>,
+    transactions: Mutex<BoundedCache<Txid, TransactionInfo>>,
+    // Bounds the getrawtransaction calls made for input values across all requests
+    prevout_lookups: Semaphore,
+}
+
+impl ExplorerService {
+    pub fn new(bitcoin_service: Arc<BitcoinRpcService>, tip_cache: Arc<TipCache>) -> Arc<Self> {
+        Arc::new(Self {
+            bitcoin_service,
+            tip_cache,
+            blocks: Mutex::new(BoundedCache::new(EXPLORER_BLOCK_CACHE_SIZE)),
+            transactions: Mutex::new(BoundedCache::new(EXPLORER_TX_CACHE_SIZE)),
+            prevout_lookups: Semaphore::new(EXPLORER_PREVOUT_LOOKUP_SLOTS),
+        })
+    }
+
+    fn confirmations(&self, block_height: u64) -> Option<u64> {
+        let tip_height = self.tip_cache.get()?.tip.block_height;
+        tip_height.checked_sub(block_height).map(|depth| depth + 1)
+    }
+
+    // Accepts either a height or a block hash
+    fn resolve_block_hash(&self, id: &str) -> Result<BlockHash, ExplorerError> {
+        if let Ok(height) = id.parse::<u64>() {
+            return Ok(self.bitcoin_service.get_block_hash(height)?);
+        }
+        BlockHash::from_str(id).map_err(|_| ExplorerError::InvalidId(format!("Not a block height or hash: {}", id)))
+    }
+
+    fn load_block(&self, block_hash: &BlockHash) -> Result<Arc<BlockDetails>, ExplorerError> {
+        if let Some(details) = self.blocks.lock().unwrap().get(block_hash) {
+            return Ok(details);
+        }
+
+        let block: Block = self.bitcoin_service.get_block(block_hash)?;
+        let header = self.bitcoin_service.get_block_header_info(block_hash)?;
+        let height = header.height as u64;
+        // The node reports -1 confirmations for a block outside its best chain, whose height
+        // belongs to another block there
+        let in_best_chain = header.confirmations > 0;
+        let total_fee_sats = in_best_chain
+            .then(|| self.bitcoin_service.get_block_total_fee(height).ok())
+            .flatten();
+        let miner_tag = block.txdata.first()
+            .and_then(|coinbase| coinbase.input.first())
+            .and_then(|input| extract_miner_tag(input.script_sig.as_bytes()));
+
+        let txs = block.txdata.iter()
+            .map(|tx| BlockTxSummary {
+                txid: tx.compute_txid().to_string(),
+                size: tx.total_size(),
+                vsize: tx.vsize(),
+                weight: tx.weight().to_wu(),
+                input_count: tx.input.len(),
+                output_count: tx.output.len(),
+                output_value_sats: tx.output.iter().map(|output| output.value.to_sat()).sum(),
+            })
+            .collect();
+
+        let info = BlockInfo {
+            height,
+            hash: block_hash.to_string(),
+            previous_block_hash: header.previous_block_hash.map(|hash| hash.to_string()),
+            time: block.header.time,
+            median_time: header.median_time.map(|time| time as u64),
+            version: block.header.version.to_consensus(),
+            bits: format!("{:08x}", block.header.bits.to_consensus()),
+            nonce: block.header.nonce,
+            difficulty: header.difficulty,
+            merkle_root: block.header.merkle_root.to_string(),
+            tx_count: block.txdata.len(),
+            size: block.total_size(),
+            weight: block.weight().to_wu(),
+            total_fee_sats,
+            miner_tag,
+            confirmations: header.confirmations.max(0) as u64,
+        };
+
+        let details = Arc::new(BlockDetails { info, txs });
+        if header.confirmations >= EXPLORER_CACHE_MIN_CONFIRMATIONS as i32 {
+            self.blocks.lock().unwrap().insert(*block_hash, details.clone());
+        }
+        Ok(details)
+    }
+
+    pub fn get_block(&self, id: &str) -> Result<BlockInfo, ExplorerError> {
+        let block_hash = self.resolve_block_hash(id)?;
+        let details = self.load_block(&block_hash)?;
+        let mut info = details.info.clone();
+        // A stale block keeps 0 confirmations whatever the tip's height
+        if info.confirmations > 0 {
+            if let Some(confirmations) = self.confirmations(info.height) {
+                info.confirmations = confirmations;
+            }
+        }
+        Ok(info)
+    }
+
+    pub fn get_block_txs(&self, hash: &str, offset: usize, limit: usize) -> Result<BlockTxsPage, ExplorerError> {
+        let block_hash = BlockHash::from_str(hash)
+            .map_err(|_| ExplorerError::InvalidId(format!("Not a block hash: {}", hash)))?;
+        let details = self.load_block(&block_hash)?;
+        let txs = details.txs.iter().skip(offset).take(limit).cloned().collect();
+        Ok(BlockTxsPage {
+            block_hash: block_hash.to_string(),
+            total: details.txs.len(),
+            offset,
+            limit,
+            txs,
+        })
+    }
+
+    pub fn get_transaction(&self, txid: &str) -> Result<TransactionInfo, ExplorerError> {
+        let txid = Txid::from_str(txid)
+            .map_err(|_| ExplorerError::InvalidId(format!("Not a transaction id: {}", txid)))?;
+
+        if let Some(mut info) = self.transactions.lock().unwrap().get(&txid) {
+            if let Some(confirmations) = info.status.block_height.and_then(|height| self.confirmations(height)) {
+                info.status.confirmations = confirmations;
+            }
+            return Ok(info);
+        }
+
+        let raw = self.bitcoin_service.get_raw_transaction_info(&txid, None)?;
+
+        // Input values come from the spent outputs, which needs one lookup per transaction spent
+        // from. Past the per-transaction limit, or while other requests use up the lookup slots,
+        // they are left out rather than queued behind each other on the node.
+        let mut prev_txids: Vec<Txid> = raw.vin.iter().filter_map(|vin| vin.txid).collect();
+        prev_txids.sort_unstable();
+        prev_txids.dedup();
+        let within_limit = prev_txids.len() <= EXPLORER_MAX_PREVOUT_LOOKUPS;
+        let permit = within_limit
+            .then(|| self.prevout_lookups.try_acquire_many(prev_txids.len() as u32).ok())
+            .flatten();
+        let busy = within_limit && permit.is_none();
+        let prev_outputs: HashMap<Txid, Vec<GetRawTransactionResultVout>> = match permit {
+            Some(_) => prev_txids.iter()
+                .filter_map(|prev_txid| {
+                    let prev = self.bitcoin_service.get_raw_transaction_info(prev_txid, None).ok()?;
+                    Some((*prev_txid, prev.vout))
+                })
+                .collect(),
+            None => HashMap::new(),
+        };
+        drop(permit);
+        let inputs: Vec<TxInput> = raw.vin.iter()
+            .map(|vin| {
+                let prevout = match (vin.txid, vin.vout) {
+                    (Some(prev_txid), Some(prev_vout)) => prev_outputs.get(&prev_txid)
+                        .and_then(|outputs| outputs.iter().find(|output| output.n == prev_vout))
+                        .cloned(),
+                    _ => None,
+                };
+                TxInput {
+                    coinbase: vin.is_coinbase(),
+                    prev_txid: vin.txid.map(|txid| txid.to_string()),
+                    prev_vout: vin.vout,
+                    sequence: vin.sequence,
+                    witness_items: vin.txinwitness.as_ref().map_or(0, Vec::len),
+                    value_sats: prevout.as_ref().map(|output| output.value.to_sat()),
+                    address: prevout
+                        .and_then(|output| output.script_pub_key.address)
+                        .map(|address| address.assume_checked().to_string()),
+                }
+            })
+            .collect();
+
+        let outputs: Vec<TxOutput> = raw.vout.iter()
+            .map(|vout| TxOutput {
+                n: vout.n,
+                value_sats: vout.value.to_sat(),
+                script_type: vout.script_pub_key.type_
+                    .and_then(|script_type| serde_json::to_value(script_type).ok())
+                    .and_then(|value| value.as_str().map(str::to_string)),
+                address: vout.script_pub_key.address.clone()
+                    .map(|address| address.assume_checked().to_string()),
+            })
+            .collect();
+
+        let is_coinbase = inputs.iter().any(|input| input.coinbase);
+        let input_total: Option<u64> = inputs.iter().map(|input| input.value_sats).sum();
+        let output_total: u64 = outputs.iter().map(|output| output.value_sats).sum();
+        let fee_sats = if is_coinbase {
+            Some(0)
+        } else {
+            input_total.and_then(|input_total| input_total.checked_sub(output_total))
+        };
+
+        let block_height = match raw.blockhash {
+            Some(block_hash) => Some(self.bitcoin_service.get_block_header_info(&block_hash)?.height as u64),
+            None => None,
+        };
+        let confirmations = raw.confirmations.unwrap_or(0) as u64;
+        let info = TransactionInfo {
+            txid: raw.txid.to_string(),
+            wtxid: raw.hash.to_string(),
+            version: raw.version,
+            locktime: raw.locktime,
+            size: raw.size,
+            vsize: raw.vsize,
+            fee_sats,
+            inputs,
+            outputs,
+            status: TxStatus {
+                // Outside the best chain a transaction can still name the stale block it was mined in
+                confirmed: confirmations > 0,
+                block_hash: raw.blockhash.map(|hash| hash.to_string()),
+                block_height,
+                block_time: raw.blocktime.map(|time| time as u64),
+                confirmations,
+            },
+        };
+
+        // Values left out for lack of a slot are filled in on a later request
+        if confirmations >= EXPLORER_CACHE_MIN_CONFIRMATIONS && !busy {
+            self.transactions.lock().unwrap().insert(txid, info.clone());
+        }
+        Ok(info)
+    }
+}
@@ -0,0 +1,406 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use chrono::{DateTime, NaiveDate, NaiveTime, SecondsFormat, Utc};
+use parquet::basic::Compression;
+use parquet::data_type::{DoubleType, Int32Type, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::parser::parse_message_type;
+use serde_json::{Map, Value};
+use tokio::sync::mpsc;
+use crate::config::settings::{EXPORT_CHUNK_BYTES, EXPORT_PARQUET_ROW_GROUP_ROWS};
+
+// A stored series that can be exported. The first column of every series is the date or time its
+// rows are filtered, ordered and partitioned by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportSeries {
+    DailyTx,
+    SevenDayDma,
+    FeeHistory,
+    BlockStats,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    Date,
+    Timestamp,
+    Int,
+    Float,
+}
+
+impl ExportSeries {
+    pub const ALL: [ExportSeries; 4] = [Self::DailyTx, Self::SevenDayDma, Self::FeeHistory, Self::BlockStats];
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|series| series.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::DailyTx => "daily_tx",
+            Self::SevenDayDma => "dma",
+            Self::FeeHistory => "fee_history",
+            Self::BlockStats => "block_stats",
+        }
+    }
+
+    pub fn columns(self) -> &'static [(&'static str, ColumnType)] {
+        match self {
+            Self::DailyTx => &[("date", ColumnType::Date), ("tx_count", ColumnType::Int), ("dma_value", ColumnType::Float)],
+            Self::SevenDayDma => &[("date", ColumnType::Date), ("dma_value", ColumnType::Float)],
+            Self::FeeHistory => &[
+                ("estimated_at", ColumnType::Timestamp), ("block_target", ColumnType::Int), ("fee_rate", ColumnType::Float),
+            ],
+            Self::BlockStats => &[
+                ("time", ColumnType::Timestamp), ("height", ColumnType::Int), ("tx_count", ColumnType::Int),
+                ("size", ColumnType::Int), ("weight", ColumnType::Int),
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportValue {
+    Date(NaiveDate),
+    Timestamp(DateTime<Utc>),
+    Int(i64),
+    Float(f64),
+}
+
+impl ExportValue {
+    // The UTC day of a date or time value
+    fn date(self) -> Option<NaiveDate> {
+        match self {
+            Self::Date(date) => Some(date),
+            Self::Timestamp(time) => Some(time.date_naive()),
+            Self::Int(_) | Self::Float(_) => None,
+        }
+    }
+
+    // Dates as 2024-04-20 and times as 2024-04-20T00:09:27Z, like the API's JSON
+    fn to_text(self) -> String {
+        match self {
+            Self::Date(date) => date.to_string(),
+            Self::Timestamp(time) => time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            Self::Int(value) => value.to_string(),
+            Self::Float(value) => value.to_string(),
+        }
+    }
+
+    fn to_json(self) -> Value {
+        match self {
+            Self::Int(value) => value.into(),
+            Self::Float(value) => value.into(),
+            other => Value::String(other.to_text()),
+        }
+    }
+}
+
+// Receives each row of an export; returning an error stops it
+pub type RowSink<'a> = dyn FnMut(&[ExportValue]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> + 'a;
+
+// The first instant of `from` and of the day after `to`, for filtering times by an inclusive date range
+pub fn day_bounds(from: Option<NaiveDate>, to: Option<NaiveDate>) -> (Option<DateTime<Utc>>, Option<DateTime<Utc>>) {
+    let start_of = |date: NaiveDate| date.and_time(NaiveTime::MIN).and_utc();
+    (from.map(start_of), to.and_then(|date| date.succ_opt()).map(start_of))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Ndjson,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "csv" => Some(Self::Csv),
+            "ndjson" => Some(Self::Ndjson),
+            "parquet" => Some(Self::Parquet),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Ndjson => "ndjson",
+            Self::Parquet => "parquet",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv",
+            Self::Ndjson => "application/x-ndjson",
+            Self::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+}
+
+// Encodes the rows of one series as they arrive. Rows are written through to the output, except
+// for Parquet, which holds at most one row group.
+pub trait RowEncoder: Send {
+    fn write_row(&mut self, row: &[ExportValue]) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    // Write whatever is buffered plus any footer, and flush the output
+    fn finish(self: Box<Self>) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+pub fn encoder<'a, W: Write + Send + 'a>(
+    format: ExportFormat,
+    series: ExportSeries,
+    out: W,
+) -> Result<Box<dyn RowEncoder + 'a>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(match format {
+        ExportFormat::Csv => Box::new(CsvEncoder::new(series, out)?),
+        ExportFormat::Ndjson => Box::new(NdjsonEncoder { series, out }),
+        ExportFormat::Parquet => Box::new(ParquetEncoder::new(series, out)?),
+    })
+}
+
+struct CsvEncoder<W> {
+    out: W,
+}
+
+impl<W: Write> CsvEncoder<W> {
+    fn new(series: ExportSeries, mut out: W) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let header: Vec<&str> = series.columns().iter().map(|(name, _)| *name).collect();
+        writeln!(out, "{}", header.join(","))?;
+        Ok(Self { out })
+    }
+}
+
+impl<W: Write + Send> RowEncoder for CsvEncoder<W> {
+    fn write_row(&mut self, row: &[ExportValue]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        // Every value is a number, a date or a time, so nothing needs quoting
+        let fields: Vec<String> = row.iter().map(|value| value.to_text()).collect();
+        writeln!(self.out, "{}", fields.join(","))?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+struct NdjsonEncoder<W> {
+    series: ExportSeries,
+    out: W,
+}
+
+impl<W: Write + Send> RowEncoder for NdjsonEncoder<W> {
+    fn write_row(&mut self, row: &[ExportValue]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let object: Map<String, Value> = self.series.columns().iter()
+            .zip(row)
+            .map(|((name, _), value)| (name.to_string(), value.to_json()))
+            .collect();
+        serde_json::to_writer(&mut self.out, &object)?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+// One column of the row group being built, in its Parquet physical type
+enum ColumnBuffer {
+    Int32(Vec<i32>),
+    Int64(Vec<i64>),
+    Double(Vec<f64>),
+}
+
+struct ParquetEncoder<W: Write + Send> {
+    writer: SerializedFileWriter<W>,
+    columns: Vec<ColumnBuffer>,
+    rows: usize,
+}
+
+impl<W: Write + Send> ParquetEncoder<W> {
+    fn new(series: ExportSeries, out: W) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        // Dates are days and times microseconds since the epoch, as Parquet readers expect
+        let fields: Vec<String> = series.columns().iter()
+            .map(|(name, column_type)| match column_type {
+                ColumnType::Date => format!("REQUIRED INT32 {} (DATE);", name),
+                ColumnType::Timestamp => format!("REQUIRED INT64 {} (TIMESTAMP(MICROS,true));", name),
+                ColumnType::Int => format!("REQUIRED INT64 {};", name),
+                ColumnType::Float => format!("REQUIRED DOUBLE {};", name),
+            })
+            .collect();
+        let schema = parse_message_type(&format!("message {} {{ {} }}", series.name(), fields.join(" ")))?;
+        let properties = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+        let writer = SerializedFileWriter::new(out, Arc::new(schema), Arc::new(properties))?;
+        let columns = series.columns().iter()
+            .map(|(_, column_type)| match column_type {
+                ColumnType::Date => ColumnBuffer::Int32(Vec::new()),
+                ColumnType::Timestamp | ColumnType::Int => ColumnBuffer::Int64(Vec::new()),
+                ColumnType::Float => ColumnBuffer::Double(Vec::new()),
+            })
+            .collect();
+        Ok(Self { writer, columns, rows: 0 })
+    }
+
+    fn flush_row_group(&mut self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.rows == 0 {
+            return Ok(());
+        }
+        let mut row_group = self.writer.next_row_group()?;
+        for buffer in &mut self.columns {
+            let Some(mut column) = row_group.next_column()? else {
+                return Err("Parquet schema has fewer columns than the series".into());
+            };
+            match buffer {
+                ColumnBuffer::Int32(values) => column.typed::<Int32Type>().write_batch(values, None, None)?,
+                ColumnBuffer::Int64(values) => column.typed::<Int64Type>().write_batch(values, None, None)?,
+                ColumnBuffer::Double(values) => column.typed::<DoubleType>().write_batch(values, None, None)?,
+            };
+            column.close()?;
+            match buffer {
+                ColumnBuffer::Int32(values) => values.clear(),
+                ColumnBuffer::Int64(values) => values.clear(),
+                ColumnBuffer::Double(values) => values.clear(),
+            }
+        }
+        row_group.close()?;
+        self.rows = 0;
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> RowEncoder for ParquetEncoder<W> {
+    fn write_row(&mut self, row: &[ExportValue]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for (buffer, value) in self.columns.iter_mut().zip(row) {
+            match (buffer, *value) {
+                (ColumnBuffer::Int32(values), ExportValue::Date(date)) => {
+                    values.push((date - DateTime::UNIX_EPOCH.date_naive()).num_days() as i32);
+                }
+                (ColumnBuffer::Int64(values), ExportValue::Timestamp(time)) => values.push(time.timestamp_micros()),
+                (ColumnBuffer::Int64(values), ExportValue::Int(value)) => values.push(value),
+                (ColumnBuffer::Double(values), ExportValue::Float(value)) => values.push(value),
+                (_, value) => return Err(format!("Unexpected value {:?} for the Parquet schema", value).into()),
+            }
+        }
+        self.rows += 1;
+        if self.rows >= EXPORT_PARQUET_ROW_GROUP_ROWS {
+            self.flush_row_group()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.flush_row_group()?;
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Partition {
+    Day,
+    Month,
+    Year,
+}
+
+impl Partition {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "day" => Some(Self::Day),
+            "month" => Some(Self::Month),
+            "year" => Some(Self::Year),
+            _ => None,
+        }
+    }
+
+    fn key(self, date: NaiveDate) -> String {
+        match self {
+            Self::Day => date.format("%Y-%m-%d"),
+            Self::Month => date.format("%Y-%m"),
+            Self::Year => date.format("%Y"),
+        }.to_string()
+    }
+}
+
+// Writes a series into `<dir>/<series>_<partition>.<ext>` files, e.g. `fee_history_2024-04.parquet`,
+// starting a new file whenever the date of the rows crosses into the next partition. Rows must
+// arrive in date order, which `Storage::export_series` guarantees.
+pub struct PartitionedExport {
+    dir: PathBuf,
+    series: ExportSeries,
+    format: ExportFormat,
+    partition: Partition,
+    current: Option<(String, Box<dyn RowEncoder>)>,
+    files: Vec<PathBuf>,
+}
+
+impl PartitionedExport {
+    pub fn new(dir: &Path, series: ExportSeries, format: ExportFormat, partition: Partition) -> std::io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        Ok(Self { dir: dir.to_path_buf(), series, format, partition, current: None, files: Vec::new() })
+    }
+
+    pub fn write_row(&mut self, row: &[ExportValue]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let date = row.first().and_then(|value| value.date()).ok_or("Export row does not start with a date")?;
+        let key = self.partition.key(date);
+        if self.current.as_ref().map(|(current, _)| current) != Some(&key) {
+            if let Some((_, encoder)) = self.current.take() {
+                encoder.finish()?;
+            }
+            let path = self.dir.join(format!("{}_{}.{}", self.series.name(), key, self.format.extension()));
+            let encoder = encoder(self.format, self.series, BufWriter::new(File::create(&path)?))?;
+            self.files.push(path);
+            self.current = Some((key, encoder));
+        }
+        match &mut self.current {
+            Some((_, encoder)) => encoder.write_row(row),
+            None => Ok(()),
+        }
+    }
+
+    // Close the last file and return every file written, oldest partition first
+    pub fn finish(mut self) -> Result<Vec<PathBuf>, Box<dyn std::error::Error + Send + Sync>> {
+        if let Some((_, encoder)) = self.current.take() {
+            encoder.finish()?;
+        }
+        Ok(self.files)
+    }
+}
+
+// Hands the bytes of an export to an async consumer in chunks of about EXPORT_CHUNK_BYTES, waiting
+// whenever the channel is full so a slow client holds back the query instead of filling memory.
+// Writes fail once the receiver is gone, which stops the export.
+pub struct ChannelWriter {
+    sender: mpsc::Sender<Result<Vec<u8>, std::io::Error>>,
+    buffer: Vec<u8>,
+}
+
+impl ChannelWriter {
+    pub fn new(sender: mpsc::Sender<Result<Vec<u8>, std::io::Error>>) -> Self {
+        Self { sender, buffer: Vec::with_capacity(EXPORT_CHUNK_BYTES) }
+    }
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+        self.buffer.extend_from_slice(bytes);
+        if self.buffer.len() >= EXPORT_CHUNK_BYTES {
+            self.flush()?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = std::mem::replace(&mut self.buffer, Vec::with_capacity(EXPORT_CHUNK_BYTES));
+        self.sender
+            .blocking_send(Ok(chunk))
+            .map_err(|_| std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Export receiver closed"))
+    }
+}
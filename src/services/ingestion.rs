@@ -1,58 +1,619 @@
 // ingestion.rs
 
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use chrono::{Utc, NaiveDate};
-use crate::{MySqlService, BitcoinRpcService}; // Import your services from main.rs
+use std::time::Duration;
+use bitcoincore_rpc::bitcoin::Block;
+use bitcoincore_rpc_json::GetBlockHeaderResult;
+use chrono::Utc;
+use tracing::{debug, error, info, info_span, instrument, warn, Span};
+use crate::services::alerts::AlertMetric;
+use crate::services::bitcoin_rpc::{BitcoinRpcService, ChainTip};
+use crate::services::block_files::BlockFiles;
+use crate::services::storage::{spawn_blocking_in_span, AggregatesFrom, BlockRecord, Storage, WriteBatch};
+use crate::config::settings::{
+    BACKFILL_BATCH_BLOCKS, BLOCKS_PER_DAY, BLOCK_BOOTSTRAP_DEPTH, FEE_BLOCK_TARGETS, INGESTION_INTERVAL_SECS, MAX_BLOCKS_PER_TICK, MAX_REORG_DEPTH,
+};
+use crate::config::settings::FULL_INDEX_ENABLED;
+use crate::config::connections::BITCOIN_NETWORK;
+use crate::services::block_index::{index_block, index_spent_outputs};
+use crate::services::events::{EventBus, FeeEstimate, IngestionEvent};
+use crate::services::shutdown::Shutdown;
+use crate::services::tip_cache::TipCache;
+use crate::services::watch_wallets::WatchService;
 
-// Function to calculate 7DMA from the last 7 days' transaction data
-fn calculate_7dma(transaction_data: &[(NaiveDate, usize)]) -> f64 {
-    let sum: usize = transaction_data.iter().map(|(_, tx_count)| *tx_count).sum();
-    sum as f64 / transaction_data.len() as f64 // Average the transactions over the 7 days
-}
-
-// Function to retrieve the latest data and store it in MySQL before starting the server
+// Function to retrieve the latest data and store it in the database before starting the server
 pub async fn retrieve_and_store_data(
-    mysql_service: Arc<MySqlService>,
+    storage: Arc<dyn Storage>,
     bitcoin_service: Arc<BitcoinRpcService>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Retrieving latest data...");
-
-    // Retrieve the last 7 days' transaction data from Bitcoin RPC
-    // let transaction_data = bitcoin_service.get_last_7_days_tx_data().await?;
-    // // Save the transaction data in MySQL
-    // for data in transaction_data {
-    //     mysql_service.save_daily_tx(data.date, data.tx_count).await?;
-    // }
-
-    // Optionally, calculate and store 7DMA if needed
-    // let last_7_days_data: Vec<(NaiveDate, usize)> = mysql_service.get_last_7_days().await?;
-    // let dma_value = calculate_7dma(&last_7_days_data);
-    // let today = Utc::now().naive_utc().date();
-    // mysql_service.save_7dma(today, dma_value).await?;
-    
-    retrieve_and_store_fee_estimations(mysql_service.clone(), bitcoin_service.clone()).await?;
-
-
-    println!("Data retrieval and storage completed.");
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    info!("Retrieving latest data");
+
+    spawn_blocking_in_span(move || retrieve_and_store_fee_estimations(storage.as_ref(), &bitcoin_service)).await?;
+
+    info!("Data retrieval and storage completed");
     Ok(())
 }
 
- // Function to retrieve fee estimation data and store it in MySQL
- pub async fn retrieve_and_store_fee_estimations(
-    mysql_service: Arc<MySqlService>,
-    bitcoin_service: Arc<BitcoinRpcService>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let block_targets = vec![1, 3, 6, 12, 24]; // Different block targets for fee estimation
+// Function to retrieve fee estimation data and store it in the database
+pub fn retrieve_and_store_fee_estimations(
+    storage: &dyn Storage,
+    bitcoin_service: &BitcoinRpcService,
+) -> Result<Vec<FeeEstimate>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut estimates = Vec::new();
+    let mut batch = WriteBatch::new();
+    let sampled_at = Utc::now();
 
-    for block_target in block_targets {
-        match bitcoin_service.get_fee_estimation(block_target).await {
+    for block_target in FEE_BLOCK_TARGETS {
+        match bitcoin_service.get_fee_estimation(block_target) {
             Ok(fee_rate) => {
-                println!("Fee rate for block target {}: {} sat/byte", block_target, fee_rate);
-                mysql_service.save_fee_estimation(block_target, fee_rate).await?;
+                debug!(block_target, fee_rate, "Fee rate in sat/byte");
+                batch.add_fee_estimate(block_target, fee_rate);
+                batch.add_metric_sample(AlertMetric::FeeRate { block_target }.to_string(), fee_rate, sampled_at);
+                estimates.push(FeeEstimate { block_target, fee_rate });
             }
-            Err(e) => eprintln!("Error retrieving fee estimation for block target {}: {}", block_target, e),
+            Err(e) => warn!(block_target, error = %e, "Error retrieving fee estimation"),
         }
     }
 
+    storage.write_batch(batch)?;
+    Ok(estimates)
+}
+
+// Fetch blocks `from_height..=to_height` into `batch`, with their full index rows when enabled.
+// Watched wallets are matched here, before the block rows are written, so a failure retries
+// the whole block on the next tick. Stops early on shutdown or the first error; what was fetched
+// until then stays in the batch.
+fn fetch_blocks(
+    bitcoin_service: &BitcoinRpcService,
+    watch_service: Option<&WatchService>,
+    shutdown: Option<&Shutdown>,
+    heights: impl Iterator<Item = u64>,
+    batch: &mut WriteBatch,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let heights: Vec<u64> = heights.collect();
+    // Over REST the headers of each run of consecutive heights come in bulk and each block in one
+    // binary fetch, which also gives its size and weight
+    let mut headers: HashMap<u64, GetBlockHeaderResult> = HashMap::new();
+    if bitcoin_service.uses_rest() {
+        for run in heights.chunk_by(|height, next| *next == height + 1) {
+            let first_hash = bitcoin_service.get_block_hash(run[0])?;
+            headers.extend(bitcoin_service.get_block_headers(&first_hash, run.len())?
+                .into_iter()
+                .map(|header| (header.height as u64, header)));
+        }
+    }
+
+    for height in heights {
+        if shutdown.is_some_and(Shutdown::is_triggered) {
+            break;
+        }
+        let _span = info_span!("ingest_block", block_height = height).entered();
+        let (record, block) = match headers.remove(&height) {
+            Some(header) => {
+                let block = bitcoin_service.get_block(&header.hash)?;
+                (block_record_from_header(&header, &block), Some(block))
+            }
+            None => (fetch_block_record(bitcoin_service, height)?, None),
+        };
+        let watching = watch_service.filter(|watch_service| watch_service.has_wallets());
+        if FULL_INDEX_ENABLED || watching.is_some() {
+            let block = match block {
+                Some(block) => block,
+                None => bitcoin_service.get_block(&record.hash.parse()?)?,
+            };
+            if let Some(watch_service) = watching {
+                watch_service.process_block(&block, height)?;
+            }
+            if FULL_INDEX_ENABLED {
+                batch.add_indexed_block(record, index_block(&block, height, BITCOIN_NETWORK));
+                continue;
+            }
+        }
+        batch.add_block(record);
+    }
     Ok(())
 }
+
+// Convert the node's view of a block into a row of the blocks table
+fn fetch_block_record(
+    bitcoin_service: &BitcoinRpcService,
+    height: u64,
+) -> Result<BlockRecord, Box<dyn std::error::Error + Send + Sync>> {
+    let block_hash = bitcoin_service.get_block_hash(height)?;
+    let info = bitcoin_service.get_block_info(&block_hash)?;
+    Ok(BlockRecord {
+        height,
+        hash: info.hash.to_string(),
+        prev_hash: info.previousblockhash.map(|hash| hash.to_string()),
+        time: info.time as i64,
+        median_time: info.mediantime.map(|time| time as i64),
+        version: info.version,
+        bits: info.bits,
+        difficulty: info.difficulty,
+        nonce: info.nonce,
+        size: info.size as u32,
+        weight: info.weight as u32,
+        tx_count: info.n_tx as u32,
+    })
+}
+
+// The same row from a header and the block itself, as fetched over REST
+fn block_record_from_header(header: &GetBlockHeaderResult, block: &Block) -> BlockRecord {
+    BlockRecord {
+        height: header.height as u64,
+        hash: header.hash.to_string(),
+        prev_hash: header.previous_block_hash.map(|hash| hash.to_string()),
+        time: header.time as i64,
+        median_time: header.median_time.map(|time| time as i64),
+        version: header.version.to_consensus(),
+        bits: header.bits.clone(),
+        difficulty: header.difficulty,
+        nonce: header.nonce,
+        size: block.total_size() as u32,
+        weight: block.weight().to_wu() as u32,
+        tx_count: block.txdata.len() as u32,
+    }
+}
+
+// Walk back from the stored tip until our hash agrees with the node, and drop everything above.
+// Returns the fork height and how many blocks were orphaned, or None if there was no reorg.
+fn rewind_reorg(
+    storage: &dyn Storage,
+    bitcoin_service: &BitcoinRpcService,
+    watch_service: &WatchService,
+    stored_height: u64,
+    tip_height: u64,
+) -> Result<Option<(u64, u64)>, Box<dyn std::error::Error + Send + Sync>> {
+    let lowest = stored_height.saturating_sub(MAX_REORG_DEPTH);
+    // The node may be behind what we stored, e.g. after switching to a shorter chain
+    let mut height = stored_height.min(tip_height);
+    loop {
+        let stored_hash = storage.get_block_hash_at(height)?;
+        let node_hash = bitcoin_service.get_block_hash(height)?.to_string();
+        if stored_hash.as_deref() == Some(node_hash.as_str()) {
+            break;
+        }
+        if height == lowest {
+            return Err(format!("Reorg deeper than {} blocks below height {}", MAX_REORG_DEPTH, stored_height).into());
+        }
+        height -= 1;
+    }
+
+    if height == stored_height {
+        return Ok(None);
+    }
+    if FULL_INDEX_ENABLED {
+        storage.delete_index_above(height)?;
+    }
+    watch_service.rewind(height)?;
+    let orphaned_blocks = storage.delete_blocks_above(height)?;
+    Ok(Some((height, orphaned_blocks)))
+}
+
+// Bring the blocks table up to `tip_height` (at most MAX_BLOCKS_PER_TICK blocks per call),
+// then rebuild the daily aggregates touched by the change. On shutdown the batch stops after
+// the block being stored, and the aggregates still cover every stored block.
+#[instrument(skip_all, fields(tip_height))]
+pub fn sync_blocks(
+    storage: &dyn Storage,
+    bitcoin_service: &BitcoinRpcService,
+    event_bus: &EventBus,
+    watch_service: &WatchService,
+    shutdown: &Shutdown,
+    tip_height: u64,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut first_changed_height = None;
+
+    let stored_tip = storage.get_stored_tip()?;
+    // Blocks stored after the last aggregate run, e.g. by a process killed mid-batch
+    if let (Some(checkpoint), Some((stored_height, _))) = (storage.get_aggregates_checkpoint()?, &stored_tip) {
+        if checkpoint < *stored_height {
+            first_changed_height = Some(checkpoint + 1);
+        }
+    }
+
+    let start_height = match stored_tip {
+        Some((stored_height, _)) => {
+            if let Some((fork_height, orphaned_blocks)) = rewind_reorg(storage, bitcoin_service, watch_service, stored_height, tip_height)? {
+                warn!(fork_height, orphaned_blocks, "Reorg detected");
+                event_bus.publish(IngestionEvent::Reorg { fork_height, orphaned_blocks });
+                first_changed_height = Some(first_changed_height.map_or(fork_height + 1, |height: u64| height.min(fork_height + 1)));
+                fork_height + 1
+            } else {
+                stored_height + 1
+            }
+        }
+        None => bootstrap_start_height(bitcoin_service, tip_height)?,
+    };
+
+    // New blocks and the aggregates they change are written together
+    let mut batch = WriteBatch::new();
+    let mut fetched = Ok(());
+    if start_height <= tip_height {
+        let end_height = tip_height.min(start_height + MAX_BLOCKS_PER_TICK - 1);
+        fetched = fetch_blocks(bitcoin_service, Some(watch_service), Some(shutdown), start_height..=end_height, &mut batch);
+        if batch.block_count() > 0 {
+            first_changed_height.get_or_insert(start_height);
+        }
+    }
+
+    // Recompute every day that may contain a changed block
+    let Some(first_changed_height) = first_changed_height else {
+        return fetched;
+    };
+    batch.recompute_aggregates_through_tip(first_changed_height);
+    let stored = batch.block_count() as u64;
+    storage.write_batch(batch)?;
+    if stored > 0 {
+        info!(from_height = start_height, to_height = start_height + stored - 1, "Ingested blocks");
+    }
+
+    if let Some(aggregate) = storage.get_latest_daily_aggregate()? {
+        event_bus.publish(IngestionEvent::DailyTx {
+            date: aggregate.date,
+            tx_count: aggregate.tx_count,
+            dma_value: aggregate.dma_value,
+        });
+    }
+    fetched
+}
+
+// Where to start filling an empty blocks table: the first block of the UTC day BLOCK_BOOTSTRAP_DEPTH
+// blocks below the tip, so the daily aggregates never count a day from only some of its blocks.
+// Block times may run a little backwards, so that is the block after the last one from an earlier day.
+pub fn bootstrap_start_height(
+    bitcoin_service: &BitcoinRpcService,
+    tip_height: u64,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    let candidate = tip_height.saturating_sub(BLOCK_BOOTSTRAP_DEPTH);
+    let day_of = |time: usize| time as u64 / 86_400;
+    let candidate_day = day_of(bitcoin_service.get_block_header_info(&bitcoin_service.get_block_hash(candidate)?)?.time);
+
+    // Search back a day's worth of blocks at a time
+    let mut end = candidate;
+    while end > 0 {
+        let start = end.saturating_sub(BLOCKS_PER_DAY);
+        let headers = bitcoin_service.get_block_headers(&bitcoin_service.get_block_hash(start)?, (end - start) as usize)?;
+        if let Some(position) = headers.iter().rposition(|header| day_of(header.time) < candidate_day) {
+            return Ok(start + position as u64 + 1);
+        }
+        end = start;
+    }
+    Ok(0)
+}
+
+// Check the node's tip by its best block hash, so a reorg to a tip at the same height is seen too.
+// On a new tip, record it in block_info (when given a database), refresh the tip cache and publish
+// it; otherwise just mark the cached tip as checked.
+pub fn poll_chain_tip(
+    storage: Option<&dyn Storage>,
+    bitcoin_service: &BitcoinRpcService,
+    event_bus: &EventBus,
+    tip_cache: &TipCache,
+    last_tip: &mut Option<ChainTip>,
+) {
+    let block_hash = match bitcoin_service.get_best_block_hash() {
+        Ok(block_hash) => block_hash,
+        Err(e) => {
+            error!(error = %e, "Error retrieving best block hash");
+            return;
+        }
+    };
+    if last_tip.as_ref().is_some_and(|tip| tip.block_hash == block_hash.to_string()) {
+        tip_cache.touch();
+        return;
+    }
+    match bitcoin_service.get_chain_tip(&block_hash) {
+        Ok(tip) => {
+            if let Some(storage) = storage {
+                if let Err(e) = storage.update_block_height(tip.block_height) {
+                    error!(error = %e, "Failed to update block height in the database");
+                }
+            }
+            event_bus.publish(IngestionEvent::NewBlock {
+                block_height: tip.block_height,
+                block_hash: tip.block_hash.clone(),
+                block_time: tip.block_time,
+            });
+            tip_cache.update(tip.clone());
+            *last_tip = Some(tip);
+        }
+        Err(e) => error!(%block_hash, error = %e, "Error retrieving chain tip"),
+    }
+}
+
+// Publish the mempool's size, and store it as metric samples when `storage` is given
+fn publish_mempool_snapshot(storage: Option<&dyn Storage>, bitcoin_service: &BitcoinRpcService, event_bus: &EventBus) {
+    let info = match bitcoin_service.get_mempool_info() {
+        Ok(info) => info,
+        Err(e) => {
+            error!(error = %e, "Error retrieving mempool info");
+            return;
+        }
+    };
+    if let Some(storage) = storage {
+        let mut batch = WriteBatch::new();
+        let sampled_at = Utc::now();
+        for (metric, value) in [
+            (AlertMetric::MempoolTxCount, info.size),
+            (AlertMetric::MempoolVsizeBytes, info.bytes),
+            (AlertMetric::MempoolUsageBytes, info.usage),
+        ] {
+            batch.add_metric_sample(metric.to_string(), value as f64, sampled_at);
+        }
+        if let Err(e) = storage.write_batch(batch) {
+            error!(error = %e, "Error storing mempool samples");
+        }
+    }
+    event_bus.publish(IngestionEvent::Mempool {
+        tx_count: info.size,
+        vsize_bytes: info.bytes,
+        usage_bytes: info.usage,
+    });
+}
+
+// One pass of ingestion. Everything here calls the node or the database synchronously, so the loop runs
+// it on a blocking thread.
+#[instrument(skip_all, fields(block_height = tracing::field::Empty))]
+fn ingestion_tick(
+    storage: &dyn Storage,
+    bitcoin_service: &BitcoinRpcService,
+    event_bus: &EventBus,
+    tip_cache: &TipCache,
+    watch_service: &WatchService,
+    shutdown: &Shutdown,
+    last_tip: &mut Option<ChainTip>,
+) {
+    // Step 1: New tip, the only place block_info is written
+    poll_chain_tip(Some(storage), bitcoin_service, event_bus, tip_cache, last_tip);
+    let last_block_height = last_tip.as_ref().map(|tip| tip.block_height);
+    if let Some(block_height) = last_block_height {
+        Span::current().record("block_height", block_height);
+    }
+
+    // Step 2: Fee estimates
+    match retrieve_and_store_fee_estimations(storage, bitcoin_service) {
+        Ok(estimates) if !estimates.is_empty() => {
+            event_bus.publish(IngestionEvent::FeeEstimates { estimates });
+        }
+        Ok(_) => {}
+        Err(e) => error!(error = %e, "Error storing fee estimations"),
+    }
+
+    // Step 3: Mempool snapshot
+    publish_mempool_snapshot(Some(storage), bitcoin_service, event_bus);
+
+    // Wallets registered through a separate `serve` process
+    if let Err(e) = watch_service.reload_if_changed() {
+        error!(error = %e, "Error reloading watch-only wallets");
+    }
+
+    // Step 4: Persist new blocks and the daily aggregates derived from them
+    if let Some(tip_height) = last_block_height {
+        if let Err(e) = sync_blocks(storage, bitcoin_service, event_bus, watch_service, shutdown, tip_height) {
+            error!(error = %e, "Error ingesting blocks");
+        }
+    }
+
+    // Step 5: Watched wallet transactions that entered the mempool
+    if let Err(e) = watch_service.process_mempool() {
+        error!(error = %e, "Error matching mempool against watched wallets");
+    }
+}
+
+// Poll the node on a fixed interval, store what changed and publish it on the event bus
+pub async fn run_ingestion_loop(
+    storage: Arc<dyn Storage>,
+    bitcoin_service: Arc<BitcoinRpcService>,
+    event_bus: Arc<EventBus>,
+    tip_cache: Arc<TipCache>,
+    watch_service: Arc<WatchService>,
+    shutdown: Arc<Shutdown>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(INGESTION_INTERVAL_SECS));
+    let mut last_tip = None;
+
+    loop {
+        // A tick in progress always runs to the end
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.wait() => break,
+        }
+
+        let storage = storage.clone();
+        let bitcoin_service = bitcoin_service.clone();
+        let event_bus = event_bus.clone();
+        let tip_cache = tip_cache.clone();
+        let watch_service = watch_service.clone();
+        let shutdown = shutdown.clone();
+        last_tip = spawn_blocking_in_span(move || {
+            ingestion_tick(
+                storage.as_ref(),
+                &bitcoin_service,
+                &event_bus,
+                &tip_cache,
+                &watch_service,
+                &shutdown,
+                &mut last_tip,
+            );
+            last_tip
+        }).await;
+    }
+    info!("Ingestion stopped");
+}
+
+// What the follower last published, so it only publishes changes
+#[derive(Default)]
+struct FollowerState {
+    last_tip: Option<ChainTip>,
+    last_estimates: Vec<FeeEstimate>,
+    last_daily: Option<(chrono::NaiveDate, usize, f64)>,
+}
+
+fn follower_tick(
+    storage: &dyn Storage,
+    bitcoin_service: &BitcoinRpcService,
+    event_bus: &EventBus,
+    tip_cache: &TipCache,
+    state: &mut FollowerState,
+) {
+    // Step 1: New tip, straight from the node
+    poll_chain_tip(None, bitcoin_service, event_bus, tip_cache, &mut state.last_tip);
+
+    // Step 2: Fee estimates as last stored by ingestion
+    match storage.get_fee_estimations() {
+        Ok(rows) => {
+            let estimates: Vec<FeeEstimate> = rows.into_iter()
+                .map(|(block_target, fee_rate, _)| FeeEstimate { block_target, fee_rate })
+                .collect();
+            if estimates != state.last_estimates && !estimates.is_empty() {
+                state.last_estimates = estimates.clone();
+                event_bus.publish(IngestionEvent::FeeEstimates { estimates });
+            }
+        }
+        Err(e) => error!(error = %e, "Error reading fee estimations"),
+    }
+
+    // Step 3: Mempool snapshot
+    publish_mempool_snapshot(None, bitcoin_service, event_bus);
+
+    // Step 4: Latest daily aggregate as last stored by ingestion
+    match storage.get_latest_daily_aggregate() {
+        Ok(Some(aggregate)) => {
+            let current = (aggregate.date, aggregate.tx_count, aggregate.dma_value);
+            if state.last_daily != Some(current) {
+                state.last_daily = Some(current);
+                event_bus.publish(IngestionEvent::DailyTx {
+                    date: aggregate.date,
+                    tx_count: aggregate.tx_count,
+                    dma_value: aggregate.dma_value,
+                });
+            }
+        }
+        Ok(None) => {}
+        Err(e) => error!(error = %e, "Error reading daily aggregates"),
+    }
+}
+
+// For an API-only process: keep the tip cache and push feed current from the node and from what
+// a separate `ingest` process stores. Reorgs, wallet activity and alerts are only published by
+// the ingesting process.
+pub async fn run_follower_loop(
+    storage: Arc<dyn Storage>,
+    bitcoin_service: Arc<BitcoinRpcService>,
+    event_bus: Arc<EventBus>,
+    tip_cache: Arc<TipCache>,
+) {
+    let mut interval = tokio::time::interval(Duration::from_secs(INGESTION_INTERVAL_SECS));
+    let mut state = FollowerState::default();
+
+    loop {
+        interval.tick().await;
+
+        let storage = storage.clone();
+        let bitcoin_service = bitcoin_service.clone();
+        let event_bus = event_bus.clone();
+        let tip_cache = tip_cache.clone();
+        state = spawn_blocking_in_span(move || {
+            follower_tick(storage.as_ref(), &bitcoin_service, &event_bus, &tip_cache, &mut state);
+            state
+        }).await;
+    }
+}
+
+// Store blocks `from_height..=to_height` that are missing, e.g. history older than the bootstrap
+// depth, then rebuild the daily aggregates they fall into. Watched wallets are not matched against
+// backfilled blocks. Returns how many blocks were stored.
+#[instrument(skip_all, fields(from_height, to_height))]
+pub fn backfill_blocks(
+    storage: &dyn Storage,
+    bitcoin_service: &BitcoinRpcService,
+    from_height: u64,
+    to_height: u64,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    if from_height > to_height {
+        return Err(format!("--from {} is above --to {}", from_height, to_height).into());
+    }
+    let node_height = bitcoin_service.get_block_height()?;
+    if to_height > node_height {
+        return Err(format!("--to {} is above the node's tip at {}", to_height, node_height).into());
+    }
+    store_missing_blocks(storage, from_height, to_height, |heights, batch| {
+        fetch_blocks(bitcoin_service, None, None, heights, batch)
+    })
+}
+
+// Like `backfill_blocks`, with the blocks read from Bitcoin Core's block files instead of RPC
+#[instrument(skip_all, fields(from_height, to_height))]
+pub fn import_block_files(
+    storage: &dyn Storage,
+    block_files: &mut BlockFiles,
+    from_height: u64,
+    to_height: u64,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    if from_height > to_height {
+        return Err(format!("--from {} is above --to {}", from_height, to_height).into());
+    }
+    if to_height > block_files.tip_height() {
+        return Err(format!("--to {} is above the block files' tip at {}", to_height, block_files.tip_height()).into());
+    }
+    store_missing_blocks(storage, from_height, to_height, |heights, batch| {
+        for height in heights {
+            let (record, block) = block_files.read_block(height)?;
+            if FULL_INDEX_ENABLED {
+                // With the outputs from the undo data, spends of outputs older than the import are linked too
+                let mut indexed = index_block(&block, height, BITCOIN_NETWORK);
+                indexed.outputs.extend(index_spent_outputs(&block, &block_files.read_spent_outputs(height)?, BITCOIN_NETWORK)?);
+                batch.add_indexed_block(record, indexed);
+            } else {
+                batch.add_block(record);
+            }
+        }
+        Ok(())
+    })
+}
+
+// Pass the heights in `from_height..=to_height` not stored yet to `fetch`, and write what it adds
+// to the batch BACKFILL_BATCH_BLOCKS at a time. The aggregates are rebuilt with the last chunk.
+fn store_missing_blocks<F>(
+    storage: &dyn Storage,
+    from_height: u64,
+    to_height: u64,
+    mut fetch: F,
+) -> Result<u64, Box<dyn std::error::Error + Send + Sync>>
+where
+    F: FnMut(&mut dyn Iterator<Item = u64>, &mut WriteBatch) -> Result<(), Box<dyn std::error::Error + Send + Sync>>,
+{
+    // Above the stored tip, ingestion must see the blocks itself to follow reorgs and wallets
+    if let Some((stored_height, _)) = storage.get_stored_tip()? {
+        if to_height > stored_height {
+            return Err(format!("--to {} is above the stored tip at {}; ingestion fills that range", to_height, stored_height).into());
+        }
+    }
+
+    let mut stored = 0;
+    let mut chunk_start = from_height;
+    while chunk_start <= to_height {
+        let chunk_end = to_height.min(chunk_start + BACKFILL_BATCH_BLOCKS - 1);
+        let present: HashSet<u64> = storage.get_blocks_in_range(chunk_start, chunk_end)?
+            .into_iter()
+            .map(|block| block.height)
+            .collect();
+        let mut batch = WriteBatch::new();
+        let fetched = fetch(&mut (chunk_start..=chunk_end).filter(|height| !present.contains(height)), &mut batch);
+        let count = batch.block_count() as u64;
+        // The aggregates are rebuilt in the same transaction as the last chunk
+        if chunk_end == to_height && fetched.is_ok() && stored + count > 0 {
+            batch.recompute_aggregates(AggregatesFrom::Height(from_height));
+        }
+        storage.write_batch(batch)?;
+        stored += count;
+        fetched?;
+        if count > 0 {
+            info!(stored, block_height = chunk_end, "Backfill progress");
+        }
+        chunk_start = chunk_end + 1;
+    }
+    Ok(stored)
+}
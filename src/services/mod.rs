@@ -1,3 +1,20 @@
 pub mod bitcoin_rpc;         // Declare the bitcoin_rpc module
 pub mod mysql_connection;    // Declare the mysql_connection module
-pub mod ingestion;
\ No newline at end of file
+pub mod postgres_connection;
+pub mod storage;
+pub mod export;
+pub mod ingestion;
+pub mod events;
+pub mod tip_cache;
+pub mod response_cache;
+pub mod api_auth;
+pub mod explorer;
+pub mod block_index;
+pub mod block_files;
+pub mod watch_wallets;
+pub mod webhooks;
+pub mod alerts;
+pub mod retention;
+pub mod publisher;
+pub mod shutdown;
+pub mod telemetry;
@@ -1,157 +1,1822 @@
 use mysql::*;
 use mysql::prelude::*;
-use std::{string, sync::Arc};
-use chrono::{NaiveDate, Utc, Datelike};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, instrument};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use crate::config::settings::{
+    BULK_INSERT_ROWS, DB_ACQUIRE_TIMEOUT_SECS, DB_CONNECT_TIMEOUT_SECS, DB_POOL_MAX_CONNECTIONS,
+    DB_POOL_MIN_CONNECTIONS, DB_QUERY_TIMEOUT_SECS, DB_STMT_CACHE_SIZE,
+};
+use crate::services::block_index::IndexedBlock;
+use crate::services::export::{day_bounds, ExportSeries, ExportValue, RowSink};
+use crate::services::publisher::{outbox_enabled, OutboxEvent};
+use crate::services::retention::Resolution;
+use crate::services::storage::{
+    block_ingested, AddressSummary, AddressTx, AggregatesFrom, AlertStateRecord, ApiKeyRecord, BlockRecord,
+    DailyAggregate, DbConn, FeeEstimationRow, MetricRollup, MetricSample, NewWatchDescriptor, OutboxRecord, PoolCounters, Storage, WalletEventRecord,
+    WalletSpend, WalletUtxo, WatchAddress, WatchDescriptor, WatchWallet, WebhookDeliveryRecord, WebhookRecord, WriteBatch,
+    AGGREGATES_CHECKPOINT, BLOCK_COLUMNS,
+};
 
+// MySQL through the synchronous `mysql` driver
 pub struct MySqlService {
     pool: Pool,
+    counters: PoolCounters,
+}
+
+// Insert rows in chunks of BULK_INSERT_ROWS using multi-row REPLACE statements
+fn bulk_replace(
+    tx: &mut Transaction,
+    table: &str,
+    columns: &[&str],
+    rows: Vec<Vec<Value>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let placeholders = format!("({})", vec!["?"; columns.len()].join(", "));
+    for chunk in rows.chunks(BULK_INSERT_ROWS) {
+        let query = format!(
+            "REPLACE INTO {} ({}) VALUES {}",
+            table,
+            columns.join(", "),
+            vec![placeholders.as_str(); chunk.len()].join(", "),
+        );
+        let params: Vec<Value> = chunk.iter().flatten().cloned().collect();
+        tx.exec_drop(query, params)?;
+    }
+    Ok(())
+}
+
+// Like `bulk_replace`, but rows already stored are updated in place, so columns outside `columns`
+// keep their values. `key` is the table's primary key.
+fn bulk_upsert(
+    tx: &mut Transaction,
+    table: &str,
+    columns: &[&str],
+    key: &[&str],
+    rows: Vec<Vec<Value>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let updates: Vec<String> = columns.iter()
+        .filter(|column| !key.contains(column))
+        .map(|column| format!("{0} = VALUES({0})", column))
+        .collect();
+    let placeholders = format!("({})", vec!["?"; columns.len()].join(", "));
+    for chunk in rows.chunks(BULK_INSERT_ROWS) {
+        let query = format!(
+            "INSERT INTO {} ({}) VALUES {} ON DUPLICATE KEY UPDATE {}",
+            table,
+            columns.join(", "),
+            vec![placeholders.as_str(); chunk.len()].join(", "),
+            updates.join(", "),
+        );
+        let params: Vec<Value> = chunk.iter().flatten().cloned().collect();
+        tx.exec_drop(query, params)?;
+    }
+    Ok(())
+}
+
+// Record events in the outbox as part of `tx`, so they are published exactly when the write commits
+fn insert_outbox(tx: &mut Transaction, events: &[OutboxEvent]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !outbox_enabled() || events.is_empty() {
+        return Ok(());
+    }
+    let created_at = Utc::now().timestamp();
+    for chunk in events.chunks(BULK_INSERT_ROWS) {
+        let query = format!(
+            "INSERT INTO event_outbox (event_type, payload, created_at) VALUES {}",
+            vec!["(?, ?, ?)"; chunk.len()].join(", "),
+        );
+        let params: Vec<Value> = chunk.iter()
+            .flat_map(|event| [event.event_type().into(), event.data().to_string().into(), created_at.into()])
+            .collect();
+        tx.exec_drop(query, params)?;
+    }
+    Ok(())
+}
+
+fn block_row(block: &BlockRecord) -> Vec<Value> {
+    vec![
+        block.height.into(), block.hash.clone().into(), block.prev_hash.clone().into(), block.time.into(),
+        block.median_time.into(), block.version.into(), block.bits.clone().into(), block.difficulty.into(),
+        block.nonce.into(), block.size.into(), block.weight.into(), block.tx_count.into(),
+    ]
+}
+
+// Rows for the full index of the given blocks, then spends linked through the outputs' primary
+// key; outputs older than the index simply don't match. Rows are upserted rather than replaced so
+// a block indexed again keeps the spends of its outputs by later blocks. The two BIP30 duplicate
+// coinbases take over the rows of the earlier coinbase with their txid, as they took over its
+// outputs in Core's UTXO set.
+fn write_index_rows(tx: &mut Transaction, indexed: Vec<(u64, IndexedBlock)>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (Some(lowest), Some(highest)) = (
+        indexed.iter().map(|(height, _)| *height).min(),
+        indexed.iter().map(|(height, _)| *height).max(),
+    ) else {
+        return Ok(());
+    };
+    let (mut transactions, mut inputs, mut outputs) = (Vec::new(), Vec::new(), Vec::new());
+    for (_, block) in indexed {
+        transactions.extend(block.transactions.into_iter().map(|row| vec![
+            row.txid.into(), row.block_height.into(), row.block_index.into(), row.version.into(), row.locktime.into(),
+            row.size.into(), row.vsize.into(), row.weight.into(), row.input_count.into(), row.output_count.into(),
+            row.is_coinbase.into(),
+        ]));
+        inputs.extend(block.inputs.into_iter().map(|row| vec![
+            row.txid.into(), row.input_index.into(), row.block_height.into(), row.prev_txid.into(),
+            row.prev_vout.into(), row.sequence.into(), row.witness_size.into(),
+        ]));
+        outputs.extend(block.outputs.into_iter().map(|row| vec![
+            row.txid.into(), row.output_index.into(), row.block_height.into(), row.value.into(),
+            row.script_type.into(), row.address.into(),
+        ]));
+    }
+    bulk_upsert(
+        tx,
+        "transactions",
+        &["txid", "block_height", "block_index", "version", "locktime", "size", "vsize", "weight", "input_count", "output_count", "is_coinbase"],
+        &["txid"],
+        transactions,
+    )?;
+    bulk_upsert(
+        tx,
+        "tx_inputs",
+        &["txid", "input_index", "block_height", "prev_txid", "prev_vout", "sequence", "witness_size"],
+        &["txid", "input_index"],
+        inputs,
+    )?;
+    bulk_upsert(
+        tx,
+        "tx_outputs",
+        &["txid", "output_index", "block_height", "value", "script_type", "address"],
+        &["txid", "output_index"],
+        outputs,
+    )?;
+    tx.exec_drop(
+        r"UPDATE tx_outputs o
+            JOIN tx_inputs i ON i.prev_txid = o.txid AND i.prev_vout = o.output_index
+            SET o.spent_by_txid = i.txid, o.spent_by_index = i.input_index
+            WHERE i.block_height BETWEEN :lowest AND :highest",
+        params! { "lowest" => lowest, "highest" => highest },
+    )?;
+    Ok(())
+}
+
+// Rebuild daily_transactions and seven_day_dma within `tx`. Expects the session time zone to be UTC.
+fn recompute_aggregates_in(
+    tx: &mut Transaction,
+    from: AggregatesFrom,
+    advance_checkpoint: bool,
+) -> Result<Vec<OutboxEvent>, Box<dyn std::error::Error + Send + Sync>> {
+    let from_date: Option<NaiveDate> = match from {
+        AggregatesFrom::Date(date) => date,
+        AggregatesFrom::Height(height) => {
+            let earliest: Option<Option<NaiveDate>> = tx.exec_first(
+                "SELECT DATE(FROM_UNIXTIME(MIN(time))) FROM blocks WHERE height >= :height",
+                params! { "height" => height },
+            )?;
+            match earliest.flatten() {
+                Some(date) => Some(date),
+                None => tx.query_first("SELECT DATE(FROM_UNIXTIME(time)) FROM blocks ORDER BY height DESC LIMIT 1")?,
+            }
+        }
+    };
+    let from_date = from_date.unwrap_or(DateTime::UNIX_EPOCH.date_naive());
+
+    tx.exec_drop(
+        "DELETE FROM daily_transactions WHERE date >= :from_date",
+        params! { "from_date" => from_date },
+    )?;
+    tx.exec_drop(
+        r"INSERT INTO daily_transactions (date, tx_count)
+            SELECT DATE(FROM_UNIXTIME(time)) AS day, SUM(tx_count)
+            FROM blocks
+            WHERE time >= UNIX_TIMESTAMP(:from_date)
+            GROUP BY day",
+        params! { "from_date" => from_date },
+    )?;
+    // Trailing average over the stored days among that day and the six calendar days before it;
+    // earlier days are read so the window is full
+    tx.exec_drop(
+        r"INSERT INTO seven_day_dma (date, dma_value)
+            SELECT date, dma_value FROM (
+                SELECT date, AVG(tx_count) OVER (ORDER BY date RANGE BETWEEN INTERVAL 6 DAY PRECEDING AND CURRENT ROW) AS dma_value
+                FROM daily_transactions
+                WHERE date >= DATE_SUB(:from_date, INTERVAL 6 DAY)
+            ) AS windowed
+            WHERE date >= :from_date
+            ON DUPLICATE KEY UPDATE dma_value = windowed.dma_value",
+        params! { "from_date" => from_date },
+    )?;
+    let mut rollup_events = Vec::new();
+    if outbox_enabled() {
+        let rows: Vec<Row> = tx.exec(
+            r"SELECT d.date, d.tx_count, m.dma_value
+                FROM daily_transactions d
+                JOIN seven_day_dma m ON m.date = d.date
+                WHERE d.date >= :from_date
+                ORDER BY d.date",
+            params! { "from_date" => from_date },
+        )?;
+        for row in rows {
+            let (date, tx_count, dma_value) = from_row_opt(row)?;
+            rollup_events.push(OutboxEvent::DailyTxRollup { date, tx_count, dma_value });
+        }
+    }
+    if advance_checkpoint {
+        tx.exec_drop(
+            r"REPLACE INTO ingestion_checkpoints (name, height, updated_at)
+                SELECT :name, MAX(height), :updated_at FROM blocks HAVING MAX(height) IS NOT NULL",
+            params! {
+                "name" => AGGREGATES_CHECKPOINT,
+                "updated_at" => Utc::now().timestamp(),
+            },
+        )?;
+    }
+    Ok(rollup_events)
+}
+
+// Every transaction paying to or spending from an address, as (txid, height, received, sent)
+const ADDRESS_ACTIVITY: &str = r"
+    SELECT txid, block_height, value AS received, 0 AS sent
+        FROM tx_outputs WHERE address = :address
+    UNION ALL
+    SELECT o.spent_by_txid, t.block_height, 0, o.value
+        FROM tx_outputs o JOIN transactions t ON t.txid = o.spent_by_txid
+        WHERE o.address = :address AND o.spent_by_txid IS NOT NULL";
+
+const WEBHOOK_COLUMNS: &str = "id, url, secret, events, fee_block_target, fee_threshold, created_at";
+
+fn webhook_from_row(row: Row) -> Result<WebhookRecord, Box<dyn std::error::Error + Send + Sync>> {
+    let (id, url, secret, events, fee_block_target, fee_threshold, created_at) = from_row_opt(row)?;
+    Ok(WebhookRecord { id, url, secret, events, fee_block_target, fee_threshold, created_at })
+}
+
+const DELIVERY_COLUMNS: &str =
+    "id, webhook_id, event_type, payload, status, attempts, last_status_code, last_error, created_at, delivered_at";
+
+fn delivery_from_row(row: Row) -> Result<WebhookDeliveryRecord, Box<dyn std::error::Error + Send + Sync>> {
+    let malformed = "Malformed webhook delivery row";
+    Ok(WebhookDeliveryRecord {
+        id: row.get(0).ok_or(malformed)?,
+        webhook_id: row.get(1).ok_or(malformed)?,
+        event_type: row.get(2).ok_or(malformed)?,
+        payload: row.get(3).ok_or(malformed)?,
+        status: row.get(4).ok_or(malformed)?,
+        attempts: row.get(5).ok_or(malformed)?,
+        last_status_code: row.get::<Option<u16>, _>(6).flatten(),
+        last_error: row.get::<Option<String>, _>(7).flatten(),
+        created_at: row.get(8).ok_or(malformed)?,
+        delivered_at: row.get::<Option<i64>, _>(9).flatten(),
+    })
 }
 
 impl MySqlService {
     pub fn new(database_url: &str) -> Arc<Self> {
-        let pool = Pool::new(database_url).expect("Failed to create MySQL pool");
-        Arc::new(Self { pool })
+        Self::connect(database_url).expect("Failed to create MySQL pool")
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "connect"), err(level = "debug"))]
+    pub fn connect(database_url: &str) -> Result<Arc<Self>, Box<dyn std::error::Error + Send + Sync>> {
+        let constraints = PoolConstraints::new(DB_POOL_MIN_CONNECTIONS, DB_POOL_MAX_CONNECTIONS)
+            .ok_or("DB_POOL_MIN_CONNECTIONS is above DB_POOL_MAX_CONNECTIONS")?;
+        let query_timeout = Some(Duration::from_secs(DB_QUERY_TIMEOUT_SECS));
+        let opts = OptsBuilder::from_opts(Opts::from_url(database_url)?)
+            .pool_opts(PoolOpts::default().with_constraints(constraints))
+            .tcp_connect_timeout(Some(Duration::from_secs(DB_CONNECT_TIMEOUT_SECS)))
+            .read_timeout(query_timeout)
+            .write_timeout(query_timeout)
+            .stmt_cache_size(DB_STMT_CACHE_SIZE);
+        let pool = Pool::new(opts)?;
+        Ok(Arc::new(Self { pool, counters: PoolCounters::default() }))
+    }
+
+    // Check out a connection, waiting at most DB_ACQUIRE_TIMEOUT_SECS for one to free up
+    fn conn(&self) -> Result<DbConn<'_, PooledConn>, Box<dyn std::error::Error + Send + Sync>> {
+        let started = Instant::now();
+        let conn = match self.pool.try_get_conn(Duration::from_secs(DB_ACQUIRE_TIMEOUT_SECS)) {
+            Ok(conn) => conn,
+            Err(Error::DriverError(DriverError::Timeout)) => {
+                self.counters.acquire_timeouts.fetch_add(1, Ordering::Relaxed);
+                return Err(format!("No MySQL connection free within {}s", DB_ACQUIRE_TIMEOUT_SECS).into());
+            }
+            Err(e) => {
+                self.counters.acquire_errors.fetch_add(1, Ordering::Relaxed);
+                return Err(e.into());
+            }
+        };
+        Ok(self.counters.checked_out(conn, started.elapsed()))
+    }
+
+    // Check if today's transaction data exists in MySQL
+    #[instrument(name = "mysql", skip_all, fields(operation = "check_today_data"), err(level = "debug"))]
+    pub fn check_today_data(&self) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let today = Utc::now().date_naive();
+
+        let result: Option<NaiveDate> = conn.exec_first(
+            "SELECT date FROM daily_transactions WHERE date = :date",
+            params! {
+                "date" => today,
+            },
+        )?;
+
+        Ok(result.is_some()) // Return true if data exists, false otherwise
+    }
+
+    // Save today's transaction data (update the latest block height data)
+    #[instrument(name = "mysql", skip_all, fields(operation = "save_today_tx"), err(level = "debug"))]
+    pub fn save_today_tx(&self, tx_count: usize) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let today = Utc::now().date_naive();
+
+        conn.exec_drop(
+            r"INSERT INTO daily_transactions (date, tx_count)
+                VALUES (:date, :tx_count)
+                ON DUPLICATE KEY UPDATE tx_count = :tx_count",
+            params! {
+                "date" => today,
+                "tx_count" => tx_count,
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+impl Storage for MySqlService {
+    fn pool_counters(&self) -> &PoolCounters {
+        &self.counters
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "ping"), err(level = "debug"))]
+    fn ping(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.query_drop("SELECT 1")?;
+        Ok(())
     }
 
-    pub fn update_block_height(&self, block_height: u64) -> Result<(), Box<dyn std::error::Error>> {
-        let mut conn = self.pool.get_conn()?;
+    // Tables the original deployment created by hand: the tip, fee estimates and daily aggregates
+    #[instrument(name = "mysql", skip_all, fields(operation = "ensure_core_tables"), err(level = "debug"))]
+    fn ensure_core_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS block_info (
+                block_height BIGINT UNSIGNED PRIMARY KEY
+            )",
+        )?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS fee_estimations (
+                block_target SMALLINT UNSIGNED NOT NULL,
+                fee_rate DOUBLE NOT NULL,
+                estimated_at DATETIME NOT NULL,
+                PRIMARY KEY (block_target, estimated_at)
+            )",
+        )?;
+        // Older deployments stored only the day of the estimate
+        let estimated_at_type: Option<String> = conn.query_first(
+            r"SELECT DATA_TYPE FROM information_schema.COLUMNS
+                WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 'fee_estimations' AND COLUMN_NAME = 'estimated_at'",
+        )?;
+        if estimated_at_type.as_deref() == Some("date") {
+            conn.query_drop("ALTER TABLE fee_estimations MODIFY estimated_at DATETIME NOT NULL")?;
+        }
+        // and kept only the latest estimate per target
+        let key_columns: Option<u64> = conn.query_first(
+            r"SELECT COUNT(*) FROM information_schema.KEY_COLUMN_USAGE
+                WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 'fee_estimations' AND CONSTRAINT_NAME = 'PRIMARY'",
+        )?;
+        if key_columns == Some(1) {
+            conn.query_drop("ALTER TABLE fee_estimations DROP PRIMARY KEY, ADD PRIMARY KEY (block_target, estimated_at)")?;
+        }
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS daily_transactions (
+                date DATE PRIMARY KEY,
+                tx_count BIGINT UNSIGNED NOT NULL
+            )",
+        )?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS seven_day_dma (
+                date DATE PRIMARY KEY,
+                dma_value DOUBLE NOT NULL
+            )",
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "update_block_height"), err(level = "debug"))]
+    fn update_block_height(&self, block_height: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
         let query = r"INSERT INTO block_info (block_height) VALUES (?) 
                       ON DUPLICATE KEY UPDATE block_height = VALUES(block_height)";
         conn.exec_drop(query, (block_height,))?;
         Ok(())
     }
 
-    // Save the daily transaction data (with date) to MySQL
-    pub async fn save_daily_tx(&self, date: NaiveDate, tx_count: usize) -> Result<(), Box<dyn std::error::Error>> {
-        let mut conn = self.pool.get_conn()?;
+    // Fetch the last 7 days of transaction data from the database
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_all_days_tx"), err(level = "debug"))]
+    fn get_all_days_tx(&self) -> Result<Vec<(NaiveDate, usize)>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows: Vec<Row> = conn.query("SELECT date, tx_count FROM daily_transactions ORDER BY date DESC")?;
+        rows.into_iter()
+            .map(|row| Ok(from_row_opt(row)?))
+            .collect()
+    }
 
-        conn.exec_drop(
-            r"INSERT INTO daily_transactions (date, tx_count)
-              VALUES (:date, :tx_count)
-              ON DUPLICATE KEY UPDATE tx_count = :tx_count",
+    // Fetch the newest daily transaction count together with its 7DMA
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_latest_daily_aggregate"), err(level = "debug"))]
+    fn get_latest_daily_aggregate(&self) -> Result<Option<DailyAggregate>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row: Option<Row> = conn.query_first(
+            r"SELECT d.date, d.tx_count, m.dma_value
+                FROM daily_transactions d
+                JOIN seven_day_dma m ON m.date = d.date
+                ORDER BY d.date DESC LIMIT 1",
+        )?;
+        row.map(|row| {
+            let (date, tx_count, dma_value) = from_row_opt(row)?;
+            Ok(DailyAggregate { date, tx_count, dma_value })
+        })
+        .transpose()
+    }
+
+    // Daily counts with their 7DMA, oldest first, optionally limited to an inclusive date range
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_daily_aggregates"), err(level = "debug"))]
+    fn get_daily_aggregates(
+        &self,
+        from_date: Option<NaiveDate>,
+        to_date: Option<NaiveDate>,
+    ) -> Result<Vec<DailyAggregate>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows: Vec<Row> = conn.exec(
+            r"SELECT d.date, d.tx_count, m.dma_value
+                FROM daily_transactions d
+                JOIN seven_day_dma m ON m.date = d.date
+                WHERE (:from_date IS NULL OR d.date >= :from_date)
+                  AND (:to_date IS NULL OR d.date <= :to_date)
+                ORDER BY d.date",
             params! {
-                "date" => date.format("%Y-%m-%d").to_string(),
-                "tx_count" => tx_count,
+                "from_date" => from_date,
+                "to_date" => to_date,
             },
         )?;
+        rows.into_iter()
+            .map(|row| {
+                let (date, tx_count, dma_value) = from_row_opt(row)?;
+                Ok(DailyAggregate { date, tx_count, dma_value })
+            })
+            .collect()
+    }
 
+    /* -------------------- Block operations -------------------- */
+    #[instrument(name = "mysql", skip_all, fields(operation = "ensure_blocks_table"), err(level = "debug"))]
+    fn ensure_blocks_table(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS blocks (
+                height BIGINT UNSIGNED PRIMARY KEY,
+                hash CHAR(64) NOT NULL UNIQUE,
+                prev_hash CHAR(64) NULL,
+                time BIGINT NOT NULL,
+                median_time BIGINT NULL,
+                version INT NOT NULL,
+                bits CHAR(8) NOT NULL,
+                difficulty DOUBLE NOT NULL,
+                nonce INT UNSIGNED NOT NULL,
+                size INT UNSIGNED NOT NULL,
+                weight INT UNSIGNED NOT NULL,
+                tx_count INT UNSIGNED NOT NULL,
+                INDEX idx_blocks_time (time)
+            )",
+        )?;
+        // Progress of work derived from the blocks table, e.g. the highest block in the daily aggregates
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS ingestion_checkpoints (
+                name VARCHAR(64) PRIMARY KEY,
+                height BIGINT UNSIGNED NOT NULL,
+                updated_at BIGINT NOT NULL
+            )",
+        )?;
         Ok(())
     }
 
-    // Fetch the last 7 days of transaction data from MySQL
-    pub async fn get_last_7_days(&self) -> Result<Vec<(NaiveDate, usize)>, Box<dyn std::error::Error>> {
-        let mut conn = self.pool.get_conn()?;
-        // Fetch the data from MySQL as (String, usize) and then parse the date string into NaiveDate
-        let result = conn.query_map(
-            "SELECT date, tx_count FROM daily_transactions ORDER BY date DESC LIMIT 7",
-            |(date_str, tx_count): (String, usize)| {
-                let date = NaiveDate::parse_from_str(&date_str, "%Y-%m-%d").unwrap();
-                (date, tx_count)
+
+    // Write a batch in one transaction: blocks, full index rows, fee estimates, metric samples, the
+    // aggregates rebuild and the outbox events for all of them
+    #[instrument(name = "mysql", skip_all, fields(operation = "write_batch", blocks = batch.block_count()), err(level = "debug"))]
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let WriteBatch { blocks, indexed, fee_estimates, samples, aggregates, advance_checkpoint } = batch;
+        let mut conn = self.conn()?;
+        if aggregates.is_some() {
+            // Block times are unix seconds; bucket them by UTC day
+            conn.query_drop("SET time_zone = '+00:00'")?;
+        }
+        let mut events: Vec<OutboxEvent> = blocks.iter().map(block_ingested).collect();
+
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        bulk_replace(&mut tx, "blocks", &BLOCK_COLUMNS, blocks.iter().map(block_row).collect())?;
+        write_index_rows(&mut tx, indexed)?;
+
+        let estimated_at = Utc::now().naive_utc();
+        bulk_replace(
+            &mut tx,
+            "fee_estimations",
+            &["block_target", "fee_rate", "estimated_at"],
+            fee_estimates.iter()
+                .map(|(block_target, fee_rate)| vec![(*block_target).into(), (*fee_rate).into(), estimated_at.into()])
+                .collect(),
+        )?;
+        events.extend(fee_estimates.iter().map(|&(block_target, fee_rate)| OutboxEvent::FeeEstimate { block_target, fee_rate }));
+        bulk_replace(
+            &mut tx,
+            "metric_samples",
+            &["metric", "sampled_at", "value"],
+            samples.into_iter()
+                .map(|sample| vec![sample.metric.into(), sample.sampled_at.naive_utc().into(), sample.value.into()])
+                .collect(),
+        )?;
+
+        if let Some(from) = aggregates {
+            events.extend(recompute_aggregates_in(&mut tx, from, advance_checkpoint)?);
+        }
+        insert_outbox(&mut tx, &events)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Stored blocks with heights in `from_height..=to_height`, lowest first
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_blocks_in_range"), err(level = "debug"))]
+    fn get_blocks_in_range(&self, from_height: u64, to_height: u64) -> Result<Vec<BlockRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows: Vec<Row> = conn.exec(
+            r"SELECT height, hash, prev_hash, time, median_time, version, bits, difficulty, nonce, size, weight, tx_count
+                FROM blocks WHERE height BETWEEN :from_height AND :to_height ORDER BY height",
+            params! {
+                "from_height" => from_height,
+                "to_height" => to_height,
             },
         )?;
-        Ok(result)
+        rows.into_iter()
+            .map(|row| {
+                let malformed = || "Malformed block row";
+                Ok(BlockRecord {
+                    height: row.get(0).ok_or_else(malformed)?,
+                    hash: row.get(1).ok_or_else(malformed)?,
+                    prev_hash: row.get(2).ok_or_else(malformed)?,
+                    time: row.get(3).ok_or_else(malformed)?,
+                    median_time: row.get(4).ok_or_else(malformed)?,
+                    version: row.get(5).ok_or_else(malformed)?,
+                    bits: row.get(6).ok_or_else(malformed)?,
+                    difficulty: row.get(7).ok_or_else(malformed)?,
+                    nonce: row.get(8).ok_or_else(malformed)?,
+                    size: row.get(9).ok_or_else(malformed)?,
+                    weight: row.get(10).ok_or_else(malformed)?,
+                    tx_count: row.get(11).ok_or_else(malformed)?,
+                })
+            })
+            .collect()
+    }
+
+    // Highest stored block as (height, hash)
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_stored_tip"), err(level = "debug"))]
+    fn get_stored_tip(&self) -> Result<Option<(u64, String)>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let tip: Option<(u64, String)> = conn.query_first("SELECT height, hash FROM blocks ORDER BY height DESC LIMIT 1")?;
+        Ok(tip)
     }
 
-    // Fetch the last 7 days of transaction data from MySQL
-    pub async fn get_all_days_tx(&self) -> Result<Vec<(NaiveDate, usize)>, Box<dyn std::error::Error>> {
-        let mut conn = self.pool.get_conn()?;
-        // Fetch the data from MySQL as (String, usize) and then parse the date string into NaiveDate
-        let result = conn.query_map(
-            "SELECT date, tx_count FROM daily_transactions_test ORDER BY date DESC",
-            |(date_str, tx_count): (String, usize)| {
-                let date = NaiveDate::parse_from_str(&date_str, "%Y-%m-%d").unwrap();
-                (date, tx_count)
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_block_hash_at"), err(level = "debug"))]
+    fn get_block_hash_at(&self, height: u64) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let hash: Option<String> = conn.exec_first(
+            "SELECT hash FROM blocks WHERE height = :height",
+            params! {
+                "height" => height,
             },
         )?;
-        Ok(result)
+        Ok(hash)
     }
 
-    // Check if today's transaction data exists in MySQL
-    pub fn check_today_data(&self) -> Result<bool, Box<dyn std::error::Error>> {
-        let mut conn = self.pool.get_conn()?;
-        let today = Utc::now().naive_utc().date(); // Get today's date
-        let formatted_date = format!("{}-{:02}-{:02}", today.year(), today.month(), today.day());
-        
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_block_time_at"), err(level = "debug"))]
+    fn get_block_time_at(&self, height: u64) -> Result<Option<i64>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let time: Option<i64> = conn.exec_first(
+            "SELECT time FROM blocks WHERE height = :height",
+            params! {
+                "height" => height,
+            },
+        )?;
+        Ok(time)
+    }
 
-        let result: Option<(String,)> = conn.exec_first(
-            "SELECT date FROM daily_transactions WHERE date = :date",
+    // Remove blocks orphaned by a reorg; returns how many were deleted
+    #[instrument(name = "mysql", skip_all, fields(operation = "delete_blocks_above"), err(level = "debug"))]
+    fn delete_blocks_above(&self, height: u64) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        tx.exec_drop(
+            "DELETE FROM blocks WHERE height > :height",
             params! {
-                "date" => formatted_date,
+                "height" => height,
             },
         )?;
+        let orphaned_blocks = tx.affected_rows();
+        if orphaned_blocks > 0 {
+            insert_outbox(&mut tx, &[OutboxEvent::Reorg { fork_height: height, orphaned_blocks }])?;
+        }
+        tx.commit()?;
+        Ok(orphaned_blocks)
+    }
 
-        Ok(result.is_some()) // Return true if data exists, false otherwise
+    // Highest block the daily aggregates were last computed through
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_aggregates_checkpoint"), err(level = "debug"))]
+    fn get_aggregates_checkpoint(&self) -> Result<Option<u64>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let height: Option<u64> = conn.exec_first(
+            "SELECT height FROM ingestion_checkpoints WHERE name = :name",
+            params! { "name" => AGGREGATES_CHECKPOINT },
+        )?;
+        Ok(height)
     }
 
-        // Save today's transaction data (update the latest block height data)
-    pub fn save_today_tx(&self, tx_count: usize) -> Result<(), Box<dyn std::error::Error>> {
-        let mut conn = self.pool.get_conn()?;
-        let today = Utc::now().naive_utc().date(); // Get today's date
 
+    /* -------------------- Off chain data operations -------------------- */
+
+    // Fetch all fee estimation data from the database
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_fee_estimations"), err(level = "debug"))]
+    fn get_fee_estimations(&self) -> Result<Vec<FeeEstimationRow>, Box<dyn std::error::Error + Send + Sync>> {
+        // Get a connection from the pool
+        let mut conn = self.conn()?;
+    
+        let rows: Vec<Row> = conn.query(
+            r"SELECT f.block_target, f.fee_rate, f.estimated_at
+                FROM fee_estimations f
+                JOIN (SELECT block_target, MAX(estimated_at) AS estimated_at FROM fee_estimations GROUP BY block_target) latest
+                  ON latest.block_target = f.block_target AND latest.estimated_at = f.estimated_at
+                ORDER BY f.block_target",
+        )?;
+        rows.into_iter()
+            .map(|row| {
+                // Stored as UTC without a zone
+                let (block_target, fee_rate, estimated_at): (u16, f64, NaiveDateTime) = from_row_opt(row)?;
+                Ok((block_target, fee_rate, estimated_at.and_utc()))
+            })
+            .collect()
+    }
+
+    /* -------------------- Export operations -------------------- */
+    // Rows are read off the connection as they arrive rather than collected first
+    #[instrument(name = "mysql", skip_all, fields(operation = "export_series", series = series.name()), err(level = "debug"))]
+    fn export_series(
+        &self,
+        series: ExportSeries,
+        from_date: Option<NaiveDate>,
+        to_date: Option<NaiveDate>,
+        row: &mut RowSink<'_>,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let (from_time, to_time) = day_bounds(from_date, to_date);
+        let (query, params) = match series {
+            ExportSeries::DailyTx => (
+                r"SELECT d.date, d.tx_count, m.dma_value
+                    FROM daily_transactions d
+                    JOIN seven_day_dma m ON m.date = d.date
+                    WHERE (:from_date IS NULL OR d.date >= :from_date)
+                      AND (:to_date IS NULL OR d.date <= :to_date)
+                    ORDER BY d.date",
+                params! { "from_date" => from_date, "to_date" => to_date },
+            ),
+            ExportSeries::SevenDayDma => (
+                r"SELECT date, dma_value FROM seven_day_dma
+                    WHERE (:from_date IS NULL OR date >= :from_date)
+                      AND (:to_date IS NULL OR date <= :to_date)
+                    ORDER BY date",
+                params! { "from_date" => from_date, "to_date" => to_date },
+            ),
+            ExportSeries::FeeHistory => (
+                r"SELECT estimated_at, block_target, fee_rate FROM fee_estimations
+                    WHERE (:from_time IS NULL OR estimated_at >= :from_time)
+                      AND (:to_time IS NULL OR estimated_at < :to_time)
+                    ORDER BY estimated_at, block_target",
+                params! {
+                    "from_time" => from_time.map(|time| time.naive_utc()),
+                    "to_time" => to_time.map(|time| time.naive_utc()),
+                },
+            ),
+            ExportSeries::BlockStats => (
+                r"SELECT time, height, tx_count, size, weight FROM blocks
+                    WHERE (:from_time IS NULL OR time >= :from_time)
+                      AND (:to_time IS NULL OR time < :to_time)
+                    ORDER BY time, height",
+                params! {
+                    "from_time" => from_time.map(|time| time.timestamp()),
+                    "to_time" => to_time.map(|time| time.timestamp()),
+                },
+            ),
+        };
+
+        let mut count = 0;
+        for result in conn.exec_iter(query, params)? {
+            let result = result?;
+            let values = match series {
+                ExportSeries::DailyTx => {
+                    let (date, tx_count, dma_value): (NaiveDate, i64, f64) = from_row_opt(result)?;
+                    vec![ExportValue::Date(date), ExportValue::Int(tx_count), ExportValue::Float(dma_value)]
+                }
+                ExportSeries::SevenDayDma => {
+                    let (date, dma_value): (NaiveDate, f64) = from_row_opt(result)?;
+                    vec![ExportValue::Date(date), ExportValue::Float(dma_value)]
+                }
+                ExportSeries::FeeHistory => {
+                    let (estimated_at, block_target, fee_rate): (NaiveDateTime, i64, f64) = from_row_opt(result)?;
+                    vec![ExportValue::Timestamp(estimated_at.and_utc()), ExportValue::Int(block_target), ExportValue::Float(fee_rate)]
+                }
+                ExportSeries::BlockStats => {
+                    let (time, height, tx_count, size, weight): (i64, i64, i64, i64, i64) = from_row_opt(result)?;
+                    let time = DateTime::from_timestamp(time, 0).ok_or("Block time out of range")?;
+                    vec![
+                        ExportValue::Timestamp(time), ExportValue::Int(height), ExportValue::Int(tx_count),
+                        ExportValue::Int(size), ExportValue::Int(weight),
+                    ]
+                }
+            };
+            row(&values)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /* -------------------- Full index operations -------------------- */
+    // Base tables only carry the indexes ingestion itself needs; see `build_full_index_secondary_indexes`
+    #[instrument(name = "mysql", skip_all, fields(operation = "ensure_full_index_tables"), err(level = "debug"))]
+    fn ensure_full_index_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS transactions (
+                txid CHAR(64) PRIMARY KEY,
+                block_height BIGINT UNSIGNED NOT NULL,
+                block_index INT UNSIGNED NOT NULL,
+                version INT NOT NULL,
+                locktime INT UNSIGNED NOT NULL,
+                size INT UNSIGNED NOT NULL,
+                vsize INT UNSIGNED NOT NULL,
+                weight INT UNSIGNED NOT NULL,
+                input_count INT UNSIGNED NOT NULL,
+                output_count INT UNSIGNED NOT NULL,
+                is_coinbase BOOLEAN NOT NULL,
+                INDEX idx_transactions_height (block_height)
+            )",
+        )?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS tx_inputs (
+                txid CHAR(64) NOT NULL,
+                input_index INT UNSIGNED NOT NULL,
+                block_height BIGINT UNSIGNED NOT NULL,
+                prev_txid CHAR(64) NULL,
+                prev_vout INT UNSIGNED NULL,
+                sequence INT UNSIGNED NOT NULL,
+                witness_size INT UNSIGNED NOT NULL,
+                PRIMARY KEY (txid, input_index),
+                INDEX idx_tx_inputs_height (block_height)
+            )",
+        )?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS tx_outputs (
+                txid CHAR(64) NOT NULL,
+                output_index INT UNSIGNED NOT NULL,
+                block_height BIGINT UNSIGNED NOT NULL,
+                value BIGINT UNSIGNED NOT NULL,
+                script_type VARCHAR(32) NOT NULL,
+                address VARCHAR(100) NULL,
+                spent_by_txid CHAR(64) NULL,
+                spent_by_index INT UNSIGNED NULL,
+                PRIMARY KEY (txid, output_index),
+                INDEX idx_tx_outputs_height (block_height)
+            )",
+        )?;
+        Ok(())
+    }
+
+    // Indexes for lookups by address and spender; slow to maintain during the initial load,
+    // so they are created on demand with `project-rust index build`
+    #[instrument(name = "mysql", skip_all, fields(operation = "build_full_index_secondary_indexes"), err(level = "debug"))]
+    fn build_full_index_secondary_indexes(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let indexes = [
+            ("tx_outputs", "idx_tx_outputs_address", "address, block_height"),
+            ("tx_outputs", "idx_tx_outputs_spent_by", "spent_by_txid"),
+            ("tx_inputs", "idx_tx_inputs_prevout", "prev_txid, prev_vout"),
+        ];
+        for (table, name, columns) in indexes {
+            let exists: Option<u64> = conn.exec_first(
+                r"SELECT 1 FROM information_schema.statistics
+                    WHERE table_schema = DATABASE() AND table_name = :table AND index_name = :name LIMIT 1",
+                params! {
+                    "table" => table,
+                    "name" => name,
+                },
+            )?;
+            if exists.is_none() {
+                info!(index = name, table, columns, "Creating index");
+                conn.query_drop(format!("CREATE INDEX {} ON {} ({})", name, table, columns))?;
+            }
+        }
+        Ok(())
+    }
+
+
+    // Undo the full index above `height` after a reorg, including spends made by orphaned blocks
+    #[instrument(name = "mysql", skip_all, fields(operation = "delete_index_above"), err(level = "debug"))]
+    fn delete_index_above(&self, height: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        tx.exec_drop(
+            r"UPDATE tx_outputs o
+                JOIN tx_inputs i ON i.prev_txid = o.txid AND i.prev_vout = o.output_index
+                SET o.spent_by_txid = NULL, o.spent_by_index = NULL
+                WHERE i.block_height > :height",
+            params! { "height" => height },
+        )?;
+        for table in ["tx_outputs", "tx_inputs", "transactions"] {
+            tx.exec_drop(
+                format!("DELETE FROM {} WHERE block_height > :height", table),
+                params! { "height" => height },
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /* -------------------- Address operations -------------------- */
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_address_summary"), err(level = "debug"))]
+    fn get_address_summary(&self, address: &str) -> Result<AddressSummary, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row: Option<Row> = conn.exec_first(
+            format!(
+                r"SELECT COALESCE(CAST(SUM(received) - SUM(sent) AS UNSIGNED), 0), COALESCE(CAST(SUM(received) AS UNSIGNED), 0),
+                    COUNT(DISTINCT txid), MIN(block_height), MAX(block_height)
+                  FROM ({}) activity",
+                ADDRESS_ACTIVITY
+            ),
+            params! {
+                "address" => address,
+            },
+        )?;
+        let row = row.ok_or("Address summary query returned no row")?;
+        Ok(AddressSummary {
+            balance: row.get(0).unwrap_or(0),
+            total_received: row.get(1).unwrap_or(0),
+            tx_count: row.get(2).unwrap_or(0),
+            first_seen_height: row.get::<Option<u64>, _>(3).flatten(),
+            last_seen_height: row.get::<Option<u64>, _>(4).flatten(),
+        })
+    }
+
+    // Newest first; `before` is the (height, txid) of the last row of the previous page
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_address_history"), err(level = "debug"))]
+    fn get_address_history(
+        &self,
+        address: &str,
+        before: Option<(u64, String)>,
+        limit: usize,
+    ) -> Result<Vec<AddressTx>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let (before_height, before_txid) = before.unwrap_or((u64::MAX, String::new()));
+        let history = conn.exec_map(
+            format!(
+                r"SELECT txid, block_height, CAST(SUM(received) AS UNSIGNED), CAST(SUM(sent) AS UNSIGNED)
+                  FROM ({}) activity
+                  GROUP BY txid, block_height
+                  HAVING block_height < :before_height OR (block_height = :before_height AND txid < :before_txid)
+                  ORDER BY block_height DESC, txid DESC
+                  LIMIT :limit",
+                ADDRESS_ACTIVITY
+            ),
+            params! {
+                "address" => address,
+                "before_height" => before_height,
+                "before_txid" => before_txid,
+                "limit" => limit,
+            },
+            |(txid, block_height, received, sent): (String, u64, u64, u64)| AddressTx { txid, block_height, received, sent },
+        )?;
+        Ok(history)
+    }
+
+    /* -------------------- Watch-only wallet operations -------------------- */
+    #[instrument(name = "mysql", skip_all, fields(operation = "ensure_watch_tables"), err(level = "debug"))]
+    fn ensure_watch_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS watch_wallets (
+                id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+                name VARCHAR(128) NOT NULL UNIQUE,
+                gap_limit INT UNSIGNED NOT NULL,
+                created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+        )?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS watch_descriptors (
+                id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+                wallet_id BIGINT UNSIGNED NOT NULL,
+                descriptor TEXT NOT NULL,
+                derived_count INT UNSIGNED NOT NULL DEFAULT 0,
+                INDEX idx_watch_descriptors_wallet (wallet_id)
+            )",
+        )?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS watch_addresses (
+                wallet_id BIGINT UNSIGNED NOT NULL,
+                descriptor_id BIGINT UNSIGNED NOT NULL,
+                derivation_index INT UNSIGNED NOT NULL,
+                address VARCHAR(100) NOT NULL,
+                PRIMARY KEY (descriptor_id, derivation_index),
+                INDEX idx_watch_addresses_address (address)
+            )",
+        )?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS wallet_utxos (
+                wallet_id BIGINT UNSIGNED NOT NULL,
+                txid CHAR(64) NOT NULL,
+                vout INT UNSIGNED NOT NULL,
+                address VARCHAR(100) NOT NULL,
+                value BIGINT UNSIGNED NOT NULL,
+                block_height BIGINT UNSIGNED NOT NULL,
+                spent_by_txid CHAR(64) NULL,
+                spent_height BIGINT UNSIGNED NULL,
+                PRIMARY KEY (wallet_id, txid, vout),
+                INDEX idx_wallet_utxos_height (block_height),
+                INDEX idx_wallet_utxos_spent_height (spent_height)
+            )",
+        )?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS wallet_events (
+                id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+                wallet_id BIGINT UNSIGNED NOT NULL,
+                txid CHAR(64) NOT NULL,
+                received BIGINT UNSIGNED NOT NULL,
+                sent BIGINT UNSIGNED NOT NULL,
+                block_height BIGINT UNSIGNED NULL,
+                confirmed BOOLEAN NOT NULL,
+                seen_at BIGINT NOT NULL,
+                UNIQUE KEY uq_wallet_events (wallet_id, txid, confirmed),
+                INDEX idx_wallet_events_height (block_height)
+            )",
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "create_watch_wallet"), err(level = "debug"))]
+    fn create_watch_wallet(
+        &self,
+        name: &str,
+        gap_limit: u32,
+        descriptors: &[NewWatchDescriptor],
+        utxos: &[WalletUtxo],
+    ) -> Result<(u64, Vec<u64>), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        tx.exec_drop(
+            "INSERT INTO watch_wallets (name, gap_limit) VALUES (:name, :gap_limit)",
+            params! {
+                "name" => name,
+                "gap_limit" => gap_limit,
+            },
+        )?;
+        let wallet_id = tx.last_insert_id().ok_or("Wallet insert returned no id")?;
+        let mut descriptor_ids = Vec::with_capacity(descriptors.len());
+        let mut addresses: Vec<Vec<Value>> = Vec::new();
+        for descriptor in descriptors {
+            tx.exec_drop(
+                r"INSERT INTO watch_descriptors (wallet_id, descriptor, derived_count)
+                    VALUES (:wallet_id, :descriptor, :derived_count)",
+                params! {
+                    "wallet_id" => wallet_id,
+                    "descriptor" => &descriptor.descriptor,
+                    "derived_count" => descriptor.addresses.len() as u32,
+                },
+            )?;
+            let descriptor_id = tx.last_insert_id().ok_or("Descriptor insert returned no id")?;
+            descriptor_ids.push(descriptor_id);
+            for (index, address) in descriptor.addresses.iter().enumerate() {
+                addresses.push(vec![wallet_id.into(), descriptor_id.into(), (index as u32).into(), address.clone().into()]);
+            }
+        }
+        bulk_replace(&mut tx, "watch_addresses", &["wallet_id", "descriptor_id", "derivation_index", "address"], addresses)?;
+        bulk_replace(
+            &mut tx,
+            "wallet_utxos",
+            &["wallet_id", "txid", "vout", "address", "value", "block_height"],
+            utxos.iter().map(|utxo| vec![
+                wallet_id.into(), utxo.txid.clone().into(), utxo.vout.into(), utxo.address.clone().into(),
+                utxo.value.into(), utxo.block_height.into(),
+            ]).collect(),
+        )?;
+        tx.commit()?;
+        Ok((wallet_id, descriptor_ids))
+    }
+
+    // Store newly derived addresses and move the descriptor's derived_count past them
+    #[instrument(name = "mysql", skip_all, fields(operation = "save_watch_addresses"), err(level = "debug"))]
+    fn save_watch_addresses(
+        &self,
+        descriptor_id: u64,
+        derived_count: u32,
+        addresses: &[WatchAddress],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        bulk_replace(
+            &mut tx,
+            "watch_addresses",
+            &["wallet_id", "descriptor_id", "derivation_index", "address"],
+            addresses.iter().map(|row| vec![
+                row.wallet_id.into(), row.descriptor_id.into(), row.derivation_index.into(), row.address.clone().into(),
+            ]).collect(),
+        )?;
+        tx.exec_drop(
+            "UPDATE watch_descriptors SET derived_count = GREATEST(derived_count, :derived_count) WHERE id = :id",
+            params! {
+                "derived_count" => derived_count,
+                "id" => descriptor_id,
+            },
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "find_watch_wallet"), err(level = "debug"))]
+    fn find_watch_wallet(&self, name: &str) -> Result<Option<WatchWallet>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row: Option<(u64, String, u32)> = conn.exec_first(
+            "SELECT id, name, gap_limit FROM watch_wallets WHERE name = :name",
+            params! {
+                "name" => name,
+            },
+        )?;
+        Ok(row.map(|(id, name, gap_limit)| WatchWallet { id, name, gap_limit }))
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "list_watch_wallets"), err(level = "debug"))]
+    fn list_watch_wallets(&self) -> Result<Vec<WatchWallet>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let wallets = conn.query_map(
+            "SELECT id, name, gap_limit FROM watch_wallets ORDER BY id",
+            |(id, name, gap_limit): (u64, String, u32)| WatchWallet { id, name, gap_limit },
+        )?;
+        Ok(wallets)
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "list_watch_descriptors"), err(level = "debug"))]
+    fn list_watch_descriptors(&self) -> Result<Vec<WatchDescriptor>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let descriptors = conn.query_map(
+            "SELECT id, wallet_id, descriptor, derived_count FROM watch_descriptors ORDER BY id",
+            |(id, wallet_id, descriptor, derived_count): (u64, u64, String, u32)| {
+                WatchDescriptor { id, wallet_id, descriptor, derived_count }
+            },
+        )?;
+        Ok(descriptors)
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "list_watch_addresses"), err(level = "debug"))]
+    fn list_watch_addresses(&self) -> Result<Vec<WatchAddress>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let addresses = conn.query_map(
+            "SELECT wallet_id, descriptor_id, derivation_index, address FROM watch_addresses",
+            |(wallet_id, descriptor_id, derivation_index, address): (u64, u64, u32, String)| {
+                WatchAddress { wallet_id, descriptor_id, derivation_index, address }
+            },
+        )?;
+        Ok(addresses)
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "list_unspent_wallet_utxos"), err(level = "debug"))]
+    fn list_unspent_wallet_utxos(&self) -> Result<Vec<WalletUtxo>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let utxos = conn.query_map(
+            "SELECT wallet_id, txid, vout, address, value, block_height FROM wallet_utxos WHERE spent_by_txid IS NULL",
+            |(wallet_id, txid, vout, address, value, block_height): (u64, String, u32, String, u64, u64)| {
+                WalletUtxo { wallet_id, txid, vout, address, value, block_height }
+            },
+        )?;
+        Ok(utxos)
+    }
+
+    // Record wallet outputs, spends and events in one database transaction.
+    // Safe to repeat for the same block; returns the events that were not recorded before.
+    #[instrument(name = "mysql", skip_all, fields(operation = "save_wallet_activity"), err(level = "debug"))]
+    fn save_wallet_activity(
+        &self,
+        utxos: &[WalletUtxo],
+        spends: &[WalletSpend],
+        events: Vec<WalletEventRecord>,
+    ) -> Result<Vec<WalletEventRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+
+        // Outputs first, so spends of outputs created in the same block find their row.
+        // IGNORE rather than REPLACE keeps the spend of an output recorded by an earlier attempt.
+        for utxo in utxos {
+            tx.exec_drop(
+                r"INSERT IGNORE INTO wallet_utxos (wallet_id, txid, vout, address, value, block_height)
+                    VALUES (:wallet_id, :txid, :vout, :address, :value, :block_height)",
+                params! {
+                    "wallet_id" => utxo.wallet_id,
+                    "txid" => &utxo.txid,
+                    "vout" => utxo.vout,
+                    "address" => &utxo.address,
+                    "value" => utxo.value,
+                    "block_height" => utxo.block_height,
+                },
+            )?;
+        }
+        for spend in spends {
+            tx.exec_drop(
+                r"UPDATE wallet_utxos SET spent_by_txid = :spent_by_txid, spent_height = :spent_height
+                    WHERE wallet_id = :wallet_id AND txid = :txid AND vout = :vout",
+                params! {
+                    "spent_by_txid" => &spend.spent_by_txid,
+                    "spent_height" => spend.spent_height,
+                    "wallet_id" => spend.wallet_id,
+                    "txid" => &spend.txid,
+                    "vout" => spend.vout,
+                },
+            )?;
+        }
+
+        let mut recorded = Vec::new();
+        for event in events {
+            tx.exec_drop(
+                r"INSERT IGNORE INTO wallet_events (wallet_id, txid, received, sent, block_height, confirmed, seen_at)
+                    VALUES (:wallet_id, :txid, :received, :sent, :block_height, :confirmed, :seen_at)",
+                params! {
+                    "wallet_id" => event.wallet_id,
+                    "txid" => &event.txid,
+                    "received" => event.received,
+                    "sent" => event.sent,
+                    "block_height" => event.block_height,
+                    "confirmed" => event.block_height.is_some(),
+                    "seen_at" => event.seen_at,
+                },
+            )?;
+            if tx.affected_rows() > 0 {
+                recorded.push(event);
+            }
+        }
+
+        tx.commit()?;
+        Ok(recorded)
+    }
+
+    // Undo wallet activity from blocks above `height` after a reorg
+    #[instrument(name = "mysql", skip_all, fields(operation = "delete_wallet_activity_above"), err(level = "debug"))]
+    fn delete_wallet_activity_above(&self, height: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        tx.exec_drop(
+            "UPDATE wallet_utxos SET spent_by_txid = NULL, spent_height = NULL WHERE spent_height > :height",
+            params! { "height" => height },
+        )?;
+        tx.exec_drop(
+            "DELETE FROM wallet_utxos WHERE block_height > :height",
+            params! { "height" => height },
+        )?;
+        tx.exec_drop(
+            "DELETE FROM wallet_events WHERE block_height > :height",
+            params! { "height" => height },
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    // Confirmed balance and number of unspent outputs of a wallet
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_wallet_balance"), err(level = "debug"))]
+    fn get_wallet_balance(&self, wallet_id: u64) -> Result<(u64, u64), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let balance: Option<(u64, u64)> = conn.exec_first(
+            r"SELECT COALESCE(CAST(SUM(value) AS UNSIGNED), 0), COUNT(*)
+                FROM wallet_utxos WHERE wallet_id = :wallet_id AND spent_by_txid IS NULL",
+            params! {
+                "wallet_id" => wallet_id,
+            },
+        )?;
+        Ok(balance.unwrap_or((0, 0)))
+    }
+
+    // Most recent events first
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_wallet_events"), err(level = "debug"))]
+    fn get_wallet_events(&self, wallet_id: u64, limit: usize) -> Result<Vec<WalletEventRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let events = conn.exec_map(
+            r"SELECT wallet_id, txid, received, sent, block_height, seen_at FROM wallet_events
+                WHERE wallet_id = :wallet_id ORDER BY id DESC LIMIT :limit",
+            params! {
+                "wallet_id" => wallet_id,
+                "limit" => limit,
+            },
+            |(wallet_id, txid, received, sent, block_height, seen_at): (u64, String, u64, u64, Option<u64>, i64)| {
+                WalletEventRecord { wallet_id, txid, received, sent, block_height, seen_at }
+            },
+        )?;
+        Ok(events)
+    }
+
+    /* -------------------- Webhook operations -------------------- */
+    #[instrument(name = "mysql", skip_all, fields(operation = "ensure_webhook_tables"), err(level = "debug"))]
+    fn ensure_webhook_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS webhooks (
+                id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+                url VARCHAR(2048) NOT NULL,
+                secret CHAR(64) NOT NULL,
+                events VARCHAR(255) NOT NULL,
+                fee_block_target SMALLINT UNSIGNED NULL,
+                fee_threshold DOUBLE NULL,
+                created_at BIGINT NOT NULL
+            )",
+        )?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS webhook_deliveries (
+                id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+                webhook_id BIGINT UNSIGNED NOT NULL,
+                event_type VARCHAR(32) NOT NULL,
+                payload MEDIUMTEXT NOT NULL,
+                status VARCHAR(16) NOT NULL,
+                attempts INT UNSIGNED NOT NULL DEFAULT 0,
+                last_status_code SMALLINT UNSIGNED NULL,
+                last_error TEXT NULL,
+                created_at BIGINT NOT NULL,
+                delivered_at BIGINT NULL,
+                INDEX idx_webhook_deliveries_webhook (webhook_id, id),
+                INDEX idx_webhook_deliveries_status (status)
+            )",
+        )?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS webhook_fee_state (
+                webhook_id BIGINT UNSIGNED PRIMARY KEY,
+                above BOOLEAN NOT NULL
+            )",
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "create_webhook"), err(level = "debug"))]
+    fn create_webhook(&self, webhook: &WebhookRecord) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
         conn.exec_drop(
-            r"INSERT INTO daily_transactions (date, tx_count)
-                VALUES (:date, :tx_count)
-                ON DUPLICATE KEY UPDATE tx_count = :tx_count",
+            r"INSERT INTO webhooks (url, secret, events, fee_block_target, fee_threshold, created_at)
+                VALUES (:url, :secret, :events, :fee_block_target, :fee_threshold, :created_at)",
             params! {
-                "date" => today.format("%Y-%m-%d").to_string(),
-                "tx_count" => tx_count,
+                "url" => &webhook.url,
+                "secret" => &webhook.secret,
+                "events" => &webhook.events,
+                "fee_block_target" => webhook.fee_block_target,
+                "fee_threshold" => webhook.fee_threshold,
+                "created_at" => webhook.created_at,
+            },
+        )?;
+        Ok(conn.last_insert_id())
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "list_webhooks"), err(level = "debug"))]
+    fn list_webhooks(&self) -> Result<Vec<WebhookRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows: Vec<Row> = conn.query(format!("SELECT {} FROM webhooks ORDER BY id", WEBHOOK_COLUMNS))?;
+        rows.into_iter().map(webhook_from_row).collect()
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "find_webhook"), err(level = "debug"))]
+    fn find_webhook(&self, id: u64) -> Result<Option<WebhookRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row: Option<Row> = conn.exec_first(
+            format!("SELECT {} FROM webhooks WHERE id = :id", WEBHOOK_COLUMNS),
+            params! {
+                "id" => id,
+            },
+        )?;
+        row.map(webhook_from_row).transpose()
+    }
+
+    // Returns false if no webhook with that id exists; its delivery log is kept
+    #[instrument(name = "mysql", skip_all, fields(operation = "delete_webhook"), err(level = "debug"))]
+    fn delete_webhook(&self, id: u64) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        tx.exec_drop(
+            "DELETE FROM webhooks WHERE id = :id",
+            params! {
+                "id" => id,
+            },
+        )?;
+        let deleted = tx.affected_rows() > 0;
+        tx.exec_drop(
+            "DELETE FROM webhook_fee_state WHERE webhook_id = :id",
+            params! {
+                "id" => id,
+            },
+        )?;
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "swap_webhook_fee_above"), err(level = "debug"))]
+    fn swap_webhook_fee_above(&self, webhook_id: u64, above: bool) -> Result<Option<bool>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        let previous: Option<bool> = tx.exec_first(
+            "SELECT above FROM webhook_fee_state WHERE webhook_id = :webhook_id FOR UPDATE",
+            params! {
+                "webhook_id" => webhook_id,
+            },
+        )?;
+        tx.exec_drop(
+            r"INSERT INTO webhook_fee_state (webhook_id, above) VALUES (:webhook_id, :above)
+                ON DUPLICATE KEY UPDATE above = VALUES(above)",
+            params! {
+                "webhook_id" => webhook_id,
+                "above" => above,
+            },
+        )?;
+        tx.commit()?;
+        Ok(previous)
+    }
+
+    // Log a new pending delivery and return its id
+    #[instrument(name = "mysql", skip_all, fields(operation = "create_webhook_delivery"), err(level = "debug"))]
+    fn create_webhook_delivery(
+        &self,
+        webhook_id: u64,
+        event_type: &str,
+        payload: &str,
+        created_at: i64,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.exec_drop(
+            r"INSERT INTO webhook_deliveries (webhook_id, event_type, payload, status, created_at)
+                VALUES (:webhook_id, :event_type, :payload, 'pending', :created_at)",
+            params! {
+                "webhook_id" => webhook_id,
+                "event_type" => event_type,
+                "payload" => payload,
+                "created_at" => created_at,
+            },
+        )?;
+        Ok(conn.last_insert_id())
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "record_webhook_attempt"), err(level = "debug"))]
+    fn record_webhook_attempt(
+        &self,
+        delivery_id: u64,
+        status: &str,
+        attempts: u32,
+        last_status_code: Option<u16>,
+        last_error: Option<&str>,
+        delivered_at: Option<i64>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.exec_drop(
+            r"UPDATE webhook_deliveries
+                SET status = :status, attempts = :attempts, last_status_code = :last_status_code,
+                    last_error = :last_error, delivered_at = :delivered_at
+                WHERE id = :id",
+            params! {
+                "status" => status,
+                "attempts" => attempts,
+                "last_status_code" => last_status_code,
+                "last_error" => last_error,
+                "delivered_at" => delivered_at,
+                "id" => delivery_id,
+            },
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "find_webhook_delivery"), err(level = "debug"))]
+    fn find_webhook_delivery(&self, id: u64) -> Result<Option<WebhookDeliveryRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row: Option<Row> = conn.exec_first(
+            format!("SELECT {} FROM webhook_deliveries WHERE id = :id", DELIVERY_COLUMNS),
+            params! {
+                "id" => id,
             },
         )?;
+        row.map(delivery_from_row).transpose()
+    }
+
+    // Newest first
+    #[instrument(name = "mysql", skip_all, fields(operation = "list_webhook_deliveries"), err(level = "debug"))]
+    fn list_webhook_deliveries(&self, webhook_id: u64, limit: usize) -> Result<Vec<WebhookDeliveryRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows: Vec<Row> = conn.exec(
+            format!(
+                "SELECT {} FROM webhook_deliveries WHERE webhook_id = :webhook_id ORDER BY id DESC LIMIT :limit",
+                DELIVERY_COLUMNS
+            ),
+            params! {
+                "webhook_id" => webhook_id,
+                "limit" => limit,
+            },
+        )?;
+        rows.into_iter().map(delivery_from_row).collect()
+    }
+
+    // Deliveries interrupted by a restart, oldest first
+    #[instrument(name = "mysql", skip_all, fields(operation = "list_pending_webhook_deliveries"), err(level = "debug"))]
+    fn list_pending_webhook_deliveries(&self) -> Result<Vec<WebhookDeliveryRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows: Vec<Row> = conn.query(format!(
+            "SELECT {} FROM webhook_deliveries WHERE status = 'pending' ORDER BY id",
+            DELIVERY_COLUMNS
+        ))?;
+        rows.into_iter().map(delivery_from_row).collect()
+    }
 
+    /* -------------------- Event outbox operations -------------------- */
+    #[instrument(name = "mysql", skip_all, fields(operation = "ensure_outbox_table"), err(level = "debug"))]
+    fn ensure_outbox_table(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS event_outbox (
+                id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+                event_type VARCHAR(64) NOT NULL,
+                payload TEXT NOT NULL,
+                created_at BIGINT NOT NULL,
+                published_at BIGINT NULL,
+                INDEX idx_event_outbox_published (published_at, id)
+            )",
+        )?;
+        Ok(())
+    }
+
+    // Oldest events not yet published
+    #[instrument(name = "mysql", skip_all, fields(operation = "list_unpublished_outbox"), err(level = "debug"))]
+    fn list_unpublished_outbox(&self, limit: usize) -> Result<Vec<OutboxRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let records = conn.exec_map(
+            r"SELECT id, event_type, payload, created_at FROM event_outbox
+                WHERE published_at IS NULL
+                ORDER BY id LIMIT :limit",
+            params! { "limit" => limit },
+            |(id, event_type, payload, created_at)| OutboxRecord { id, event_type, payload, created_at },
+        )?;
+        Ok(records)
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "mark_outbox_published"), err(level = "debug"))]
+    fn mark_outbox_published(&self, ids: &[u64]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn()?;
+        let query = format!(
+            "UPDATE event_outbox SET published_at = ? WHERE id IN ({})",
+            vec!["?"; ids.len()].join(", "),
+        );
+        let mut params: Vec<Value> = vec![Utc::now().timestamp().into()];
+        params.extend(ids.iter().map(|id| Value::from(*id)));
+        conn.exec_drop(query, params)?;
         Ok(())
     }
 
-        // Save the calculated 7DMA data for a specific date
-    pub async fn save_7dma(&self, date: NaiveDate, dma_value: f64) -> Result<(), Box<dyn std::error::Error>> {
-        let mut conn = self.pool.get_conn()?;
+    // Delete events published before `published_before` (unix seconds); returns how many were deleted
+    #[instrument(name = "mysql", skip_all, fields(operation = "prune_published_outbox"), err(level = "debug"))]
+    fn prune_published_outbox(&self, published_before: i64) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
         conn.exec_drop(
-            r"INSERT INTO seven_day_dma (date, dma_value)
-                VALUES (:date, :dma_value)
-                ON DUPLICATE KEY UPDATE dma_value = :dma_value",
+            "DELETE FROM event_outbox WHERE published_at IS NOT NULL AND published_at < :published_before",
+            params! { "published_before" => published_before },
+        )?;
+        Ok(conn.affected_rows())
+    }
+
+    /* -------------------- Metric history operations -------------------- */
+    #[instrument(name = "mysql", skip_all, fields(operation = "ensure_metric_tables"), err(level = "debug"))]
+    fn ensure_metric_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS metric_samples (
+                metric VARCHAR(64) NOT NULL,
+                sampled_at DATETIME NOT NULL,
+                value DOUBLE NOT NULL,
+                PRIMARY KEY (metric, sampled_at),
+                INDEX idx_metric_samples_time (sampled_at)
+            )",
+        )?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS metric_rollups (
+                metric VARCHAR(64) NOT NULL,
+                resolution VARCHAR(8) NOT NULL,
+                bucket_start DATETIME NOT NULL,
+                samples BIGINT UNSIGNED NOT NULL,
+                min_value DOUBLE NOT NULL,
+                max_value DOUBLE NOT NULL,
+                avg_value DOUBLE NOT NULL,
+                p50_value DOUBLE NOT NULL,
+                p90_value DOUBLE NOT NULL,
+                p99_value DOUBLE NOT NULL,
+                PRIMARY KEY (metric, resolution, bucket_start),
+                INDEX idx_metric_rollups_time (resolution, bucket_start)
+            )",
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_metric_samples"), err(level = "debug"))]
+    fn get_metric_samples(
+        &self,
+        metric: Option<&str>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<MetricSample>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows: Vec<(String, NaiveDateTime, f64)> = conn.exec(
+            r"SELECT metric, sampled_at, value FROM metric_samples
+                WHERE (:metric IS NULL OR metric = :metric) AND sampled_at >= :from_time AND sampled_at < :to_time
+                ORDER BY metric, sampled_at",
             params! {
-                "date" => date.format("%Y-%m-%d").to_string(),
-                "dma_value" => dma_value,
+                "metric" => metric,
+                "from_time" => from.naive_utc(),
+                "to_time" => to.naive_utc(),
             },
         )?;
+        Ok(rows.into_iter()
+            .map(|(metric, sampled_at, value)| MetricSample { metric, sampled_at: sampled_at.and_utc(), value })
+            .collect())
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_earliest_metric_sample"), err(level = "debug"))]
+    fn get_earliest_metric_sample(&self) -> Result<Option<DateTime<Utc>>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let earliest: Option<Option<NaiveDateTime>> = conn.query_first("SELECT MIN(sampled_at) FROM metric_samples")?;
+        Ok(earliest.flatten().map(|time| time.and_utc()))
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "delete_metric_samples_before"), err(level = "debug"))]
+    fn delete_metric_samples_before(&self, before: DateTime<Utc>) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.exec_drop(
+            "DELETE FROM metric_samples WHERE sampled_at < :before",
+            params! { "before" => before.naive_utc() },
+        )?;
+        Ok(conn.affected_rows())
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "save_metric_rollups"), err(level = "debug"))]
+    fn save_metric_rollups(&self, rollups: &[MetricRollup]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if rollups.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        bulk_replace(
+            &mut tx,
+            "metric_rollups",
+            &[
+                "metric", "resolution", "bucket_start", "samples", "min_value", "max_value", "avg_value",
+                "p50_value", "p90_value", "p99_value",
+            ],
+            rollups.iter()
+                .map(|rollup| vec![
+                    rollup.metric.clone().into(), rollup.resolution.name().into(), rollup.bucket_start.naive_utc().into(),
+                    rollup.samples.into(), rollup.min.into(), rollup.max.into(), rollup.avg.into(),
+                    rollup.p50.into(), rollup.p90.into(), rollup.p99.into(),
+                ])
+                .collect(),
+        )?;
+        tx.commit()?;
         Ok(())
     }
 
-    /* -------------------- Off chain data operations -------------------- */
-    // Save fee estimation data into MySQL
-    pub async fn save_fee_estimation(&self, block_target: u16, fee_rate: f64) -> Result<(), Box<dyn std::error::Error>> {
-        let mut conn = self.pool.get_conn()?;
-        let estimated_at = Utc::now().naive_utc().format("%Y-%m-%d").to_string();
-        
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_metric_rollups"), err(level = "debug"))]
+    fn get_metric_rollups(
+        &self,
+        metric: &str,
+        resolution: Resolution,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<MetricRollup>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows: Vec<Row> = conn.exec(
+            r"SELECT bucket_start, samples, min_value, max_value, avg_value, p50_value, p90_value, p99_value
+                FROM metric_rollups
+                WHERE metric = :metric AND resolution = :resolution
+                  AND bucket_start >= :from_time AND bucket_start < :to_time
+                ORDER BY bucket_start",
+            params! {
+                "metric" => metric,
+                "resolution" => resolution.name(),
+                "from_time" => from.naive_utc(),
+                "to_time" => to.naive_utc(),
+            },
+        )?;
+        rows.into_iter()
+            .map(|row| {
+                let malformed = || "Malformed metric rollup row";
+                Ok(MetricRollup {
+                    metric: metric.to_string(),
+                    resolution,
+                    bucket_start: row.get::<NaiveDateTime, _>(0).ok_or_else(malformed)?.and_utc(),
+                    samples: row.get(1).ok_or_else(malformed)?,
+                    min: row.get(2).ok_or_else(malformed)?,
+                    max: row.get(3).ok_or_else(malformed)?,
+                    avg: row.get(4).ok_or_else(malformed)?,
+                    p50: row.get(5).ok_or_else(malformed)?,
+                    p90: row.get(6).ok_or_else(malformed)?,
+                    p99: row.get(7).ok_or_else(malformed)?,
+                })
+            })
+            .collect()
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "get_latest_metric_rollup"), err(level = "debug"))]
+    fn get_latest_metric_rollup(&self, resolution: Resolution) -> Result<Option<DateTime<Utc>>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let latest: Option<Option<NaiveDateTime>> = conn.exec_first(
+            "SELECT MAX(bucket_start) FROM metric_rollups WHERE resolution = :resolution",
+            params! { "resolution" => resolution.name() },
+        )?;
+        Ok(latest.flatten().map(|time| time.and_utc()))
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "delete_metric_rollups_before"), err(level = "debug"))]
+    fn delete_metric_rollups_before(
+        &self,
+        resolution: Resolution,
+        before: DateTime<Utc>,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
         conn.exec_drop(
-            r"INSERT INTO fee_estimations (block_target, fee_rate, estimated_at) 
-                VALUES (:block_target, :fee_rate, :estimated_at)
-                ON DUPLICATE KEY UPDATE fee_rate = :fee_rate, estimated_at = :estimated_at",
+            "DELETE FROM metric_rollups WHERE resolution = :resolution AND bucket_start < :before",
             params! {
-                "block_target" => block_target,
-                "fee_rate" => fee_rate,
-                "estimated_at" => estimated_at,
+                "resolution" => resolution.name(),
+                "before" => before.naive_utc(),
             },
         )?;
-        
+        Ok(conn.affected_rows())
+    }
+
+    /* -------------------- Alert operations -------------------- */
+    #[instrument(name = "mysql", skip_all, fields(operation = "ensure_alert_tables"), err(level = "debug"))]
+    fn ensure_alert_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS alert_states (
+                rule_name VARCHAR(128) PRIMARY KEY,
+                status VARCHAR(16) NOT NULL,
+                value DOUBLE NULL,
+                pending_since BIGINT NULL,
+                fired_at BIGINT NULL,
+                resolved_at BIGINT NULL,
+                updated_at BIGINT NULL
+            )",
+        )?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS alert_history (
+                id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+                rule_name VARCHAR(128) NOT NULL,
+                status VARCHAR(16) NOT NULL,
+                value DOUBLE NULL,
+                changed_at BIGINT NULL,
+                INDEX idx_alert_history_rule (rule_name, id)
+            )",
+        )?;
         Ok(())
     }
 
-    // Fetch all fee estimation data from MySQL
-    pub async fn get_fee_estimations(&self) -> Result<Vec<(u16, f64, String)>, Box<dyn std::error::Error>> {
-        // Get a connection from the pool
-        let mut conn = self.pool.get_conn()?;
-    
-        // Execute the query asynchronously and await the result
-        let fee_estimations = conn.query_map(
-            "SELECT block_target, fee_rate, estimated_at FROM fee_estimations",
-            |(block_target, fee_rate, estimated_at): (u16, f64, String)| {
-                (block_target, fee_rate, estimated_at)
+    #[instrument(name = "mysql", skip_all, fields(operation = "list_alert_states"), err(level = "debug"))]
+    fn list_alert_states(&self) -> Result<Vec<AlertStateRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows: Vec<Row> = conn.query(
+            "SELECT rule_name, status, value, pending_since, fired_at, resolved_at, updated_at FROM alert_states",
+        )?;
+        rows.into_iter()
+            .map(|row| {
+                Ok(AlertStateRecord {
+                    rule_name: row.get(0).ok_or("Malformed alert state row")?,
+                    status: row.get(1).ok_or("Malformed alert state row")?,
+                    value: row.get::<Option<f64>, _>(2).flatten(),
+                    pending_since: row.get::<Option<i64>, _>(3).flatten(),
+                    fired_at: row.get::<Option<i64>, _>(4).flatten(),
+                    resolved_at: row.get::<Option<i64>, _>(5).flatten(),
+                    updated_at: row.get::<Option<i64>, _>(6).flatten(),
+                })
+            })
+            .collect()
+    }
+
+    // Store a rule's latest state, and log it in alert_history when the status changed
+    #[instrument(name = "mysql", skip_all, fields(operation = "save_alert_state"), err(level = "debug"))]
+    fn save_alert_state(&self, state: &AlertStateRecord, status_changed: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.start_transaction(TxOpts::default())?;
+        tx.exec_drop(
+            r"REPLACE INTO alert_states (rule_name, status, value, pending_since, fired_at, resolved_at, updated_at)
+                VALUES (:rule_name, :status, :value, :pending_since, :fired_at, :resolved_at, :updated_at)",
+            params! {
+                "rule_name" => &state.rule_name,
+                "status" => &state.status,
+                "value" => state.value,
+                "pending_since" => state.pending_since,
+                "fired_at" => state.fired_at,
+                "resolved_at" => state.resolved_at,
+                "updated_at" => state.updated_at,
             },
         )?;
-    
-        // Return the result
-        Ok(fee_estimations)
+        if status_changed {
+            tx.exec_drop(
+                r"INSERT INTO alert_history (rule_name, status, value, changed_at)
+                    VALUES (:rule_name, :status, :value, :changed_at)",
+                params! {
+                    "rule_name" => &state.rule_name,
+                    "status" => &state.status,
+                    "value" => state.value,
+                    "changed_at" => state.updated_at,
+                },
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
     }
 
+    /* -------------------- API key operations -------------------- */
+    #[instrument(name = "mysql", skip_all, fields(operation = "ensure_api_keys_table"), err(level = "debug"))]
+    fn ensure_api_keys_table(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.query_drop(
+            r"CREATE TABLE IF NOT EXISTS api_keys (
+                id BIGINT UNSIGNED AUTO_INCREMENT PRIMARY KEY,
+                name VARCHAR(128) NOT NULL UNIQUE,
+                key_hash CHAR(64) NOT NULL UNIQUE,
+                rate_limit_per_minute INT UNSIGNED NULL,
+                revoked BOOLEAN NOT NULL DEFAULT FALSE,
+                created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+        )?;
+        Ok(())
+    }
 
+    // Store a new key; only the SHA-256 hash of the key is ever persisted
+    #[instrument(name = "mysql", skip_all, fields(operation = "create_api_key"), err(level = "debug"))]
+    fn create_api_key(&self, name: &str, key_hash: &str, rate_limit_per_minute: Option<u32>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.exec_drop(
+            r"INSERT INTO api_keys (name, key_hash, rate_limit_per_minute)
+                VALUES (:name, :key_hash, :rate_limit_per_minute)",
+            params! {
+                "name" => name,
+                "key_hash" => key_hash,
+                "rate_limit_per_minute" => rate_limit_per_minute,
+            },
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "find_api_key"), err(level = "debug"))]
+    fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row: Option<(u64, String, Option<u32>, bool)> = conn.exec_first(
+            "SELECT id, name, rate_limit_per_minute, revoked FROM api_keys WHERE key_hash = :key_hash",
+            params! {
+                "key_hash" => key_hash,
+            },
+        )?;
+        Ok(row.map(|(id, name, rate_limit_per_minute, revoked)| ApiKeyRecord { id, name, rate_limit_per_minute, revoked }))
+    }
+
+    #[instrument(name = "mysql", skip_all, fields(operation = "list_api_keys"), err(level = "debug"))]
+    fn list_api_keys(&self) -> Result<Vec<ApiKeyRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let keys = conn.query_map(
+            "SELECT id, name, rate_limit_per_minute, revoked FROM api_keys ORDER BY id",
+            |(id, name, rate_limit_per_minute, revoked): (u64, String, Option<u32>, bool)| {
+                ApiKeyRecord { id, name, rate_limit_per_minute, revoked }
+            },
+        )?;
+        Ok(keys)
+    }
+
+    // Returns false if no key with that name exists
+    #[instrument(name = "mysql", skip_all, fields(operation = "revoke_api_key"), err(level = "debug"))]
+    fn revoke_api_key(&self, name: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.exec_drop(
+            "UPDATE api_keys SET revoked = TRUE WHERE name = :name",
+            params! {
+                "name" => name,
+            },
+        )?;
+        // affected_rows skips keys that were already revoked, so look the name up instead
+        let found: Option<u64> = conn.exec_first(
+            "SELECT id FROM api_keys WHERE name = :name",
+            params! {
+                "name" => name,
+            },
+        )?;
+        Ok(found.is_some())
+    }
 }
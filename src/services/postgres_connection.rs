@@ -0,0 +1,1680 @@
+use postgres::types::ToSql;
+use postgres::{Config, NoTls, Row, Transaction};
+use r2d2::{Pool, PooledConnection};
+use r2d2_postgres::PostgresConnectionManager;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use tracing::{info, instrument};
+use crate::config::settings::{
+    BULK_INSERT_ROWS, DB_ACQUIRE_TIMEOUT_SECS, EXPORT_FETCH_ROWS, DB_CONNECT_TIMEOUT_SECS, DB_POOL_MAX_CONNECTIONS,
+    DB_POOL_MIN_CONNECTIONS, DB_QUERY_TIMEOUT_SECS, TIMESCALEDB_ENABLED,
+};
+use crate::services::block_index::IndexedBlock;
+use crate::services::export::{day_bounds, ExportSeries, ExportValue, RowSink};
+use crate::services::publisher::{outbox_enabled, OutboxEvent};
+use crate::services::retention::Resolution;
+use crate::services::storage::{
+    block_ingested, AddressSummary, AddressTx, AggregatesFrom, AlertStateRecord, ApiKeyRecord, BlockRecord,
+    DailyAggregate, DbConn, FeeEstimationRow, MetricRollup, MetricSample, NewWatchDescriptor, OutboxRecord, PoolCounters, Storage, WalletEventRecord,
+    WalletSpend, WalletUtxo, WatchAddress, WatchDescriptor, WatchWallet, WebhookDeliveryRecord, WebhookRecord, WriteBatch,
+    AGGREGATES_CHECKPOINT, BLOCK_COLUMNS,
+};
+
+type Manager = PostgresConnectionManager<NoTls>;
+type Param = Box<dyn ToSql + Sync>;
+
+// PostgreSQL through the synchronous `postgres` driver, optionally with TimescaleDB.
+// Unsigned values are stored in signed columns one size up, since Postgres has no unsigned types.
+pub struct PostgresService {
+    pool: Pool<Manager>,
+    counters: PoolCounters,
+}
+
+fn u64_at(row: &Row, index: usize) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(u64::try_from(row.try_get::<_, i64>(index)?)?)
+}
+
+fn opt_u64_at(row: &Row, index: usize) -> Result<Option<u64>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(row.try_get::<_, Option<i64>>(index)?.map(u64::try_from).transpose()?)
+}
+
+fn u32_at(row: &Row, index: usize) -> Result<u32, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(u32::try_from(row.try_get::<_, i64>(index)?)?)
+}
+
+fn opt_u32_at(row: &Row, index: usize) -> Result<Option<u32>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(row.try_get::<_, Option<i64>>(index)?.map(u32::try_from).transpose()?)
+}
+
+fn opt_u16_at(row: &Row, index: usize) -> Result<Option<u16>, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(row.try_get::<_, Option<i32>>(index)?.map(u16::try_from).transpose()?)
+}
+
+fn as_params(params: &[Param]) -> Vec<&(dyn ToSql + Sync)> {
+    params.iter().map(|param| param.as_ref()).collect()
+}
+
+// Insert rows in chunks of BULK_INSERT_ROWS using multi-row statements, replacing rows whose
+// `key` already exists. A chunk must not contain the same key twice.
+fn bulk_upsert(
+    tx: &mut Transaction,
+    table: &str,
+    columns: &[&str],
+    key: &[&str],
+    rows: Vec<Vec<Param>>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let updates: Vec<String> = columns.iter()
+        .filter(|column| !key.contains(column))
+        .map(|column| format!("{0} = EXCLUDED.{0}", column))
+        .collect();
+    let on_conflict = if updates.is_empty() {
+        "DO NOTHING".to_string()
+    } else {
+        format!("DO UPDATE SET {}", updates.join(", "))
+    };
+    for chunk in rows.chunks(BULK_INSERT_ROWS) {
+        let values: Vec<String> = (0..chunk.len())
+            .map(|row| {
+                let placeholders: Vec<String> = (1..=columns.len()).map(|column| format!("${}", row * columns.len() + column)).collect();
+                format!("({})", placeholders.join(", "))
+            })
+            .collect();
+        let query = format!(
+            "INSERT INTO {} ({}) VALUES {} ON CONFLICT ({}) {}",
+            table,
+            columns.join(", "),
+            values.join(", "),
+            key.join(", "),
+            on_conflict,
+        );
+        let params: Vec<&(dyn ToSql + Sync)> = chunk.iter().flat_map(|row| as_params(row)).collect();
+        tx.execute(query.as_str(), &params)?;
+    }
+    Ok(())
+}
+
+// Record events in the outbox as part of `tx`, so they are published exactly when the write commits
+fn insert_outbox(tx: &mut Transaction, events: &[OutboxEvent]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if !outbox_enabled() || events.is_empty() {
+        return Ok(());
+    }
+    let created_at = Utc::now().timestamp();
+    for chunk in events.chunks(BULK_INSERT_ROWS) {
+        let values: Vec<String> = (0..chunk.len())
+            .map(|row| format!("(${}, ${}, ${})", row * 3 + 1, row * 3 + 2, row * 3 + 3))
+            .collect();
+        let query = format!("INSERT INTO event_outbox (event_type, payload, created_at) VALUES {}", values.join(", "));
+        let params: Vec<Param> = chunk.iter()
+            .flat_map(|event| -> [Param; 3] {
+                [Box::new(event.event_type()), Box::new(event.data().to_string()), Box::new(created_at)]
+            })
+            .collect();
+        tx.execute(query.as_str(), &as_params(&params))?;
+    }
+    Ok(())
+}
+
+fn block_row(block: &BlockRecord) -> Vec<Param> {
+    vec![
+        Box::new(block.height as i64), Box::new(block.hash.clone()), Box::new(block.prev_hash.clone()), Box::new(block.time),
+        Box::new(block.median_time), Box::new(block.version), Box::new(block.bits.clone()), Box::new(block.difficulty),
+        Box::new(block.nonce as i64), Box::new(block.size as i64), Box::new(block.weight as i64), Box::new(block.tx_count as i64),
+    ]
+}
+
+fn block_from_row(row: &Row) -> Result<BlockRecord, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(BlockRecord {
+        height: u64_at(row, 0)?,
+        hash: row.try_get(1)?,
+        prev_hash: row.try_get(2)?,
+        time: row.try_get(3)?,
+        median_time: row.try_get(4)?,
+        version: row.try_get(5)?,
+        bits: row.try_get(6)?,
+        difficulty: row.try_get(7)?,
+        nonce: u32_at(row, 8)?,
+        size: u32_at(row, 9)?,
+        weight: u32_at(row, 10)?,
+        tx_count: u32_at(row, 11)?,
+    })
+}
+
+// Rows for the full index of the given blocks, then spends linked through the outputs' primary
+// key; outputs older than the index simply don't match. Upserts leave the spent columns alone, so
+// a block indexed again keeps the spends of its outputs by later blocks.
+fn write_index_rows(tx: &mut Transaction, indexed: Vec<(u64, IndexedBlock)>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (Some(lowest), Some(highest)) = (
+        indexed.iter().map(|(height, _)| *height).min(),
+        indexed.iter().map(|(height, _)| *height).max(),
+    ) else {
+        return Ok(());
+    };
+    // Keyed so a txid repeated within the batch (the BIP30 duplicate coinbases) keeps its last row,
+    // as it does across batches
+    let (mut transactions, mut inputs, mut outputs) = (HashMap::new(), HashMap::new(), HashMap::new());
+    for (_, block) in indexed {
+        for row in block.transactions {
+            transactions.insert(row.txid.clone(), vec![
+                Box::new(row.txid) as Param, Box::new(row.block_height as i64), Box::new(row.block_index as i64),
+                Box::new(row.version), Box::new(row.locktime as i64), Box::new(row.size as i64), Box::new(row.vsize as i64),
+                Box::new(row.weight as i64), Box::new(row.input_count as i64), Box::new(row.output_count as i64),
+                Box::new(row.is_coinbase),
+            ]);
+        }
+        for row in block.inputs {
+            inputs.insert((row.txid.clone(), row.input_index), vec![
+                Box::new(row.txid) as Param, Box::new(row.input_index as i64), Box::new(row.block_height as i64),
+                Box::new(row.prev_txid), Box::new(row.prev_vout.map(i64::from)), Box::new(row.sequence as i64),
+                Box::new(row.witness_size as i64),
+            ]);
+        }
+        for row in block.outputs {
+            outputs.insert((row.txid.clone(), row.output_index), vec![
+                Box::new(row.txid) as Param, Box::new(row.output_index as i64), Box::new(row.block_height as i64),
+                Box::new(row.value as i64), Box::new(row.script_type), Box::new(row.address),
+            ]);
+        }
+    }
+    bulk_upsert(
+        tx,
+        "transactions",
+        &["txid", "block_height", "block_index", "version", "locktime", "size", "vsize", "weight", "input_count", "output_count", "is_coinbase"],
+        &["txid"],
+        transactions.into_values().collect(),
+    )?;
+    bulk_upsert(
+        tx,
+        "tx_inputs",
+        &["txid", "input_index", "block_height", "prev_txid", "prev_vout", "sequence", "witness_size"],
+        &["txid", "input_index"],
+        inputs.into_values().collect(),
+    )?;
+    bulk_upsert(
+        tx,
+        "tx_outputs",
+        &["txid", "output_index", "block_height", "value", "script_type", "address"],
+        &["txid", "output_index"],
+        outputs.into_values().collect(),
+    )?;
+    tx.execute(
+        r"UPDATE tx_outputs o
+            SET spent_by_txid = i.txid, spent_by_index = i.input_index
+            FROM tx_inputs i
+            WHERE i.prev_txid = o.txid AND i.prev_vout = o.output_index
+              AND i.block_height BETWEEN $1 AND $2",
+        &[&(lowest as i64), &(highest as i64)],
+    )?;
+    Ok(())
+}
+
+// Rebuild the daily aggregates within `tx`. With TimescaleDB the continuous aggregate already
+// covers the batch's blocks, so only the rollup events and the checkpoint are written.
+fn recompute_aggregates_in(
+    tx: &mut Transaction,
+    from: AggregatesFrom,
+    advance_checkpoint: bool,
+) -> Result<Vec<OutboxEvent>, Box<dyn std::error::Error + Send + Sync>> {
+    let from_date: Option<NaiveDate> = match from {
+        AggregatesFrom::Date(date) => date,
+        AggregatesFrom::Height(height) => {
+            let earliest: Option<NaiveDate> = tx.query_one(
+                "SELECT (to_timestamp(MIN(time)) AT TIME ZONE 'UTC')::date FROM blocks WHERE height >= $1",
+                &[&(height as i64)],
+            )?.try_get(0)?;
+            match earliest {
+                Some(date) => Some(date),
+                None => tx.query_opt("SELECT (to_timestamp(time) AT TIME ZONE 'UTC')::date FROM blocks ORDER BY height DESC LIMIT 1", &[])?
+                    .map(|row| row.try_get(0))
+                    .transpose()?,
+            }
+        }
+    };
+    let from_date = from_date.unwrap_or(DateTime::UNIX_EPOCH.date_naive());
+
+    if !TIMESCALEDB_ENABLED {
+        tx.execute("DELETE FROM daily_transactions WHERE date >= $1", &[&from_date])?;
+        tx.execute(
+            r"INSERT INTO daily_transactions (date, tx_count)
+                SELECT (to_timestamp(time) AT TIME ZONE 'UTC')::date AS day, SUM(tx_count)
+                FROM blocks
+                WHERE time >= $1
+                GROUP BY day",
+            &[&from_date.and_time(NaiveTime::MIN).and_utc().timestamp()],
+        )?;
+        // Trailing average over the stored days among that day and the six calendar days before it;
+        // earlier days are read so the window is full
+        tx.execute(
+            r"INSERT INTO seven_day_dma (date, dma_value)
+                SELECT date, dma_value FROM (
+                    SELECT date, AVG(tx_count) OVER (ORDER BY date RANGE BETWEEN INTERVAL '6 days' PRECEDING AND CURRENT ROW) AS dma_value
+                    FROM daily_transactions
+                    WHERE date >= $1::date - 6
+                ) AS windowed
+                WHERE date >= $1
+                ON CONFLICT (date) DO UPDATE SET dma_value = EXCLUDED.dma_value",
+            &[&from_date],
+        )?;
+    }
+    let mut rollup_events = Vec::new();
+    if outbox_enabled() {
+        let rows = tx.query(
+            "SELECT date, tx_count, dma_value FROM daily_aggregates WHERE date >= $1 ORDER BY date",
+            &[&from_date],
+        )?;
+        for row in &rows {
+            let aggregate = daily_aggregate_from_row(row)?;
+            rollup_events.push(OutboxEvent::DailyTxRollup {
+                date: aggregate.date,
+                tx_count: aggregate.tx_count,
+                dma_value: aggregate.dma_value,
+            });
+        }
+    }
+    if advance_checkpoint {
+        tx.execute(
+            r"INSERT INTO ingestion_checkpoints (name, height, updated_at)
+                SELECT $1, MAX(height), $2 FROM blocks HAVING MAX(height) IS NOT NULL
+                ON CONFLICT (name) DO UPDATE SET height = EXCLUDED.height, updated_at = EXCLUDED.updated_at",
+            &[&AGGREGATES_CHECKPOINT, &Utc::now().timestamp()],
+        )?;
+    }
+    Ok(rollup_events)
+}
+
+fn daily_aggregate_from_row(row: &Row) -> Result<DailyAggregate, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(DailyAggregate {
+        date: row.try_get(0)?,
+        tx_count: usize::try_from(row.try_get::<_, i64>(1)?)?,
+        dma_value: row.try_get(2)?,
+    })
+}
+
+// Every transaction paying to or spending from address $1, as (txid, height, received, sent)
+const ADDRESS_ACTIVITY: &str = r"
+    SELECT txid, block_height, value AS received, 0::BIGINT AS sent
+        FROM tx_outputs WHERE address = $1
+    UNION ALL
+    SELECT o.spent_by_txid, t.block_height, 0::BIGINT, o.value
+        FROM tx_outputs o JOIN transactions t ON t.txid = o.spent_by_txid
+        WHERE o.address = $1 AND o.spent_by_txid IS NOT NULL";
+
+const WEBHOOK_COLUMNS: &str = "id, url, secret, events, fee_block_target, fee_threshold, created_at";
+
+fn webhook_from_row(row: &Row) -> Result<WebhookRecord, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(WebhookRecord {
+        id: u64_at(row, 0)?,
+        url: row.try_get(1)?,
+        secret: row.try_get(2)?,
+        events: row.try_get(3)?,
+        fee_block_target: opt_u16_at(row, 4)?,
+        fee_threshold: row.try_get(5)?,
+        created_at: row.try_get(6)?,
+    })
+}
+
+const DELIVERY_COLUMNS: &str =
+    "id, webhook_id, event_type, payload, status, attempts, last_status_code, last_error, created_at, delivered_at";
+
+fn delivery_from_row(row: &Row) -> Result<WebhookDeliveryRecord, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(WebhookDeliveryRecord {
+        id: u64_at(row, 0)?,
+        webhook_id: u64_at(row, 1)?,
+        event_type: row.try_get(2)?,
+        payload: row.try_get(3)?,
+        status: row.try_get(4)?,
+        attempts: u32_at(row, 5)?,
+        last_status_code: opt_u16_at(row, 6)?,
+        last_error: row.try_get(7)?,
+        created_at: row.try_get(8)?,
+        delivered_at: row.try_get(9)?,
+    })
+}
+
+fn api_key_from_row(row: &Row) -> Result<ApiKeyRecord, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(ApiKeyRecord {
+        id: u64_at(row, 0)?,
+        name: row.try_get(1)?,
+        rate_limit_per_minute: opt_u32_at(row, 2)?,
+        revoked: row.try_get(3)?,
+    })
+}
+
+impl PostgresService {
+    #[instrument(name = "postgres", skip_all, fields(operation = "connect"), err(level = "debug"))]
+    pub fn connect(database_url: &str) -> Result<Arc<Self>, Box<dyn std::error::Error + Send + Sync>> {
+        if DB_POOL_MIN_CONNECTIONS > DB_POOL_MAX_CONNECTIONS {
+            return Err("DB_POOL_MIN_CONNECTIONS is above DB_POOL_MAX_CONNECTIONS".into());
+        }
+        let mut config = Config::from_str(database_url)?;
+        // The server cancels statements running longer than the query timeout
+        let options = format!(
+            "{} -c statement_timeout={}",
+            config.get_options().unwrap_or_default(),
+            DB_QUERY_TIMEOUT_SECS * 1000,
+        );
+        config.options(options.trim_start()).connect_timeout(Duration::from_secs(DB_CONNECT_TIMEOUT_SECS));
+        let pool = Pool::builder()
+            .max_size(DB_POOL_MAX_CONNECTIONS as u32)
+            .min_idle(Some(DB_POOL_MIN_CONNECTIONS as u32))
+            .connection_timeout(Duration::from_secs(DB_ACQUIRE_TIMEOUT_SECS))
+            .build(PostgresConnectionManager::new(config, NoTls))?;
+        Ok(Arc::new(Self { pool, counters: PoolCounters::default() }))
+    }
+
+    // Check out a connection, waiting at most DB_ACQUIRE_TIMEOUT_SECS for one to free up
+    fn conn(&self) -> Result<DbConn<'_, PooledConnection<Manager>>, Box<dyn std::error::Error + Send + Sync>> {
+        let started = Instant::now();
+        let conn = match self.pool.get() {
+            Ok(conn) => conn,
+            // The pool keeps retrying failed connects until the timeout, so a full pool tells them apart
+            Err(_) if self.pool.state().connections >= self.pool.max_size() => {
+                self.counters.acquire_timeouts.fetch_add(1, Ordering::Relaxed);
+                return Err(format!("No PostgreSQL connection free within {}s", DB_ACQUIRE_TIMEOUT_SECS).into());
+            }
+            Err(e) => {
+                self.counters.acquire_errors.fetch_add(1, Ordering::Relaxed);
+                return Err(e.into());
+            }
+        };
+        Ok(self.counters.checked_out(conn, started.elapsed()))
+    }
+}
+
+impl Storage for PostgresService {
+    fn pool_counters(&self) -> &PoolCounters {
+        &self.counters
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "ping"), err(level = "debug"))]
+    fn ping(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.batch_execute("SELECT 1")?;
+        Ok(())
+    }
+
+    // Fee estimates keep their history, so the latest per target is read back with DISTINCT ON
+    #[instrument(name = "postgres", skip_all, fields(operation = "ensure_core_tables"), err(level = "debug"))]
+    fn ensure_core_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        if TIMESCALEDB_ENABLED {
+            conn.batch_execute("CREATE EXTENSION IF NOT EXISTS timescaledb")?;
+        }
+        conn.batch_execute(
+            r"CREATE TABLE IF NOT EXISTS block_info (
+                block_height BIGINT PRIMARY KEY
+            );
+            CREATE TABLE IF NOT EXISTS fee_estimations (
+                block_target INTEGER NOT NULL,
+                fee_rate DOUBLE PRECISION NOT NULL,
+                estimated_at TIMESTAMPTZ NOT NULL,
+                PRIMARY KEY (block_target, estimated_at)
+            )",
+        )?;
+        if TIMESCALEDB_ENABLED {
+            conn.batch_execute(
+                "SELECT create_hypertable('fee_estimations', 'estimated_at', if_not_exists => TRUE, migrate_data => TRUE)",
+            )?;
+        } else {
+            conn.batch_execute(
+                r"CREATE TABLE IF NOT EXISTS daily_transactions (
+                    date DATE PRIMARY KEY,
+                    tx_count BIGINT NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS seven_day_dma (
+                    date DATE PRIMARY KEY,
+                    dma_value DOUBLE PRECISION NOT NULL
+                )",
+            )?;
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "update_block_height"), err(level = "debug"))]
+    fn update_block_height(&self, block_height: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.execute(
+            "INSERT INTO block_info (block_height) VALUES ($1) ON CONFLICT (block_height) DO NOTHING",
+            &[&(block_height as i64)],
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_all_days_tx"), err(level = "debug"))]
+    fn get_all_days_tx(&self) -> Result<Vec<(NaiveDate, usize)>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query("SELECT date, tx_count FROM daily_aggregates ORDER BY date DESC", &[])?;
+        rows.iter()
+            .map(|row| Ok((row.try_get(0)?, usize::try_from(row.try_get::<_, i64>(1)?)?)))
+            .collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_latest_daily_aggregate"), err(level = "debug"))]
+    fn get_latest_daily_aggregate(&self) -> Result<Option<DailyAggregate>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_opt("SELECT date, tx_count, dma_value FROM daily_aggregates ORDER BY date DESC LIMIT 1", &[])?;
+        row.as_ref().map(daily_aggregate_from_row).transpose()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_daily_aggregates"), err(level = "debug"))]
+    fn get_daily_aggregates(
+        &self,
+        from_date: Option<NaiveDate>,
+        to_date: Option<NaiveDate>,
+    ) -> Result<Vec<DailyAggregate>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query(
+            r"SELECT date, tx_count, dma_value FROM daily_aggregates
+                WHERE ($1::date IS NULL OR date >= $1)
+                  AND ($2::date IS NULL OR date <= $2)
+                ORDER BY date",
+            &[&from_date, &to_date],
+        )?;
+        rows.iter().map(daily_aggregate_from_row).collect()
+    }
+
+    // With TimescaleDB, block_stats mirrors the blocks table as a hypertable and the daily rollups
+    // come from a continuous aggregate over it. Either way they are read through daily_aggregates.
+    #[instrument(name = "postgres", skip_all, fields(operation = "ensure_blocks_table"), err(level = "debug"))]
+    fn ensure_blocks_table(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.batch_execute(
+            r"CREATE TABLE IF NOT EXISTS blocks (
+                height BIGINT PRIMARY KEY,
+                hash CHAR(64) NOT NULL UNIQUE,
+                prev_hash CHAR(64) NULL,
+                time BIGINT NOT NULL,
+                median_time BIGINT NULL,
+                version INTEGER NOT NULL,
+                bits CHAR(8) NOT NULL,
+                difficulty DOUBLE PRECISION NOT NULL,
+                nonce BIGINT NOT NULL,
+                size BIGINT NOT NULL,
+                weight BIGINT NOT NULL,
+                tx_count BIGINT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_blocks_time ON blocks (time);
+            CREATE TABLE IF NOT EXISTS ingestion_checkpoints (
+                name VARCHAR(64) PRIMARY KEY,
+                height BIGINT NOT NULL,
+                updated_at BIGINT NOT NULL
+            )",
+        )?;
+        if TIMESCALEDB_ENABLED {
+            conn.batch_execute(
+                r"CREATE TABLE IF NOT EXISTS block_stats (
+                    time TIMESTAMPTZ NOT NULL,
+                    height BIGINT NOT NULL,
+                    tx_count BIGINT NOT NULL,
+                    size BIGINT NOT NULL,
+                    weight BIGINT NOT NULL,
+                    PRIMARY KEY (height, time)
+                );
+                SELECT create_hypertable('block_stats', 'time', if_not_exists => TRUE);
+                INSERT INTO block_stats (time, height, tx_count, size, weight)
+                    SELECT to_timestamp(time), height, tx_count, size, weight FROM blocks
+                    ON CONFLICT DO NOTHING;
+                CREATE MATERIALIZED VIEW IF NOT EXISTS daily_block_stats
+                    WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+                    SELECT time_bucket(INTERVAL '1 day', time) AS day, SUM(tx_count)::BIGINT AS tx_count
+                    FROM block_stats
+                    GROUP BY day
+                    WITH NO DATA;
+                SELECT add_continuous_aggregate_policy('daily_block_stats',
+                    start_offset => NULL, end_offset => INTERVAL '1 hour', schedule_interval => INTERVAL '1 hour',
+                    if_not_exists => TRUE);
+                CREATE OR REPLACE VIEW daily_aggregates AS
+                    SELECT (day AT TIME ZONE 'UTC')::date AS date, tx_count,
+                        (AVG(tx_count) OVER (ORDER BY day RANGE BETWEEN INTERVAL '6 days' PRECEDING AND CURRENT ROW))::DOUBLE PRECISION AS dma_value
+                    FROM daily_block_stats",
+            )?;
+        } else {
+            conn.batch_execute(
+                r"CREATE OR REPLACE VIEW daily_aggregates AS
+                    SELECT d.date, d.tx_count, m.dma_value
+                    FROM daily_transactions d
+                    JOIN seven_day_dma m ON m.date = d.date",
+            )?;
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "write_batch", blocks = batch.block_count()), err(level = "debug"))]
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let WriteBatch { blocks, indexed, fee_estimates, samples, aggregates, advance_checkpoint } = batch;
+        let mut conn = self.conn()?;
+        let mut events: Vec<OutboxEvent> = blocks.iter().map(block_ingested).collect();
+
+        let mut tx = conn.transaction()?;
+        bulk_upsert(&mut tx, "blocks", &BLOCK_COLUMNS, &["height"], blocks.iter().map(block_row).collect())?;
+        if TIMESCALEDB_ENABLED {
+            let stats = blocks.iter()
+                .map(|block| -> Vec<Param> {
+                    vec![
+                        Box::new(DateTime::from_timestamp(block.time, 0).unwrap_or_default()), Box::new(block.height as i64),
+                        Box::new(block.tx_count as i64), Box::new(block.size as i64), Box::new(block.weight as i64),
+                    ]
+                })
+                .collect();
+            bulk_upsert(&mut tx, "block_stats", &["time", "height", "tx_count", "size", "weight"], &["height", "time"], stats)?;
+        }
+        write_index_rows(&mut tx, indexed)?;
+
+        let estimated_at = Utc::now();
+        bulk_upsert(
+            &mut tx,
+            "fee_estimations",
+            &["block_target", "fee_rate", "estimated_at"],
+            &["block_target", "estimated_at"],
+            fee_estimates.iter()
+                .map(|&(block_target, fee_rate)| -> Vec<Param> {
+                    vec![Box::new(i32::from(block_target)), Box::new(fee_rate), Box::new(estimated_at)]
+                })
+                .collect(),
+        )?;
+        events.extend(fee_estimates.iter().map(|&(block_target, fee_rate)| OutboxEvent::FeeEstimate { block_target, fee_rate }));
+        bulk_upsert(
+            &mut tx,
+            "metric_samples",
+            &["metric", "sampled_at", "value"],
+            &["metric", "sampled_at"],
+            samples.into_iter()
+                .map(|sample| -> Vec<Param> { vec![Box::new(sample.metric), Box::new(sample.sampled_at), Box::new(sample.value)] })
+                .collect(),
+        )?;
+
+        if let Some(from) = aggregates {
+            events.extend(recompute_aggregates_in(&mut tx, from, advance_checkpoint)?);
+        }
+        insert_outbox(&mut tx, &events)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_blocks_in_range"), err(level = "debug"))]
+    fn get_blocks_in_range(&self, from_height: u64, to_height: u64) -> Result<Vec<BlockRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query(
+            r"SELECT height, hash, prev_hash, time, median_time, version, bits, difficulty, nonce, size, weight, tx_count
+                FROM blocks WHERE height BETWEEN $1 AND $2 ORDER BY height",
+            &[&(from_height as i64), &(to_height as i64)],
+        )?;
+        rows.iter().map(block_from_row).collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_stored_tip"), err(level = "debug"))]
+    fn get_stored_tip(&self) -> Result<Option<(u64, String)>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_opt("SELECT height, hash FROM blocks ORDER BY height DESC LIMIT 1", &[])?;
+        row.map(|row| Ok((u64_at(&row, 0)?, row.try_get(1)?))).transpose()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_block_hash_at"), err(level = "debug"))]
+    fn get_block_hash_at(&self, height: u64) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_opt("SELECT hash FROM blocks WHERE height = $1", &[&(height as i64)])?;
+        Ok(row.map(|row| row.try_get(0)).transpose()?)
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_block_time_at"), err(level = "debug"))]
+    fn get_block_time_at(&self, height: u64) -> Result<Option<i64>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_opt("SELECT time FROM blocks WHERE height = $1", &[&(height as i64)])?;
+        Ok(row.map(|row| row.try_get(0)).transpose()?)
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "delete_blocks_above"), err(level = "debug"))]
+    fn delete_blocks_above(&self, height: u64) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.transaction()?;
+        let orphaned_blocks = tx.execute("DELETE FROM blocks WHERE height > $1", &[&(height as i64)])?;
+        if TIMESCALEDB_ENABLED {
+            tx.execute("DELETE FROM block_stats WHERE height > $1", &[&(height as i64)])?;
+        }
+        if orphaned_blocks > 0 {
+            insert_outbox(&mut tx, &[OutboxEvent::Reorg { fork_height: height, orphaned_blocks }])?;
+        }
+        tx.commit()?;
+        Ok(orphaned_blocks)
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_aggregates_checkpoint"), err(level = "debug"))]
+    fn get_aggregates_checkpoint(&self) -> Result<Option<u64>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_opt("SELECT height FROM ingestion_checkpoints WHERE name = $1", &[&AGGREGATES_CHECKPOINT])?;
+        row.map(|row| u64_at(&row, 0)).transpose()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_fee_estimations"), err(level = "debug"))]
+    fn get_fee_estimations(&self) -> Result<Vec<FeeEstimationRow>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query(
+            r"SELECT DISTINCT ON (block_target) block_target, fee_rate, estimated_at
+                FROM fee_estimations
+                ORDER BY block_target, estimated_at DESC",
+            &[],
+        )?;
+        rows.iter()
+            .map(|row| Ok((u16::try_from(row.try_get::<_, i32>(0)?)?, row.try_get(1)?, row.try_get::<_, DateTime<Utc>>(2)?)))
+            .collect()
+    }
+
+    // Read through a portal EXPORT_FETCH_ROWS at a time, so neither side holds the whole series
+    #[instrument(name = "postgres", skip_all, fields(operation = "export_series", series = series.name()), err(level = "debug"))]
+    fn export_series(
+        &self,
+        series: ExportSeries,
+        from_date: Option<NaiveDate>,
+        to_date: Option<NaiveDate>,
+        row: &mut RowSink<'_>,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let (from_time, to_time) = day_bounds(from_date, to_date);
+        let (query, params): (&str, Vec<Param>) = match series {
+            ExportSeries::DailyTx => (
+                r"SELECT date, tx_count, dma_value FROM daily_aggregates
+                    WHERE ($1::date IS NULL OR date >= $1) AND ($2::date IS NULL OR date <= $2)
+                    ORDER BY date",
+                vec![Box::new(from_date), Box::new(to_date)],
+            ),
+            ExportSeries::SevenDayDma => (
+                r"SELECT date, dma_value FROM daily_aggregates
+                    WHERE ($1::date IS NULL OR date >= $1) AND ($2::date IS NULL OR date <= $2)
+                    ORDER BY date",
+                vec![Box::new(from_date), Box::new(to_date)],
+            ),
+            ExportSeries::FeeHistory => (
+                r"SELECT estimated_at, block_target, fee_rate FROM fee_estimations
+                    WHERE ($1::timestamptz IS NULL OR estimated_at >= $1) AND ($2::timestamptz IS NULL OR estimated_at < $2)
+                    ORDER BY estimated_at, block_target",
+                vec![Box::new(from_time), Box::new(to_time)],
+            ),
+            ExportSeries::BlockStats => (
+                r"SELECT time, height, tx_count, size, weight FROM blocks
+                    WHERE ($1::bigint IS NULL OR time >= $1) AND ($2::bigint IS NULL OR time < $2)
+                    ORDER BY time, height",
+                vec![
+                    Box::new(from_time.map(|time| time.timestamp())),
+                    Box::new(to_time.map(|time| time.timestamp())),
+                ],
+            ),
+        };
+
+        let mut tx = conn.transaction()?;
+        let portal = tx.bind(query, &as_params(&params))?;
+        let mut count = 0;
+        loop {
+            let rows = tx.query_portal(&portal, EXPORT_FETCH_ROWS)?;
+            if rows.is_empty() {
+                break;
+            }
+            for result in &rows {
+                let values = match series {
+                    ExportSeries::DailyTx => vec![
+                        ExportValue::Date(result.try_get(0)?), ExportValue::Int(result.try_get(1)?),
+                        ExportValue::Float(result.try_get(2)?),
+                    ],
+                    ExportSeries::SevenDayDma => vec![ExportValue::Date(result.try_get(0)?), ExportValue::Float(result.try_get(1)?)],
+                    ExportSeries::FeeHistory => vec![
+                        ExportValue::Timestamp(result.try_get(0)?), ExportValue::Int(i64::from(result.try_get::<_, i32>(1)?)),
+                        ExportValue::Float(result.try_get(2)?),
+                    ],
+                    ExportSeries::BlockStats => vec![
+                        ExportValue::Timestamp(DateTime::from_timestamp(result.try_get(0)?, 0).ok_or("Block time out of range")?),
+                        ExportValue::Int(result.try_get(1)?), ExportValue::Int(result.try_get(2)?),
+                        ExportValue::Int(result.try_get(3)?), ExportValue::Int(result.try_get(4)?),
+                    ],
+                };
+                row(&values)?;
+                count += 1;
+            }
+        }
+        tx.commit()?;
+        Ok(count)
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "ensure_full_index_tables"), err(level = "debug"))]
+    fn ensure_full_index_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.batch_execute(
+            r"CREATE TABLE IF NOT EXISTS transactions (
+                txid CHAR(64) PRIMARY KEY,
+                block_height BIGINT NOT NULL,
+                block_index BIGINT NOT NULL,
+                version INTEGER NOT NULL,
+                locktime BIGINT NOT NULL,
+                size BIGINT NOT NULL,
+                vsize BIGINT NOT NULL,
+                weight BIGINT NOT NULL,
+                input_count BIGINT NOT NULL,
+                output_count BIGINT NOT NULL,
+                is_coinbase BOOLEAN NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_transactions_height ON transactions (block_height);
+            CREATE TABLE IF NOT EXISTS tx_inputs (
+                txid CHAR(64) NOT NULL,
+                input_index BIGINT NOT NULL,
+                block_height BIGINT NOT NULL,
+                prev_txid CHAR(64) NULL,
+                prev_vout BIGINT NULL,
+                sequence BIGINT NOT NULL,
+                witness_size BIGINT NOT NULL,
+                PRIMARY KEY (txid, input_index)
+            );
+            CREATE INDEX IF NOT EXISTS idx_tx_inputs_height ON tx_inputs (block_height);
+            CREATE TABLE IF NOT EXISTS tx_outputs (
+                txid CHAR(64) NOT NULL,
+                output_index BIGINT NOT NULL,
+                block_height BIGINT NOT NULL,
+                value BIGINT NOT NULL,
+                script_type VARCHAR(32) NOT NULL,
+                address VARCHAR(100) NULL,
+                spent_by_txid CHAR(64) NULL,
+                spent_by_index BIGINT NULL,
+                PRIMARY KEY (txid, output_index)
+            );
+            CREATE INDEX IF NOT EXISTS idx_tx_outputs_height ON tx_outputs (block_height)",
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "build_full_index_secondary_indexes"), err(level = "debug"))]
+    fn build_full_index_secondary_indexes(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let indexes = [
+            ("tx_outputs", "idx_tx_outputs_address", "address, block_height"),
+            ("tx_outputs", "idx_tx_outputs_spent_by", "spent_by_txid"),
+            ("tx_inputs", "idx_tx_inputs_prevout", "prev_txid, prev_vout"),
+        ];
+        for (table, name, columns) in indexes {
+            let exists = conn.query_opt(
+                "SELECT 1 FROM pg_indexes WHERE schemaname = current_schema() AND tablename = $1 AND indexname = $2",
+                &[&table, &name],
+            )?;
+            if exists.is_none() {
+                info!(index = name, table, columns, "Creating index");
+                conn.batch_execute(&format!("CREATE INDEX {} ON {} ({})", name, table, columns))?;
+            }
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "delete_index_above"), err(level = "debug"))]
+    fn delete_index_above(&self, height: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.transaction()?;
+        tx.execute(
+            r"UPDATE tx_outputs o
+                SET spent_by_txid = NULL, spent_by_index = NULL
+                FROM tx_inputs i
+                WHERE i.prev_txid = o.txid AND i.prev_vout = o.output_index AND i.block_height > $1",
+            &[&(height as i64)],
+        )?;
+        for table in ["tx_outputs", "tx_inputs", "transactions"] {
+            tx.execute(format!("DELETE FROM {} WHERE block_height > $1", table).as_str(), &[&(height as i64)])?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_address_summary"), err(level = "debug"))]
+    fn get_address_summary(&self, address: &str) -> Result<AddressSummary, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_one(
+            format!(
+                r"SELECT COALESCE(SUM(received) - SUM(sent), 0)::BIGINT, COALESCE(SUM(received), 0)::BIGINT,
+                    COUNT(DISTINCT txid), MIN(block_height), MAX(block_height)
+                  FROM ({}) activity",
+                ADDRESS_ACTIVITY
+            ).as_str(),
+            &[&address],
+        )?;
+        Ok(AddressSummary {
+            balance: u64_at(&row, 0)?,
+            total_received: u64_at(&row, 1)?,
+            tx_count: u64_at(&row, 2)?,
+            first_seen_height: opt_u64_at(&row, 3)?,
+            last_seen_height: opt_u64_at(&row, 4)?,
+        })
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_address_history"), err(level = "debug"))]
+    fn get_address_history(
+        &self,
+        address: &str,
+        before: Option<(u64, String)>,
+        limit: usize,
+    ) -> Result<Vec<AddressTx>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let (before_height, before_txid) = before
+            .map(|(height, txid)| (height as i64, txid))
+            .unwrap_or((i64::MAX, String::new()));
+        let rows = conn.query(
+            format!(
+                r"SELECT txid, block_height, SUM(received)::BIGINT, SUM(sent)::BIGINT
+                  FROM ({}) activity
+                  GROUP BY txid, block_height
+                  HAVING block_height < $2 OR (block_height = $2 AND txid < $3)
+                  ORDER BY block_height DESC, txid DESC
+                  LIMIT $4",
+                ADDRESS_ACTIVITY
+            ).as_str(),
+            &[&address, &before_height, &before_txid, &(limit as i64)],
+        )?;
+        rows.iter()
+            .map(|row| {
+                Ok(AddressTx {
+                    txid: row.try_get(0)?,
+                    block_height: u64_at(row, 1)?,
+                    received: u64_at(row, 2)?,
+                    sent: u64_at(row, 3)?,
+                })
+            })
+            .collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "ensure_watch_tables"), err(level = "debug"))]
+    fn ensure_watch_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.batch_execute(
+            r"CREATE TABLE IF NOT EXISTS watch_wallets (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                name VARCHAR(128) NOT NULL UNIQUE,
+                gap_limit BIGINT NOT NULL,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+            );
+            CREATE TABLE IF NOT EXISTS watch_descriptors (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                wallet_id BIGINT NOT NULL,
+                descriptor TEXT NOT NULL,
+                derived_count BIGINT NOT NULL DEFAULT 0
+            );
+            CREATE INDEX IF NOT EXISTS idx_watch_descriptors_wallet ON watch_descriptors (wallet_id);
+            CREATE TABLE IF NOT EXISTS watch_addresses (
+                wallet_id BIGINT NOT NULL,
+                descriptor_id BIGINT NOT NULL,
+                derivation_index BIGINT NOT NULL,
+                address VARCHAR(100) NOT NULL,
+                PRIMARY KEY (descriptor_id, derivation_index)
+            );
+            CREATE INDEX IF NOT EXISTS idx_watch_addresses_address ON watch_addresses (address);
+            CREATE TABLE IF NOT EXISTS wallet_utxos (
+                wallet_id BIGINT NOT NULL,
+                txid CHAR(64) NOT NULL,
+                vout BIGINT NOT NULL,
+                address VARCHAR(100) NOT NULL,
+                value BIGINT NOT NULL,
+                block_height BIGINT NOT NULL,
+                spent_by_txid CHAR(64) NULL,
+                spent_height BIGINT NULL,
+                PRIMARY KEY (wallet_id, txid, vout)
+            );
+            CREATE INDEX IF NOT EXISTS idx_wallet_utxos_height ON wallet_utxos (block_height);
+            CREATE INDEX IF NOT EXISTS idx_wallet_utxos_spent_height ON wallet_utxos (spent_height);
+            CREATE TABLE IF NOT EXISTS wallet_events (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                wallet_id BIGINT NOT NULL,
+                txid CHAR(64) NOT NULL,
+                received BIGINT NOT NULL,
+                sent BIGINT NOT NULL,
+                block_height BIGINT NULL,
+                confirmed BOOLEAN NOT NULL,
+                seen_at BIGINT NOT NULL,
+                CONSTRAINT uq_wallet_events UNIQUE (wallet_id, txid, confirmed)
+            );
+            CREATE INDEX IF NOT EXISTS idx_wallet_events_height ON wallet_events (block_height)",
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "create_watch_wallet"), err(level = "debug"))]
+    fn create_watch_wallet(
+        &self,
+        name: &str,
+        gap_limit: u32,
+        descriptors: &[NewWatchDescriptor],
+        utxos: &[WalletUtxo],
+    ) -> Result<(u64, Vec<u64>), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.transaction()?;
+        let row = tx.query_one(
+            "INSERT INTO watch_wallets (name, gap_limit) VALUES ($1, $2) RETURNING id",
+            &[&name, &i64::from(gap_limit)],
+        )?;
+        let wallet_id: i64 = row.try_get(0)?;
+        let mut descriptor_ids = Vec::with_capacity(descriptors.len());
+        let mut addresses: Vec<Vec<Param>> = Vec::new();
+        for descriptor in descriptors {
+            let row = tx.query_one(
+                "INSERT INTO watch_descriptors (wallet_id, descriptor, derived_count) VALUES ($1, $2, $3) RETURNING id",
+                &[&wallet_id, &descriptor.descriptor, &(descriptor.addresses.len() as i64)],
+            )?;
+            let descriptor_id: i64 = row.try_get(0)?;
+            descriptor_ids.push(u64::try_from(descriptor_id)?);
+            for (index, address) in descriptor.addresses.iter().enumerate() {
+                addresses.push(vec![
+                    Box::new(wallet_id), Box::new(descriptor_id), Box::new(index as i64), Box::new(address.clone()),
+                ]);
+            }
+        }
+        bulk_upsert(
+            &mut tx,
+            "watch_addresses",
+            &["wallet_id", "descriptor_id", "derivation_index", "address"],
+            &["descriptor_id", "derivation_index"],
+            addresses,
+        )?;
+        bulk_upsert(
+            &mut tx,
+            "wallet_utxos",
+            &["wallet_id", "txid", "vout", "address", "value", "block_height"],
+            &["wallet_id", "txid", "vout"],
+            utxos.iter()
+                .map(|utxo| -> Vec<Param> {
+                    vec![
+                        Box::new(wallet_id), Box::new(utxo.txid.clone()), Box::new(i64::from(utxo.vout)),
+                        Box::new(utxo.address.clone()), Box::new(utxo.value as i64), Box::new(utxo.block_height as i64),
+                    ]
+                })
+                .collect(),
+        )?;
+        tx.commit()?;
+        Ok((u64::try_from(wallet_id)?, descriptor_ids))
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "save_watch_addresses"), err(level = "debug"))]
+    fn save_watch_addresses(
+        &self,
+        descriptor_id: u64,
+        derived_count: u32,
+        addresses: &[WatchAddress],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.transaction()?;
+        bulk_upsert(
+            &mut tx,
+            "watch_addresses",
+            &["wallet_id", "descriptor_id", "derivation_index", "address"],
+            &["descriptor_id", "derivation_index"],
+            addresses.iter()
+                .map(|row| -> Vec<Param> {
+                    vec![
+                        Box::new(row.wallet_id as i64), Box::new(row.descriptor_id as i64),
+                        Box::new(i64::from(row.derivation_index)), Box::new(row.address.clone()),
+                    ]
+                })
+                .collect(),
+        )?;
+        tx.execute(
+            "UPDATE watch_descriptors SET derived_count = GREATEST(derived_count, $1) WHERE id = $2",
+            &[&i64::from(derived_count), &(descriptor_id as i64)],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "find_watch_wallet"), err(level = "debug"))]
+    fn find_watch_wallet(&self, name: &str) -> Result<Option<WatchWallet>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_opt("SELECT id, name, gap_limit FROM watch_wallets WHERE name = $1", &[&name])?;
+        row.map(|row| Ok(WatchWallet { id: u64_at(&row, 0)?, name: row.try_get(1)?, gap_limit: u32_at(&row, 2)? }))
+            .transpose()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "list_watch_wallets"), err(level = "debug"))]
+    fn list_watch_wallets(&self) -> Result<Vec<WatchWallet>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query("SELECT id, name, gap_limit FROM watch_wallets ORDER BY id", &[])?;
+        rows.iter()
+            .map(|row| Ok(WatchWallet { id: u64_at(row, 0)?, name: row.try_get(1)?, gap_limit: u32_at(row, 2)? }))
+            .collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "list_watch_descriptors"), err(level = "debug"))]
+    fn list_watch_descriptors(&self) -> Result<Vec<WatchDescriptor>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query("SELECT id, wallet_id, descriptor, derived_count FROM watch_descriptors ORDER BY id", &[])?;
+        rows.iter()
+            .map(|row| {
+                Ok(WatchDescriptor {
+                    id: u64_at(row, 0)?,
+                    wallet_id: u64_at(row, 1)?,
+                    descriptor: row.try_get(2)?,
+                    derived_count: u32_at(row, 3)?,
+                })
+            })
+            .collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "list_watch_addresses"), err(level = "debug"))]
+    fn list_watch_addresses(&self) -> Result<Vec<WatchAddress>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query("SELECT wallet_id, descriptor_id, derivation_index, address FROM watch_addresses", &[])?;
+        rows.iter()
+            .map(|row| {
+                Ok(WatchAddress {
+                    wallet_id: u64_at(row, 0)?,
+                    descriptor_id: u64_at(row, 1)?,
+                    derivation_index: u32_at(row, 2)?,
+                    address: row.try_get(3)?,
+                })
+            })
+            .collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "list_unspent_wallet_utxos"), err(level = "debug"))]
+    fn list_unspent_wallet_utxos(&self) -> Result<Vec<WalletUtxo>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query(
+            "SELECT wallet_id, txid, vout, address, value, block_height FROM wallet_utxos WHERE spent_by_txid IS NULL",
+            &[],
+        )?;
+        rows.iter()
+            .map(|row| {
+                Ok(WalletUtxo {
+                    wallet_id: u64_at(row, 0)?,
+                    txid: row.try_get(1)?,
+                    vout: u32_at(row, 2)?,
+                    address: row.try_get(3)?,
+                    value: u64_at(row, 4)?,
+                    block_height: u64_at(row, 5)?,
+                })
+            })
+            .collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "save_wallet_activity"), err(level = "debug"))]
+    fn save_wallet_activity(
+        &self,
+        utxos: &[WalletUtxo],
+        spends: &[WalletSpend],
+        events: Vec<WalletEventRecord>,
+    ) -> Result<Vec<WalletEventRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.transaction()?;
+
+        // Outputs first, so spends of outputs created in the same block find their row.
+        // DO NOTHING keeps the spend of an output recorded by an earlier attempt.
+        for utxo in utxos {
+            tx.execute(
+                r"INSERT INTO wallet_utxos (wallet_id, txid, vout, address, value, block_height)
+                    VALUES ($1, $2, $3, $4, $5, $6)
+                    ON CONFLICT DO NOTHING",
+                &[
+                    &(utxo.wallet_id as i64), &utxo.txid, &i64::from(utxo.vout), &utxo.address,
+                    &(utxo.value as i64), &(utxo.block_height as i64),
+                ],
+            )?;
+        }
+        for spend in spends {
+            tx.execute(
+                r"UPDATE wallet_utxos SET spent_by_txid = $1, spent_height = $2
+                    WHERE wallet_id = $3 AND txid = $4 AND vout = $5",
+                &[
+                    &spend.spent_by_txid, &(spend.spent_height as i64), &(spend.wallet_id as i64), &spend.txid,
+                    &i64::from(spend.vout),
+                ],
+            )?;
+        }
+
+        let mut recorded = Vec::new();
+        for event in events {
+            let inserted = tx.execute(
+                r"INSERT INTO wallet_events (wallet_id, txid, received, sent, block_height, confirmed, seen_at)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7)
+                    ON CONFLICT DO NOTHING",
+                &[
+                    &(event.wallet_id as i64), &event.txid, &(event.received as i64), &(event.sent as i64),
+                    &event.block_height.map(|height| height as i64), &event.block_height.is_some(), &event.seen_at,
+                ],
+            )?;
+            if inserted > 0 {
+                recorded.push(event);
+            }
+        }
+
+        tx.commit()?;
+        Ok(recorded)
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "delete_wallet_activity_above"), err(level = "debug"))]
+    fn delete_wallet_activity_above(&self, height: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.transaction()?;
+        let height = height as i64;
+        tx.execute("UPDATE wallet_utxos SET spent_by_txid = NULL, spent_height = NULL WHERE spent_height > $1", &[&height])?;
+        tx.execute("DELETE FROM wallet_utxos WHERE block_height > $1", &[&height])?;
+        tx.execute("DELETE FROM wallet_events WHERE block_height > $1", &[&height])?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_wallet_balance"), err(level = "debug"))]
+    fn get_wallet_balance(&self, wallet_id: u64) -> Result<(u64, u64), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_one(
+            r"SELECT COALESCE(SUM(value), 0)::BIGINT, COUNT(*)
+                FROM wallet_utxos WHERE wallet_id = $1 AND spent_by_txid IS NULL",
+            &[&(wallet_id as i64)],
+        )?;
+        Ok((u64_at(&row, 0)?, u64_at(&row, 1)?))
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_wallet_events"), err(level = "debug"))]
+    fn get_wallet_events(&self, wallet_id: u64, limit: usize) -> Result<Vec<WalletEventRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query(
+            r"SELECT wallet_id, txid, received, sent, block_height, seen_at FROM wallet_events
+                WHERE wallet_id = $1 ORDER BY id DESC LIMIT $2",
+            &[&(wallet_id as i64), &(limit as i64)],
+        )?;
+        rows.iter()
+            .map(|row| {
+                Ok(WalletEventRecord {
+                    wallet_id: u64_at(row, 0)?,
+                    txid: row.try_get(1)?,
+                    received: u64_at(row, 2)?,
+                    sent: u64_at(row, 3)?,
+                    block_height: opt_u64_at(row, 4)?,
+                    seen_at: row.try_get(5)?,
+                })
+            })
+            .collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "ensure_webhook_tables"), err(level = "debug"))]
+    fn ensure_webhook_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.batch_execute(
+            r"CREATE TABLE IF NOT EXISTS webhooks (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                url VARCHAR(2048) NOT NULL,
+                secret CHAR(64) NOT NULL,
+                events VARCHAR(255) NOT NULL,
+                fee_block_target INTEGER NULL,
+                fee_threshold DOUBLE PRECISION NULL,
+                created_at BIGINT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS webhook_deliveries (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                webhook_id BIGINT NOT NULL,
+                event_type VARCHAR(32) NOT NULL,
+                payload TEXT NOT NULL,
+                status VARCHAR(16) NOT NULL,
+                attempts BIGINT NOT NULL DEFAULT 0,
+                last_status_code INTEGER NULL,
+                last_error TEXT NULL,
+                created_at BIGINT NOT NULL,
+                delivered_at BIGINT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_webhook ON webhook_deliveries (webhook_id, id);
+            CREATE INDEX IF NOT EXISTS idx_webhook_deliveries_status ON webhook_deliveries (status);
+            CREATE TABLE IF NOT EXISTS webhook_fee_state (
+                webhook_id BIGINT PRIMARY KEY,
+                above BOOLEAN NOT NULL
+            )",
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "create_webhook"), err(level = "debug"))]
+    fn create_webhook(&self, webhook: &WebhookRecord) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_one(
+            r"INSERT INTO webhooks (url, secret, events, fee_block_target, fee_threshold, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6) RETURNING id",
+            &[
+                &webhook.url, &webhook.secret, &webhook.events, &webhook.fee_block_target.map(i32::from),
+                &webhook.fee_threshold, &webhook.created_at,
+            ],
+        )?;
+        u64_at(&row, 0)
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "list_webhooks"), err(level = "debug"))]
+    fn list_webhooks(&self) -> Result<Vec<WebhookRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query(format!("SELECT {} FROM webhooks ORDER BY id", WEBHOOK_COLUMNS).as_str(), &[])?;
+        rows.iter().map(webhook_from_row).collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "find_webhook"), err(level = "debug"))]
+    fn find_webhook(&self, id: u64) -> Result<Option<WebhookRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_opt(format!("SELECT {} FROM webhooks WHERE id = $1", WEBHOOK_COLUMNS).as_str(), &[&(id as i64)])?;
+        row.as_ref().map(webhook_from_row).transpose()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "delete_webhook"), err(level = "debug"))]
+    fn delete_webhook(&self, id: u64) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.transaction()?;
+        let deleted = tx.execute("DELETE FROM webhooks WHERE id = $1", &[&(id as i64)])? > 0;
+        tx.execute("DELETE FROM webhook_fee_state WHERE webhook_id = $1", &[&(id as i64)])?;
+        tx.commit()?;
+        Ok(deleted)
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "swap_webhook_fee_above"), err(level = "debug"))]
+    fn swap_webhook_fee_above(&self, webhook_id: u64, above: bool) -> Result<Option<bool>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.transaction()?;
+        let previous = tx.query_opt(
+            "SELECT above FROM webhook_fee_state WHERE webhook_id = $1 FOR UPDATE",
+            &[&(webhook_id as i64)],
+        )?;
+        let previous: Option<bool> = previous.map(|row| row.try_get(0)).transpose()?;
+        tx.execute(
+            r"INSERT INTO webhook_fee_state (webhook_id, above) VALUES ($1, $2)
+                ON CONFLICT (webhook_id) DO UPDATE SET above = EXCLUDED.above",
+            &[&(webhook_id as i64), &above],
+        )?;
+        tx.commit()?;
+        Ok(previous)
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "create_webhook_delivery"), err(level = "debug"))]
+    fn create_webhook_delivery(
+        &self,
+        webhook_id: u64,
+        event_type: &str,
+        payload: &str,
+        created_at: i64,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_one(
+            r"INSERT INTO webhook_deliveries (webhook_id, event_type, payload, status, created_at)
+                VALUES ($1, $2, $3, 'pending', $4) RETURNING id",
+            &[&(webhook_id as i64), &event_type, &payload, &created_at],
+        )?;
+        u64_at(&row, 0)
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "record_webhook_attempt"), err(level = "debug"))]
+    fn record_webhook_attempt(
+        &self,
+        delivery_id: u64,
+        status: &str,
+        attempts: u32,
+        last_status_code: Option<u16>,
+        last_error: Option<&str>,
+        delivered_at: Option<i64>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.execute(
+            r"UPDATE webhook_deliveries
+                SET status = $1, attempts = $2, last_status_code = $3, last_error = $4, delivered_at = $5
+                WHERE id = $6",
+            &[
+                &status, &i64::from(attempts), &last_status_code.map(i32::from), &last_error, &delivered_at,
+                &(delivery_id as i64),
+            ],
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "find_webhook_delivery"), err(level = "debug"))]
+    fn find_webhook_delivery(&self, id: u64) -> Result<Option<WebhookDeliveryRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_opt(
+            format!("SELECT {} FROM webhook_deliveries WHERE id = $1", DELIVERY_COLUMNS).as_str(),
+            &[&(id as i64)],
+        )?;
+        row.as_ref().map(delivery_from_row).transpose()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "list_webhook_deliveries"), err(level = "debug"))]
+    fn list_webhook_deliveries(&self, webhook_id: u64, limit: usize) -> Result<Vec<WebhookDeliveryRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query(
+            format!("SELECT {} FROM webhook_deliveries WHERE webhook_id = $1 ORDER BY id DESC LIMIT $2", DELIVERY_COLUMNS).as_str(),
+            &[&(webhook_id as i64), &(limit as i64)],
+        )?;
+        rows.iter().map(delivery_from_row).collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "list_pending_webhook_deliveries"), err(level = "debug"))]
+    fn list_pending_webhook_deliveries(&self) -> Result<Vec<WebhookDeliveryRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query(
+            format!("SELECT {} FROM webhook_deliveries WHERE status = 'pending' ORDER BY id", DELIVERY_COLUMNS).as_str(),
+            &[],
+        )?;
+        rows.iter().map(delivery_from_row).collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "ensure_outbox_table"), err(level = "debug"))]
+    fn ensure_outbox_table(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.batch_execute(
+            r"CREATE TABLE IF NOT EXISTS event_outbox (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                event_type VARCHAR(64) NOT NULL,
+                payload TEXT NOT NULL,
+                created_at BIGINT NOT NULL,
+                published_at BIGINT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_event_outbox_published ON event_outbox (published_at, id)",
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "list_unpublished_outbox"), err(level = "debug"))]
+    fn list_unpublished_outbox(&self, limit: usize) -> Result<Vec<OutboxRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query(
+            r"SELECT id, event_type, payload, created_at FROM event_outbox
+                WHERE published_at IS NULL
+                ORDER BY id LIMIT $1",
+            &[&(limit as i64)],
+        )?;
+        rows.iter()
+            .map(|row| {
+                Ok(OutboxRecord {
+                    id: u64_at(row, 0)?,
+                    event_type: row.try_get(1)?,
+                    payload: row.try_get(2)?,
+                    created_at: row.try_get(3)?,
+                })
+            })
+            .collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "mark_outbox_published"), err(level = "debug"))]
+    fn mark_outbox_published(&self, ids: &[u64]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn()?;
+        let ids: Vec<i64> = ids.iter().map(|&id| id as i64).collect();
+        conn.execute(
+            "UPDATE event_outbox SET published_at = $1 WHERE id = ANY($2)",
+            &[&Utc::now().timestamp(), &ids],
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "prune_published_outbox"), err(level = "debug"))]
+    fn prune_published_outbox(&self, published_before: i64) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let deleted = conn.execute(
+            "DELETE FROM event_outbox WHERE published_at IS NOT NULL AND published_at < $1",
+            &[&published_before],
+        )?;
+        Ok(deleted)
+    }
+
+    // With TimescaleDB the samples are a hypertable, like fee_estimations
+    #[instrument(name = "postgres", skip_all, fields(operation = "ensure_metric_tables"), err(level = "debug"))]
+    fn ensure_metric_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.batch_execute(
+            r"CREATE TABLE IF NOT EXISTS metric_samples (
+                metric VARCHAR(64) NOT NULL,
+                sampled_at TIMESTAMPTZ NOT NULL,
+                value DOUBLE PRECISION NOT NULL,
+                PRIMARY KEY (metric, sampled_at)
+            );
+            CREATE INDEX IF NOT EXISTS idx_metric_samples_time ON metric_samples (sampled_at);
+            CREATE TABLE IF NOT EXISTS metric_rollups (
+                metric VARCHAR(64) NOT NULL,
+                resolution VARCHAR(8) NOT NULL,
+                bucket_start TIMESTAMPTZ NOT NULL,
+                samples BIGINT NOT NULL,
+                min_value DOUBLE PRECISION NOT NULL,
+                max_value DOUBLE PRECISION NOT NULL,
+                avg_value DOUBLE PRECISION NOT NULL,
+                p50_value DOUBLE PRECISION NOT NULL,
+                p90_value DOUBLE PRECISION NOT NULL,
+                p99_value DOUBLE PRECISION NOT NULL,
+                PRIMARY KEY (metric, resolution, bucket_start)
+            );
+            CREATE INDEX IF NOT EXISTS idx_metric_rollups_time ON metric_rollups (resolution, bucket_start)",
+        )?;
+        if TIMESCALEDB_ENABLED {
+            conn.batch_execute(
+                "SELECT create_hypertable('metric_samples', 'sampled_at', if_not_exists => TRUE, migrate_data => TRUE)",
+            )?;
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_metric_samples"), err(level = "debug"))]
+    fn get_metric_samples(
+        &self,
+        metric: Option<&str>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<MetricSample>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query(
+            r"SELECT metric, sampled_at, value FROM metric_samples
+                WHERE ($1::varchar IS NULL OR metric = $1) AND sampled_at >= $2 AND sampled_at < $3
+                ORDER BY metric, sampled_at",
+            &[&metric, &from, &to],
+        )?;
+        rows.iter()
+            .map(|row| Ok(MetricSample { metric: row.try_get(0)?, sampled_at: row.try_get(1)?, value: row.try_get(2)? }))
+            .collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_earliest_metric_sample"), err(level = "debug"))]
+    fn get_earliest_metric_sample(&self) -> Result<Option<DateTime<Utc>>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_one("SELECT MIN(sampled_at) FROM metric_samples", &[])?;
+        Ok(row.try_get(0)?)
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "delete_metric_samples_before"), err(level = "debug"))]
+    fn delete_metric_samples_before(&self, before: DateTime<Utc>) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        Ok(conn.execute("DELETE FROM metric_samples WHERE sampled_at < $1", &[&before])?)
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "save_metric_rollups"), err(level = "debug"))]
+    fn save_metric_rollups(&self, rollups: &[MetricRollup]) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if rollups.is_empty() {
+            return Ok(());
+        }
+        let mut conn = self.conn()?;
+        let mut tx = conn.transaction()?;
+        bulk_upsert(
+            &mut tx,
+            "metric_rollups",
+            &[
+                "metric", "resolution", "bucket_start", "samples", "min_value", "max_value", "avg_value",
+                "p50_value", "p90_value", "p99_value",
+            ],
+            &["metric", "resolution", "bucket_start"],
+            rollups.iter()
+                .map(|rollup| -> Vec<Param> {
+                    vec![
+                        Box::new(rollup.metric.clone()), Box::new(rollup.resolution.name()), Box::new(rollup.bucket_start),
+                        Box::new(rollup.samples as i64), Box::new(rollup.min), Box::new(rollup.max), Box::new(rollup.avg),
+                        Box::new(rollup.p50), Box::new(rollup.p90), Box::new(rollup.p99),
+                    ]
+                })
+                .collect(),
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_metric_rollups"), err(level = "debug"))]
+    fn get_metric_rollups(
+        &self,
+        metric: &str,
+        resolution: Resolution,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<MetricRollup>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query(
+            r"SELECT bucket_start, samples, min_value, max_value, avg_value, p50_value, p90_value, p99_value
+                FROM metric_rollups
+                WHERE metric = $1 AND resolution = $2 AND bucket_start >= $3 AND bucket_start < $4
+                ORDER BY bucket_start",
+            &[&metric, &resolution.name(), &from, &to],
+        )?;
+        rows.iter()
+            .map(|row| {
+                Ok(MetricRollup {
+                    metric: metric.to_string(),
+                    resolution,
+                    bucket_start: row.try_get(0)?,
+                    samples: u64_at(row, 1)?,
+                    min: row.try_get(2)?,
+                    max: row.try_get(3)?,
+                    avg: row.try_get(4)?,
+                    p50: row.try_get(5)?,
+                    p90: row.try_get(6)?,
+                    p99: row.try_get(7)?,
+                })
+            })
+            .collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "get_latest_metric_rollup"), err(level = "debug"))]
+    fn get_latest_metric_rollup(&self, resolution: Resolution) -> Result<Option<DateTime<Utc>>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_one("SELECT MAX(bucket_start) FROM metric_rollups WHERE resolution = $1", &[&resolution.name()])?;
+        Ok(row.try_get(0)?)
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "delete_metric_rollups_before"), err(level = "debug"))]
+    fn delete_metric_rollups_before(
+        &self,
+        resolution: Resolution,
+        before: DateTime<Utc>,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        Ok(conn.execute(
+            "DELETE FROM metric_rollups WHERE resolution = $1 AND bucket_start < $2",
+            &[&resolution.name(), &before],
+        )?)
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "ensure_alert_tables"), err(level = "debug"))]
+    fn ensure_alert_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.batch_execute(
+            r"CREATE TABLE IF NOT EXISTS alert_states (
+                rule_name VARCHAR(128) PRIMARY KEY,
+                status VARCHAR(16) NOT NULL,
+                value DOUBLE PRECISION NULL,
+                pending_since BIGINT NULL,
+                fired_at BIGINT NULL,
+                resolved_at BIGINT NULL,
+                updated_at BIGINT NULL
+            );
+            CREATE TABLE IF NOT EXISTS alert_history (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                rule_name VARCHAR(128) NOT NULL,
+                status VARCHAR(16) NOT NULL,
+                value DOUBLE PRECISION NULL,
+                changed_at BIGINT NULL
+            );
+            CREATE INDEX IF NOT EXISTS idx_alert_history_rule ON alert_history (rule_name, id)",
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "list_alert_states"), err(level = "debug"))]
+    fn list_alert_states(&self) -> Result<Vec<AlertStateRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query(
+            "SELECT rule_name, status, value, pending_since, fired_at, resolved_at, updated_at FROM alert_states",
+            &[],
+        )?;
+        rows.iter()
+            .map(|row| {
+                Ok(AlertStateRecord {
+                    rule_name: row.try_get(0)?,
+                    status: row.try_get(1)?,
+                    value: row.try_get(2)?,
+                    pending_since: row.try_get(3)?,
+                    fired_at: row.try_get(4)?,
+                    resolved_at: row.try_get(5)?,
+                    updated_at: row.try_get(6)?,
+                })
+            })
+            .collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "save_alert_state"), err(level = "debug"))]
+    fn save_alert_state(&self, state: &AlertStateRecord, status_changed: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let mut tx = conn.transaction()?;
+        tx.execute(
+            r"INSERT INTO alert_states (rule_name, status, value, pending_since, fired_at, resolved_at, updated_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT (rule_name) DO UPDATE SET
+                    status = EXCLUDED.status, value = EXCLUDED.value, pending_since = EXCLUDED.pending_since,
+                    fired_at = EXCLUDED.fired_at, resolved_at = EXCLUDED.resolved_at, updated_at = EXCLUDED.updated_at",
+            &[
+                &state.rule_name, &state.status, &state.value, &state.pending_since, &state.fired_at,
+                &state.resolved_at, &state.updated_at,
+            ],
+        )?;
+        if status_changed {
+            tx.execute(
+                "INSERT INTO alert_history (rule_name, status, value, changed_at) VALUES ($1, $2, $3, $4)",
+                &[&state.rule_name, &state.status, &state.value, &state.updated_at],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "ensure_api_keys_table"), err(level = "debug"))]
+    fn ensure_api_keys_table(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.batch_execute(
+            r"CREATE TABLE IF NOT EXISTS api_keys (
+                id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+                name VARCHAR(128) NOT NULL UNIQUE,
+                key_hash CHAR(64) NOT NULL UNIQUE,
+                rate_limit_per_minute BIGINT NULL,
+                revoked BOOLEAN NOT NULL DEFAULT FALSE,
+                created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+            )",
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "create_api_key"), err(level = "debug"))]
+    fn create_api_key(&self, name: &str, key_hash: &str, rate_limit_per_minute: Option<u32>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        conn.execute(
+            "INSERT INTO api_keys (name, key_hash, rate_limit_per_minute) VALUES ($1, $2, $3)",
+            &[&name, &key_hash, &rate_limit_per_minute.map(i64::from)],
+        )?;
+        Ok(())
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "find_api_key"), err(level = "debug"))]
+    fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let row = conn.query_opt("SELECT id, name, rate_limit_per_minute, revoked FROM api_keys WHERE key_hash = $1", &[&key_hash])?;
+        row.as_ref().map(api_key_from_row).transpose()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "list_api_keys"), err(level = "debug"))]
+    fn list_api_keys(&self) -> Result<Vec<ApiKeyRecord>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        let rows = conn.query("SELECT id, name, rate_limit_per_minute, revoked FROM api_keys ORDER BY id", &[])?;
+        rows.iter().map(api_key_from_row).collect()
+    }
+
+    #[instrument(name = "postgres", skip_all, fields(operation = "revoke_api_key"), err(level = "debug"))]
+    fn revoke_api_key(&self, name: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut conn = self.conn()?;
+        Ok(conn.execute("UPDATE api_keys SET revoked = TRUE WHERE name = $1", &[&name])? > 0)
+    }
+}
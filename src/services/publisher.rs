@@ -0,0 +1,322 @@
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{DateTime, NaiveDate, Utc};
+use futures_util::future::BoxFuture;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::error;
+use crate::config::settings::{
+    EVENT_SINK, OUTBOX_BATCH_SIZE, OUTBOX_POLL_INTERVAL_MS, OUTBOX_PRUNE_INTERVAL_SECS, OUTBOX_RETENTION_SECS,
+};
+use crate::services::storage::{OutboxRecord, Storage};
+
+// Bumped whenever a field is removed or changes meaning; adding fields keeps the version
+pub const EVENT_SCHEMA_VERSION: u32 = 1;
+
+// Where outbox events are published, chosen in settings
+#[derive(Debug, Clone, Copy)]
+pub enum EventSink {
+    // One JSON object per line on standard output
+    Stdout,
+    // One JSON object per line appended to a file
+    File { path: &'static str },
+    // Needs the `kafka` feature; the event type is used as the message key
+    Kafka { brokers: &'static str, topic: &'static str },
+    // Needs the `nats` feature; published through JetStream to `<subject_prefix>.<event type>`,
+    // so a stream must cover those subjects
+    Nats { url: &'static str, subject_prefix: &'static str },
+}
+
+// Changes recorded in the outbox in the same database transaction as the data they describe
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutboxEvent {
+    BlockIngested {
+        height: u64,
+        hash: String,
+        prev_hash: Option<String>,
+        time: i64,
+        tx_count: u32,
+        size: u32,
+        weight: u32,
+    },
+    DailyTxRollup {
+        date: NaiveDate,
+        tx_count: usize,
+        dma_value: f64,
+    },
+    FeeEstimate {
+        block_target: u16,
+        fee_rate: f64,
+    },
+    Reorg {
+        fork_height: u64,
+        orphaned_blocks: u64,
+    },
+}
+
+impl OutboxEvent {
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            OutboxEvent::BlockIngested { .. } => "block.ingested",
+            OutboxEvent::DailyTxRollup { .. } => "daily_tx.rollup",
+            OutboxEvent::FeeEstimate { .. } => "fee.estimate",
+            OutboxEvent::Reorg { .. } => "chain.reorg",
+        }
+    }
+
+    pub fn data(&self) -> serde_json::Value {
+        match self {
+            OutboxEvent::BlockIngested { height, hash, prev_hash, time, tx_count, size, weight } => json!({
+                "height": height,
+                "hash": hash,
+                "prev_hash": prev_hash,
+                "time": time,
+                "tx_count": tx_count,
+                "size": size,
+                "weight": weight,
+            }),
+            OutboxEvent::DailyTxRollup { date, tx_count, dma_value } => json!({
+                "date": date,
+                "tx_count": tx_count,
+                "dma_value": dma_value,
+            }),
+            OutboxEvent::FeeEstimate { block_target, fee_rate } => json!({
+                "block_target": block_target,
+                "fee_rate": fee_rate,
+            }),
+            OutboxEvent::Reorg { fork_height, orphaned_blocks } => json!({
+                "fork_height": fork_height,
+                "orphaned_blocks": orphaned_blocks,
+            }),
+        }
+    }
+}
+
+// What consumers receive. Delivery is at-least-once, so consumers should drop ids they have seen.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    pub schema_version: u32,
+    // Outbox row id; increases with every event but may arrive out of order after a retry
+    pub id: u64,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub occurred_at: DateTime<Utc>,
+    pub data: serde_json::Value,
+}
+
+impl TryFrom<OutboxRecord> for EventEnvelope {
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn try_from(record: OutboxRecord) -> Result<Self, Self::Error> {
+        Ok(EventEnvelope {
+            schema_version: EVENT_SCHEMA_VERSION,
+            id: record.id,
+            event_type: record.event_type,
+            occurred_at: DateTime::from_timestamp(record.created_at, 0).unwrap_or_default(),
+            data: serde_json::from_str(&record.payload)?,
+        })
+    }
+}
+
+// A destination for outbox events. `publish` must only return Ok once every event in the batch
+// has been accepted by the sink; on Err the whole batch is sent again later.
+pub trait EventPublisher: Send + Sync {
+    fn publish<'a>(&'a self, events: &'a [EventEnvelope]) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error + Send + Sync>>>;
+}
+
+// Newline-delimited JSON to any writer, flushed after every batch
+pub struct LinePublisher<W: Write + Send> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> LinePublisher<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer: Mutex::new(writer) }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer.into_inner().unwrap()
+    }
+}
+
+impl<W: Write + Send> EventPublisher for LinePublisher<W> {
+    fn publish<'a>(&'a self, events: &'a [EventEnvelope]) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error + Send + Sync>>> {
+        Box::pin(async move {
+            let mut writer = self.writer.lock().unwrap();
+            for event in events {
+                serde_json::to_writer(&mut *writer, event)?;
+                writer.write_all(b"\n")?;
+            }
+            writer.flush()?;
+            Ok(())
+        })
+    }
+}
+
+// Appends to a file and syncs it to disk before reporting success
+pub struct FilePublisher {
+    file: Mutex<std::fs::File>,
+}
+
+impl FilePublisher {
+    pub fn open(path: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file: Mutex::new(file) })
+    }
+}
+
+impl EventPublisher for FilePublisher {
+    fn publish<'a>(&'a self, events: &'a [EventEnvelope]) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error + Send + Sync>>> {
+        Box::pin(async move {
+            let mut lines = Vec::new();
+            for event in events {
+                serde_json::to_writer(&mut lines, event)?;
+                lines.push(b'\n');
+            }
+            let mut file = self.file.lock().unwrap();
+            file.write_all(&lines)?;
+            file.sync_data()?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(feature = "kafka")]
+pub struct KafkaPublisher {
+    producer: rdkafka::producer::FutureProducer,
+    topic: &'static str,
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaPublisher {
+    pub fn new(brokers: &str, topic: &'static str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let producer = rdkafka::config::ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("enable.idempotence", "true")
+            .set("message.timeout.ms", "30000")
+            .create()?;
+        Ok(Self { producer, topic })
+    }
+}
+
+#[cfg(feature = "kafka")]
+impl EventPublisher for KafkaPublisher {
+    fn publish<'a>(&'a self, events: &'a [EventEnvelope]) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error + Send + Sync>>> {
+        Box::pin(async move {
+            for event in events {
+                let body = serde_json::to_string(event)?;
+                let record = rdkafka::producer::FutureRecord::to(self.topic).key(&event.event_type).payload(&body);
+                self.producer.send(record, Duration::from_secs(0)).await.map_err(|(e, _)| e)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(feature = "nats")]
+pub struct NatsPublisher {
+    jetstream: async_nats::jetstream::Context,
+    subject_prefix: &'static str,
+}
+
+#[cfg(feature = "nats")]
+impl NatsPublisher {
+    pub async fn connect(url: &str, subject_prefix: &'static str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let client = async_nats::connect(url).await?;
+        Ok(Self { jetstream: async_nats::jetstream::new(client), subject_prefix })
+    }
+}
+
+#[cfg(feature = "nats")]
+impl EventPublisher for NatsPublisher {
+    fn publish<'a>(&'a self, events: &'a [EventEnvelope]) -> BoxFuture<'a, Result<(), Box<dyn std::error::Error + Send + Sync>>> {
+        Box::pin(async move {
+            for event in events {
+                let subject = format!("{}.{}", self.subject_prefix, event.event_type);
+                let body = serde_json::to_vec(event)?;
+                // The second await waits for the stream's acknowledgement
+                self.jetstream.publish(subject, body.into()).await?.await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+pub async fn connect_publisher(sink: EventSink) -> Result<Arc<dyn EventPublisher>, Box<dyn std::error::Error + Send + Sync>> {
+    match sink {
+        EventSink::Stdout => Ok(Arc::new(LinePublisher::new(std::io::stdout()))),
+        EventSink::File { path } => Ok(Arc::new(FilePublisher::open(path)?)),
+        #[cfg(feature = "kafka")]
+        EventSink::Kafka { brokers, topic } => Ok(Arc::new(KafkaPublisher::new(brokers, topic)?)),
+        #[cfg(not(feature = "kafka"))]
+        EventSink::Kafka { .. } => Err("The Kafka event sink needs a build with the `kafka` feature".into()),
+        #[cfg(feature = "nats")]
+        EventSink::Nats { url, subject_prefix } => Ok(Arc::new(NatsPublisher::connect(url, subject_prefix).await?)),
+        #[cfg(not(feature = "nats"))]
+        EventSink::Nats { .. } => Err("The NATS event sink needs a build with the `nats` feature".into()),
+    }
+}
+
+// Whether writes should record outbox events at all; without a sink nothing would drain them
+pub const fn outbox_enabled() -> bool {
+    EVENT_SINK.is_some()
+}
+
+// Publish one batch of unpublished outbox rows; returns how many rows it took off the outbox.
+// A row whose payload doesn't parse would otherwise hold up every later row, so it is logged
+// and marked published along with the batch without being sent.
+pub async fn relay_outbox_batch(
+    storage: &Arc<dyn Storage>,
+    publisher: &dyn EventPublisher,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let records = storage.run(|db| db.list_unpublished_outbox(OUTBOX_BATCH_SIZE)).await?;
+    if records.is_empty() {
+        return Ok(0);
+    }
+    let ids: Vec<u64> = records.iter().map(|record| record.id).collect();
+    let mut events = Vec::with_capacity(records.len());
+    for record in records {
+        let (id, event_type) = (record.id, record.event_type.clone());
+        match EventEnvelope::try_from(record) {
+            Ok(event) => events.push(event),
+            Err(e) => error!(outbox_id = id, event_type, error = %e, "Skipping malformed outbox event"),
+        }
+    }
+    if !events.is_empty() {
+        publisher.publish(&events).await?;
+    }
+    // A crash before this line republishes the batch, which at-least-once allows
+    let published = ids.len();
+    storage.run(move |db| db.mark_outbox_published(&ids)).await?;
+    Ok(published)
+}
+
+// Drain the outbox into the publisher, and prune rows that were published long ago
+pub async fn run_outbox_relay(storage: Arc<dyn Storage>, publisher: Arc<dyn EventPublisher>) {
+    let mut interval = tokio::time::interval(Duration::from_millis(OUTBOX_POLL_INTERVAL_MS));
+    let mut last_prune = Utc::now();
+    loop {
+        interval.tick().await;
+        loop {
+            match relay_outbox_batch(&storage, publisher.as_ref()).await {
+                // A full batch means there may be more waiting
+                Ok(published) if published == OUTBOX_BATCH_SIZE => continue,
+                Ok(_) => break,
+                Err(e) => {
+                    error!(error = %e, "Error publishing outbox events");
+                    break;
+                }
+            }
+        }
+
+        let now = Utc::now();
+        if (now - last_prune).num_seconds() >= OUTBOX_PRUNE_INTERVAL_SECS {
+            last_prune = now;
+            let published_before = now.timestamp() - OUTBOX_RETENTION_SECS;
+            if let Err(e) = storage.run(move |db| db.prune_published_outbox(published_before)).await {
+                error!(error = %e, "Error pruning the event outbox");
+            }
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast;
+use warp::http::{header, StatusCode};
+use warp::Filter;
+use crate::config::settings::{CACHE_MAX_AGE_SECS, RESPONSE_CACHE_MAX_ENTRIES};
+use crate::services::api_auth::ApiAuth;
+use crate::services::events::{EventBus, Topic};
+
+// A serialized JSON body together with its entity tag
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub body: String,
+    pub etag: String,
+}
+
+impl CachedResponse {
+    pub fn new(body: String) -> Self {
+        let etag = format!("\"{:x}\"", Sha256::digest(body.as_bytes()));
+        Self { body, etag }
+    }
+}
+
+struct Entries {
+    // Each response with the tick it was last used at
+    responses: HashMap<String, (CachedResponse, u64)>,
+    tick: u64,
+    // Bumped by every invalidation
+    generation: u64,
+}
+
+// In-process cache of API responses keyed by route and the query parameters it reads. Holds at
+// most `capacity` entries and evicts the least recently used one to make room.
+pub struct ResponseCache {
+    entries: Mutex<Entries>,
+    capacity: usize,
+}
+
+impl ResponseCache {
+    pub fn new() -> Arc<Self> {
+        Self::with_capacity(RESPONSE_CACHE_MAX_ENTRIES)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Arc<Self> {
+        Arc::new(Self { entries: Mutex::new(Entries { responses: HashMap::new(), tick: 0, generation: 0 }), capacity })
+    }
+
+    pub fn get(&self, key: &str) -> Option<CachedResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.tick += 1;
+        let tick = entries.tick;
+        entries.responses.get_mut(key).map(|(response, last_used)| {
+            *last_used = tick;
+            response.clone()
+        })
+    }
+
+    pub fn insert(&self, key: String, response: CachedResponse) {
+        let generation = self.generation();
+        self.insert_unless_invalidated(key, response, generation);
+    }
+
+    // Read before loading the data a response is built from, and handed back to
+    // `insert_unless_invalidated` with it
+    pub fn generation(&self) -> u64 {
+        self.entries.lock().unwrap().generation
+    }
+
+    // Insert unless an invalidation ran since `generation` was read, since the response may then
+    // hold data that was already replaced
+    pub fn insert_unless_invalidated(&self, key: String, response: CachedResponse, generation: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.generation != generation {
+            return;
+        }
+        entries.tick += 1;
+        let tick = entries.tick;
+        if !entries.responses.contains_key(&key) && entries.responses.len() >= self.capacity {
+            let oldest = entries.responses.iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.responses.remove(&oldest);
+            }
+        }
+        entries.responses.insert(key, (response, tick));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().responses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Drop every entry whose key starts with one of the given route prefixes
+    pub fn invalidate(&self, prefixes: &[&str]) {
+        if prefixes.is_empty() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.generation += 1;
+        entries.responses.retain(|key, _| !prefixes.iter().any(|prefix| key.starts_with(prefix)));
+    }
+
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.generation += 1;
+        entries.responses.clear();
+    }
+}
+
+// A GET request that can be answered from the response cache
+pub struct CachedRequest {
+    key: String,
+    if_none_match: Option<String>,
+    // Behind an API key, so shared caches must not hand it to other callers
+    private: bool,
+    // The cache's generation when the request came in
+    generation: u64,
+    cache: Arc<ResponseCache>,
+}
+
+impl CachedRequest {
+    // Answer from the cache if this route and query has been served since the last invalidation
+    pub fn lookup(&self) -> Option<warp::reply::Response> {
+        self.cache.get(&self.key).map(|cached| self.reply(&cached))
+    }
+
+    // Serialize fresh data, remember it unless the cache was invalidated since the request came in,
+    // and answer with it
+    pub fn store<T: Serialize>(&self, data: &T) -> Result<warp::reply::Response, serde_json::Error> {
+        let cached = CachedResponse::new(serde_json::to_string(data)?);
+        self.cache.insert_unless_invalidated(self.key.clone(), cached.clone(), self.generation);
+        Ok(self.reply(&cached))
+    }
+
+    fn etag_matches(&self, etag: &str) -> bool {
+        self.if_none_match.as_deref().is_some_and(|value| {
+            value.split(',')
+                .map(|candidate| candidate.trim().trim_start_matches("W/"))
+                .any(|candidate| candidate == "*" || candidate == etag)
+        })
+    }
+
+    fn reply(&self, cached: &CachedResponse) -> warp::reply::Response {
+        let visibility = if self.private { "private" } else { "public" };
+        let mut builder = warp::http::Response::builder()
+            .header(header::ETAG, cached.etag.as_str())
+            .header(header::CACHE_CONTROL, format!("{}, max-age={}", visibility, CACHE_MAX_AGE_SECS));
+        if self.private {
+            builder = builder.header(header::VARY, "X-API-Key, Authorization");
+        }
+        let response = if self.etag_matches(&cached.etag) {
+            builder.status(StatusCode::NOT_MODIFIED).body(Default::default())
+        } else {
+            builder
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(cached.body.clone().into())
+        };
+        response.expect("static response headers are valid")
+    }
+}
+
+// Key cached responses by full path and the values of `query_params`, the only query parameters
+// the route reads, so other parameters can't add entries. With `auth_enabled`, replies on routes
+// that need a key are marked private.
+pub fn cached_request(
+    response_cache: Arc<ResponseCache>,
+    auth_enabled: bool,
+    query_params: &'static [&'static str],
+) -> impl Filter<Extract = (CachedRequest,), Error = warp::Rejection> + Clone {
+    warp::path::full()
+        .and(warp::query::<Vec<(String, String)>>().or(warp::any().map(Vec::new)).unify())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .map(move |path: warp::path::FullPath, query: Vec<(String, String)>, if_none_match: Option<String>| {
+            let mut used: Vec<(String, String)> = query.into_iter()
+                .filter(|(name, _)| query_params.contains(&name.as_str()))
+                .collect();
+            used.sort();
+            let mut key = path.as_str().to_string();
+            for (index, (name, value)) in used.iter().enumerate() {
+                key.push(if index == 0 { '?' } else { '&' });
+                key.push_str(&format!("{}={}", name, value));
+            }
+            let private = auth_enabled && !ApiAuth::is_public(path.as_str());
+            let generation = response_cache.generation();
+            CachedRequest { key, if_none_match, private, generation, cache: response_cache.clone() }
+        })
+}
+
+// Routes whose data can change when an event of the given topic is published
+fn routes_for_topic(topic: Topic) -> &'static [&'static str] {
+    match topic {
+        Topic::Blocks | Topic::DailyTx => &["/api/v1/7d_tx"],
+        Topic::Fees => &["/api/v1/fee_estimations"],
+        Topic::Mempool | Topic::Wallets | Topic::Alerts => &[],
+    }
+}
+
+// Invalidate cached responses as ingestion publishes new data
+pub async fn run_cache_invalidation(cache: Arc<ResponseCache>, event_bus: Arc<EventBus>) {
+    let mut events = event_bus.subscribe();
+    loop {
+        match events.recv().await {
+            Ok(sequenced) => cache.invalidate(routes_for_topic(sequenced.event.topic())),
+            // We may have missed an invalidation, so start over
+            Err(broadcast::error::RecvError::Lagged(_)) => cache.clear(),
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
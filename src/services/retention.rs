@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use serde::Serialize;
+use tracing::{error, info};
+use utoipa::ToSchema;
+use crate::config::settings::{
+    FEE_BLOCK_TARGETS, INGESTION_INTERVAL_SECS, METRIC_DAILY_RETENTION_DAYS, METRIC_HOURLY_RETENTION_DAYS,
+    METRIC_MAX_POINTS, METRIC_RAW_RETENTION_DAYS, METRIC_ROLLUP_INTERVAL_SECS,
+};
+use crate::services::alerts::AlertMetric;
+use crate::services::storage::{MetricRollup, MetricSample, Storage};
+
+// How finely a metric's history is stored; hourly and daily buckets start on the UTC hour and day
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Resolution {
+    // One sample per ingestion tick
+    Raw,
+    Hour,
+    Day,
+}
+
+impl Resolution {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "raw" => Some(Resolution::Raw),
+            "hour" => Some(Resolution::Hour),
+            "day" => Some(Resolution::Day),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Resolution::Raw => "raw",
+            Resolution::Hour => "hour",
+            Resolution::Day => "day",
+        }
+    }
+
+    fn step(self) -> TimeDelta {
+        match self {
+            Resolution::Raw => TimeDelta::seconds(INGESTION_INTERVAL_SECS as i64),
+            Resolution::Hour => TimeDelta::hours(1),
+            Resolution::Day => TimeDelta::days(1),
+        }
+    }
+
+    // How long rows at this resolution are kept; None keeps them forever
+    fn retention(self) -> Option<TimeDelta> {
+        match self {
+            Resolution::Raw => Some(TimeDelta::days(METRIC_RAW_RETENTION_DAYS)),
+            Resolution::Hour => Some(TimeDelta::days(METRIC_HOURLY_RETENTION_DAYS)),
+            Resolution::Day => METRIC_DAILY_RETENTION_DAYS.map(TimeDelta::days),
+        }
+    }
+
+    // Start of the bucket `time` falls in
+    pub fn bucket_start(self, time: DateTime<Utc>) -> DateTime<Utc> {
+        time.duration_trunc(self.step()).unwrap_or(time)
+    }
+}
+
+// Names of the sampled metrics, the same ones alert rules use
+pub fn metric_names() -> Vec<String> {
+    let mut names: Vec<String> = FEE_BLOCK_TARGETS.iter()
+        .map(|&block_target| AlertMetric::FeeRate { block_target }.to_string())
+        .collect();
+    for metric in [AlertMetric::MempoolTxCount, AlertMetric::MempoolVsizeBytes, AlertMetric::MempoolUsageBytes] {
+        names.push(metric.to_string());
+    }
+    names
+}
+
+// The finest resolution still stored for all of `from..to` that needs at most METRIC_MAX_POINTS
+// points to cover it. Daily rollups are the fallback even when they have been pruned too.
+pub fn choose_resolution(from: DateTime<Utc>, to: DateTime<Utc>, now: DateTime<Utc>) -> Resolution {
+    let span = to - from;
+    [Resolution::Raw, Resolution::Hour]
+        .into_iter()
+        .find(|resolution| {
+            let stored = resolution.retention().is_none_or(|retention| from >= now - retention);
+            stored && span.num_seconds() / resolution.step().num_seconds() <= METRIC_MAX_POINTS
+        })
+        .unwrap_or(Resolution::Day)
+}
+
+// Value below which `fraction` of `sorted` falls, interpolating between the closest ranks
+fn percentile(sorted: &[f64], fraction: f64) -> f64 {
+    let rank = fraction * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    sorted[lower] + (sorted[upper] - sorted[lower]) * (rank - lower as f64)
+}
+
+// Roll samples up into one row per metric and bucket
+pub fn rollup(samples: &[MetricSample], resolution: Resolution) -> Vec<MetricRollup> {
+    let mut buckets: BTreeMap<(&str, DateTime<Utc>), Vec<f64>> = BTreeMap::new();
+    for sample in samples {
+        buckets.entry((&sample.metric, resolution.bucket_start(sample.sampled_at)))
+            .or_default()
+            .push(sample.value);
+    }
+    buckets.into_iter()
+        .map(|((metric, bucket_start), mut values)| {
+            values.sort_by(f64::total_cmp);
+            MetricRollup {
+                metric: metric.to_string(),
+                resolution,
+                bucket_start,
+                samples: values.len() as u64,
+                min: values[0],
+                max: values[values.len() - 1],
+                avg: values.iter().sum::<f64>() / values.len() as f64,
+                p50: percentile(&values, 0.5),
+                p90: percentile(&values, 0.9),
+                p99: percentile(&values, 0.99),
+            }
+        })
+        .collect()
+}
+
+// One point of a metric's history. A raw sample is a point of one sample with every field equal.
+#[derive(Debug, Clone, PartialEq, Serialize, ToSchema)]
+pub struct MetricPoint {
+    pub time: DateTime<Utc>,
+    pub samples: u64,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+impl From<MetricRollup> for MetricPoint {
+    fn from(rollup: MetricRollup) -> Self {
+        MetricPoint {
+            time: rollup.bucket_start,
+            samples: rollup.samples,
+            min: rollup.min,
+            max: rollup.max,
+            avg: rollup.avg,
+            p50: rollup.p50,
+            p90: rollup.p90,
+            p99: rollup.p99,
+        }
+    }
+}
+
+// `metric` within `from..to` at `resolution`, oldest first. Rollups cover closed buckets only, so
+// the newest hour or day shows up once it ends.
+pub fn metric_history(
+    storage: &dyn Storage,
+    metric: &str,
+    resolution: Resolution,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<MetricPoint>, Box<dyn std::error::Error + Send + Sync>> {
+    match resolution {
+        Resolution::Raw => Ok(storage.get_metric_samples(Some(metric), from, to)?
+            .into_iter()
+            .map(|sample| MetricPoint {
+                time: sample.sampled_at,
+                samples: 1,
+                min: sample.value,
+                max: sample.value,
+                avg: sample.value,
+                p50: sample.value,
+                p90: sample.value,
+                p99: sample.value,
+            })
+            .collect()),
+        _ => Ok(storage.get_metric_rollups(metric, resolution, resolution.bucket_start(from), to)?
+            .into_iter()
+            .map(MetricPoint::from)
+            .collect()),
+    }
+}
+
+// What one retention pass changed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionReport {
+    pub hourly_rollups: usize,
+    pub daily_rollups: usize,
+    pub deleted_samples: u64,
+    pub deleted_rollups: u64,
+}
+
+// Roll up every closed bucket at `resolution` that has no rollup yet, a day of samples at a time;
+// returns how many rollups were written
+fn rollup_closed_buckets(
+    storage: &dyn Storage,
+    resolution: Resolution,
+    now: DateTime<Utc>,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    let start = match storage.get_latest_metric_rollup(resolution)? {
+        Some(latest) => latest + resolution.step(),
+        None => match storage.get_earliest_metric_sample()? {
+            Some(earliest) => resolution.bucket_start(earliest),
+            None => return Ok(0),
+        },
+    };
+    let closed = resolution.bucket_start(now);
+    let mut written = 0;
+    let mut from = start;
+    while from < closed {
+        let to = (from + TimeDelta::days(1)).min(closed);
+        let rollups = rollup(&storage.get_metric_samples(None, from, to)?, resolution);
+        storage.save_metric_rollups(&rollups)?;
+        written += rollups.len();
+        from = to;
+    }
+    Ok(written)
+}
+
+// Roll closed hours and days up from the raw samples, then delete what is past retention. Raw
+// samples are only deleted once both rollups cover them, so a pass that fails part way loses nothing.
+pub fn run_retention_pass(
+    storage: &dyn Storage,
+    now: DateTime<Utc>,
+) -> Result<RetentionReport, Box<dyn std::error::Error + Send + Sync>> {
+    let mut report = RetentionReport {
+        hourly_rollups: rollup_closed_buckets(storage, Resolution::Hour, now)?,
+        daily_rollups: rollup_closed_buckets(storage, Resolution::Day, now)?,
+        ..RetentionReport::default()
+    };
+
+    if let Some(retention) = Resolution::Raw.retention() {
+        let before = (now - retention).min(Resolution::Day.bucket_start(now));
+        report.deleted_samples = storage.delete_metric_samples_before(before)?;
+    }
+    for resolution in [Resolution::Hour, Resolution::Day] {
+        if let Some(retention) = resolution.retention() {
+            report.deleted_rollups += storage.delete_metric_rollups_before(resolution, now - retention)?;
+        }
+    }
+    Ok(report)
+}
+
+// Keep the metric history within its retention, every METRIC_ROLLUP_INTERVAL_SECS
+pub async fn run_retention_loop(storage: Arc<dyn Storage>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(METRIC_ROLLUP_INTERVAL_SECS));
+    loop {
+        interval.tick().await;
+        match storage.run(|db| run_retention_pass(db, Utc::now())).await {
+            Ok(report) if report != RetentionReport::default() => info!(
+                hourly_rollups = report.hourly_rollups,
+                daily_rollups = report.daily_rollups,
+                deleted_samples = report.deleted_samples,
+                deleted_rollups = report.deleted_rollups,
+                "Metric history rolled up",
+            ),
+            Ok(_) => {}
+            Err(e) => error!(error = %e, "Error rolling up metric history"),
+        }
+    }
+}
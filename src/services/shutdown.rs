@@ -0,0 +1,69 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+// Set once a stop signal arrives. Long-running tasks watch it to finish their current work and stop.
+pub struct Shutdown {
+    sender: watch::Sender<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Arc<Self> {
+        let (sender, _) = watch::channel(false);
+        Arc::new(Self { sender })
+    }
+
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    // Resolves once shutdown is triggered, immediately if it already was
+    pub async fn wait(&self) {
+        let mut receiver = self.sender.subscribe();
+        // Only fails if the sender is dropped, which `self` prevents
+        let _ = receiver.wait_for(|triggered| *triggered).await;
+    }
+}
+
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+// Trigger `shutdown` on SIGTERM or SIGINT; a second signal exits without waiting
+pub async fn handle_signals(shutdown: Arc<Shutdown>) {
+    wait_for_signal().await;
+    info!("Shutting down, finishing in-flight work (signal again to exit now)");
+    shutdown.trigger();
+    wait_for_signal().await;
+    warn!("Exiting with work still in flight");
+    std::process::exit(130);
+}
+
+// Run `work` to completion, unless it is still running `deadline` after shutdown was triggered
+pub async fn with_deadline<F: Future>(shutdown: &Shutdown, deadline: Duration, work: F) -> Option<F::Output> {
+    tokio::select! {
+        output = work => Some(output),
+        _ = async {
+            shutdown.wait().await;
+            tokio::time::sleep(deadline).await;
+        } => None,
+    }
+}
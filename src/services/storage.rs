@@ -0,0 +1,657 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use tracing::Span;
+use utoipa::ToSchema;
+use crate::config::settings::{DB_POOL_MAX_CONNECTIONS, DB_POOL_MIN_CONNECTIONS};
+use crate::services::block_index::IndexedBlock;
+use crate::services::export::{ExportSeries, RowSink};
+use crate::services::mysql_connection::MySqlService;
+use crate::services::postgres_connection::PostgresService;
+use crate::services::publisher::OutboxEvent;
+use crate::services::retention::Resolution;
+
+// Name of the ingestion_checkpoints row tracking the daily aggregates
+pub(crate) const AGGREGATES_CHECKPOINT: &str = "daily_aggregates";
+
+// Columns of the blocks table, in the order backends write them
+pub(crate) const BLOCK_COLUMNS: [&str; 12] = [
+    "height", "hash", "prev_hash", "time", "median_time", "version", "bits", "difficulty", "nonce", "size", "weight", "tx_count",
+];
+
+// Block target, fee rate in sat/vB and when it was estimated
+pub type FeeEstimationRow = (u16, f64, DateTime<Utc>);
+
+#[derive(Debug, Default)]
+// Shared by both backends; read through `Storage::pool_stats`
+pub struct PoolCounters {
+    pub(crate) acquired: AtomicU64,
+    pub(crate) in_use: AtomicU64,
+    pub(crate) acquire_timeouts: AtomicU64,
+    pub(crate) acquire_errors: AtomicU64,
+    pub(crate) wait_micros_total: AtomicU64,
+    pub(crate) wait_micros_max: AtomicU64,
+    // Calls handed to `run` that have not finished, including those still waiting for a thread
+    pub(crate) blocking_calls: AtomicU64,
+}
+
+// Connection pool usage since startup
+#[derive(Debug, Clone, Serialize, ToSchema)]
+pub struct PoolStats {
+    pub min_connections: usize,
+    pub max_connections: usize,
+    pub in_use: u64,
+    pub acquired_total: u64,
+    // Checkouts that gave up after DB_ACQUIRE_TIMEOUT_SECS because every connection was busy
+    pub acquire_timeouts: u64,
+    // Checkouts that failed to open a new connection
+    pub acquire_errors: u64,
+    pub avg_wait_ms: f64,
+    pub max_wait_ms: f64,
+    pub blocking_calls: u64,
+}
+impl PoolCounters {
+    // Count a connection handed out after waiting `waited` for it
+    pub(crate) fn checked_out<C>(&self, conn: C, waited: Duration) -> DbConn<'_, C> {
+        let waited = waited.as_micros() as u64;
+        self.acquired.fetch_add(1, Ordering::Relaxed);
+        self.in_use.fetch_add(1, Ordering::Relaxed);
+        self.wait_micros_total.fetch_add(waited, Ordering::Relaxed);
+        self.wait_micros_max.fetch_max(waited, Ordering::Relaxed);
+        DbConn { conn, counters: self }
+    }
+
+    fn stats(&self) -> PoolStats {
+        let acquired = self.acquired.load(Ordering::Relaxed);
+        let wait_micros_total = self.wait_micros_total.load(Ordering::Relaxed);
+        PoolStats {
+            min_connections: DB_POOL_MIN_CONNECTIONS,
+            max_connections: DB_POOL_MAX_CONNECTIONS,
+            in_use: self.in_use.load(Ordering::Relaxed),
+            acquired_total: acquired,
+            acquire_timeouts: self.acquire_timeouts.load(Ordering::Relaxed),
+            acquire_errors: self.acquire_errors.load(Ordering::Relaxed),
+            avg_wait_ms: if acquired == 0 { 0.0 } else { wait_micros_total as f64 / acquired as f64 / 1000.0 },
+            max_wait_ms: self.wait_micros_max.load(Ordering::Relaxed) as f64 / 1000.0,
+            blocking_calls: self.blocking_calls.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// A pooled connection that counts as in use until dropped
+pub(crate) struct DbConn<'a, C> {
+    conn: C,
+    counters: &'a PoolCounters,
+}
+
+impl<C> Deref for DbConn<'_, C> {
+    type Target = C;
+
+    fn deref(&self) -> &C {
+        &self.conn
+    }
+}
+
+impl<C> DerefMut for DbConn<'_, C> {
+    fn deref_mut(&mut self) -> &mut C {
+        &mut self.conn
+    }
+}
+
+impl<C> Drop for DbConn<'_, C> {
+    fn drop(&mut self) {
+        self.counters.in_use.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Run blocking work on tokio's blocking threads, inside the caller's span. A panic in `work` is
+// resumed in the caller, as if it had run inline.
+pub async fn spawn_blocking_in_span<T, F>(work: F) -> T
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let span = Span::current();
+    match tokio::task::spawn_blocking(move || span.in_scope(work)).await {
+        Ok(output) => output,
+        Err(e) => std::panic::resume_unwind(e.into_panic()),
+    }
+}
+
+pub(crate) fn block_ingested(block: &BlockRecord) -> OutboxEvent {
+    OutboxEvent::BlockIngested {
+        height: block.height,
+        hash: block.hash.clone(),
+        prev_hash: block.prev_hash.clone(),
+        time: block.time,
+        tx_count: block.tx_count,
+        size: block.size,
+        weight: block.weight,
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DailyAggregate {
+    pub date: NaiveDate,
+    pub tx_count: usize,
+    pub dma_value: f64,
+}
+
+// One row of the `blocks` table, the canonical record of ingested blocks
+#[derive(Debug, Clone)]
+pub struct BlockRecord {
+    pub height: u64,
+    pub hash: String,
+    pub prev_hash: Option<String>,
+    pub time: i64,
+    pub median_time: Option<i64>,
+    pub version: i32,
+    pub bits: String,
+    pub difficulty: f64,
+    pub nonce: u32,
+    pub size: u32,
+    pub weight: u32,
+    pub tx_count: u32,
+}
+
+// Where a daily aggregates rebuild starts
+#[derive(Debug, Clone, Copy)]
+pub enum AggregatesFrom {
+    // Every day from this one on; None rebuilds everything
+    Date(Option<NaiveDate>),
+    // The day of the earliest block stored at or above this height, or of the stored tip when
+    // there is none, e.g. after a reorg removed the range
+    Height(u64),
+}
+
+// Everything derived from one ingestion step, written by `Storage::write_batch` in a single
+// transaction with multi-row statements. Nothing in a batch is visible until all of it is.
+#[derive(Debug, Default)]
+pub struct WriteBatch {
+    pub(crate) blocks: Vec<BlockRecord>,
+    pub(crate) indexed: Vec<(u64, IndexedBlock)>,
+    pub(crate) fee_estimates: Vec<(u16, f64)>,
+    pub(crate) samples: Vec<MetricSample>,
+    pub(crate) aggregates: Option<AggregatesFrom>,
+    pub(crate) advance_checkpoint: bool,
+}
+
+impl WriteBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_block(&mut self, block: BlockRecord) {
+        self.blocks.push(block);
+    }
+
+    // A block together with its rows for the full index
+    pub fn add_indexed_block(&mut self, block: BlockRecord, indexed: IndexedBlock) {
+        self.indexed.push((block.height, indexed));
+        self.blocks.push(block);
+    }
+
+    pub fn add_fee_estimate(&mut self, block_target: u16, fee_rate: f64) {
+        self.fee_estimates.push((block_target, fee_rate));
+    }
+
+    pub fn add_metric_sample(&mut self, metric: String, value: f64, sampled_at: DateTime<Utc>) {
+        self.samples.push(MetricSample { metric, sampled_at, value });
+    }
+
+    // Rebuild the daily aggregates after the rest of the batch is written
+    pub fn recompute_aggregates(&mut self, from: AggregatesFrom) {
+        self.aggregates = Some(from);
+    }
+
+    // Like `recompute_aggregates`, and record that the aggregates now cover every stored block
+    pub fn recompute_aggregates_through_tip(&mut self, from_height: u64) {
+        self.aggregates = Some(AggregatesFrom::Height(from_height));
+        self.advance_checkpoint = true;
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty() && self.fee_estimates.is_empty() && self.samples.is_empty() && self.aggregates.is_none()
+    }
+}
+
+// One stored value of a fee or mempool metric, named as in `retention::metric_names`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricSample {
+    pub metric: String,
+    pub sampled_at: DateTime<Utc>,
+    pub value: f64,
+}
+
+// Summary of a metric's samples within one hourly or daily bucket
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricRollup {
+    pub metric: String,
+    pub resolution: Resolution,
+    pub bucket_start: DateTime<Utc>,
+    pub samples: u64,
+    pub min: f64,
+    pub max: f64,
+    pub avg: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct AddressSummary {
+    pub balance: u64,
+    pub total_received: u64,
+    pub tx_count: u64,
+    pub first_seen_height: Option<u64>,
+    pub last_seen_height: Option<u64>,
+}
+
+// One transaction touching an address, with the amounts it moved in and out
+#[derive(Debug, Clone)]
+pub struct AddressTx {
+    pub txid: String,
+    pub block_height: u64,
+    pub received: u64,
+    pub sent: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchWallet {
+    pub id: u64,
+    pub name: String,
+    pub gap_limit: u32,
+}
+
+// A ranged output descriptor of a watched wallet; addresses `0..derived_count` are in watch_addresses
+#[derive(Debug, Clone)]
+pub struct WatchDescriptor {
+    pub id: u64,
+    pub wallet_id: u64,
+    pub descriptor: String,
+    pub derived_count: u32,
+}
+
+// A descriptor of a wallet being registered, with the addresses derived for it so far, in order
+#[derive(Debug, Clone)]
+pub struct NewWatchDescriptor {
+    pub descriptor: String,
+    pub addresses: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WatchAddress {
+    pub wallet_id: u64,
+    pub descriptor_id: u64,
+    pub derivation_index: u32,
+    pub address: String,
+}
+
+// A confirmed output paying to a watched wallet
+#[derive(Debug, Clone)]
+pub struct WalletUtxo {
+    pub wallet_id: u64,
+    pub txid: String,
+    pub vout: u32,
+    pub address: String,
+    pub value: u64,
+    pub block_height: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct WalletSpend {
+    pub wallet_id: u64,
+    pub txid: String,
+    pub vout: u32,
+    pub spent_by_txid: String,
+    pub spent_height: u64,
+}
+
+// What one transaction did to one wallet; `block_height` is None while it is in the mempool
+#[derive(Debug, Clone)]
+pub struct WalletEventRecord {
+    pub wallet_id: u64,
+    pub txid: String,
+    pub received: u64,
+    pub sent: u64,
+    pub block_height: Option<u64>,
+    pub seen_at: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookRecord {
+    pub id: u64,
+    pub url: String,
+    pub secret: String,
+    // Comma-separated event types the webhook is subscribed to
+    pub events: String,
+    pub fee_block_target: Option<u16>,
+    pub fee_threshold: Option<f64>,
+    pub created_at: i64,
+}
+
+// One event sent to one webhook; `status` is pending, delivered or failed
+#[derive(Debug, Clone)]
+pub struct WebhookDeliveryRecord {
+    pub id: u64,
+    pub webhook_id: u64,
+    pub event_type: String,
+    pub payload: String,
+    pub status: String,
+    pub attempts: u32,
+    pub last_status_code: Option<u16>,
+    pub last_error: Option<String>,
+    pub created_at: i64,
+    pub delivered_at: Option<i64>,
+}
+
+// Event waiting in (or already sent from) the outbox; `payload` is the event's JSON data
+#[derive(Debug, Clone)]
+pub struct OutboxRecord {
+    pub id: u64,
+    pub event_type: String,
+    pub payload: String,
+    pub created_at: i64,
+}
+
+// Current state of one alert rule; times are unix seconds
+#[derive(Debug, Clone)]
+pub struct AlertStateRecord {
+    pub rule_name: String,
+    pub status: String,
+    pub value: Option<f64>,
+    pub pending_since: Option<i64>,
+    pub fired_at: Option<i64>,
+    pub resolved_at: Option<i64>,
+    pub updated_at: Option<i64>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiKeyRecord {
+    pub id: u64,
+    pub name: String,
+    pub rate_limit_per_minute: Option<u32>,
+    pub revoked: bool,
+}
+
+// Every query the services make, implemented once per database. Drivers are synchronous: methods
+// block the calling thread until the query returns, so async code goes through `run`.
+pub trait Storage: Send + Sync {
+    fn pool_counters(&self) -> &PoolCounters;
+
+    // Connection pool usage since startup
+    fn pool_stats(&self) -> PoolStats {
+        self.pool_counters().stats()
+    }
+
+    fn ping(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    /* -------------------- Core operations -------------------- */
+    // Tables the original deployment created by hand: the tip, fee estimates and daily aggregates
+    fn ensure_core_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    fn update_block_height(&self, block_height: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    // Daily transaction counts, newest first
+    fn get_all_days_tx(&self) -> Result<Vec<(NaiveDate, usize)>, Box<dyn std::error::Error + Send + Sync>>;
+    // The newest daily transaction count together with its 7DMA
+    fn get_latest_daily_aggregate(&self) -> Result<Option<DailyAggregate>, Box<dyn std::error::Error + Send + Sync>>;
+    // Daily counts with their 7DMA, oldest first, optionally limited to an inclusive date range
+    fn get_daily_aggregates(
+        &self,
+        from_date: Option<NaiveDate>,
+        to_date: Option<NaiveDate>,
+    ) -> Result<Vec<DailyAggregate>, Box<dyn std::error::Error + Send + Sync>>;
+
+    /* -------------------- Block operations -------------------- */
+    fn ensure_blocks_table(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    // Write a batch in one transaction: blocks, full index rows, fee estimates, metric samples, the
+    // aggregates rebuild and the outbox events for all of them
+    fn write_batch(&self, batch: WriteBatch) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    fn save_block(&self, block: &BlockRecord) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut batch = WriteBatch::new();
+        batch.add_block(block.clone());
+        self.write_batch(batch)
+    }
+
+    // Stored blocks with heights in `from_height..=to_height`, lowest first
+    fn get_blocks_in_range(&self, from_height: u64, to_height: u64) -> Result<Vec<BlockRecord>, Box<dyn std::error::Error + Send + Sync>>;
+    // Highest stored block as (height, hash)
+    fn get_stored_tip(&self) -> Result<Option<(u64, String)>, Box<dyn std::error::Error + Send + Sync>>;
+    fn get_block_hash_at(&self, height: u64) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>>;
+    fn get_block_time_at(&self, height: u64) -> Result<Option<i64>, Box<dyn std::error::Error + Send + Sync>>;
+    // Remove blocks orphaned by a reorg; returns how many were deleted
+    fn delete_blocks_above(&self, height: u64) -> Result<u64, Box<dyn std::error::Error + Send + Sync>>;
+    // Highest block the daily aggregates were last computed through
+    fn get_aggregates_checkpoint(&self) -> Result<Option<u64>, Box<dyn std::error::Error + Send + Sync>>;
+
+    // Rebuild the daily aggregates from the blocks table for every day on or after `from_date`.
+    // Pass None to rebuild everything.
+    fn recompute_daily_aggregates(&self, from_date: Option<NaiveDate>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut batch = WriteBatch::new();
+        batch.recompute_aggregates(AggregatesFrom::Date(from_date));
+        self.write_batch(batch)
+    }
+
+    /* -------------------- Off chain data operations -------------------- */
+    fn save_fee_estimation(&self, block_target: u16, fee_rate: f64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut batch = WriteBatch::new();
+        batch.add_fee_estimate(block_target, fee_rate);
+        self.write_batch(batch)
+    }
+
+    // The latest estimate for each block target
+    fn get_fee_estimations(&self) -> Result<Vec<FeeEstimationRow>, Box<dyn std::error::Error + Send + Sync>>;
+
+    /* -------------------- Export operations -------------------- */
+    // Stream the rows of `series` dated within the inclusive range to `row`, oldest first, without
+    // holding them in memory; returns how many were passed. An error from `row` stops the query.
+    fn export_series(
+        &self,
+        series: ExportSeries,
+        from_date: Option<NaiveDate>,
+        to_date: Option<NaiveDate>,
+        row: &mut RowSink<'_>,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>>;
+
+    /* -------------------- Full index operations -------------------- */
+    // Base tables only carry the indexes ingestion itself needs; see `build_full_index_secondary_indexes`
+    fn ensure_full_index_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    // Indexes for lookups by address and spender; slow to maintain during the initial load,
+    // so they are created on demand with `project-rust index build`
+    fn build_full_index_secondary_indexes(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    // Write a block and all of its transactions, inputs and outputs in one database transaction,
+    // then mark the outputs its inputs spend
+    fn save_block_with_index(&self, block: &BlockRecord, indexed: IndexedBlock) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut batch = WriteBatch::new();
+        batch.add_indexed_block(block.clone(), indexed);
+        self.write_batch(batch)
+    }
+
+    // Undo the full index above `height` after a reorg, including spends made by orphaned blocks
+    fn delete_index_above(&self, height: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    /* -------------------- Address operations -------------------- */
+    fn get_address_summary(&self, address: &str) -> Result<AddressSummary, Box<dyn std::error::Error + Send + Sync>>;
+    // Newest first; `before` is the (height, txid) of the last row of the previous page
+    fn get_address_history(
+        &self,
+        address: &str,
+        before: Option<(u64, String)>,
+        limit: usize,
+    ) -> Result<Vec<AddressTx>, Box<dyn std::error::Error + Send + Sync>>;
+
+    /* -------------------- Watch-only wallet operations -------------------- */
+    fn ensure_watch_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    // Create a wallet with its descriptors, their addresses and its unspent outputs in one database
+    // transaction, ignoring the outputs' `wallet_id`; returns the wallet id and the descriptor ids in order
+    fn create_watch_wallet(
+        &self,
+        name: &str,
+        gap_limit: u32,
+        descriptors: &[NewWatchDescriptor],
+        utxos: &[WalletUtxo],
+    ) -> Result<(u64, Vec<u64>), Box<dyn std::error::Error + Send + Sync>>;
+    // Store newly derived addresses and move the descriptor's derived_count past them
+    fn save_watch_addresses(
+        &self,
+        descriptor_id: u64,
+        derived_count: u32,
+        addresses: &[WatchAddress],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    fn find_watch_wallet(&self, name: &str) -> Result<Option<WatchWallet>, Box<dyn std::error::Error + Send + Sync>>;
+    fn list_watch_wallets(&self) -> Result<Vec<WatchWallet>, Box<dyn std::error::Error + Send + Sync>>;
+    fn list_watch_descriptors(&self) -> Result<Vec<WatchDescriptor>, Box<dyn std::error::Error + Send + Sync>>;
+    fn list_watch_addresses(&self) -> Result<Vec<WatchAddress>, Box<dyn std::error::Error + Send + Sync>>;
+    fn list_unspent_wallet_utxos(&self) -> Result<Vec<WalletUtxo>, Box<dyn std::error::Error + Send + Sync>>;
+    // Record wallet outputs, spends and events in one database transaction.
+    // Safe to repeat for the same block; returns the events that were not recorded before.
+    fn save_wallet_activity(
+        &self,
+        utxos: &[WalletUtxo],
+        spends: &[WalletSpend],
+        events: Vec<WalletEventRecord>,
+    ) -> Result<Vec<WalletEventRecord>, Box<dyn std::error::Error + Send + Sync>>;
+    // Undo wallet activity from blocks above `height` after a reorg
+    fn delete_wallet_activity_above(&self, height: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    // Confirmed balance and number of unspent outputs of a wallet
+    fn get_wallet_balance(&self, wallet_id: u64) -> Result<(u64, u64), Box<dyn std::error::Error + Send + Sync>>;
+    // Most recent events first
+    fn get_wallet_events(&self, wallet_id: u64, limit: usize) -> Result<Vec<WalletEventRecord>, Box<dyn std::error::Error + Send + Sync>>;
+
+    /* -------------------- Webhook operations -------------------- */
+    fn ensure_webhook_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    fn create_webhook(&self, webhook: &WebhookRecord) -> Result<u64, Box<dyn std::error::Error + Send + Sync>>;
+    fn list_webhooks(&self) -> Result<Vec<WebhookRecord>, Box<dyn std::error::Error + Send + Sync>>;
+    fn find_webhook(&self, id: u64) -> Result<Option<WebhookRecord>, Box<dyn std::error::Error + Send + Sync>>;
+    // Returns false if no webhook with that id exists; its delivery log is kept
+    fn delete_webhook(&self, id: u64) -> Result<bool, Box<dyn std::error::Error + Send + Sync>>;
+    // Record whether a webhook's fee estimate is above its threshold; returns what was recorded before
+    fn swap_webhook_fee_above(&self, webhook_id: u64, above: bool) -> Result<Option<bool>, Box<dyn std::error::Error + Send + Sync>>;
+    // Log a new pending delivery and return its id
+    fn create_webhook_delivery(
+        &self,
+        webhook_id: u64,
+        event_type: &str,
+        payload: &str,
+        created_at: i64,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>>;
+    fn record_webhook_attempt(
+        &self,
+        delivery_id: u64,
+        status: &str,
+        attempts: u32,
+        last_status_code: Option<u16>,
+        last_error: Option<&str>,
+        delivered_at: Option<i64>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    fn find_webhook_delivery(&self, id: u64) -> Result<Option<WebhookDeliveryRecord>, Box<dyn std::error::Error + Send + Sync>>;
+    // Newest first
+    fn list_webhook_deliveries(&self, webhook_id: u64, limit: usize) -> Result<Vec<WebhookDeliveryRecord>, Box<dyn std::error::Error + Send + Sync>>;
+    // Deliveries interrupted by a restart, oldest first
+    fn list_pending_webhook_deliveries(&self) -> Result<Vec<WebhookDeliveryRecord>, Box<dyn std::error::Error + Send + Sync>>;
+
+    /* -------------------- Event outbox operations -------------------- */
+    fn ensure_outbox_table(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    // Oldest events not yet published
+    fn list_unpublished_outbox(&self, limit: usize) -> Result<Vec<OutboxRecord>, Box<dyn std::error::Error + Send + Sync>>;
+    fn mark_outbox_published(&self, ids: &[u64]) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    // Delete events published before `published_before` (unix seconds); returns how many were deleted
+    fn prune_published_outbox(&self, published_before: i64) -> Result<u64, Box<dyn std::error::Error + Send + Sync>>;
+
+    /* -------------------- Metric history operations -------------------- */
+    fn ensure_metric_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    // Samples taken within `from..to`, of one metric or all of them, ordered by metric then time
+    fn get_metric_samples(
+        &self,
+        metric: Option<&str>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<MetricSample>, Box<dyn std::error::Error + Send + Sync>>;
+    fn get_earliest_metric_sample(&self) -> Result<Option<DateTime<Utc>>, Box<dyn std::error::Error + Send + Sync>>;
+    // Returns how many were deleted
+    fn delete_metric_samples_before(&self, before: DateTime<Utc>) -> Result<u64, Box<dyn std::error::Error + Send + Sync>>;
+    // Write rollups, replacing any already stored for the same metric and bucket
+    fn save_metric_rollups(&self, rollups: &[MetricRollup]) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    // Rollups of `metric` with buckets starting within `from..to`, oldest first
+    fn get_metric_rollups(
+        &self,
+        metric: &str,
+        resolution: Resolution,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Result<Vec<MetricRollup>, Box<dyn std::error::Error + Send + Sync>>;
+    // Start of the newest bucket rolled up at `resolution`
+    fn get_latest_metric_rollup(&self, resolution: Resolution) -> Result<Option<DateTime<Utc>>, Box<dyn std::error::Error + Send + Sync>>;
+    fn delete_metric_rollups_before(
+        &self,
+        resolution: Resolution,
+        before: DateTime<Utc>,
+    ) -> Result<u64, Box<dyn std::error::Error + Send + Sync>>;
+
+    /* -------------------- Alert operations -------------------- */
+    fn ensure_alert_tables(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    fn list_alert_states(&self) -> Result<Vec<AlertStateRecord>, Box<dyn std::error::Error + Send + Sync>>;
+    // Store a rule's latest state, and log it in alert_history when the status changed
+    fn save_alert_state(&self, state: &AlertStateRecord, status_changed: bool) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+
+    /* -------------------- API key operations -------------------- */
+    fn ensure_api_keys_table(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    // Store a new key; only the SHA-256 hash of the key is ever persisted
+    fn create_api_key(&self, name: &str, key_hash: &str, rate_limit_per_minute: Option<u32>) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+    fn find_api_key(&self, key_hash: &str) -> Result<Option<ApiKeyRecord>, Box<dyn std::error::Error + Send + Sync>>;
+    fn list_api_keys(&self) -> Result<Vec<ApiKeyRecord>, Box<dyn std::error::Error + Send + Sync>>;
+    // Returns false if no key with that name exists
+    fn revoke_api_key(&self, name: &str) -> Result<bool, Box<dyn std::error::Error + Send + Sync>>;
+}
+
+impl dyn Storage {
+    // Run `call` against this storage on a blocking thread; the way async code should query.
+    // This is tokio's shared blocking pool rather than one of our own: nearly all of a call's time
+    // on the thread is spent holding or waiting for a pooled connection, so DB_POOL_MAX_CONNECTIONS
+    // and the acquire timeout already bound how many calls run and how long the rest wait.
+    pub async fn run<T, F>(self: &Arc<Self>, call: F) -> Result<T, Box<dyn std::error::Error + Send + Sync>>
+    where
+        T: Send + 'static,
+        F: FnOnce(&dyn Storage) -> Result<T, Box<dyn std::error::Error + Send + Sync>> + Send + 'static,
+    {
+        let counted = BlockingCall::start(self.clone());
+        spawn_blocking_in_span(move || call(counted.storage.as_ref())).await
+    }
+}
+
+// Counts a `run` call until its work is done. It goes with the work onto the blocking thread, so a
+// call whose caller gave up keeps counting while it still holds a connection, and one that
+// panics stops counting too.
+struct BlockingCall {
+    storage: Arc<dyn Storage>,
+}
+
+impl BlockingCall {
+    fn start(storage: Arc<dyn Storage>) -> Self {
+        storage.pool_counters().blocking_calls.fetch_add(1, Ordering::Relaxed);
+        Self { storage }
+    }
+}
+
+impl Drop for BlockingCall {
+    fn drop(&mut self) {
+        self.storage.pool_counters().blocking_calls.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Open the database named by `database_url`; the scheme picks the backend
+pub fn connect(database_url: &str) -> Result<Arc<dyn Storage>, Box<dyn std::error::Error + Send + Sync>> {
+    match database_url.split_once("://").map(|(scheme, _)| scheme) {
+        Some("mysql") => Ok(MySqlService::connect(database_url)?),
+        Some("postgres" | "postgresql") => Ok(PostgresService::connect(database_url)?),
+        _ => Err("Database URL must start with mysql:// or postgres://".into()),
+    }
+}
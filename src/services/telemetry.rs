@@ -0,0 +1,77 @@
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{fmt, EnvFilter};
+use crate::config::settings::{LOG_FILTER, LOG_FORMAT, OTEL_ENDPOINT};
+#[cfg(feature = "otel")]
+use crate::config::settings::OTEL_SERVICE_NAME;
+
+// How log lines are written to stderr, chosen in settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    // Human-readable lines
+    Text,
+    // One JSON object per line with the fields of the event and its enclosing spans
+    Json,
+}
+
+// Flushes exported spans when dropped; keep it alive until the process exits
+pub struct TelemetryGuard {
+    #[cfg(feature = "otel")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("Error flushing trace spans: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "otel")]
+fn otel_provider(endpoint: &str) -> Result<opentelemetry_sdk::trace::SdkTracerProvider, Box<dyn std::error::Error + Send + Sync>> {
+    use opentelemetry_otlp::WithExportConfig;
+
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(endpoint)
+        .build()?;
+    let resource = opentelemetry_sdk::Resource::builder().with_service_name(OTEL_SERVICE_NAME).build();
+    Ok(opentelemetry_sdk::trace::SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build())
+}
+
+// Install the global subscriber: level filter, text or JSON logs on stderr, and span export
+// when an OTLP endpoint is configured
+pub fn init_tracing() -> Result<TelemetryGuard, Box<dyn std::error::Error + Send + Sync>> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(LOG_FILTER));
+    let json = LOG_FORMAT == LogFormat::Json;
+    let registry = tracing_subscriber::registry()
+        .with(filter)
+        .with((!json).then(|| fmt::layer().with_writer(std::io::stderr)))
+        .with(json.then(|| fmt::layer().json().with_current_span(true).with_span_list(true).with_writer(std::io::stderr)));
+
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::trace::TracerProvider;
+
+        let provider = OTEL_ENDPOINT.map(otel_provider).transpose()?;
+        let layer = provider.as_ref()
+            .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("project-rust")));
+        registry.with(layer).try_init()?;
+        Ok(TelemetryGuard { provider })
+    }
+    #[cfg(not(feature = "otel"))]
+    {
+        registry.try_init()?;
+        if OTEL_ENDPOINT.is_some() {
+            tracing::warn!("OTEL_ENDPOINT is set but this build lacks the `otel` feature; spans are not exported");
+        }
+        Ok(TelemetryGuard {})
+    }
+}
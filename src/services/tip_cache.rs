@@ -0,0 +1,39 @@
+use std::sync::{Arc, RwLock};
+use chrono::{DateTime, Utc};
+use crate::services::bitcoin_rpc::ChainTip;
+
+// Latest chain tip as seen by ingestion, with the moment it was first observed and the last time
+// the node still reported it
+#[derive(Debug, Clone)]
+pub struct CachedTip {
+    pub tip: ChainTip,
+    pub observed_at: DateTime<Utc>,
+    pub checked_at: DateTime<Utc>,
+}
+
+// In-memory tip shared between the ingestion loop (writer) and the API (readers)
+pub struct TipCache {
+    current: RwLock<Option<CachedTip>>,
+}
+
+impl TipCache {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self { current: RwLock::new(None) })
+    }
+
+    pub fn update(&self, tip: ChainTip) {
+        let now = Utc::now();
+        *self.current.write().unwrap() = Some(CachedTip { tip, observed_at: now, checked_at: now });
+    }
+
+    // Record that the node still reports the same tip; when it was observed stays as it was
+    pub fn touch(&self) {
+        if let Some(cached) = self.current.write().unwrap().as_mut() {
+            cached.checked_at = Utc::now();
+        }
+    }
+
+    pub fn get(&self) -> Option<CachedTip> {
+        self.current.read().unwrap().clone()
+    }
+}
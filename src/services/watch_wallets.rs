@@ -0,0 +1,630 @@
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use bitcoincore_rpc::bitcoin::{Address, Block, OutPoint, Script, Transaction, Txid};
+use bitcoincore_rpc_json::ScanTxOutRequest;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use utoipa::ToSchema;
+use crate::config::connections::BITCOIN_NETWORK;
+use crate::config::settings::{
+    WATCH_DEFAULT_GAP_LIMIT, WATCH_MAX_GAP_LIMIT, WATCH_MAX_MEMPOOL_TXS_PER_TICK, WATCH_RECENT_EVENTS,
+};
+use crate::services::bitcoin_rpc::BitcoinRpcService;
+use crate::services::events::{EventBus, IngestionEvent};
+use crate::services::storage::{
+    NewWatchDescriptor, Storage, WalletEventRecord, WalletSpend, WalletUtxo, WatchAddress, WatchWallet,
+};
+
+#[derive(Debug)]
+pub enum WatchError {
+    Invalid(String),
+    Conflict(String),
+    NotFound,
+    Backend(String),
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for WatchError {
+    fn from(error: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        WatchError::Backend(error.to_string())
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WalletSummary {
+    pub name: String,
+    pub gap_limit: u32,
+    pub descriptors: Vec<String>,
+    // Confirmed balance; mempool activity only shows up in events
+    pub balance_sats: u64,
+    pub utxo_count: u64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WalletEvent {
+    pub txid: String,
+    pub received_sats: u64,
+    pub sent_sats: u64,
+    // None while the transaction is in the mempool
+    pub block_height: Option<u64>,
+    pub seen_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WalletDetail {
+    #[serde(flatten)]
+    pub summary: WalletSummary,
+    // Newest first
+    pub recent_events: Vec<WalletEvent>,
+}
+
+struct DescriptorState {
+    wallet_id: u64,
+    descriptor: String,
+    derived_count: u32,
+    gap_limit: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct AddressOwner {
+    wallet_id: u64,
+    descriptor_id: u64,
+    derivation_index: u32,
+}
+
+// Unspent confirmed wallet outputs, as (wallet_id, value) per outpoint
+type WalletUtxoMap = HashMap<OutPoint, Vec<(u64, u64)>>;
+
+#[derive(Default)]
+struct WatchState {
+    wallet_names: HashMap<u64, String>,
+    descriptors: HashMap<u64, DescriptorState>,
+    // The same address may be watched by more than one wallet
+    addresses: HashMap<String, Vec<AddressOwner>>,
+    utxos: WalletUtxoMap,
+    // Mempool transactions already matched, pruned to the current mempool every tick
+    seen_mempool: HashSet<Txid>,
+}
+
+// A new wallet's addresses and unspent outputs as found by `scantxoutset`, before it is stored
+struct ScannedWallet {
+    descriptors: Vec<NewWatchDescriptor>,
+    utxos: Vec<(OutPoint, WalletUtxo)>,
+    // The height of the UTXO set the node scanned
+    height: u64,
+}
+
+// Outputs and spends of one transaction that belong to watched wallets
+#[derive(Default)]
+struct TxMatches {
+    outputs: Vec<(AddressOwner, u32, String, u64)>,
+    spends: Vec<(u64, OutPoint, u64)>,
+}
+
+// Track an unspent wallet output, once per wallet even if a block is matched twice
+fn add_utxo(state: &mut WatchState, outpoint: OutPoint, wallet_id: u64, value: u64) {
+    let owners = state.utxos.entry(outpoint).or_default();
+    if !owners.iter().any(|(owner, _)| *owner == wallet_id) {
+        owners.push((wallet_id, value));
+    }
+}
+
+fn address_of(script: &Script) -> Option<String> {
+    Address::from_script(script, BITCOIN_NETWORK).ok().map(|address| address.to_string())
+}
+
+// A bare extended public key is watched as native segwit, on its receive and change chains;
+// anything else must be a full ranged descriptor such as `tr([fingerprint/86h/0h/0h]xpub.../0/*)`
+fn expand_descriptor(input: &str) -> Vec<String> {
+    let input = input.trim();
+    if input.contains('(') {
+        vec![input.to_string()]
+    } else {
+        vec![format!("wpkh({}/0/*)", input), format!("wpkh({}/1/*)", input)]
+    }
+}
+
+// Match one transaction against the watched addresses and unspent wallet outputs
+fn match_transaction(state: &WatchState, tx: &Transaction) -> TxMatches {
+    let mut matches = TxMatches::default();
+    if !tx.is_coinbase() {
+        for input in &tx.input {
+            for &(wallet_id, value) in state.utxos.get(&input.previous_output).into_iter().flatten() {
+                matches.spends.push((wallet_id, input.previous_output, value));
+            }
+        }
+    }
+    for (vout, output) in tx.output.iter().enumerate() {
+        let Some(address) = address_of(&output.script_pubkey) else { continue };
+        for &owner in state.addresses.get(&address).into_iter().flatten() {
+            matches.outputs.push((owner, vout as u32, address.clone(), output.value.to_sat()));
+        }
+    }
+    matches
+}
+
+// Per-wallet totals of a transaction's matches, one event per wallet it touched
+fn matches_to_events(txid: &Txid, matches: &TxMatches, block_height: Option<u64>, seen_at: i64) -> Vec<WalletEventRecord> {
+    let mut events: Vec<WalletEventRecord> = Vec::new();
+    let touched = matches.outputs.iter().map(|(owner, _, _, value)| (owner.wallet_id, *value, 0))
+        .chain(matches.spends.iter().map(|(wallet_id, _, value)| (*wallet_id, 0, *value)));
+    for (wallet_id, received, sent) in touched {
+        match events.iter_mut().find(|event| event.wallet_id == wallet_id) {
+            Some(event) => {
+                event.received += received;
+                event.sent += sent;
+            }
+            None => events.push(WalletEventRecord {
+                wallet_id,
+                txid: txid.to_string(),
+                received,
+                sent,
+                block_height,
+                seen_at,
+            }),
+        }
+    }
+    events
+}
+
+// Watch-only wallets built from output descriptors. Addresses are derived by the node up to
+// each wallet's gap limit past the last used one, and matched against every ingested block
+// and new mempool transactions.
+pub struct WatchService {
+    storage: Arc<dyn Storage>,
+    bitcoin_service: Arc<BitcoinRpcService>,
+    event_bus: Arc<EventBus>,
+    // Registration scans without it and only takes it to add the finished wallet, so a wallet is
+    // never half set up while a block is matched
+    state: Mutex<WatchState>,
+}
+
+impl WatchService {
+    pub fn new(
+        storage: Arc<dyn Storage>,
+        bitcoin_service: Arc<BitcoinRpcService>,
+        event_bus: Arc<EventBus>,
+    ) -> Result<Arc<Self>, Box<dyn std::error::Error + Send + Sync>> {
+        let service = Self {
+            storage,
+            bitcoin_service,
+            event_bus,
+            state: Mutex::new(WatchState::default()),
+        };
+        let state = service.load_state()?;
+        *service.state.lock().unwrap() = state;
+        Ok(Arc::new(service))
+    }
+
+    fn load_state(&self) -> Result<WatchState, Box<dyn std::error::Error + Send + Sync>> {
+        let mut state = WatchState::default();
+        let wallets = self.storage.list_watch_wallets()?;
+        for descriptor in self.storage.list_watch_descriptors()? {
+            let gap_limit = wallets.iter()
+                .find(|wallet| wallet.id == descriptor.wallet_id)
+                .map_or(WATCH_DEFAULT_GAP_LIMIT, |wallet| wallet.gap_limit);
+            state.descriptors.insert(descriptor.id, DescriptorState {
+                wallet_id: descriptor.wallet_id,
+                descriptor: descriptor.descriptor,
+                derived_count: descriptor.derived_count,
+                gap_limit,
+            });
+        }
+        for address in self.storage.list_watch_addresses()? {
+            state.addresses.entry(address.address).or_default().push(AddressOwner {
+                wallet_id: address.wallet_id,
+                descriptor_id: address.descriptor_id,
+                derivation_index: address.derivation_index,
+            });
+        }
+        state.utxos = self.load_utxos()?;
+        state.wallet_names = wallets.into_iter().map(|wallet| (wallet.id, wallet.name)).collect();
+        Ok(state)
+    }
+
+    fn load_utxos(&self) -> Result<WalletUtxoMap, Box<dyn std::error::Error + Send + Sync>> {
+        let mut utxos = WalletUtxoMap::new();
+        for utxo in self.storage.list_unspent_wallet_utxos()? {
+            let outpoint = OutPoint::new(Txid::from_str(&utxo.txid)?, utxo.vout);
+            utxos.entry(outpoint).or_default().push((utxo.wallet_id, utxo.value));
+        }
+        Ok(utxos)
+    }
+
+    // Reload everything when wallets were registered by another process sharing the database
+    pub fn reload_if_changed(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let stored = self.storage.list_watch_wallets()?;
+        let mut state = self.state.lock().unwrap();
+        let unchanged = stored.len() == state.wallet_names.len()
+            && stored.iter().all(|wallet| state.wallet_names.contains_key(&wallet.id));
+        if !unchanged {
+            *state = self.load_state()?;
+        }
+        Ok(())
+    }
+
+    pub fn has_wallets(&self) -> bool {
+        !self.state.lock().unwrap().descriptors.is_empty()
+    }
+
+    // Addresses of a descriptor for derivation indexes `start..end`
+    fn derive_range(&self, descriptor: &str, start: u32, end: u32) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        self.bitcoin_service.derive_addresses(descriptor, start, end - 1)?
+            .into_iter()
+            .map(|address| Ok(address.require_network(BITCOIN_NETWORK)?.to_string()))
+            .collect()
+    }
+
+    // Derive and store the descriptor's addresses up to (not including) index `count`
+    fn derive_up_to(&self, state: &mut WatchState, descriptor_id: u64, count: u32) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let descriptor = state.descriptors.get(&descriptor_id).ok_or("Unknown descriptor")?;
+        if count <= descriptor.derived_count {
+            return Ok(());
+        }
+        let start = descriptor.derived_count;
+        let rows: Vec<WatchAddress> = self.derive_range(&descriptor.descriptor, start, count)?
+            .into_iter()
+            .enumerate()
+            .map(|(offset, address)| WatchAddress {
+                wallet_id: descriptor.wallet_id,
+                descriptor_id,
+                derivation_index: start + offset as u32,
+                address,
+            })
+            .collect();
+        self.storage.save_watch_addresses(descriptor_id, count, &rows)?;
+
+        for row in rows {
+            state.addresses.entry(row.address).or_default().push(AddressOwner {
+                wallet_id: row.wallet_id,
+                descriptor_id,
+                derivation_index: row.derivation_index,
+            });
+        }
+        if let Some(descriptor) = state.descriptors.get_mut(&descriptor_id) {
+            descriptor.derived_count = count;
+        }
+        Ok(())
+    }
+
+    // Keep `gap_limit` unused addresses derived past a used one; returns whether new ones were derived
+    fn extend_gap(&self, state: &mut WatchState, owner: AddressOwner) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let Some(descriptor) = state.descriptors.get(&owner.descriptor_id) else {
+            return Ok(false);
+        };
+        let wanted = owner.derivation_index + 1 + descriptor.gap_limit;
+        if wanted <= descriptor.derived_count {
+            return Ok(false);
+        }
+        self.derive_up_to(state, owner.descriptor_id, wanted)?;
+        Ok(true)
+    }
+
+    fn publish(&self, state: &WatchState, events: Vec<WalletEventRecord>) {
+        for event in events {
+            let Some(wallet) = state.wallet_names.get(&event.wallet_id) else { continue };
+            self.event_bus.publish(IngestionEvent::WalletActivity {
+                wallet: wallet.clone(),
+                txid: event.txid,
+                received_sats: event.received,
+                sent_sats: event.sent,
+                block_height: event.block_height,
+            });
+        }
+    }
+
+    // Register a wallet and load its current balance from the node's UTXO set. Nothing is stored
+    // unless the whole registration succeeds, so a failed one can simply be retried.
+    // Past transactions are not imported; events start with the next matching block or mempool entry.
+    pub fn register_wallet(&self, name: &str, descriptors: &[String], gap_limit: Option<u32>) -> Result<WalletDetail, WatchError> {
+        let name = name.trim();
+        if name.is_empty() || name.len() > 128 {
+            return Err(WatchError::Invalid("Wallet name must be 1 to 128 characters".to_string()));
+        }
+        let gap_limit = gap_limit.unwrap_or(WATCH_DEFAULT_GAP_LIMIT);
+        if gap_limit == 0 || gap_limit > WATCH_MAX_GAP_LIMIT {
+            return Err(WatchError::Invalid(format!("Gap limit must be between 1 and {}", WATCH_MAX_GAP_LIMIT)));
+        }
+        if descriptors.is_empty() {
+            return Err(WatchError::Invalid("At least one descriptor or xpub is required".to_string()));
+        }
+
+        // The node checks the syntax and adds the checksum
+        let mut normalized = Vec::new();
+        for descriptor in descriptors.iter().flat_map(|input| expand_descriptor(input)) {
+            let info = self.bitcoin_service.get_descriptor_info(&descriptor)
+                .map_err(|e| WatchError::Invalid(format!("Invalid descriptor {}: {}", descriptor, e)))?;
+            if info.has_private_keys {
+                return Err(WatchError::Invalid("Descriptors must not contain private keys".to_string()));
+            }
+            if !info.is_range {
+                return Err(WatchError::Invalid(format!("Descriptor is not ranged: {}", info.descriptor)));
+            }
+            normalized.push(info.descriptor);
+        }
+
+        let conflict = || WatchError::Conflict(format!("A wallet named '{}' already exists", name));
+        if self.storage.find_watch_wallet(name)?.is_some() {
+            return Err(conflict());
+        }
+        // The scan can take minutes, so blocks and the mempool keep being matched meanwhile
+        let scanned = self.scan_wallet(normalized, gap_limit)?;
+
+        let mut state = self.state.lock().unwrap();
+        if self.storage.find_watch_wallet(name)?.is_some() {
+            return Err(conflict());
+        }
+        self.add_scanned_wallet(&mut state, name, gap_limit, scanned)?;
+        drop(state);
+
+        self.get_wallet(name)
+    }
+
+    // Derive a new wallet's addresses and find its unspent outputs with `scantxoutset`, deriving
+    // further while funded addresses turn up near the end of the range. Emptied addresses are not
+    // in the UTXO set, so a gap of spent-from addresses longer than the gap limit hides anything
+    // beyond it.
+    fn scan_wallet(&self, descriptors: Vec<String>, gap_limit: u32) -> Result<ScannedWallet, Box<dyn std::error::Error + Send + Sync>> {
+        let mut descriptors: Vec<NewWatchDescriptor> = descriptors.into_iter()
+            .map(|descriptor| NewWatchDescriptor { descriptor, addresses: Vec::new() })
+            .collect();
+        let mut wanted = vec![gap_limit; descriptors.len()];
+        loop {
+            for (descriptor, &count) in descriptors.iter_mut().zip(&wanted) {
+                let derived_count = descriptor.addresses.len() as u32;
+                if count > derived_count {
+                    let derived = self.derive_range(&descriptor.descriptor, derived_count, count)?;
+                    descriptor.addresses.extend(derived);
+                }
+            }
+            let requests: Vec<ScanTxOutRequest> = descriptors.iter()
+                .map(|descriptor| ScanTxOutRequest::Extended {
+                    desc: descriptor.descriptor.clone(),
+                    range: (0, descriptor.addresses.len() as u64 - 1),
+                })
+                .collect();
+            let scan = self.bitcoin_service.scan_tx_out_set(&requests)?;
+
+            // Position of each address as (descriptor, derivation index)
+            let positions: HashMap<&str, (usize, u32)> = descriptors.iter()
+                .enumerate()
+                .flat_map(|(position, descriptor)| {
+                    descriptor.addresses.iter().enumerate().map(move |(index, address)| (address.as_str(), (position, index as u32)))
+                })
+                .collect();
+            let mut utxos: Vec<(OutPoint, WalletUtxo)> = Vec::new();
+            let mut extended = false;
+            for unspent in &scan.unspents {
+                let Some(address) = address_of(&unspent.script_pub_key) else { continue };
+                let Some(&(position, index)) = positions.get(address.as_str()) else { continue };
+                if index + 1 + gap_limit > wanted[position] {
+                    wanted[position] = index + 1 + gap_limit;
+                    extended = true;
+                }
+                let outpoint = OutPoint::new(unspent.txid, unspent.vout);
+                if utxos.iter().all(|(seen, _)| *seen != outpoint) {
+                    utxos.push((outpoint, WalletUtxo {
+                        wallet_id: 0,
+                        txid: unspent.txid.to_string(),
+                        vout: unspent.vout,
+                        address,
+                        value: unspent.amount.to_sat(),
+                        block_height: unspent.height,
+                    }));
+                }
+            }
+            if !extended {
+                let height = scan.height.ok_or("scantxoutset did not report the height it scanned at")?;
+                return Ok(ScannedWallet { descriptors, utxos, height });
+            }
+        }
+    }
+
+    // Store a scanned wallet and start matching it. Blocks the node added after the scan were
+    // matched without it, so they are matched again first; they are fetched before anything is
+    // stored, and matching a block twice is harmless.
+    fn add_scanned_wallet(
+        &self,
+        state: &mut WatchState,
+        name: &str,
+        gap_limit: u32,
+        scanned: ScannedWallet,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut missed = Vec::new();
+        for height in scanned.height + 1..=self.bitcoin_service.get_block_height()? {
+            missed.push((self.bitcoin_service.get_block(&self.bitcoin_service.get_block_hash(height)?)?, height));
+        }
+
+        let utxos: Vec<WalletUtxo> = scanned.utxos.iter().map(|(_, utxo)| utxo.clone()).collect();
+        let (wallet_id, descriptor_ids) = self.storage.create_watch_wallet(name, gap_limit, &scanned.descriptors, &utxos)?;
+        state.wallet_names.insert(wallet_id, name.to_string());
+        for (&descriptor_id, descriptor) in descriptor_ids.iter().zip(scanned.descriptors) {
+            let derived_count = descriptor.addresses.len() as u32;
+            for (index, address) in descriptor.addresses.into_iter().enumerate() {
+                state.addresses.entry(address).or_default().push(AddressOwner {
+                    wallet_id,
+                    descriptor_id,
+                    derivation_index: index as u32,
+                });
+            }
+            state.descriptors.insert(descriptor_id, DescriptorState {
+                wallet_id,
+                descriptor: descriptor.descriptor,
+                derived_count,
+                gap_limit,
+            });
+        }
+        for (outpoint, utxo) in scanned.utxos {
+            add_utxo(state, outpoint, wallet_id, utxo.value);
+        }
+
+        for (block, height) in missed {
+            self.match_block(state, &block, height)?;
+        }
+        Ok(())
+    }
+
+    // Record what a block did to watched wallets. Safe to call again for the same block.
+    pub fn process_block(&self, block: &Block, block_height: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut state = self.state.lock().unwrap();
+        self.match_block(&mut state, block, block_height)
+    }
+
+    fn match_block(&self, state: &mut WatchState, block: &Block, block_height: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if state.descriptors.is_empty() {
+            return Ok(());
+        }
+
+        // Move the gap forward first, so outputs to newly derived addresses in this block match too
+        loop {
+            let mut extended = false;
+            for tx in &block.txdata {
+                for owner in match_transaction(state, tx).outputs.into_iter().map(|(owner, ..)| owner) {
+                    extended |= self.extend_gap(state, owner)?;
+                }
+            }
+            if !extended {
+                break;
+            }
+        }
+
+        // Transactions in block order, so spends of outputs created earlier in the block are seen
+        let seen_at = Utc::now().timestamp();
+        let mut utxos = Vec::new();
+        let mut spends = Vec::new();
+        let mut events = Vec::new();
+        for tx in &block.txdata {
+            let matches = match_transaction(state, tx);
+            if matches.outputs.is_empty() && matches.spends.is_empty() {
+                continue;
+            }
+            let txid = tx.compute_txid();
+            for &(wallet_id, outpoint, _) in &matches.spends {
+                if let Some(owners) = state.utxos.get_mut(&outpoint) {
+                    owners.retain(|(owner, _)| *owner != wallet_id);
+                    if owners.is_empty() {
+                        state.utxos.remove(&outpoint);
+                    }
+                }
+                spends.push(WalletSpend {
+                    wallet_id,
+                    txid: outpoint.txid.to_string(),
+                    vout: outpoint.vout,
+                    spent_by_txid: txid.to_string(),
+                    spent_height: block_height,
+                });
+            }
+            for (owner, vout, address, value) in &matches.outputs {
+                add_utxo(state, OutPoint::new(txid, *vout), owner.wallet_id, *value);
+                utxos.push(WalletUtxo {
+                    wallet_id: owner.wallet_id,
+                    txid: txid.to_string(),
+                    vout: *vout,
+                    address: address.clone(),
+                    value: *value,
+                    block_height,
+                });
+            }
+            events.extend(matches_to_events(&txid, &matches, Some(block_height), seen_at));
+        }
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        match self.storage.save_wallet_activity(&utxos, &spends, events) {
+            Ok(recorded) => {
+                self.publish(state, recorded);
+                Ok(())
+            }
+            Err(e) => {
+                // Go back to what is stored; the block is retried on the next tick
+                state.utxos = self.load_utxos()?;
+                Err(e)
+            }
+        }
+    }
+
+    // Match transactions that entered the mempool since the last call. Spends are only recognised
+    // for confirmed wallet outputs, and addresses past the gap limit wait until confirmation.
+    pub fn process_mempool(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut state = self.state.lock().unwrap();
+        if state.descriptors.is_empty() {
+            return Ok(());
+        }
+
+        let mempool = self.bitcoin_service.get_raw_mempool()?;
+        let current: HashSet<Txid> = mempool.iter().copied().collect();
+        state.seen_mempool.retain(|txid| current.contains(txid));
+        let new_txids: Vec<Txid> = mempool.into_iter()
+            .filter(|txid| !state.seen_mempool.contains(txid))
+            .take(WATCH_MAX_MEMPOOL_TXS_PER_TICK)
+            .collect();
+
+        let seen_at = Utc::now().timestamp();
+        let mut events = Vec::new();
+        for txid in new_txids {
+            state.seen_mempool.insert(txid);
+            // Transactions can leave the mempool between the two calls
+            let Ok(tx) = self.bitcoin_service.get_raw_transaction(&txid) else { continue };
+            let matches = match_transaction(&state, &tx);
+            events.extend(matches_to_events(&txid, &matches, None, seen_at));
+        }
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let recorded = self.storage.save_wallet_activity(&[], &[], events)?;
+        self.publish(&state, recorded);
+        Ok(())
+    }
+
+    // Forget wallet activity from blocks above `height` after a reorg
+    pub fn rewind(&self, height: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut state = self.state.lock().unwrap();
+        self.storage.delete_wallet_activity_above(height)?;
+        state.utxos = self.load_utxos()?;
+        Ok(())
+    }
+
+    fn summary(&self, wallet: &WatchWallet) -> Result<WalletSummary, WatchError> {
+        let descriptors = {
+            let state = self.state.lock().unwrap();
+            let mut descriptors: Vec<(u64, String)> = state.descriptors.iter()
+                .filter(|(_, descriptor)| descriptor.wallet_id == wallet.id)
+                .map(|(id, descriptor)| (*id, descriptor.descriptor.clone()))
+                .collect();
+            descriptors.sort();
+            descriptors.into_iter().map(|(_, descriptor)| descriptor).collect()
+        };
+        let (balance_sats, utxo_count) = self.storage.get_wallet_balance(wallet.id)?;
+        Ok(WalletSummary {
+            name: wallet.name.clone(),
+            gap_limit: wallet.gap_limit,
+            descriptors,
+            balance_sats,
+            utxo_count,
+        })
+    }
+
+    pub fn list_wallets(&self) -> Result<Vec<WalletSummary>, WatchError> {
+        self.storage.list_watch_wallets()?
+            .iter()
+            .map(|wallet| self.summary(wallet))
+            .collect()
+    }
+
+    pub fn get_wallet(&self, name: &str) -> Result<WalletDetail, WatchError> {
+        let wallet = self.storage.find_watch_wallet(name)?.ok_or(WatchError::NotFound)?;
+        let recent_events = self.storage.get_wallet_events(wallet.id, WATCH_RECENT_EVENTS)?
+            .into_iter()
+            .map(|event| WalletEvent {
+                txid: event.txid,
+                received_sats: event.received,
+                sent_sats: event.sent,
+                block_height: event.block_height,
+                seen_at: DateTime::from_timestamp(event.seen_at, 0).unwrap_or_default(),
+            })
+            .collect();
+        Ok(WalletDetail { summary: self.summary(&wallet)?, recent_events })
+    }
+}
@@ -0,0 +1,496 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use serde_json::json;
+use sha2::Sha256;
+use tokio::sync::broadcast;
+use tracing::{error, warn};
+use utoipa::ToSchema;
+use crate::config::settings::{
+    WEBHOOK_DELIVERY_LOG_LIMIT, WEBHOOK_INITIAL_BACKOFF_SECS, WEBHOOK_MAX_ATTEMPTS, WEBHOOK_MAX_BACKOFF_SECS,
+    WEBHOOK_TIMEOUT_SECS,
+};
+use crate::services::api_auth::generate_api_key;
+use crate::services::events::{EventBus, IngestionEvent};
+use crate::services::storage::{spawn_blocking_in_span, Storage, WebhookDeliveryRecord, WebhookRecord};
+
+// Headers sent with every delivery
+pub const EVENT_HEADER: &str = "X-Webhook-Event";
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+
+#[derive(Debug)]
+pub enum WebhookError {
+    Invalid(String),
+    NotFound,
+    Storage(String),
+}
+
+impl From<Box<dyn std::error::Error + Send + Sync>> for WebhookError {
+    fn from(error: Box<dyn std::error::Error + Send + Sync>) -> Self {
+        WebhookError::Storage(error.to_string())
+    }
+}
+
+// Event types a webhook can subscribe to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookEvent {
+    NewBlock,
+    // The webhook's fee estimate moved across its threshold, in either direction
+    FeeThreshold,
+    Reorg,
+    DailyTx,
+    WalletActivity,
+    // An alert rule started firing or resolved
+    Alert,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::NewBlock => "new_block",
+            WebhookEvent::FeeThreshold => "fee_threshold",
+            WebhookEvent::Reorg => "reorg",
+            WebhookEvent::DailyTx => "daily_tx",
+            WebhookEvent::WalletActivity => "wallet_activity",
+            WebhookEvent::Alert => "alert",
+        }
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "new_block" => Ok(WebhookEvent::NewBlock),
+            "fee_threshold" => Ok(WebhookEvent::FeeThreshold),
+            "reorg" => Ok(WebhookEvent::Reorg),
+            "daily_tx" => Ok(WebhookEvent::DailyTx),
+            "wallet_activity" => Ok(WebhookEvent::WalletActivity),
+            "alert" => Ok(WebhookEvent::Alert),
+            other => Err(format!("Unknown webhook event: {}", other)),
+        }
+    }
+}
+
+// `sha256=<hex>` HMAC of `<timestamp>.<body>` keyed with the webhook's secret.
+// Receivers recompute it and should reject old timestamps to stop replays.
+pub fn sign_payload(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    let digest = mac.finalize().into_bytes();
+    format!("sha256={}", digest.iter().map(|byte| format!("{:02x}", byte)).collect::<String>())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: WEBHOOK_MAX_ATTEMPTS,
+            initial_backoff: Duration::from_secs(WEBHOOK_INITIAL_BACKOFF_SECS),
+            max_backoff: Duration::from_secs(WEBHOOK_MAX_BACKOFF_SECS),
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Wait before the attempt following `attempt` (counted from 1)
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+// Everything needed to send one delivery
+#[derive(Debug, Clone)]
+pub struct DeliveryRequest {
+    pub delivery_id: u64,
+    pub url: String,
+    pub secret: String,
+    pub event_type: String,
+    pub body: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttemptOutcome {
+    Delivered { status_code: u16 },
+    Failed { status_code: Option<u16>, error: String },
+}
+
+// POST the payload once; any 2xx answer counts as delivered
+pub async fn send_once(client: &reqwest::Client, request: &DeliveryRequest) -> AttemptOutcome {
+    let timestamp = Utc::now().timestamp();
+    let result = client.post(&request.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(EVENT_HEADER, &request.event_type)
+        .header(DELIVERY_HEADER, request.delivery_id.to_string())
+        .header(TIMESTAMP_HEADER, timestamp.to_string())
+        .header(SIGNATURE_HEADER, sign_payload(&request.secret, timestamp, &request.body))
+        .body(request.body.clone())
+        .send()
+        .await;
+    match result {
+        Ok(response) if response.status().is_success() => AttemptOutcome::Delivered { status_code: response.status().as_u16() },
+        Ok(response) => AttemptOutcome::Failed {
+            status_code: Some(response.status().as_u16()),
+            error: format!("Receiver answered {}", response.status()),
+        },
+        Err(e) => AttemptOutcome::Failed { status_code: None, error: e.to_string() },
+    }
+}
+
+// Send until delivered or `policy.max_attempts` is used up, backing off exponentially between attempts.
+// `on_attempt` sees every attempt's number and outcome; the last outcome is returned.
+pub async fn deliver_with_retries<F>(
+    client: &reqwest::Client,
+    request: &DeliveryRequest,
+    policy: RetryPolicy,
+    first_attempt: u32,
+    mut on_attempt: F,
+) -> AttemptOutcome
+where
+    F: FnMut(u32, &AttemptOutcome),
+{
+    let mut attempt = first_attempt.max(1);
+    loop {
+        let outcome = send_once(client, request).await;
+        on_attempt(attempt, &outcome);
+        if matches!(outcome, AttemptOutcome::Delivered { .. }) || attempt >= policy.max_attempts {
+            return outcome;
+        }
+        tokio::time::sleep(policy.backoff(attempt)).await;
+        attempt += 1;
+    }
+}
+
+pub fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(Duration::from_secs(WEBHOOK_TIMEOUT_SECS))
+        .build()
+        .expect("Failed to create webhook HTTP client")
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct WebhookInfo {
+    pub id: u64,
+    pub url: String,
+    pub events: Vec<String>,
+    pub fee_block_target: Option<u16>,
+    pub fee_threshold: Option<f64>,
+    pub created_at: DateTime<Utc>,
+    // Signing secret, only returned when the webhook is created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeliveryInfo {
+    pub id: u64,
+    pub webhook_id: u64,
+    pub event_type: String,
+    pub status: String,
+    pub attempts: u32,
+    pub last_status_code: Option<u16>,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub delivered_at: Option<DateTime<Utc>>,
+    // The exact body that was signed and sent
+    pub payload: serde_json::Value,
+}
+
+fn timestamp_to_utc(timestamp: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(timestamp, 0).unwrap_or_default()
+}
+
+impl From<WebhookRecord> for WebhookInfo {
+    fn from(webhook: WebhookRecord) -> Self {
+        WebhookInfo {
+            id: webhook.id,
+            url: webhook.url,
+            events: webhook.events.split(',').map(str::to_string).collect(),
+            fee_block_target: webhook.fee_block_target,
+            fee_threshold: webhook.fee_threshold,
+            created_at: timestamp_to_utc(webhook.created_at),
+            secret: None,
+        }
+    }
+}
+
+impl From<WebhookDeliveryRecord> for DeliveryInfo {
+    fn from(delivery: WebhookDeliveryRecord) -> Self {
+        DeliveryInfo {
+            id: delivery.id,
+            webhook_id: delivery.webhook_id,
+            event_type: delivery.event_type,
+            status: delivery.status,
+            attempts: delivery.attempts,
+            last_status_code: delivery.last_status_code,
+            last_error: delivery.last_error,
+            created_at: timestamp_to_utc(delivery.created_at),
+            delivered_at: delivery.delivered_at.map(timestamp_to_utc),
+            payload: serde_json::from_str(&delivery.payload).unwrap_or(serde_json::Value::String(delivery.payload)),
+        }
+    }
+}
+
+fn subscribes_to(webhook: &WebhookRecord, event: WebhookEvent) -> bool {
+    webhook.events.split(',').any(|subscribed| subscribed == event.as_str())
+}
+
+// Registered webhooks and their deliveries. Every delivery is logged before it is sent,
+// retried in the background and picked up again after a restart while still pending.
+pub struct WebhookService {
+    storage: Arc<dyn Storage>,
+    client: reqwest::Client,
+    policy: RetryPolicy,
+}
+
+impl WebhookService {
+    pub fn new(storage: Arc<dyn Storage>) -> Arc<Self> {
+        Arc::new(Self {
+            storage,
+            client: http_client(),
+            policy: RetryPolicy::default(),
+        })
+    }
+
+    pub fn register(
+        &self,
+        url: &str,
+        events: &[String],
+        fee_block_target: Option<u16>,
+        fee_threshold: Option<f64>,
+    ) -> Result<WebhookInfo, WebhookError> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| WebhookError::Invalid(format!("Invalid URL {}: {}", url, e)))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(WebhookError::Invalid("Webhook URLs must be http or https".to_string()));
+        }
+        if events.is_empty() {
+            return Err(WebhookError::Invalid("At least one event is required".to_string()));
+        }
+        let mut parsed_events = Vec::new();
+        for event in events {
+            let event = event.parse::<WebhookEvent>().map_err(WebhookError::Invalid)?;
+            if !parsed_events.contains(&event) {
+                parsed_events.push(event);
+            }
+        }
+        if parsed_events.contains(&WebhookEvent::FeeThreshold) && (fee_block_target.is_none() || fee_threshold.is_none()) {
+            return Err(WebhookError::Invalid("fee_threshold events need fee_block_target and fee_threshold".to_string()));
+        }
+
+        let mut webhook = WebhookRecord {
+            id: 0,
+            url: parsed.to_string(),
+            secret: generate_api_key(),
+            events: parsed_events.iter().map(WebhookEvent::as_str).collect::<Vec<_>>().join(","),
+            fee_block_target,
+            fee_threshold,
+            created_at: Utc::now().timestamp(),
+        };
+        webhook.id = self.storage.create_webhook(&webhook)?;
+        let secret = webhook.secret.clone();
+        Ok(WebhookInfo { secret: Some(secret), ..WebhookInfo::from(webhook) })
+    }
+
+    pub fn list(&self) -> Result<Vec<WebhookInfo>, WebhookError> {
+        Ok(self.storage.list_webhooks()?.into_iter().map(WebhookInfo::from).collect())
+    }
+
+    pub fn delete(&self, id: u64) -> Result<(), WebhookError> {
+        if !self.storage.delete_webhook(id)? {
+            return Err(WebhookError::NotFound);
+        }
+        Ok(())
+    }
+
+    pub fn deliveries(&self, webhook_id: u64) -> Result<Vec<DeliveryInfo>, WebhookError> {
+        let deliveries = self.storage.list_webhook_deliveries(webhook_id, WEBHOOK_DELIVERY_LOG_LIMIT)?;
+        Ok(deliveries.into_iter().map(DeliveryInfo::from).collect())
+    }
+
+    // Send a logged delivery's payload again, as a new delivery so the log keeps both
+    pub fn replay(self: &Arc<Self>, delivery_id: u64) -> Result<DeliveryInfo, WebhookError> {
+        let original = self.storage.find_webhook_delivery(delivery_id)?.ok_or(WebhookError::NotFound)?;
+        let webhook = self.storage.find_webhook(original.webhook_id)?
+            .ok_or_else(|| WebhookError::Invalid("The delivery's webhook was deleted".to_string()))?;
+        let created_at = Utc::now().timestamp();
+        let replay_id = self.storage.create_webhook_delivery(webhook.id, &original.event_type, &original.payload, created_at)?;
+        self.spawn_delivery(&webhook, replay_id, &original.event_type, original.payload, 1);
+        let replayed = self.storage.find_webhook_delivery(replay_id)?.ok_or(WebhookError::NotFound)?;
+        Ok(DeliveryInfo::from(replayed))
+    }
+
+    // Restart deliveries that were still being retried when the service stopped
+    pub fn resume_pending(self: &Arc<Self>) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let webhooks: HashMap<u64, WebhookRecord> = self.storage.list_webhooks()?
+            .into_iter()
+            .map(|webhook| (webhook.id, webhook))
+            .collect();
+        for delivery in self.storage.list_pending_webhook_deliveries()? {
+            match webhooks.get(&delivery.webhook_id) {
+                Some(webhook) => {
+                    self.spawn_delivery(webhook, delivery.id, &delivery.event_type, delivery.payload, delivery.attempts + 1);
+                }
+                None => self.storage.record_webhook_attempt(
+                    delivery.id, "failed", delivery.attempts, None, Some("Webhook deleted"), None,
+                )?,
+            }
+        }
+        Ok(())
+    }
+
+    fn spawn_delivery(self: &Arc<Self>, webhook: &WebhookRecord, delivery_id: u64, event_type: &str, body: String, first_attempt: u32) {
+        let request = DeliveryRequest {
+            delivery_id,
+            url: webhook.url.clone(),
+            secret: webhook.secret.clone(),
+            event_type: event_type.to_string(),
+            body,
+        };
+        let service = self.clone();
+        tokio::spawn(async move {
+            let storage = service.storage.clone();
+            let max_attempts = service.policy.max_attempts;
+            deliver_with_retries(&service.client, &request, service.policy, first_attempt, |attempt, outcome| {
+                // Logged on a blocking thread; attempts are at least one backoff apart, so they land in order
+                let storage = storage.clone();
+                let outcome = outcome.clone();
+                tokio::task::spawn_blocking(move || {
+                    let result = match outcome {
+                        AttemptOutcome::Delivered { status_code } => storage.record_webhook_attempt(
+                            delivery_id, "delivered", attempt, Some(status_code), None, Some(Utc::now().timestamp()),
+                        ),
+                        AttemptOutcome::Failed { status_code, error } => {
+                            let status = if attempt >= max_attempts { "failed" } else { "pending" };
+                            storage.record_webhook_attempt(delivery_id, status, attempt, status_code, Some(&error), None)
+                        }
+                    };
+                    if let Err(e) = result {
+                        error!(delivery_id, error = %e, "Failed to log webhook delivery");
+                    }
+                });
+            }).await;
+        });
+    }
+
+    // The webhook payloads an ingestion event turns into, as (event type, data)
+    fn payloads_for(
+        &self,
+        webhook: &WebhookRecord,
+        event: &IngestionEvent,
+    ) -> Result<Vec<(WebhookEvent, serde_json::Value)>, Box<dyn std::error::Error + Send + Sync>> {
+        let simple = match event {
+            IngestionEvent::NewBlock { .. } => Some(WebhookEvent::NewBlock),
+            IngestionEvent::Reorg { .. } => Some(WebhookEvent::Reorg),
+            IngestionEvent::DailyTx { .. } => Some(WebhookEvent::DailyTx),
+            IngestionEvent::WalletActivity { .. } => Some(WebhookEvent::WalletActivity),
+            IngestionEvent::Alert { .. } => Some(WebhookEvent::Alert),
+            IngestionEvent::FeeEstimates { .. } | IngestionEvent::Mempool { .. } => None,
+        };
+        if let Some(kind) = simple {
+            if !subscribes_to(webhook, kind) {
+                return Ok(Vec::new());
+            }
+            return Ok(serde_json::to_value(event).map(|data| vec![(kind, data)]).unwrap_or_default());
+        }
+
+        let IngestionEvent::FeeEstimates { estimates } = event else {
+            return Ok(Vec::new());
+        };
+        let (Some(block_target), Some(threshold)) = (webhook.fee_block_target, webhook.fee_threshold) else {
+            return Ok(Vec::new());
+        };
+        if !subscribes_to(webhook, WebhookEvent::FeeThreshold) {
+            return Ok(Vec::new());
+        }
+        let Some(estimate) = estimates.iter().find(|estimate| estimate.block_target == block_target) else {
+            return Ok(Vec::new());
+        };
+        // The side is stored, so a restart doesn't lose or repeat a crossing; the first estimate
+        // after registering only sets the baseline
+        let above = estimate.fee_rate > threshold;
+        let previous = self.storage.swap_webhook_fee_above(webhook.id, above)?;
+        if previous.is_none_or(|previous| previous == above) {
+            return Ok(Vec::new());
+        }
+        Ok(vec![(WebhookEvent::FeeThreshold, json!({
+            "block_target": block_target,
+            "fee_rate": estimate.fee_rate,
+            "threshold": threshold,
+            "direction": if above { "above" } else { "below" },
+        }))])
+    }
+
+    // Log and send one delivery per webhook interested in the event
+    pub fn dispatch(self: &Arc<Self>, event: &IngestionEvent) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for webhook in self.storage.list_webhooks()? {
+            for (kind, data) in self.payloads_for(&webhook, event)? {
+                let created_at = Utc::now();
+                let body = json!({
+                    "event": kind.as_str(),
+                    "created_at": created_at.to_rfc3339(),
+                    "data": data,
+                }).to_string();
+                let delivery_id = self.storage.create_webhook_delivery(webhook.id, kind.as_str(), &body, created_at.timestamp())?;
+                self.spawn_delivery(&webhook, delivery_id, kind.as_str(), body, 1);
+            }
+        }
+        Ok(())
+    }
+
+    // Log events a lagging dispatcher dropped as a failed delivery to every webhook, so the gap
+    // shows up in each delivery log. They are never sent.
+    pub fn record_missed(&self, skipped: u64) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let created_at = Utc::now();
+        let body = json!({
+            "event": "missed_events",
+            "created_at": created_at.to_rfc3339(),
+            "data": { "skipped": skipped },
+        }).to_string();
+        let error = format!("The dispatcher fell behind and dropped {} events", skipped);
+        for webhook in self.storage.list_webhooks()? {
+            let delivery_id = self.storage.create_webhook_delivery(webhook.id, "missed_events", &body, created_at.timestamp())?;
+            self.storage.record_webhook_attempt(delivery_id, "failed", 0, None, Some(&error), None)?;
+        }
+        Ok(())
+    }
+}
+
+// Turn ingestion events into webhook deliveries
+pub async fn run_webhook_dispatcher(webhook_service: Arc<WebhookService>, event_bus: Arc<EventBus>) {
+    let mut events = event_bus.subscribe();
+    loop {
+        match events.recv().await {
+            Ok(sequenced) => {
+                let service = webhook_service.clone();
+                let event = sequenced.event.clone();
+                if let Err(e) = spawn_blocking_in_span(move || service.dispatch(&event)).await {
+                    error!(event_id = %sequenced.id, error = %e, "Failed to dispatch webhooks");
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!(skipped, "Webhook dispatcher fell behind, events not delivered");
+                let service = webhook_service.clone();
+                if let Err(e) = spawn_blocking_in_span(move || service.record_missed(skipped)).await {
+                    error!(skipped, error = %e, "Failed to log missed webhook events");
+                }
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
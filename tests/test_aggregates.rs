@@ -0,0 +1,52 @@
+use std::sync::Arc;
+use chrono::{NaiveDate, TimeZone, Utc};
+use project_rust::services::storage::{AggregatesFrom, Storage, WriteBatch};
+
+mod common;
+
+use common::{block, connect_mysql, connect_postgres};
+
+fn date(day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2024, 4, day).unwrap()
+}
+
+// One block at noon on each of `days` of April 2024, with ten transactions per day of the month
+fn store_days(storage: &dyn Storage, first_height: u64, days: &[u32]) {
+    let mut batch = WriteBatch::new();
+    for (offset, &day) in days.iter().enumerate() {
+        let time = Utc.with_ymd_and_hms(2024, 4, day, 12, 0, 0).unwrap().timestamp();
+        batch.add_block(block(first_height + offset as u64, time, day * 10));
+    }
+    batch.recompute_aggregates(AggregatesFrom::Height(first_height));
+    storage.write_batch(batch).unwrap();
+}
+
+// Days without stored blocks leave the window; it still spans seven calendar days, not seven rows
+fn averages_span_seven_calendar_days(storage: Arc<dyn Storage>) {
+    storage.ensure_core_tables().unwrap();
+    storage.ensure_blocks_table().unwrap();
+    store_days(storage.as_ref(), 1, &[1, 2, 3, 4, 5, 6, 7]);
+    store_days(storage.as_ref(), 8, &[10]);
+
+    let aggregates = storage.get_daily_aggregates(Some(date(7)), None).unwrap();
+    let dma: Vec<(NaiveDate, f64)> = aggregates.iter().map(|aggregate| (aggregate.date, aggregate.dma_value)).collect();
+    assert_eq!(dma, vec![(date(7), 40.0), (date(10), 64.0)]);
+
+    // Recomputing from a later block keeps the earlier days and still reads them for the window
+    store_days(storage.as_ref(), 9, &[11]);
+    let aggregates = storage.get_daily_aggregates(None, None).unwrap();
+    assert_eq!(aggregates.len(), 9);
+    assert_eq!((aggregates[8].date, aggregates[8].tx_count, aggregates[8].dma_value), (date(11), 110, 78.0));
+}
+
+#[test]
+#[ignore = "needs TEST_POSTGRES_URL"]
+fn averages_span_seven_calendar_days_on_postgres() {
+    averages_span_seven_calendar_days(connect_postgres("dma_gaps"));
+}
+
+#[test]
+#[ignore = "needs TEST_MYSQL_URL"]
+fn averages_span_seven_calendar_days_on_mysql() {
+    averages_span_seven_calendar_days(connect_mysql("dma_gaps"));
+}
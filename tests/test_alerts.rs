@@ -0,0 +1,97 @@
+use chrono::{DateTime, Duration, Utc};
+use project_rust::services::alerts::{evaluate, AlertMetric, AlertRule, AlertStatus, Comparison, RuleState};
+
+fn fee_rule(for_secs: i64) -> AlertRule {
+    AlertRule {
+        name: "fee_high",
+        metric: AlertMetric::FeeRate { block_target: 1 },
+        comparison: Comparison::Above,
+        threshold: 100.0,
+        clear_threshold: 80.0,
+        for_secs,
+    }
+}
+
+fn at(secs: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(1_700_000_000, 0).unwrap() + Duration::seconds(secs)
+}
+
+// Feed `(seconds, value)` observations through the rule and collect the status after each
+fn statuses(rule: &AlertRule, observations: &[(i64, f64)]) -> Vec<AlertStatus> {
+    let mut state = RuleState::default();
+    observations.iter()
+        .map(|&(secs, value)| {
+            state = evaluate(rule, &state, value, at(secs));
+            state.status
+        })
+        .collect()
+}
+
+#[test]
+fn rule_fires_only_after_its_duration() {
+    let rule = fee_rule(600);
+    assert_eq!(
+        statuses(&rule, &[(0, 120.0), (300, 130.0), (600, 125.0)]),
+        vec![AlertStatus::Pending, AlertStatus::Pending, AlertStatus::Firing]
+    );
+}
+
+#[test]
+fn rule_without_duration_fires_immediately() {
+    let rule = fee_rule(0);
+    let state = evaluate(&rule, &RuleState::default(), 101.0, at(0));
+    assert_eq!(state.status, AlertStatus::Firing);
+    assert_eq!(state.fired_at, Some(at(0)));
+}
+
+#[test]
+fn pending_rule_drops_back_when_the_value_recovers() {
+    let rule = fee_rule(600);
+    assert_eq!(
+        statuses(&rule, &[(0, 120.0), (300, 90.0), (600, 120.0)]),
+        vec![AlertStatus::Pending, AlertStatus::Inactive, AlertStatus::Pending]
+    );
+}
+
+#[test]
+fn firing_rule_holds_between_threshold_and_clear_threshold() {
+    let rule = fee_rule(0);
+    assert_eq!(
+        statuses(&rule, &[(0, 120.0), (60, 95.0), (120, 101.0), (180, 85.0), (240, 79.0)]),
+        vec![
+            AlertStatus::Firing,
+            AlertStatus::Firing,
+            AlertStatus::Firing,
+            AlertStatus::Firing,
+            AlertStatus::Resolved,
+        ]
+    );
+}
+
+#[test]
+fn resolved_rule_fires_again_on_a_new_breach() {
+    let rule = fee_rule(0);
+    let mut state = RuleState::default();
+    for (secs, value) in [(0, 120.0), (60, 50.0), (120, 150.0)] {
+        state = evaluate(&rule, &state, value, at(secs));
+    }
+    assert_eq!(state.status, AlertStatus::Firing);
+    assert_eq!(state.fired_at, Some(at(120)));
+    assert_eq!(state.resolved_at, Some(at(60)));
+}
+
+#[test]
+fn below_rule_mirrors_the_comparison() {
+    let rule = AlertRule {
+        name: "mempool_empty",
+        metric: AlertMetric::MempoolTxCount,
+        comparison: Comparison::Below,
+        threshold: 1_000.0,
+        clear_threshold: 2_000.0,
+        for_secs: 0,
+    };
+    assert_eq!(
+        statuses(&rule, &[(0, 500.0), (60, 1_500.0), (120, 2_500.0)]),
+        vec![AlertStatus::Firing, AlertStatus::Firing, AlertStatus::Resolved]
+    );
+}
@@ -0,0 +1,63 @@
+use std::net::IpAddr;
+use std::time::Duration;
+use project_rust::config::settings::KEY_LOOKUP_BURST;
+use project_rust::services::api_auth::{hash_api_key, ApiAuth, AuthError, TokenBucket};
+
+mod common;
+
+use common::connect_postgres;
+
+#[test]
+fn public_routes_match_whole_path_segments() {
+    for path in ["/docs", "/docs/", "/docs/index.html", "/api/openapi.json", "/api/v1/block_info/block_height"] {
+        assert!(ApiAuth::is_public(path), "{}", path);
+    }
+    for path in ["/docsfoo", "/api/openapi.jsonx", "/api/v1/block_info/block_height_x", "/api/v1/block_info", "/api/v1/7d_tx"] {
+        assert!(!ApiAuth::is_public(path), "{}", path);
+    }
+}
+
+#[test]
+fn the_bucket_allows_a_burst_then_waits_for_a_refill() {
+    let mut bucket = TokenBucket::new(3.0);
+    for _ in 0..3 {
+        assert!(bucket.take(3.0, 1.0 / 60.0).is_ok());
+    }
+    let wait = bucket.take(3.0, 1.0 / 60.0).unwrap_err();
+    assert!(wait > Duration::from_secs(55) && wait <= Duration::from_secs(60), "{:?}", wait);
+}
+
+#[test]
+fn the_bucket_refills_over_time_up_to_its_capacity() {
+    let mut bucket = TokenBucket::new(2.0);
+    assert!(bucket.take(2.0, 1000.0).is_ok() && bucket.take(2.0, 1000.0).is_ok());
+    std::thread::sleep(Duration::from_millis(50));
+
+    // Fifty tokens' worth of time passed, but only two fit
+    assert!(bucket.take(2.0, 1000.0).is_ok() && bucket.take(2.0, 1000.0).is_ok());
+    assert!(bucket.take(2.0, 0.001).is_err());
+}
+
+#[test]
+#[ignore = "needs TEST_POSTGRES_URL"]
+fn unknown_keys_are_limited_per_client_before_reaching_the_database() {
+    let storage = connect_postgres("key_lookups");
+    storage.ensure_api_keys_table().unwrap();
+    storage.create_api_key("ops", &hash_api_key("known"), None).unwrap();
+    let auth = ApiAuth::new(storage);
+    let (flooding, other) = (Some(IpAddr::from([10, 0, 0, 1])), Some(IpAddr::from([10, 0, 0, 2])));
+
+    // Every unknown key is a database lookup, until the client has used up its lookups
+    for n in 0..KEY_LOOKUP_BURST {
+        let api_key = format!("random-{}", n);
+        assert!(auth.authorize_cached("/api/v1/7d_tx", Some(&api_key), flooding).is_none());
+        assert!(matches!(auth.authorize_stored(&api_key), Err(AuthError::InvalidKey)));
+    }
+    let limited = auth.authorize_cached("/api/v1/7d_tx", Some("random"), flooding);
+    assert!(matches!(limited, Some(Err(AuthError::RateLimited { .. }))), "{:?}", limited);
+
+    // Other clients still get lookups, and a key found once is then answered from the cache
+    assert!(auth.authorize_cached("/api/v1/7d_tx", Some("known"), other).is_none());
+    assert!(auth.authorize_stored("known").is_ok());
+    assert!(matches!(auth.authorize_cached("/api/v1/7d_tx", Some("known"), flooding), Some(Ok(()))));
+}
@@ -0,0 +1,317 @@
+use std::path::{Path, PathBuf};
+use bitcoincore_rpc::bitcoin::absolute::LockTime;
+use bitcoincore_rpc::bitcoin::consensus::serialize;
+use bitcoincore_rpc::bitcoin::constants::genesis_block;
+use bitcoincore_rpc::bitcoin::hashes::{sha256d, Hash};
+use bitcoincore_rpc::bitcoin::secp256k1::PublicKey;
+use bitcoincore_rpc::bitcoin::transaction::Version;
+use bitcoincore_rpc::bitcoin::{
+    Address, Amount, Block, BlockHash, Network, OutPoint, PubkeyHash, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Txid, WPubkeyHash, Witness,
+};
+use chrono::NaiveDate;
+use project_rust::services::block_files::BlockFiles;
+use project_rust::services::block_index::{index_block, index_spent_outputs, SpentOutput};
+use project_rust::services::ingestion::import_block_files;
+use project_rust::services::storage::WriteBatch;
+
+mod common;
+
+use common::{child, connect_postgres};
+
+const XOR_KEY: [u8; 8] = [0x9c, 0x31, 0x07, 0xe4, 0x5a, 0x88, 0x12, 0xfd];
+
+// Write blocks the way Core does: magic, size and block, obfuscated with `key`, then the
+// preallocated tail of the file as plain zeros
+fn write_block_file(path: &Path, key: [u8; 8], blocks: &[&Block]) {
+    let mut bytes = Vec::new();
+    for block in blocks {
+        let raw = serialize(*block);
+        bytes.extend(Network::Regtest.magic().to_bytes());
+        bytes.extend((raw.len() as u32).to_le_bytes());
+        bytes.extend(raw);
+    }
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte ^= key[index % 8];
+    }
+    bytes.extend([0; 4096]);
+    std::fs::write(path, bytes).unwrap();
+}
+
+// Undo records the way Core writes them: magic, size, the undo data and its checksum over the
+// parent's hash, obfuscated with `key`
+fn write_undo_file(path: &Path, key: [u8; 8], records: &[(BlockHash, Vec<u8>)]) {
+    let mut bytes = Vec::new();
+    for (parent, undo) in records {
+        bytes.extend(Network::Regtest.magic().to_bytes());
+        bytes.extend((undo.len() as u32).to_le_bytes());
+        bytes.extend(undo);
+        let mut checked = parent.to_byte_array().to_vec();
+        checked.extend(undo);
+        bytes.extend(sha256d::Hash::hash(&checked).to_byte_array());
+    }
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte ^= key[index % 8];
+    }
+    bytes.extend([0; 1024]);
+    std::fs::write(path, bytes).unwrap();
+}
+
+// Core's VARINT
+fn varint(mut n: u64) -> Vec<u8> {
+    let mut bytes = vec![(n & 0x7f) as u8];
+    while n > 0x7f {
+        n = (n >> 7) - 1;
+        bytes.push((n & 0x7f) as u8 | 0x80);
+    }
+    bytes.reverse();
+    bytes
+}
+
+// Core's CompressAmount
+fn compress_amount(mut n: u64) -> u64 {
+    if n == 0 {
+        return 0;
+    }
+    let mut exponent = 0;
+    while n.is_multiple_of(10) && exponent < 9 {
+        n /= 10;
+        exponent += 1;
+    }
+    if exponent < 9 {
+        let digit = n % 10;
+        n /= 10;
+        1 + (n * 9 + digit - 1) * 10 + exponent
+    } else {
+        1 + (n - 1) * 10 + 9
+    }
+}
+
+// One spent output in the undo format, its script already compressed
+fn spent_undo(height: u64, coinbase: bool, sats: u64, script: &[u8]) -> Vec<u8> {
+    let mut bytes = varint(height * 2 + u64::from(coinbase));
+    if height > 0 {
+        bytes.extend(varint(0));
+    }
+    bytes.extend(varint(compress_amount(sats)));
+    bytes.extend(script);
+    bytes
+}
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("project_rust_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// genesis <- a1 <- a2 <- a3 is the best chain; b1 is a stale sibling of a1 and `orphan` has no
+// known parent. a2 is stored before a1, as happens with headers-first sync. a3 is a day later.
+struct Fixture {
+    dir: PathBuf,
+    main: Vec<Block>,
+    stale: Block,
+}
+
+fn fixture(name: &str, key: Option<[u8; 8]>) -> Fixture {
+    let dir = scratch_dir(name);
+    let genesis = genesis_block(Network::Regtest);
+    let start = genesis.header.time;
+    let a1 = child(genesis.block_hash(), start + 600, 1);
+    let a2 = child(a1.block_hash(), start + 1200, 2);
+    let a3 = child(a2.block_hash(), start + 86_400, 3);
+    let stale = child(genesis.block_hash(), start + 500, 4);
+    let orphan = child(BlockHash::all_zeros(), start + 700, 5);
+
+    if let Some(key) = key {
+        std::fs::write(dir.join("xor.dat"), key).unwrap();
+    }
+    let key = key.unwrap_or([0; 8]);
+    write_block_file(&dir.join("blk00000.dat"), key, &[&genesis, &stale, &a2]);
+    write_block_file(&dir.join("blk00001.dat"), key, &[&orphan, &a1, &a3]);
+    Fixture { dir, main: vec![genesis, a1, a2, a3], stale }
+}
+
+#[test]
+fn the_most_work_chain_is_followed_through_obfuscated_files() {
+    let fixture = fixture("blk_xor", Some(XOR_KEY));
+    let mut block_files = BlockFiles::open(&fixture.dir, Network::Regtest).unwrap();
+
+    assert_eq!(block_files.tip_height(), 3);
+    for (height, block) in fixture.main.iter().enumerate() {
+        assert_eq!(block_files.block_hash(height as u64), Some(block.block_hash()));
+    }
+    assert_ne!(block_files.block_hash(1), Some(fixture.stale.block_hash()));
+
+    let (record, block) = block_files.read_block(2).unwrap();
+    assert_eq!(block, fixture.main[2]);
+    assert_eq!(record.hash, fixture.main[2].block_hash().to_string());
+    assert_eq!(record.prev_hash, Some(fixture.main[1].block_hash().to_string()));
+    assert_eq!((record.bits.as_str(), record.tx_count), ("207fffff", 1));
+    assert_eq!(record.size as usize, serialize(&fixture.main[2]).len());
+    // Median of the genesis, a1 and a2 times
+    assert_eq!(record.median_time, Some(i64::from(fixture.main[1].header.time)));
+    assert_eq!(block_files.read_block(0).unwrap().0.prev_hash, None);
+    assert!(block_files.read_block(4).is_err());
+    std::fs::remove_dir_all(&fixture.dir).unwrap();
+}
+
+#[test]
+fn files_without_a_key_or_for_another_network() {
+    let fixture = fixture("blk_plain", None);
+    assert_eq!(BlockFiles::open(&fixture.dir, Network::Regtest).unwrap().tip_height(), 3);
+    assert!(BlockFiles::open(&fixture.dir, Network::Bitcoin).is_err());
+
+    // A key that does not match the files hides every record
+    std::fs::write(fixture.dir.join("xor.dat"), XOR_KEY).unwrap();
+    assert!(BlockFiles::open(&fixture.dir, Network::Regtest).is_err());
+    std::fs::remove_dir_all(&fixture.dir).unwrap();
+}
+
+#[test]
+#[ignore = "needs TEST_POSTGRES_URL"]
+fn imported_blocks_feed_the_daily_aggregates() {
+    let storage = connect_postgres("import");
+    storage.ensure_core_tables().unwrap();
+    storage.ensure_blocks_table().unwrap();
+    let fixture = fixture("blk_import", Some(XOR_KEY));
+    let mut block_files = BlockFiles::open(&fixture.dir, Network::Regtest).unwrap();
+
+    assert_eq!(import_block_files(storage.as_ref(), &mut block_files, 0, 3).unwrap(), 4);
+    // Stored heights are skipped on a second run
+    assert_eq!(import_block_files(storage.as_ref(), &mut block_files, 2, 3).unwrap(), 0);
+    assert!(import_block_files(storage.as_ref(), &mut block_files, 0, 4).is_err());
+
+    let stored = storage.get_blocks_in_range(0, 3).unwrap();
+    assert_eq!(stored[3].hash, fixture.main[3].block_hash().to_string());
+    let days: Vec<(NaiveDate, usize)> = storage.get_daily_aggregates(None, None).unwrap()
+        .iter()
+        .map(|aggregate| (aggregate.date, aggregate.tx_count))
+        .collect();
+    let day = |day| NaiveDate::from_ymd_opt(2011, 2, day).unwrap();
+    assert_eq!(days, vec![(day(2), 3), (day(3), 1)]);
+    std::fs::remove_dir_all(&fixture.dir).unwrap();
+}
+
+// The x coordinate of secp256k1's generator, a key with an even y
+const GENERATOR_X: [u8; 32] = [
+    0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce, 0x87, 0x0b, 0x07,
+    0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81, 0x5b, 0x16, 0xf8, 0x17, 0x98,
+];
+
+// genesis <- a1 <- a2, where a2 spends a1's P2PKH coinbase, a P2WPKH output from block 7 and an
+// uncompressed P2PK output from block 3. Their undo records are followed by the record of a block
+// that is not on the chain.
+struct SpendFixture {
+    dir: PathBuf,
+    a1: Block,
+    a2: Block,
+    spent: Vec<SpentOutput>,
+}
+
+fn spend_fixture(name: &str) -> SpendFixture {
+    let dir = scratch_dir(name);
+    let genesis = genesis_block(Network::Regtest);
+    let mut a1 = child(genesis.block_hash(), genesis.header.time + 600, 1);
+    let p2pkh = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([1; 20]));
+    a1.txdata[0].output[0].script_pubkey = p2pkh.clone();
+    a1.header.merkle_root = a1.compute_merkle_root().unwrap();
+
+    let p2wpkh = ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([2; 20]));
+    let mut compressed = vec![0x02];
+    compressed.extend(GENERATOR_X);
+    let mut p2pk = vec![0x41];
+    p2pk.extend(PublicKey::from_slice(&compressed).unwrap().serialize_uncompressed());
+    p2pk.push(0xac);
+    let spent = vec![
+        SpentOutput { height: 1, output: TxOut { value: Amount::from_sat(50_0000_0000), script_pubkey: p2pkh } },
+        SpentOutput { height: 7, output: TxOut { value: Amount::from_sat(12_345), script_pubkey: p2wpkh.clone() } },
+        SpentOutput { height: 3, output: TxOut { value: Amount::from_sat(1_000_000), script_pubkey: ScriptBuf::from_bytes(p2pk) } },
+    ];
+
+    let mut a2 = child(a1.block_hash(), genesis.header.time + 1200, 2);
+    let previous_outputs = [
+        OutPoint { txid: a1.txdata[0].compute_txid(), vout: 0 },
+        OutPoint { txid: Txid::from_byte_array([7; 32]), vout: 1 },
+        OutPoint { txid: Txid::from_byte_array([3; 32]), vout: 0 },
+    ];
+    a2.txdata.push(Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: previous_outputs.iter()
+            .map(|&previous_output| TxIn { previous_output, script_sig: ScriptBuf::new(), sequence: Sequence::MAX, witness: Witness::new() })
+            .collect(),
+        output: vec![TxOut { value: Amount::from_sat(51_0000_0000), script_pubkey: p2wpkh.clone() }],
+    });
+    a2.header.merkle_root = a2.compute_merkle_root().unwrap();
+
+    // One transaction after the coinbase, spending three outputs
+    let mut a2_undo = vec![1, 3];
+    let mut p2pkh_compressed = vec![0x00];
+    p2pkh_compressed.extend([1; 20]);
+    a2_undo.extend(spent_undo(1, true, 50_0000_0000, &p2pkh_compressed));
+    let mut p2wpkh_raw = varint(p2wpkh.len() as u64 + 6);
+    p2wpkh_raw.extend(p2wpkh.as_bytes());
+    a2_undo.extend(spent_undo(7, false, 12_345, &p2wpkh_raw));
+    let mut p2pk_compressed = vec![0x04];
+    p2pk_compressed.extend(GENERATOR_X);
+    a2_undo.extend(spent_undo(3, false, 1_000_000, &p2pk_compressed));
+
+    write_block_file(&dir.join("blk00000.dat"), XOR_KEY, &[&genesis, &a1, &a2]);
+    std::fs::write(dir.join("xor.dat"), XOR_KEY).unwrap();
+    write_undo_file(&dir.join("rev00000.dat"), XOR_KEY, &[
+        (genesis.block_hash(), vec![0]),
+        (a1.block_hash(), a2_undo),
+        (BlockHash::all_zeros(), vec![0]),
+    ]);
+    SpendFixture { dir, a1, a2, spent }
+}
+
+#[test]
+fn spent_outputs_come_from_the_undo_files() {
+    let fixture = spend_fixture("rev_read");
+    let mut block_files = BlockFiles::open(&fixture.dir, Network::Regtest).unwrap();
+
+    assert_eq!(block_files.read_spent_outputs(2).unwrap(), vec![fixture.spent.clone()]);
+    // A record before the last one matched is found by going round to the start of the file
+    assert_eq!(block_files.read_spent_outputs(1).unwrap(), Vec::<Vec<SpentOutput>>::new());
+    assert!(block_files.read_spent_outputs(0).unwrap().is_empty());
+    assert!(block_files.read_spent_outputs(3).is_err());
+
+    let rows = index_spent_outputs(&fixture.a2, std::slice::from_ref(&fixture.spent), Network::Regtest).unwrap();
+    let rows: Vec<(String, u32, u64, u64, &str)> = rows.into_iter()
+        .map(|row| (row.txid, row.output_index, row.block_height, row.value, row.script_type))
+        .collect();
+    assert_eq!(rows, vec![
+        (fixture.a1.txdata[0].compute_txid().to_string(), 0, 1, 50_0000_0000, "pubkeyhash"),
+        (Txid::from_byte_array([7; 32]).to_string(), 1, 7, 12_345, "witness_v0_keyhash"),
+        (Txid::from_byte_array([3; 32]).to_string(), 0, 3, 1_000_000, "pubkey"),
+    ]);
+    // Undo data of another block doesn't fit
+    assert!(index_spent_outputs(&fixture.a1, std::slice::from_ref(&fixture.spent), Network::Regtest).is_err());
+    std::fs::remove_dir_all(&fixture.dir).unwrap();
+}
+
+#[test]
+#[ignore = "needs TEST_POSTGRES_URL"]
+fn spends_of_outputs_older_than_the_import_are_linked() {
+    let storage = connect_postgres("import_spends");
+    storage.ensure_core_tables().unwrap();
+    storage.ensure_blocks_table().unwrap();
+    storage.ensure_full_index_tables().unwrap();
+    let fixture = spend_fixture("rev_import");
+    let mut block_files = BlockFiles::open(&fixture.dir, Network::Regtest).unwrap();
+
+    // Only block 2 is indexed, the way an import does with the full index enabled
+    let (record, block) = block_files.read_block(2).unwrap();
+    let mut indexed = index_block(&block, 2, Network::Regtest);
+    indexed.outputs.extend(index_spent_outputs(&block, &block_files.read_spent_outputs(2).unwrap(), Network::Regtest).unwrap());
+    let mut batch = WriteBatch::new();
+    batch.add_indexed_block(record, indexed);
+    storage.write_batch(batch).unwrap();
+
+    let spender = Address::from_script(&fixture.spent[1].output.script_pubkey, Network::Regtest).unwrap().to_string();
+    let summary = storage.get_address_summary(&spender).unwrap();
+    assert_eq!((summary.total_received, summary.balance), (12_345 + 51_0000_0000, 51_0000_0000));
+    std::fs::remove_dir_all(&fixture.dir).unwrap();
+}
@@ -0,0 +1,143 @@
+use std::sync::Arc;
+use bitcoincore_rpc::bitcoin::absolute::LockTime;
+use bitcoincore_rpc::bitcoin::constants::genesis_block;
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::transaction::Version;
+use bitcoincore_rpc::bitcoin::{
+    Address, Amount, Block, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, WPubkeyHash, Witness,
+};
+use project_rust::services::block_index::{index_block, script_type};
+use project_rust::services::storage::{BlockRecord, Storage, WriteBatch};
+
+mod common;
+
+use common::{child, connect_mysql, connect_postgres};
+
+fn p2wpkh(byte: u8) -> ScriptBuf {
+    ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([byte; 20]))
+}
+
+fn address(script: &ScriptBuf) -> String {
+    Address::from_script(script, Network::Regtest).unwrap().to_string()
+}
+
+// `child` with its coinbase paying to `script`, plus a transaction spending `spends` to `script`
+fn block_paying(prev: &Block, tag: u8, script: ScriptBuf, spends: Option<OutPoint>) -> Block {
+    let mut block = child(prev.block_hash(), prev.header.time + 600, tag);
+    block.txdata[0].output[0].script_pubkey = script.clone();
+    if let Some(previous_output) = spends {
+        block.txdata.push(Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::from_slice(&[vec![0x30; 71], vec![0x02; 33]]),
+            }],
+            output: vec![TxOut { value: Amount::from_sat(49_0000_0000), script_pubkey: script }],
+        });
+    }
+    block.header.merkle_root = block.compute_merkle_root().unwrap();
+    block
+}
+
+fn record(height: u64, block: &Block) -> BlockRecord {
+    BlockRecord {
+        height,
+        hash: block.block_hash().to_string(),
+        prev_hash: Some(block.header.prev_blockhash.to_string()),
+        time: i64::from(block.header.time),
+        median_time: None,
+        version: block.header.version.to_consensus(),
+        bits: format!("{:08x}", block.header.bits.to_consensus()),
+        difficulty: 1.0,
+        nonce: block.header.nonce,
+        size: block.total_size() as u32,
+        weight: block.weight().to_wu() as u32,
+        tx_count: block.txdata.len() as u32,
+    }
+}
+
+#[test]
+fn script_types_use_core_names() {
+    let hash20 = "11".repeat(20);
+    let hash32 = "22".repeat(32);
+    let pubkey = format!("02{}", "33".repeat(32));
+    let cases = [
+        (format!("76a914{}88ac", hash20), "pubkeyhash"),
+        (format!("a914{}87", hash20), "scripthash"),
+        (format!("0014{}", hash20), "witness_v0_keyhash"),
+        (format!("0020{}", hash32), "witness_v0_scripthash"),
+        (format!("5120{}", hash32), "witness_v1_taproot"),
+        (format!("5220{}", hash32), "witness_unknown"),
+        ("6a04deadbeef".to_string(), "nulldata"),
+        (format!("21{}ac", pubkey), "pubkey"),
+        (format!("5121{}51ae", pubkey), "multisig"),
+        ("51".to_string(), "nonstandard"),
+    ];
+    for (hex, expected) in cases {
+        assert_eq!(script_type(&ScriptBuf::from_hex(&hex).unwrap()), expected, "{}", hex);
+    }
+}
+
+#[test]
+fn blocks_flatten_into_transaction_input_and_output_rows() {
+    let genesis = genesis_block(Network::Regtest);
+    let funding = block_paying(&genesis, 1, p2wpkh(1), None);
+    let coinbase = OutPoint { txid: funding.txdata[0].compute_txid(), vout: 0 };
+    let block = block_paying(&funding, 2, p2wpkh(2), Some(coinbase));
+
+    let indexed = index_block(&block, 2, Network::Regtest);
+    assert_eq!(indexed.transactions.len(), 2);
+    let spend = &indexed.transactions[1];
+    assert_eq!((spend.block_index, spend.input_count, spend.output_count, spend.is_coinbase), (1, 1, 1, false));
+    assert!(indexed.transactions[0].is_coinbase);
+    assert!(spend.vsize < spend.size && spend.weight == block.txdata[1].weight().to_wu() as u32);
+
+    assert_eq!((indexed.inputs[0].prev_txid.as_deref(), indexed.inputs[0].prev_vout), (None, None));
+    assert_eq!(indexed.inputs[1].prev_txid, Some(coinbase.txid.to_string()));
+    assert_eq!(indexed.inputs[1].witness_size as usize, block.txdata[1].input[0].witness.size());
+
+    let output = &indexed.outputs[1];
+    assert_eq!((output.value, output.script_type), (49_0000_0000, "witness_v0_keyhash"));
+    assert_eq!(output.address, Some(address(&p2wpkh(2))));
+    assert!(indexed.outputs.iter().all(|output| output.block_height == 2));
+}
+
+// Block 2 spends block 1's coinbase; indexing block 1 again, as a backfill overlap, reorg replay
+// or import does, must not mark that output unspent
+fn spends_survive_indexing_a_block_again(storage: Arc<dyn Storage>) {
+    storage.ensure_core_tables().unwrap();
+    storage.ensure_blocks_table().unwrap();
+    storage.ensure_full_index_tables().unwrap();
+    let genesis = genesis_block(Network::Regtest);
+    let first = block_paying(&genesis, 1, p2wpkh(1), None);
+    let coinbase = OutPoint { txid: first.txdata[0].compute_txid(), vout: 0 };
+    let second = block_paying(&first, 2, p2wpkh(2), Some(coinbase));
+
+    let mut batch = WriteBatch::new();
+    batch.add_indexed_block(record(1, &first), index_block(&first, 1, Network::Regtest));
+    batch.add_indexed_block(record(2, &second), index_block(&second, 2, Network::Regtest));
+    storage.write_batch(batch).unwrap();
+    let spender = address(&p2wpkh(1));
+    assert_eq!(storage.get_address_summary(&spender).unwrap().balance, 0);
+
+    let mut batch = WriteBatch::new();
+    batch.add_indexed_block(record(1, &first), index_block(&first, 1, Network::Regtest));
+    storage.write_batch(batch).unwrap();
+    let summary = storage.get_address_summary(&spender).unwrap();
+    assert_eq!((summary.balance, summary.total_received, summary.tx_count), (0, 50_0000_0000, 2));
+}
+
+#[test]
+#[ignore = "needs TEST_POSTGRES_URL"]
+fn spends_survive_indexing_a_block_again_on_postgres() {
+    spends_survive_indexing_a_block_again(connect_postgres("reindex"));
+}
+
+#[test]
+#[ignore = "needs TEST_MYSQL_URL"]
+fn spends_survive_indexing_a_block_again_on_mysql() {
+    spends_survive_indexing_a_block_again(connect_mysql("reindex"));
+}
@@ -0,0 +1,62 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use project_rust::services::storage::spawn_blocking_in_span;
+
+mod common;
+
+use common::connect_postgres;
+
+#[tokio::test]
+async fn blocking_work_leaves_the_runtime_free() {
+    // The test runtime has a single worker thread, so this only ticks if the sleep runs elsewhere
+    let ticks = Arc::new(AtomicU32::new(0));
+    let ticker = tokio::spawn({
+        let ticks = ticks.clone();
+        async move {
+            loop {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                ticks.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    });
+
+    let output = spawn_blocking_in_span(|| {
+        std::thread::sleep(Duration::from_millis(100));
+        7
+    }).await;
+    ticker.abort();
+
+    assert_eq!(output, 7);
+    assert!(ticks.load(Ordering::Relaxed) >= 3);
+}
+
+#[tokio::test]
+#[should_panic(expected = "query exploded")]
+async fn panics_resume_in_the_caller() {
+    spawn_blocking_in_span(|| panic!("query exploded")).await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs TEST_POSTGRES_URL"]
+async fn calls_count_until_their_work_is_done() {
+    let storage = tokio::task::spawn_blocking(|| connect_postgres("blocking_calls")).await.unwrap();
+
+    let panicked = tokio::spawn({
+        let storage = storage.clone();
+        async move { storage.run(|_| -> Result<(), _> { panic!("query exploded") }).await }
+    });
+    assert!(panicked.await.unwrap_err().is_panic());
+
+    let abandoned = storage.run(|_| {
+        std::thread::sleep(Duration::from_millis(200));
+        Ok(())
+    });
+    assert!(tokio::time::timeout(Duration::from_millis(20), abandoned).await.is_err());
+    assert_eq!(storage.pool_stats().blocking_calls, 1);
+
+    // The abandoned call is still on its thread until the sleep ends
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(storage.pool_stats().blocking_calls, 0);
+    tokio::task::spawn_blocking(move || drop(storage)).await.unwrap();
+}
@@ -0,0 +1,128 @@
+use chrono::{DateTime, NaiveDate};
+use futures_util::StreamExt;
+use project_rust::feed::{sse_event_stream, FeedQuery};
+use project_rust::services::events::{EventBus, EventId, IngestionEvent, Resume};
+use project_rust::services::shutdown::Shutdown;
+use serde_json::json;
+
+#[test]
+fn timestamps_serialize_as_iso_8601_utc() {
+    let event = IngestionEvent::NewBlock {
+        block_height: 840_000,
+        block_hash: "0000000000000000000320283a032748cef8227873ff4872689bf23f1cda83a5".to_string(),
+        block_time: DateTime::from_timestamp(1_713_571_767, 0).unwrap(),
+    };
+    let value = serde_json::to_value(&event).unwrap();
+    assert_eq!(value["block_time"], json!("2024-04-20T00:09:27Z"));
+}
+
+#[test]
+fn dates_serialize_without_a_time() {
+    let event = IngestionEvent::DailyTx {
+        date: NaiveDate::from_ymd_opt(2024, 4, 20).unwrap(),
+        tx_count: 3,
+        dma_value: 1.5,
+    };
+    let value = serde_json::to_value(&event).unwrap();
+    assert_eq!(value["date"], json!("2024-04-20"));
+}
+
+fn mempool(tx_count: usize) -> IngestionEvent {
+    IngestionEvent::Mempool { tx_count, vsize_bytes: 0, usage_bytes: 0 }
+}
+
+// Ids of the events published on `event_bus` so far, oldest first
+fn publish(event_bus: &EventBus, count: usize) -> Vec<EventId> {
+    let mut receiver = event_bus.subscribe();
+    (0..count)
+        .map(|tx_count| {
+            event_bus.publish(mempool(tx_count));
+            receiver.try_recv().unwrap().id
+        })
+        .collect()
+}
+
+fn replayed(resume: Resume) -> Vec<u64> {
+    match resume {
+        Resume::Replay(events) => events.iter().map(|sequenced| sequenced.id.sequence).collect(),
+        Resume::Reset { .. } => panic!("expected a replay, got {:?}", resume),
+    }
+}
+
+#[test]
+fn event_ids_round_trip_through_their_text_form() {
+    let id = EventId { epoch: 1_713_571_767_123_456, sequence: 42 };
+    assert_eq!(id.to_string(), "1713571767123456-42");
+    assert_eq!("1713571767123456-42".parse::<EventId>(), Ok(id));
+    assert!("42".parse::<EventId>().is_err());
+}
+
+#[test]
+fn resuming_replays_what_is_still_buffered() {
+    let event_bus = EventBus::new(16, 2);
+    let ids = publish(&event_bus, 3);
+
+    assert_eq!(replayed(event_bus.subscribe_from(&ids[0].to_string()).0), vec![2, 3]);
+    assert_eq!(replayed(event_bus.subscribe_from(&ids[2].to_string()).0), Vec::<u64>::new());
+}
+
+#[test]
+fn resuming_past_the_buffer_or_a_restart_resets() {
+    let event_bus = EventBus::new(16, 2);
+    let ids = publish(&event_bus, 4);
+
+    // Event 2 is no longer buffered
+    let before_buffer = EventId { sequence: 1, ..ids[0] }.to_string();
+    let past_the_end = [9, u64::MAX].map(|sequence| EventId { sequence, ..ids[0] }.to_string());
+    for last_event_id in [before_buffer, "1-3".to_string(), "garbage".to_string()].into_iter().chain(past_the_end) {
+        match event_bus.subscribe_from(&last_event_id).0 {
+            Resume::Reset { last_id } => assert_eq!(last_id, ids[3], "{}", last_event_id),
+            Resume::Replay(events) => panic!("{} replayed {} events", last_event_id, events.len()),
+        }
+    }
+}
+
+#[test]
+fn without_a_replay_buffer_nothing_is_kept() {
+    let event_bus = EventBus::new(16, 0);
+    let ids = publish(&event_bus, 3);
+
+    assert!(matches!(event_bus.subscribe_from(&ids[1].to_string()).0, Resume::Reset { .. }));
+    assert_eq!(replayed(event_bus.subscribe_from(&ids[2].to_string()).0), Vec::<u64>::new());
+}
+
+#[tokio::test]
+async fn the_event_stream_starts_with_a_reset_when_events_were_lost() {
+    // Only the third event is buffered, so the second is lost to a client that saw the first
+    let event_bus = EventBus::new(16, 1);
+    let ids = publish(&event_bus, 3);
+    let shutdown = Shutdown::new();
+    let query: FeedQuery = serde_json::from_value(json!({ "topics": "mempool" })).unwrap();
+    let stream = sse_event_stream(query, Some(ids[0].to_string()), false, event_bus.clone(), shutdown.clone());
+
+    let mut stream = Box::pin(stream);
+    let first = stream.next().await.unwrap().unwrap().to_string();
+    assert!(first.contains("event:reset\n") && first.contains(&format!("id:{}\n", ids[2])), "{}", first);
+
+    event_bus.publish(mempool(7));
+    let live = stream.next().await.unwrap().unwrap().to_string();
+    assert!(live.contains("event:mempool\n") && live.contains("\"tx_count\":7"), "{}", live);
+
+    shutdown.trigger();
+    assert!(stream.next().await.is_none());
+}
+
+#[tokio::test]
+async fn the_event_stream_replays_then_follows_the_feed() {
+    let event_bus = EventBus::new(16, 8);
+    let ids = publish(&event_bus, 3);
+    let query: FeedQuery = serde_json::from_value(json!({})).unwrap();
+    let stream = sse_event_stream(query, Some(ids[0].to_string()), false, event_bus.clone(), Shutdown::new());
+
+    event_bus.publish(mempool(9));
+    let sent: Vec<String> = Box::pin(stream).take(3).map(|event| event.unwrap().to_string()).collect().await;
+    let expected: Vec<String> = vec![ids[1].to_string(), ids[2].to_string(), EventId { sequence: 4, ..ids[0] }.to_string()];
+    for (event, id) in sent.iter().zip(&expected) {
+        assert!(event.contains(&format!("id:{}\n", id)), "{}", event);
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use bitcoincore_rpc::bitcoin::absolute::LockTime;
+use bitcoincore_rpc::bitcoin::consensus::encode::serialize_hex;
+use bitcoincore_rpc::bitcoin::constants::genesis_block;
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::transaction::Version;
+use bitcoincore_rpc::bitcoin::{
+    Amount, Block, Network, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, WPubkeyHash, Witness,
+};
+use project_rust::config::settings::{EXPLORER_MAX_PREVOUT_LOOKUPS, EXPLORER_PREVOUT_LOOKUP_SLOTS};
+use chrono::DateTime;
+use project_rust::services::bitcoin_rpc::ChainTip;
+use project_rust::services::explorer::{ExplorerService, TransactionInfo};
+use project_rust::services::tip_cache::TipCache;
+use serde_json::{json, Value};
+
+mod common;
+
+use common::{child, header_json, start_rpc_node};
+
+// A transaction spending `inputs` to P2WPKH outputs of `outputs` sats; `tag` keeps otherwise equal
+// transactions distinct
+fn transaction(tag: u32, inputs: &[OutPoint], outputs: &[u64]) -> Transaction {
+    Transaction {
+        version: Version::TWO,
+        lock_time: LockTime::from_consensus(tag),
+        input: inputs.iter()
+            .map(|&previous_output| TxIn { previous_output, script_sig: ScriptBuf::new(), sequence: Sequence::MAX, witness: Witness::new() })
+            .collect(),
+        output: outputs.iter()
+            .map(|&sats| TxOut { value: Amount::from_sat(sats), script_pubkey: ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([1; 20])) })
+            .collect(),
+    }
+}
+
+// Funding transactions with one 1,000 sat output, numbered from `first`
+fn funding(first: u32, count: u32) -> Vec<Transaction> {
+    (first..first + count).map(|tag| transaction(tag, &[OutPoint::null()], &[1_000])).collect()
+}
+
+fn spending(tag: u32, funding: &[Transaction], fee: u64) -> Transaction {
+    let inputs: Vec<OutPoint> = funding.iter().map(|tx| OutPoint { txid: tx.compute_txid(), vout: 0 }).collect();
+    transaction(tag, &inputs, &[1_000 * funding.len() as u64 - fee])
+}
+
+// A node that has every transaction in `transactions` confirmed in block 2, deep enough to cache.
+// Lookups of the transactions in `slow` take SLOW_LOOKUP each. The RPC client sends one call at a
+// time, so calls of other requests get in between them rather than waiting for them all.
+struct FakeNode {
+    chain: Vec<Block>,
+    transactions: HashMap<Txid, Transaction>,
+    slow: HashSet<Txid>,
+    lookups: Mutex<Vec<Txid>>,
+}
+
+const SLOW_LOOKUP: Duration = Duration::from_millis(40);
+
+impl FakeNode {
+    fn new(transactions: &[Transaction], slow: &[Transaction]) -> Arc<Self> {
+        let genesis = genesis_block(Network::Regtest);
+        let first = child(genesis.block_hash(), genesis.header.time + 600, 1);
+        let second = child(first.block_hash(), first.header.time + 600, 2);
+        Arc::new(Self {
+            chain: vec![genesis, first, second],
+            transactions: transactions.iter().chain(slow).map(|tx| (tx.compute_txid(), tx.clone())).collect(),
+            slow: slow.iter().map(Transaction::compute_txid).collect(),
+            lookups: Mutex::new(Vec::new()),
+        })
+    }
+
+    fn answer(&self, method: &str, params: &Value) -> Result<Value, String> {
+        match method {
+            "getrawtransaction" => {
+                let txid: Txid = params[0].as_str().unwrap().parse().unwrap();
+                self.lookups.lock().unwrap().push(txid);
+                if self.slow.contains(&txid) {
+                    std::thread::sleep(SLOW_LOOKUP);
+                }
+                Ok(raw_transaction(&self.transactions[&txid], &self.chain[2], 10))
+            }
+            "getblockheader" => Ok(header_json(&self.chain, 2)),
+            _ => Err(format!("Method not found: {}", method)),
+        }
+    }
+
+    fn lookups_of(&self, txid: Txid) -> usize {
+        self.lookups.lock().unwrap().iter().filter(|looked_up| **looked_up == txid).count()
+    }
+}
+
+// getrawtransaction's answer for `tx` mined in `block`
+fn raw_transaction(tx: &Transaction, block: &Block, confirmations: u32) -> Value {
+    json!({
+        "hex": serialize_hex(tx),
+        "txid": tx.compute_txid(),
+        "hash": tx.compute_wtxid(),
+        "size": tx.total_size(),
+        "vsize": tx.vsize(),
+        "version": 2,
+        "locktime": tx.lock_time.to_consensus_u32(),
+        "vin": tx.input.iter().map(|input| json!({
+            "txid": input.previous_output.txid,
+            "vout": input.previous_output.vout,
+            "sequence": input.sequence.0,
+        })).collect::<Vec<_>>(),
+        "vout": tx.output.iter().enumerate().map(|(n, output)| json!({
+            "value": output.value.to_btc(),
+            "n": n,
+            "scriptPubKey": { "asm": "", "hex": output.script_pubkey.to_hex_string(), "type": "witness_v0_keyhash" },
+        })).collect::<Vec<_>>(),
+        "blockhash": block.block_hash(),
+        "confirmations": confirmations,
+        "blocktime": block.header.time,
+    })
+}
+
+fn explorer(node: Arc<FakeNode>) -> Arc<ExplorerService> {
+    ExplorerService::new(start_rpc_node(move |method, params| node.answer(method, params)), TipCache::new())
+}
+
+fn input_values(info: &TransactionInfo) -> Vec<Option<u64>> {
+    info.inputs.iter().map(|input| input.value_sats).collect()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn each_transaction_spent_from_is_looked_up_once() {
+    let funded = transaction(1, &[OutPoint::null()], &[1_000, 2_000]);
+    let inputs = [OutPoint { txid: funded.compute_txid(), vout: 0 }, OutPoint { txid: funded.compute_txid(), vout: 1 }];
+    let spend = transaction(2, &inputs, &[2_900]);
+    let node = FakeNode::new(&[funded.clone(), spend.clone()], &[]);
+    let explorer = explorer(node.clone());
+
+    let info = tokio::task::spawn_blocking(move || explorer.get_transaction(&spend.compute_txid().to_string()).unwrap()).await.unwrap();
+    assert_eq!(input_values(&info), vec![Some(1_000), Some(2_000)]);
+    assert_eq!(info.fee_sats, Some(100));
+    assert_eq!(node.lookups_of(funded.compute_txid()), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn transactions_spending_from_too_many_others_get_no_input_values() {
+    let funding = funding(100, EXPLORER_MAX_PREVOUT_LOOKUPS as u32 + 1);
+    let spend = spending(1, &funding, 100);
+    let node = FakeNode::new(&[funding.clone(), vec![spend.clone()]].concat(), &[]);
+    let explorer = explorer(node.clone());
+
+    let info = tokio::task::spawn_blocking(move || explorer.get_transaction(&spend.compute_txid().to_string()).unwrap()).await.unwrap();
+    assert!(input_values(&info).iter().all(Option::is_none));
+    assert_eq!(info.fee_sats, None);
+    // Only the transaction itself
+    assert_eq!(node.lookups.lock().unwrap().len(), 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn lookups_past_the_shared_slots_are_left_out_and_not_cached() {
+    // Two transactions whose slow lookups take every slot between them
+    let per_request = EXPLORER_PREVOUT_LOOKUP_SLOTS / 2;
+    assert!(per_request <= EXPLORER_MAX_PREVOUT_LOOKUPS);
+    let slow = funding(1_000, EXPLORER_PREVOUT_LOOKUP_SLOTS as u32);
+    let busy: Vec<Transaction> = slow.chunks(per_request).enumerate().map(|(tag, funding)| spending(tag as u32, funding, 0)).collect();
+    let funded = funding(1, 1);
+    let spend = spending(10, &funded, 100);
+    let node = FakeNode::new(&[busy.clone(), funded, vec![spend.clone()]].concat(), &slow);
+    let explorer = explorer(node.clone());
+
+    let held: Vec<_> = busy.iter()
+        .map(|tx| {
+            let (explorer, txid) = (explorer.clone(), tx.compute_txid().to_string());
+            tokio::task::spawn_blocking(move || explorer.get_transaction(&txid).unwrap())
+        })
+        .collect();
+    // Both hold their slots once the node has seen a lookup from each
+    while slow.chunks(per_request).any(|chunk| chunk.iter().all(|tx| node.lookups_of(tx.compute_txid()) == 0)) {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+
+    let (txid, lookup) = (spend.compute_txid().to_string(), explorer.clone());
+    let info = tokio::task::spawn_blocking(move || lookup.get_transaction(&txid).unwrap()).await.unwrap();
+    assert_eq!((input_values(&info), info.fee_sats), (vec![None], None));
+
+    for request in held {
+        assert_eq!(request.await.unwrap().fee_sats, Some(0));
+    }
+    let txid = spend.compute_txid().to_string();
+    let info = tokio::task::spawn_blocking(move || explorer.get_transaction(&txid).unwrap()).await.unwrap();
+    assert_eq!((input_values(&info), info.fee_sats), (vec![Some(1_000)], Some(100)));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn stale_blocks_and_their_transactions_have_no_confirmations() {
+    let genesis = genesis_block(Network::Regtest);
+    let first = child(genesis.block_hash(), genesis.header.time + 600, 1);
+    let best = vec![genesis.clone(), first.clone(), child(first.block_hash(), first.header.time + 600, 2)];
+    let stale = vec![genesis, first.clone(), child(first.block_hash(), first.header.time + 600, 3)];
+    let (best_block, stale_block) = (best[2].clone(), stale[2].clone());
+    let stale_tx = stale_block.txdata[0].clone();
+
+    let node_stale = stale_block.clone();
+    let bitcoin_service = start_rpc_node(move |method, params| {
+        let is_stale = params[0] == json!(node_stale.block_hash());
+        match method {
+            "getblock" if is_stale => Ok(json!(serialize_hex(&node_stale))),
+            "getblockheader" if is_stale => {
+                let mut header = header_json(&stale, 2);
+                header["confirmations"] = json!(-1);
+                Ok(header)
+            }
+            "getblockheader" => Ok(header_json(&best, 2)),
+            // The fee of the block at that height in the best chain
+            "getblockstats" => Ok(json!({ "totalfee": 1_000 })),
+            "getrawtransaction" if params[0] == json!(node_stale.txdata[0].compute_txid()) => {
+                Ok(raw_transaction(&node_stale.txdata[0], &node_stale, 0))
+            }
+            _ => Err(format!("Method not found: {}", method)),
+        }
+    });
+    let tip_cache = TipCache::new();
+    tip_cache.update(ChainTip {
+        block_height: 2,
+        block_hash: best_block.block_hash().to_string(),
+        block_time: DateTime::from_timestamp(i64::from(best_block.header.time), 0).unwrap(),
+    });
+    let explorer = ExplorerService::new(bitcoin_service, tip_cache);
+
+    let (block, tx) = tokio::task::spawn_blocking(move || {
+        let block = explorer.get_block(&stale_block.block_hash().to_string()).unwrap();
+        (block, explorer.get_transaction(&stale_tx.compute_txid().to_string()).unwrap())
+    }).await.unwrap();
+    assert_eq!((block.confirmations, block.total_fee_sats), (0, None));
+    assert_eq!((tx.status.confirmed, tx.status.confirmations), (false, 0));
+    assert_eq!(tx.status.block_height, Some(2));
+}
@@ -0,0 +1,174 @@
+use std::fs::File;
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, NaiveDate};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use project_rust::services::export::{
+    encoder, ChannelWriter, ExportFormat, ExportSeries, ExportValue, Partition, PartitionedExport,
+};
+use project_rust::server::{export_route, handle_rejection};
+use project_rust::services::storage::{Storage, WriteBatch};
+use std::io::Write;
+use tokio::sync::Semaphore;
+use warp::http::StatusCode;
+use warp::Filter;
+
+mod common;
+
+use common::{connect_mysql, connect_postgres};
+
+fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+fn fee_row(time: i64, block_target: i64, fee_rate: f64) -> Vec<ExportValue> {
+    vec![
+        ExportValue::Timestamp(DateTime::from_timestamp(time, 0).unwrap()),
+        ExportValue::Int(block_target),
+        ExportValue::Float(fee_rate),
+    ]
+}
+
+fn encode(format: ExportFormat, series: ExportSeries, rows: &[Vec<ExportValue>]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut encoder = encoder(format, series, &mut out).unwrap();
+    for row in rows {
+        encoder.write_row(row).unwrap();
+    }
+    encoder.finish().unwrap();
+    out
+}
+
+fn scratch_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("project_rust_{}_{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+#[test]
+fn csv_has_a_header_and_iso_dates() {
+    let rows = [vec![ExportValue::Date(date(2024, 4, 20)), ExportValue::Int(650_000), ExportValue::Float(612_345.5)]];
+    let csv = String::from_utf8(encode(ExportFormat::Csv, ExportSeries::DailyTx, &rows)).unwrap();
+    assert_eq!(csv, "date,tx_count,dma_value\n2024-04-20,650000,612345.5\n");
+}
+
+#[test]
+fn ndjson_writes_one_object_per_line() {
+    let rows = [fee_row(1_713_571_767, 6, 12.5), fee_row(1_713_571_767, 12, 9.0)];
+    let ndjson = String::from_utf8(encode(ExportFormat::Ndjson, ExportSeries::FeeHistory, &rows)).unwrap();
+    let lines: Vec<serde_json::Value> = ndjson.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["estimated_at"], "2024-04-20T00:09:27Z");
+    assert_eq!(lines[1]["block_target"], 12);
+    assert_eq!(lines[1]["fee_rate"], 9.0);
+}
+
+#[test]
+fn parquet_files_read_back_with_every_row() {
+    let rows: Vec<Vec<ExportValue>> = (0..1000).map(|i| fee_row(1_713_571_767 + i * 60, 6, i as f64)).collect();
+    let dir = scratch_dir("parquet");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("fees.parquet");
+    File::create(&path).unwrap().write_all(&encode(ExportFormat::Parquet, ExportSeries::FeeHistory, &rows)).unwrap();
+
+    let reader = SerializedFileReader::new(File::open(&path).unwrap()).unwrap();
+    let metadata = reader.metadata();
+    assert_eq!(metadata.file_metadata().num_rows(), 1000);
+    let columns: Vec<&str> = metadata.file_metadata().schema_descr().columns().iter().map(|column| column.name()).collect();
+    assert_eq!(columns, ["estimated_at", "block_target", "fee_rate"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn partitioned_exports_start_a_file_per_month() {
+    let dir = scratch_dir("partitioned");
+    let mut export = PartitionedExport::new(&dir, ExportSeries::SevenDayDma, ExportFormat::Csv, Partition::Month).unwrap();
+    for (day, dma) in [(date(2024, 3, 30), 1.0), (date(2024, 3, 31), 2.0), (date(2024, 4, 1), 3.0)] {
+        export.write_row(&[ExportValue::Date(day), ExportValue::Float(dma)]).unwrap();
+    }
+    let files = export.finish().unwrap();
+
+    assert_eq!(files, vec![dir.join("dma_2024-03.csv"), dir.join("dma_2024-04.csv")]);
+    assert_eq!(std::fs::read_to_string(&files[0]).unwrap(), "date,dma_value\n2024-03-30,1\n2024-03-31,2\n");
+    assert_eq!(std::fs::read_to_string(&files[1]).unwrap(), "date,dma_value\n2024-04-01,3\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unknown_names_do_not_parse() {
+    assert_eq!(ExportSeries::parse("fee_history"), Some(ExportSeries::FeeHistory));
+    assert_eq!(ExportSeries::parse("mempool"), None);
+    assert_eq!(ExportFormat::parse("xlsx"), None);
+    assert_eq!(Partition::parse("week"), None);
+}
+
+#[test]
+fn channel_writer_stops_once_the_receiver_is_gone() {
+    let (sender, mut receiver) = tokio::sync::mpsc::channel(4);
+    let mut writer = ChannelWriter::new(sender);
+    writer.write_all(b"date,tx_count\n").unwrap();
+    writer.flush().unwrap();
+    assert_eq!(receiver.try_recv().unwrap().unwrap(), b"date,tx_count\n");
+
+    drop(receiver);
+    writer.write_all(b"2024-04-20,3\n").unwrap();
+    assert_eq!(writer.flush().unwrap_err().kind(), std::io::ErrorKind::BrokenPipe);
+}
+
+// Each round of estimates is kept for the export, while the API serves the latest per target
+fn fee_history_keeps_every_estimate(storage: Arc<dyn Storage>) {
+    storage.ensure_core_tables().unwrap();
+    for fee_rate in [10.0, 12.0] {
+        let mut batch = WriteBatch::new();
+        batch.add_fee_estimate(6, fee_rate);
+        storage.write_batch(batch).unwrap();
+        // MySQL keeps the time to the second
+        std::thread::sleep(Duration::from_millis(1100));
+    }
+
+    let mut rates = Vec::new();
+    storage.export_series(ExportSeries::FeeHistory, None, None, &mut |row| {
+        rates.push((row[1], row[2]));
+        Ok(())
+    }).unwrap();
+    assert_eq!(rates, vec![(ExportValue::Int(6), ExportValue::Float(10.0)), (ExportValue::Int(6), ExportValue::Float(12.0))]);
+    let latest: Vec<(u16, f64)> = storage.get_fee_estimations().unwrap().iter().map(|&(target, rate, _)| (target, rate)).collect();
+    assert_eq!(latest, vec![(6, 12.0)]);
+}
+
+#[test]
+#[ignore = "needs TEST_POSTGRES_URL"]
+fn fee_history_keeps_every_estimate_on_postgres() {
+    fee_history_keeps_every_estimate(connect_postgres("fee_history"));
+}
+
+#[test]
+#[ignore = "needs TEST_MYSQL_URL"]
+fn fee_history_keeps_every_estimate_on_mysql() {
+    fee_history_keeps_every_estimate(connect_mysql("fee_history"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs TEST_POSTGRES_URL"]
+async fn exports_past_the_free_slots_are_refused() {
+    let storage = tokio::task::spawn_blocking(|| {
+        let storage = connect_postgres("export_slots");
+        storage.ensure_core_tables().unwrap();
+        storage
+    }).await.unwrap();
+    let export_slots = Arc::new(Semaphore::new(1));
+    let route = export_route(storage, export_slots.clone()).recover(handle_rejection);
+
+    let held = export_slots.clone().try_acquire_owned().unwrap();
+    let refused = warp::test::request().path("/export?series=fee_history").reply(&route).await;
+    assert_eq!(refused.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    drop(held);
+    let served = warp::test::request().path("/export?series=fee_history").reply(&route).await;
+    assert_eq!(served.status(), StatusCode::OK);
+    assert!(served.body().starts_with(b"estimated_at,"));
+    // The slot is given back once the export has been written
+    assert_eq!(export_slots.available_permits(), 1);
+    // The pool has to be dropped outside the runtime
+    tokio::task::spawn_blocking(move || drop(route)).await.unwrap();
+}
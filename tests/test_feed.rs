@@ -0,0 +1,128 @@
+use std::sync::Arc;
+use std::time::Duration;
+use project_rust::feed::{handle_ws_client, FeedQuery};
+use project_rust::services::events::{EventBus, FeeEstimate, IngestionEvent};
+use project_rust::services::shutdown::Shutdown;
+use serde_json::{json, Value};
+use warp::test::WsClient;
+use warp::Filter;
+
+// How long a client must stay quiet before we take it that nothing else is coming
+const QUIET: Duration = Duration::from_millis(100);
+
+fn mempool(tx_count: usize) -> IngestionEvent {
+    IngestionEvent::Mempool { tx_count, vsize_bytes: 0, usage_bytes: 0 }
+}
+
+fn fees(fee_rate: f64) -> IngestionEvent {
+    IngestionEvent::FeeEstimates { estimates: vec![FeeEstimate { block_target: 1, fee_rate }] }
+}
+
+fn wallet_activity() -> IngestionEvent {
+    IngestionEvent::WalletActivity {
+        wallet: "cold".to_string(),
+        txid: "00".repeat(32),
+        received_sats: 1_000,
+        sent_sats: 0,
+        block_height: None,
+    }
+}
+
+async fn connect(path: &str, admin: bool, event_bus: &Arc<EventBus>) -> WsClient {
+    let event_bus = event_bus.clone();
+    let shutdown = Shutdown::new();
+    let route = warp::ws().and(warp::query::<FeedQuery>()).map(move |ws: warp::ws::Ws, query: FeedQuery| {
+        let (event_bus, shutdown) = (event_bus.clone(), shutdown.clone());
+        ws.on_upgrade(move |socket| handle_ws_client(socket, query, admin, event_bus, shutdown))
+    });
+    warp::test::ws().path(path).handshake(route).await.expect("handshake")
+}
+
+async fn next_event(client: &mut WsClient) -> Option<Value> {
+    let message = tokio::time::timeout(QUIET, client.recv()).await.ok()?.ok()?;
+    Some(serde_json::from_str(message.to_str().expect("text frame")).expect("json event"))
+}
+
+// The client subscribes and handles its messages in its own task, so keep publishing until
+// `event` comes through, then drop the extra copies
+async fn publish_until_received(client: &mut WsClient, event_bus: &EventBus, event: IngestionEvent) -> Value {
+    for _ in 0..50 {
+        event_bus.publish(event.clone());
+        if let Some(received) = next_event(client).await {
+            while next_event(client).await.is_some() {}
+            return received;
+        }
+    }
+    panic!("{} was never delivered", event.kind());
+}
+
+#[tokio::test]
+async fn clients_only_receive_the_topics_they_asked_for() {
+    let event_bus = EventBus::new(16, 0);
+    let mut client = connect("/?topics=fees,wallets", false, &event_bus).await;
+    publish_until_received(&mut client, &event_bus, fees(1.0)).await;
+
+    // Wallet activity stays hidden from non-admin clients even when asked for
+    event_bus.publish(wallet_activity());
+    event_bus.publish(mempool(3));
+    event_bus.publish(fees(5.0));
+    let event = next_event(&mut client).await.expect("fee estimates");
+    assert_eq!(event["type"], json!("fee_estimates"));
+    assert_eq!(event["estimates"][0]["fee_rate"], json!(5.0));
+    assert_eq!(next_event(&mut client).await, None);
+}
+
+#[tokio::test]
+async fn admin_clients_receive_wallet_activity() {
+    let event_bus = EventBus::new(16, 0);
+    let mut client = connect("/?topics=wallets", true, &event_bus).await;
+    let event = publish_until_received(&mut client, &event_bus, wallet_activity()).await;
+    assert_eq!(event["type"], json!("wallet_activity"));
+}
+
+#[tokio::test]
+async fn subscribe_and_unsubscribe_messages_change_the_topics() {
+    let event_bus = EventBus::new(16, 0);
+    let mut client = connect("/", false, &event_bus).await;
+
+    client.send_text(json!({"action": "subscribe", "topics": ["mempool"]}).to_string()).await;
+    let event = publish_until_received(&mut client, &event_bus, mempool(1)).await;
+    assert_eq!(event["type"], json!("mempool"));
+
+    // Messages are handled in order, so once fees arrive mempool is already unsubscribed
+    client.send_text(json!({"action": "unsubscribe", "topics": ["mempool"]}).to_string()).await;
+    client.send_text(json!({"action": "subscribe", "topics": ["fees"]}).to_string()).await;
+    publish_until_received(&mut client, &event_bus, fees(1.0)).await;
+
+    event_bus.publish(mempool(2));
+    event_bus.publish(fees(9.0));
+    let event = next_event(&mut client).await.expect("fee estimates");
+    assert_eq!(event["estimates"][0]["fee_rate"], json!(9.0));
+    assert_eq!(next_event(&mut client).await, None);
+}
+
+#[tokio::test]
+async fn malformed_messages_are_ignored() {
+    let event_bus = EventBus::new(16, 0);
+    let mut client = connect("/?topics=mempool", false, &event_bus).await;
+    client.send_text("{\"action\":\"shout\"}").await;
+    client.send_text("not json").await;
+    let event = publish_until_received(&mut client, &event_bus, mempool(1)).await;
+    assert_eq!(event["type"], json!("mempool"));
+}
+
+#[tokio::test]
+async fn lagging_clients_are_disconnected() {
+    let event_bus = EventBus::new(2, 0);
+    let mut client = connect("/?topics=mempool", false, &event_bus).await;
+    publish_until_received(&mut client, &event_bus, mempool(0)).await;
+
+    // The test runtime is single threaded, so the client cannot read until we yield
+    for tx_count in 1..=10 {
+        event_bus.publish(mempool(tx_count));
+    }
+    tokio::time::timeout(Duration::from_secs(5), client.recv_closed())
+        .await
+        .expect("lagging client was not disconnected")
+        .expect("connection closed");
+}
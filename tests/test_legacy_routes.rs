@@ -0,0 +1,36 @@
+use project_rust::server::{legacy_route, openapi_document};
+use project_rust::services::api_auth::ApiAuth;
+use utoipa::openapi::path::HttpMethod;
+use utoipa::openapi::Deprecated;
+use warp::http::{header, StatusCode};
+
+#[tokio::test]
+async fn legacy_routes_redirect_to_v1() {
+    let route = legacy_route();
+    for (path, location) in [
+        ("/api/block_info/block_height", "/api/v1/block_info/block_height"),
+        ("/api/7d_tx", "/api/v1/7d_tx"),
+        ("/api/fee_estimations?x=1", "/api/v1/fee_estimations?x=1"),
+    ] {
+        let reply = warp::test::request().path(path).reply(&route).await;
+        assert_eq!(reply.status(), StatusCode::PERMANENT_REDIRECT, "{}", path);
+        assert_eq!(reply.headers()[header::LOCATION], location);
+        assert_eq!(reply.headers()["deprecation"], "true");
+    }
+    for path in ["/api/v1/7d_tx", "/api/blocks", "/api/7d_tx/x"] {
+        assert!(!warp::test::request().path(path).matches(&route).await, "{}", path);
+    }
+    // Polled by the front-end without a key, as its new path is
+    assert!(ApiAuth::is_public("/api/block_info/block_height"));
+}
+
+#[test]
+fn legacy_routes_are_deprecated_in_the_openapi_document() {
+    let document = openapi_document();
+    for path in ["/api/block_info/block_height", "/api/7d_tx", "/api/fee_estimations"] {
+        let operation = document.paths.get_path_operation(path, HttpMethod::Get).unwrap_or_else(|| panic!("{}", path));
+        assert!(matches!(operation.deprecated, Some(Deprecated::True)), "{}", path);
+    }
+    let current = document.paths.get_path_operation("/api/v1/7d_tx", HttpMethod::Get).unwrap();
+    assert!(!matches!(current.deprecated, Some(Deprecated::True)));
+}
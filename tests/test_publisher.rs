@@ -0,0 +1,130 @@
+use chrono::NaiveDate;
+use project_rust::services::storage::{self, OutboxRecord};
+use project_rust::services::publisher::{
+    relay_outbox_batch, EventEnvelope, EventPublisher, FilePublisher, LinePublisher, OutboxEvent, EVENT_SCHEMA_VERSION,
+};
+use serde_json::json;
+
+mod common;
+
+use common::postgres_url;
+
+fn envelope(id: u64, event: &OutboxEvent) -> EventEnvelope {
+    EventEnvelope::try_from(OutboxRecord {
+        id,
+        event_type: event.event_type().to_string(),
+        payload: event.data().to_string(),
+        created_at: 1_700_000_000,
+    })
+    .unwrap()
+}
+
+#[test]
+fn outbox_events_have_stable_types_and_fields() {
+    let rollup = OutboxEvent::DailyTxRollup {
+        date: NaiveDate::from_ymd_opt(2024, 3, 1).unwrap(),
+        tx_count: 450_000,
+        dma_value: 420_000.5,
+    };
+    assert_eq!(rollup.event_type(), "daily_tx.rollup");
+    assert_eq!(rollup.data(), json!({ "date": "2024-03-01", "tx_count": 450_000, "dma_value": 420_000.5 }));
+
+    let reorg = OutboxEvent::Reorg { fork_height: 800_000, orphaned_blocks: 2 };
+    assert_eq!(reorg.event_type(), "chain.reorg");
+    assert_eq!(reorg.data(), json!({ "fork_height": 800_000, "orphaned_blocks": 2 }));
+
+    let fee = OutboxEvent::FeeEstimate { block_target: 6, fee_rate: 12.5 };
+    assert_eq!(fee.event_type(), "fee.estimate");
+    assert_eq!(fee.data(), json!({ "block_target": 6, "fee_rate": 12.5 }));
+}
+
+#[test]
+fn envelope_is_versioned_and_carries_the_outbox_id() {
+    let event = OutboxEvent::BlockIngested {
+        height: 840_000,
+        hash: "00".repeat(32),
+        prev_hash: None,
+        time: 1_713_571_767,
+        tx_count: 3_050,
+        size: 2_325_617,
+        weight: 3_993_281,
+    };
+    let value = serde_json::to_value(envelope(17, &event)).unwrap();
+    assert_eq!(value["schema_version"], json!(EVENT_SCHEMA_VERSION));
+    assert_eq!(value["id"], json!(17));
+    assert_eq!(value["type"], json!("block.ingested"));
+    assert_eq!(value["occurred_at"], json!("2023-11-14T22:13:20Z"));
+    assert_eq!(value["data"]["height"], json!(840_000));
+    assert_eq!(value["data"]["prev_hash"], json!(null));
+}
+
+#[test]
+fn malformed_outbox_payload_is_an_error() {
+    let record = OutboxRecord {
+        id: 1,
+        event_type: "fee.estimate".to_string(),
+        payload: "{not json".to_string(),
+        created_at: 0,
+    };
+    assert!(EventEnvelope::try_from(record).is_err());
+}
+
+#[tokio::test]
+async fn line_publisher_writes_one_json_object_per_line() {
+    let events = vec![
+        envelope(1, &OutboxEvent::FeeEstimate { block_target: 1, fee_rate: 30.0 }),
+        envelope(2, &OutboxEvent::Reorg { fork_height: 10, orphaned_blocks: 1 }),
+    ];
+    let publisher = LinePublisher::new(Vec::new());
+    publisher.publish(&events).await.unwrap();
+
+    let output = String::from_utf8(publisher.into_inner()).unwrap();
+    let parsed: Vec<EventEnvelope> = output.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(parsed, events);
+}
+
+#[tokio::test]
+async fn file_publisher_appends_across_batches() {
+    let path = std::env::temp_dir().join(format!("outbox-test-{}.ndjson", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let publisher = FilePublisher::open(path.to_str().unwrap()).unwrap();
+
+    publisher.publish(&[envelope(1, &OutboxEvent::FeeEstimate { block_target: 1, fee_rate: 30.0 })]).await.unwrap();
+    publisher.publish(&[envelope(2, &OutboxEvent::FeeEstimate { block_target: 1, fee_rate: 31.0 })]).await.unwrap();
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let ids: Vec<u64> = contents.lines()
+        .map(|line| serde_json::from_str::<EventEnvelope>(line).unwrap().id)
+        .collect();
+    assert_eq!(ids, vec![1, 2]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs TEST_POSTGRES_URL"]
+async fn malformed_outbox_rows_are_skipped_instead_of_blocking_the_relay() {
+    // The synchronous postgres client can't be used, or dropped, on the runtime's own threads
+    let runtime = tokio::runtime::Handle::current();
+    tokio::task::spawn_blocking(move || {
+        let url = postgres_url("relay");
+        let storage = storage::connect(&url).unwrap();
+        storage.ensure_outbox_table().unwrap();
+        let mut client = postgres::Client::connect(&url, postgres::NoTls).unwrap();
+        for payload in [r#"{"block_target":1,"fee_rate":30.0}"#, "{not json", r#"{"block_target":2,"fee_rate":20.0}"#] {
+            client.execute(
+                "INSERT INTO event_outbox (event_type, payload, created_at) VALUES ('fee.estimate', $1, 0)",
+                &[&payload],
+            ).unwrap();
+        }
+
+        let publisher = LinePublisher::new(Vec::new());
+        assert_eq!(runtime.block_on(relay_outbox_batch(&storage, &publisher)).unwrap(), 3);
+        assert_eq!(runtime.block_on(relay_outbox_batch(&storage, &publisher)).unwrap(), 0);
+
+        let output = String::from_utf8(publisher.into_inner()).unwrap();
+        let ids: Vec<u64> = output.lines()
+            .map(|line| serde_json::from_str::<EventEnvelope>(line).unwrap().id)
+            .collect();
+        assert_eq!(ids, vec![1, 3]);
+    }).await.unwrap();
+}
@@ -0,0 +1,133 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use project_rust::services::events::{EventBus, FeeEstimate, IngestionEvent};
+use project_rust::services::response_cache::{
+    cached_request, run_cache_invalidation, CachedRequest, CachedResponse, ResponseCache,
+};
+use warp::http::{header, StatusCode};
+use warp::Filter;
+
+// A route answering from `cache`, keyed on its `days` parameter, that counts fresh responses
+fn route(
+    cache: Arc<ResponseCache>,
+    served: Arc<AtomicUsize>,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    keyed_route(cache, served, false)
+}
+
+fn keyed_route(
+    cache: Arc<ResponseCache>,
+    served: Arc<AtomicUsize>,
+    auth_enabled: bool,
+) -> impl Filter<Extract = (warp::reply::Response,), Error = warp::Rejection> + Clone {
+    warp::path!("api" / "v1" / "7d_tx")
+        .and(cached_request(cache, auth_enabled, &["days"]))
+        .map(move |request: CachedRequest| {
+            request.lookup().unwrap_or_else(|| {
+                served.fetch_add(1, Ordering::SeqCst);
+                request.store(&vec![1, 2, 3]).unwrap()
+            })
+        })
+}
+
+#[tokio::test]
+async fn matching_etags_get_not_modified() {
+    let served = Arc::new(AtomicUsize::new(0));
+    let route = route(ResponseCache::new(), served.clone());
+
+    let first = warp::test::request().path("/api/v1/7d_tx").reply(&route).await;
+    assert_eq!(first.status(), StatusCode::OK);
+    assert_eq!(first.body().as_ref(), b"[1,2,3]");
+    let etag = first.headers()[header::ETAG].to_str().unwrap().to_string();
+    assert!(first.headers()[header::CACHE_CONTROL].to_str().unwrap().starts_with("public, max-age="));
+
+    for if_none_match in [etag.clone(), format!("W/{}", etag), format!("\"other\", {}", etag), "*".to_string()] {
+        let reply = warp::test::request().path("/api/v1/7d_tx").header("if-none-match", if_none_match).reply(&route).await;
+        assert_eq!(reply.status(), StatusCode::NOT_MODIFIED);
+        assert!(reply.body().is_empty());
+    }
+    let stale = warp::test::request().path("/api/v1/7d_tx").header("if-none-match", "\"other\"").reply(&route).await;
+    assert_eq!(stale.status(), StatusCode::OK);
+    assert_eq!(served.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn keyed_routes_are_not_stored_by_shared_caches() {
+    for (auth_enabled, cache_control) in [(false, "public, max-age=30"), (true, "private, max-age=30")] {
+        let route = keyed_route(ResponseCache::new(), Arc::new(AtomicUsize::new(0)), auth_enabled);
+        // The second request is answered from the cache and must say the same
+        for _ in 0..2 {
+            let reply = warp::test::request().path("/api/v1/7d_tx").reply(&route).await;
+            assert_eq!(reply.headers()[header::CACHE_CONTROL], cache_control);
+            let vary = reply.headers().get(header::VARY).map(|value| value.to_str().unwrap());
+            assert_eq!(vary, auth_enabled.then_some("X-API-Key, Authorization"));
+        }
+    }
+}
+
+#[tokio::test]
+async fn only_the_query_parameters_a_route_reads_make_new_entries() {
+    let cache = ResponseCache::new();
+    let served = Arc::new(AtomicUsize::new(0));
+    let route = route(cache.clone(), served.clone());
+
+    for path in ["/api/v1/7d_tx?x=1", "/api/v1/7d_tx?x=2", "/api/v1/7d_tx", "/api/v1/7d_tx?days=7&x=3", "/api/v1/7d_tx?x=4&days=7"] {
+        warp::test::request().path(path).reply(&route).await;
+    }
+    assert_eq!((cache.len(), served.load(Ordering::SeqCst)), (2, 2));
+}
+
+#[tokio::test]
+async fn responses_read_before_an_invalidation_are_not_stored() {
+    let cache = ResponseCache::new();
+    let invalidate_during_read = Arc::new(AtomicBool::new(true));
+    let route = {
+        let (cache, invalidate_during_read) = (cache.clone(), invalidate_during_read.clone());
+        warp::path!("api" / "v1" / "7d_tx")
+            .and(cached_request(cache.clone(), false, &[]))
+            .map(move |request: CachedRequest| {
+                // New data lands between the handler's read and its store
+                if invalidate_during_read.load(Ordering::SeqCst) {
+                    cache.invalidate(&["/api/v1/7d_tx"]);
+                }
+                request.store(&vec![1, 2, 3]).unwrap()
+            })
+    };
+
+    let reply = warp::test::request().path("/api/v1/7d_tx").reply(&route).await;
+    assert_eq!(reply.body().as_ref(), b"[1,2,3]");
+    assert!(cache.is_empty());
+
+    invalidate_during_read.store(false, Ordering::SeqCst);
+    warp::test::request().path("/api/v1/7d_tx").reply(&route).await;
+    assert_eq!(cache.len(), 1);
+}
+
+#[test]
+fn the_least_recently_used_entry_is_evicted() {
+    let cache = ResponseCache::with_capacity(2);
+    cache.insert("/a".to_string(), CachedResponse::new("1".to_string()));
+    cache.insert("/b".to_string(), CachedResponse::new("2".to_string()));
+    assert!(cache.get("/a").is_some());
+    cache.insert("/c".to_string(), CachedResponse::new("3".to_string()));
+
+    assert_eq!(cache.len(), 2);
+    assert!(cache.get("/b").is_none());
+    assert!(cache.get("/a").is_some() && cache.get("/c").is_some());
+}
+
+#[tokio::test]
+async fn published_events_invalidate_the_routes_they_change() {
+    let cache = ResponseCache::new();
+    let event_bus = EventBus::new(16, 16);
+    tokio::spawn(run_cache_invalidation(cache.clone(), event_bus.clone()));
+    cache.insert("/api/v1/fee_estimations".to_string(), CachedResponse::new("[]".to_string()));
+    cache.insert("/api/v1/7d_tx".to_string(), CachedResponse::new("[]".to_string()));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+
+    event_bus.publish(IngestionEvent::FeeEstimates { estimates: vec![FeeEstimate { block_target: 6, fee_rate: 12.0 }] });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(cache.get("/api/v1/fee_estimations").is_none());
+    assert!(cache.get("/api/v1/7d_tx").is_some());
+}
@@ -0,0 +1,185 @@
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use bitcoincore_rpc::bitcoin::consensus::serialize;
+use bitcoincore_rpc::bitcoin::constants::genesis_block;
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::{Block, BlockHash, Network};
+use project_rust::services::bitcoin_rpc::{is_not_found, BitcoinRpcService};
+use project_rust::services::ingestion::{backfill_blocks, bootstrap_start_height};
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+use warp::Filter;
+
+mod common;
+
+use common::{child, connect_postgres, header_json};
+
+// Regtest genesis and four blocks on it, ten minutes apart
+fn chain() -> Vec<Block> {
+    let mut chain = vec![genesis_block(Network::Regtest)];
+    for tag in 1..=4 {
+        let prev = &chain[chain.len() - 1];
+        chain.push(child(prev.block_hash(), prev.header.time + 600, tag));
+    }
+    chain
+}
+
+// A node on a free local port that serves `chain` over REST, at most `page_size` headers per
+// request, and answers getblockcount over JSON-RPC. Every RPC method called is recorded.
+fn start_node(chain: Vec<Block>, page_size: usize) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let chain = Arc::new(chain);
+    let rpc_calls = Arc::new(Mutex::new(Vec::new()));
+    let height_of = {
+        let chain = chain.clone();
+        move |hash: &str| chain.iter().position(|block| block.block_hash().to_string() == hash)
+    };
+
+    let block_hash = {
+        let chain = chain.clone();
+        warp::path!("rest" / "blockhashbyheight" / String).map(move |file: String| {
+            let height: usize = file.trim_end_matches(".bin").parse().unwrap();
+            match chain.get(height) {
+                Some(block) => warp::reply::with_status(serialize(&block.block_hash()), StatusCode::OK),
+                None => warp::reply::with_status(b"Block height out of range".to_vec(), StatusCode::NOT_FOUND),
+            }
+        })
+    };
+    let block = {
+        let (chain, height_of) = (chain.clone(), height_of.clone());
+        warp::path!("rest" / "block" / String).map(move |file: String| {
+            let hash = file.trim_end_matches(".bin");
+            match height_of(hash) {
+                Some(height) => warp::reply::with_status(serialize(&chain[height]), StatusCode::OK),
+                None => warp::reply::with_status(format!("{} not found", hash).into_bytes(), StatusCode::NOT_FOUND),
+            }
+        })
+    };
+    let headers = {
+        let chain = chain.clone();
+        warp::path!("rest" / "headers" / String)
+            .and(warp::query::<std::collections::HashMap<String, usize>>())
+            .map(move |file: String, query: std::collections::HashMap<String, usize>| {
+                let count = query["count"].min(page_size);
+                let headers: Vec<Value> = match height_of(file.trim_end_matches(".json")) {
+                    Some(start) => (start..chain.len().min(start + count)).map(|height| header_json(&chain, height)).collect(),
+                    None => Vec::new(),
+                };
+                warp::reply::json(&headers)
+            })
+    };
+    let rpc = {
+        let (chain, rpc_calls) = (chain.clone(), rpc_calls.clone());
+        warp::post().and(warp::body::json()).map(move |request: Value| {
+            let method = request["method"].as_str().unwrap_or_default().to_string();
+            rpc_calls.lock().unwrap().push(method.clone());
+            let result = match method.as_str() {
+                "getblockcount" => json!(chain.len() - 1),
+                _ => Value::Null,
+            };
+            warp::reply::json(&json!({ "result": result, "error": null, "id": request["id"] }))
+        })
+    };
+
+    let routes = warp::get().and(block_hash.or(block).or(headers)).or(rpc);
+    let (addr, server) = warp::serve(routes).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    (addr, rpc_calls)
+}
+
+fn service(addr: SocketAddr) -> Arc<BitcoinRpcService> {
+    let url = format!("http://{}", addr);
+    BitcoinRpcService::new(&url, "user", "password", Some(&url))
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn blocks_and_headers_come_over_rest() {
+    let chain = chain();
+    let (addr, rpc_calls) = start_node(chain.clone(), 2);
+    let bitcoin_service = service(addr);
+
+    tokio::task::spawn_blocking(move || {
+        let hash = bitcoin_service.get_block_hash(3).unwrap();
+        assert_eq!(hash, chain[3].block_hash());
+        assert_eq!(bitcoin_service.get_block(&hash).unwrap(), chain[3]);
+        assert_eq!(bitcoin_service.get_block_header_info(&hash).unwrap().height, 3);
+
+        // Pages of two, followed until the tip
+        let heights: Vec<usize> = bitcoin_service.get_block_headers(&chain[0].block_hash(), 10).unwrap()
+            .iter()
+            .map(|header| header.height)
+            .collect();
+        assert_eq!(heights, vec![0, 1, 2, 3, 4]);
+        assert_eq!(bitcoin_service.get_block_headers(&chain[1].block_hash(), 3).unwrap().len(), 3);
+
+        // Calls REST does not cover still go over RPC
+        assert_eq!(bitcoin_service.get_block_height().unwrap(), 4);
+    }).await.unwrap();
+    assert_eq!(*rpc_calls.lock().unwrap(), vec!["getblockcount"]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unknown_blocks_are_not_found_unlike_a_node_without_rest() {
+    let (addr, _) = start_node(chain(), 2000);
+    let bitcoin_service = service(addr);
+    let route = warp::any().map(|| warp::reply::with_status("", StatusCode::NOT_FOUND));
+    let (disabled_addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    let without_rest = service(disabled_addr);
+
+    tokio::task::spawn_blocking(move || {
+        let unknown = BlockHash::all_zeros();
+        assert!(is_not_found(bitcoin_service.get_block(&unknown).unwrap_err().as_ref()));
+        assert!(is_not_found(bitcoin_service.get_block_hash(5).unwrap_err().as_ref()));
+        assert!(is_not_found(bitcoin_service.get_block_header_info(&unknown).unwrap_err().as_ref()));
+
+        let error = without_rest.get_block_hash(0).unwrap_err();
+        assert!(!is_not_found(error.as_ref()));
+        assert!(error.to_string().contains("-rest"));
+    }).await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn bootstrap_starts_at_the_first_block_of_a_utc_day() {
+    // Blocks 15 minutes apart from midnight, 96 a day, with block 195 stamped a minute before day 2
+    let midnight = 1_700_006_400;
+    let mut chain = vec![genesis_block(Network::Regtest)];
+    for height in 1..=1250u32 {
+        let time = if height == 195 { midnight + 2 * 86_400 - 60 } else { midnight + height * 900 };
+        chain.push(child(chain[chain.len() - 1].block_hash(), time, height as u8));
+    }
+    let (addr, _) = start_node(chain, 2000);
+    let bitcoin_service = service(addr);
+
+    tokio::task::spawn_blocking(move || {
+        // 1008 below the tip is block 242, on day 2, which starts after block 195
+        assert_eq!(bootstrap_start_height(&bitcoin_service, 1250).unwrap(), 196);
+        // Close to genesis everything is stored
+        assert_eq!(bootstrap_start_height(&bitcoin_service, 1000).unwrap(), 0);
+    }).await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs TEST_POSTGRES_URL"]
+async fn backfilled_rows_match_the_blocks_fetched_over_rest() {
+    let chain = chain();
+    let (addr, rpc_calls) = start_node(chain.clone(), 2000);
+    let bitcoin_service = service(addr);
+
+    // The synchronous postgres client can't be used on the runtime's own threads
+    tokio::task::spawn_blocking(move || {
+        let storage = connect_postgres("rest_backfill");
+        storage.ensure_core_tables().unwrap();
+        storage.ensure_blocks_table().unwrap();
+        assert_eq!(backfill_blocks(storage.as_ref(), &bitcoin_service, 0, 4).unwrap(), 5);
+
+        let stored = storage.get_blocks_in_range(0, 4).unwrap();
+        let record = &stored[4];
+        assert_eq!(record.hash, chain[4].block_hash().to_string());
+        assert_eq!(record.prev_hash, Some(chain[3].block_hash().to_string()));
+        assert_eq!((record.bits.as_str(), record.tx_count), ("207fffff", 1));
+        assert_eq!(record.size as usize, serialize(&chain[4]).len());
+        assert_eq!(record.median_time, Some(i64::from(chain[2].header.time)));
+        assert_eq!(stored[0].prev_hash, None);
+    }).await.unwrap();
+    assert_eq!(*rpc_calls.lock().unwrap(), vec!["getblockcount"]);
+}
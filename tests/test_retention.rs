@@ -0,0 +1,60 @@
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use project_rust::services::retention::{choose_resolution, metric_names, rollup, Resolution};
+use project_rust::services::storage::MetricSample;
+
+fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 4, 20, hour, minute, 0).unwrap()
+}
+
+fn sample(metric: &str, sampled_at: DateTime<Utc>, value: f64) -> MetricSample {
+    MetricSample { metric: metric.to_string(), sampled_at, value }
+}
+
+#[test]
+fn short_recent_ranges_are_served_raw() {
+    let now = at(12, 0);
+    assert_eq!(choose_resolution(now - TimeDelta::hours(6), now, now), Resolution::Raw);
+    // Too many points at one per ingestion tick
+    assert_eq!(choose_resolution(now - TimeDelta::days(7), now, now), Resolution::Hour);
+    // Too many hourly points
+    assert_eq!(choose_resolution(now - TimeDelta::days(365), now, now), Resolution::Day);
+}
+
+#[test]
+fn ranges_past_retention_fall_back_to_coarser_rollups() {
+    let now = at(12, 0);
+    // An hour, but from before raw samples are deleted
+    let from = now - TimeDelta::days(30);
+    assert_eq!(choose_resolution(from, from + TimeDelta::hours(1), now), Resolution::Hour);
+    let from = now - TimeDelta::days(200);
+    assert_eq!(choose_resolution(from, from + TimeDelta::hours(1), now), Resolution::Day);
+}
+
+#[test]
+fn rollups_summarize_each_metric_and_bucket() {
+    let mut samples: Vec<MetricSample> = (0..60).map(|minute| sample("fee_rate_6_blocks", at(10, minute), f64::from(minute + 1))).collect();
+    samples.push(sample("fee_rate_6_blocks", at(11, 0), 100.0));
+    samples.push(sample("mempool_tx_count", at(10, 30), 5000.0));
+
+    let rollups = rollup(&samples, Resolution::Hour);
+    assert_eq!(rollups.len(), 3);
+    let first = &rollups[0];
+    assert_eq!((first.metric.as_str(), first.bucket_start, first.samples), ("fee_rate_6_blocks", at(10, 0), 60));
+    assert_eq!((first.min, first.max, first.avg), (1.0, 60.0, 30.5));
+    assert_eq!(first.p50, 30.5);
+    assert!((first.p90 - 54.1).abs() < 1e-9);
+    assert_eq!((rollups[1].bucket_start, rollups[1].p99), (at(11, 0), 100.0));
+    assert_eq!(rollups[2].metric, "mempool_tx_count");
+
+    let daily = rollup(&samples, Resolution::Day);
+    assert_eq!(daily.iter().map(|rollup| rollup.samples).collect::<Vec<_>>(), vec![61, 1]);
+    assert_eq!(daily[0].bucket_start, at(0, 0));
+}
+
+#[test]
+fn metric_names_match_the_alert_metrics() {
+    let names = metric_names();
+    assert!(names.contains(&"fee_rate_6_blocks".to_string()));
+    assert!(names.contains(&"mempool_vsize_bytes".to_string()));
+    assert_eq!(Resolution::parse("minute"), None);
+}
@@ -18,7 +18,7 @@ mod tests {
         //     Ok(()) => assert!(true), // If the function succeeds
         //     Err(e) => panic!("RPC test failed: {:?}", e), // If the function fails, panic with the error
         // }
-        test_rpc_func();
+        let _ = test_rpc_func();
     }
 
     // #[test]
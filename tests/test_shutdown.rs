@@ -0,0 +1,65 @@
+use std::time::Duration;
+use futures_util::StreamExt;
+use project_rust::feed::{sse_event_stream, FeedQuery};
+use project_rust::services::events::{EventBus, IngestionEvent};
+use project_rust::services::shutdown::{with_deadline, Shutdown};
+
+#[tokio::test]
+async fn wait_resolves_once_triggered() {
+    let shutdown = Shutdown::new();
+    let waiter = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move { shutdown.wait().await }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!waiter.is_finished());
+    assert!(!shutdown.is_triggered());
+
+    shutdown.trigger();
+    tokio::time::timeout(Duration::from_secs(1), waiter).await.expect("waiter woke up").unwrap();
+    assert!(shutdown.is_triggered());
+    // Waiting after the fact returns immediately
+    tokio::time::timeout(Duration::from_millis(100), shutdown.wait()).await.expect("already triggered");
+}
+
+#[tokio::test]
+async fn work_finishing_in_time_is_not_cut_off() {
+    let shutdown = Shutdown::new();
+    shutdown.trigger();
+    let work = async {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        42
+    };
+    assert_eq!(with_deadline(&shutdown, Duration::from_secs(1), work).await, Some(42));
+}
+
+#[tokio::test]
+async fn deadline_only_runs_from_the_trigger() {
+    let shutdown = Shutdown::new();
+    let trigger = shutdown.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        trigger.trigger();
+    });
+    // Outlives the deadline counted from start, but not from the trigger
+    let work = tokio::time::sleep(Duration::from_millis(80));
+    assert_eq!(with_deadline(&shutdown, Duration::from_millis(100), work).await, Some(()));
+
+    let stuck = std::future::pending::<()>();
+    assert_eq!(with_deadline(&shutdown, Duration::from_millis(20), stuck).await, None);
+}
+
+#[tokio::test]
+async fn sse_stream_ends_on_shutdown() {
+    let event_bus = EventBus::new(16, 16);
+    let shutdown = Shutdown::new();
+    let query: FeedQuery = serde_json::from_str("{}").unwrap();
+    let mut stream = Box::pin(sse_event_stream(query, None, false, event_bus.clone(), shutdown.clone()));
+
+    event_bus.publish(IngestionEvent::Reorg { fork_height: 1, orphaned_blocks: 1 });
+    assert!(stream.next().await.is_some());
+
+    shutdown.trigger();
+    let end = tokio::time::timeout(Duration::from_secs(1), stream.next()).await.expect("stream ended");
+    assert!(end.is_none());
+}
@@ -0,0 +1,117 @@
+use std::sync::{Arc, Mutex};
+use bitcoincore_rpc::bitcoin::constants::genesis_block;
+use std::time::Duration;
+use bitcoincore_rpc::bitcoin::{Block, Network};
+use chrono::DateTime;
+use project_rust::server::block_height_route;
+use project_rust::services::bitcoin_rpc::{BitcoinRpcService, ChainTip};
+use project_rust::services::events::{EventBus, IngestionEvent};
+use project_rust::services::ingestion::poll_chain_tip;
+use project_rust::services::tip_cache::TipCache;
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+
+mod common;
+
+use common::{child, header_json, start_rpc_node};
+
+// Regtest genesis and two blocks on it, the second tagged `tag`
+fn chain(tag: u8) -> Vec<Block> {
+    let genesis = genesis_block(Network::Regtest);
+    let first = child(genesis.block_hash(), genesis.header.time + 600, 1);
+    let second = child(first.block_hash(), first.header.time + 600, tag);
+    vec![genesis, first, second]
+}
+
+// The tip at `block`, as the node reports it
+fn tip(block: &Block, block_height: u64) -> ChainTip {
+    ChainTip {
+        block_height,
+        block_hash: block.block_hash().to_string(),
+        block_time: DateTime::from_timestamp(i64::from(block.header.time), 0).unwrap(),
+    }
+}
+
+// A node answering getbestblockhash and getblockheader for whichever chain `chain` holds
+fn start_node(chain: Arc<Mutex<Vec<Block>>>) -> Arc<BitcoinRpcService> {
+    start_rpc_node(move |method, params| {
+        let chain = chain.lock().unwrap();
+        match method {
+            "getbestblockhash" => Ok(json!(chain[chain.len() - 1].block_hash())),
+            "getblockheader" => {
+                let height = chain.iter().position(|block| block.block_hash().to_string() == params[0]).unwrap();
+                Ok(header_json(&chain, height))
+            }
+            _ => Err(format!("Method not found: {}", method)),
+        }
+    })
+}
+
+#[test]
+fn touching_the_tip_keeps_when_it_was_observed() {
+    let tip_cache = TipCache::new();
+    tip_cache.touch();
+    assert!(tip_cache.get().is_none());
+
+    tip_cache.update(tip(&chain(2)[2], 2));
+    let observed = tip_cache.get().unwrap();
+    std::thread::sleep(Duration::from_millis(10));
+    tip_cache.touch();
+    let touched = tip_cache.get().unwrap();
+    assert_eq!(touched.observed_at, observed.observed_at);
+    assert!(touched.checked_at > observed.checked_at);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn a_reorg_to_a_tip_at_the_same_height_replaces_the_cached_tip() {
+    let node_chain = Arc::new(Mutex::new(chain(2)));
+    let bitcoin_service = start_node(node_chain.clone());
+    let event_bus = EventBus::new(16, 16);
+    let mut events = event_bus.subscribe();
+    let tip_cache = TipCache::new();
+
+    let new_tips = tokio::task::spawn_blocking(move || {
+        let mut last_tip = None;
+        poll_chain_tip(None, &bitcoin_service, &event_bus, &tip_cache, &mut last_tip);
+        let first = tip_cache.get().unwrap();
+        poll_chain_tip(None, &bitcoin_service, &event_bus, &tip_cache, &mut last_tip);
+        assert_eq!(tip_cache.get().unwrap().observed_at, first.observed_at);
+
+        *node_chain.lock().unwrap() = chain(3);
+        poll_chain_tip(None, &bitcoin_service, &event_bus, &tip_cache, &mut last_tip);
+        let replaced = tip_cache.get().unwrap();
+        assert_eq!(replaced.tip.block_height, 2);
+        assert_eq!(replaced.tip.block_hash, chain(3)[2].block_hash().to_string());
+        assert!(replaced.observed_at > first.observed_at);
+        vec![first.tip.block_hash, replaced.tip.block_hash]
+    }).await.unwrap();
+
+    // One event per tip, none for the unchanged poll
+    for block_hash in new_tips {
+        match events.try_recv().unwrap().event {
+            IngestionEvent::NewBlock { block_height, block_hash: published, .. } => {
+                assert_eq!((block_height, published), (2, block_hash));
+            }
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+    assert!(events.try_recv().is_err());
+}
+
+#[tokio::test]
+async fn the_block_height_endpoint_serves_the_cached_tip() {
+    let tip_cache = TipCache::new();
+    let route = block_height_route(tip_cache.clone());
+
+    let empty = warp::test::request().path("/block_info/block_height").reply(&route).await;
+    assert_eq!(empty.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+    let chain = chain(2);
+    tip_cache.update(tip(&chain[2], 2));
+    let reply = warp::test::request().path("/block_info/block_height").reply(&route).await;
+    assert_eq!(reply.status(), StatusCode::OK);
+    let body: Value = serde_json::from_slice(reply.body()).unwrap();
+    assert_eq!((body["block_height"].clone(), body["block_hash"].clone()), (json!(2), json!(chain[2].block_hash().to_string())));
+    assert_eq!(body["observed_at"], body["checked_at"]);
+    assert_eq!(body["data_age_secs"], json!(0));
+}
@@ -0,0 +1,162 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+use bitcoincore_rpc::bitcoin::consensus::encode::serialize_hex;
+use bitcoincore_rpc::bitcoin::constants::genesis_block;
+use bitcoincore_rpc::bitcoin::hashes::Hash;
+use bitcoincore_rpc::bitcoin::{Address, Block, Network, ScriptBuf, Txid, WPubkeyHash};
+use project_rust::services::bitcoin_rpc::BitcoinRpcService;
+use project_rust::services::events::EventBus;
+use project_rust::services::watch_wallets::{WatchError, WatchService};
+use serde_json::{json, Value};
+
+mod common;
+
+use common::{child, connect_postgres, start_rpc_node};
+
+const DESCRIPTOR: &str = "wpkh(xpub/0/*)#checksum";
+
+// The script of the wallet's address at `index`
+fn script(index: u64) -> ScriptBuf {
+    ScriptBuf::new_p2wpkh(&WPubkeyHash::from_byte_array([index as u8 + 1; 20]))
+}
+
+// A node whose UTXO set, scanned at height 1, holds `unspents` as (derivation index, sats), while
+// its chain is already at height 2, with block 2 paying 50 BTC to the wallet's first address.
+// The scan fails while `scan_fails` is set and waits for `scan_released` once `scan_started` is.
+struct FakeNode {
+    chain: Vec<Block>,
+    unspents: Vec<(u64, u64)>,
+    scan_fails: AtomicBool,
+    scan_started: AtomicBool,
+    scan_released: AtomicBool,
+}
+
+impl FakeNode {
+    fn new(unspents: Vec<(u64, u64)>) -> Arc<Self> {
+        let genesis = genesis_block(Network::Regtest);
+        let first = child(genesis.block_hash(), genesis.header.time + 600, 1);
+        let mut second = child(first.block_hash(), first.header.time + 600, 2);
+        second.txdata[0].output[0].script_pubkey = script(0);
+        second.header.merkle_root = second.compute_merkle_root().unwrap();
+        Arc::new(Self {
+            chain: vec![genesis, first, second],
+            unspents,
+            scan_fails: AtomicBool::new(false),
+            scan_started: AtomicBool::new(false),
+            scan_released: AtomicBool::new(true),
+        })
+    }
+
+    fn answer(&self, method: &str, params: &Value) -> Result<Value, String> {
+        match method {
+            "getdescriptorinfo" => Ok(json!({
+                "descriptor": params[0],
+                "checksum": "checksum",
+                "isrange": true,
+                "issolvable": true,
+                "hasprivatekeys": false,
+            })),
+            "deriveaddresses" => {
+                let (start, end) = (params[1][0].as_u64().unwrap(), params[1][1].as_u64().unwrap());
+                Ok((start..=end).map(|index| Address::from_script(&script(index), Network::Bitcoin).unwrap().to_string()).collect())
+            }
+            "scantxoutset" => {
+                self.scan_started.store(true, Ordering::SeqCst);
+                while !self.scan_released.load(Ordering::SeqCst) {
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                if self.scan_fails.load(Ordering::SeqCst) {
+                    return Err("Scan already in progress, use action \"abort\" or \"status\"".to_string());
+                }
+                let derived = params[1][0]["range"][1].as_u64().unwrap();
+                let unspents: Vec<Value> = self.unspents.iter()
+                    .filter(|(index, _)| *index <= derived)
+                    .map(|&(index, sats)| json!({
+                        "txid": Txid::from_byte_array([index as u8 + 1; 32]).to_string(),
+                        "vout": 0,
+                        "scriptPubKey": script(index).to_hex_string(),
+                        "desc": "addr()",
+                        "amount": sats as f64 / 1e8,
+                        "height": 1,
+                    }))
+                    .collect();
+                Ok(json!({ "success": true, "txouts": 10, "height": 1, "bestblock": self.chain[1].block_hash(), "unspents": unspents, "total_amount": 0 }))
+            }
+            "getblockcount" => Ok(json!(self.chain.len() - 1)),
+            "getblockhash" => Ok(json!(self.chain[params[0].as_u64().unwrap() as usize].block_hash())),
+            "getblock" => {
+                let block = self.chain.iter().find(|block| block.block_hash().to_string() == params[0]).unwrap();
+                Ok(json!(serialize_hex(block)))
+            }
+            _ => Err(format!("Method not found: {}", method)),
+        }
+    }
+}
+
+fn start_node(node: Arc<FakeNode>) -> Arc<BitcoinRpcService> {
+    start_rpc_node(move |method, params| node.answer(method, params))
+}
+
+fn watch_service(bitcoin_service: Arc<BitcoinRpcService>, test: &str) -> Arc<WatchService> {
+    let storage = connect_postgres(test);
+    storage.ensure_watch_tables().unwrap();
+    WatchService::new(storage, bitcoin_service, EventBus::new(16, 16)).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs TEST_POSTGRES_URL"]
+async fn a_failed_registration_stores_nothing_and_can_be_retried() {
+    let node = FakeNode::new(vec![(1, 10_000)]);
+    node.scan_fails.store(true, Ordering::SeqCst);
+    let bitcoin_service = start_node(node.clone());
+
+    tokio::task::spawn_blocking(move || {
+        let watch = watch_service(bitcoin_service, "watch_retry");
+        let descriptors = vec![DESCRIPTOR.to_string()];
+        assert!(matches!(watch.register_wallet("cold", &descriptors, Some(5)), Err(WatchError::Backend(_))));
+        assert!(watch.list_wallets().unwrap().is_empty());
+        assert!(!watch.has_wallets());
+
+        node.scan_fails.store(false, Ordering::SeqCst);
+        let wallet = watch.register_wallet("cold", &descriptors, Some(5)).unwrap();
+        assert_eq!(wallet.summary.balance_sats, 10_000 + 50_0000_0000);
+        assert!(matches!(watch.register_wallet("cold", &descriptors, Some(5)), Err(WatchError::Conflict(_))));
+    }).await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs TEST_POSTGRES_URL"]
+async fn blocks_are_matched_during_the_scan_and_caught_up_after_it() {
+    // Funds at index 2 move the gap past index 4, where more funds are found
+    let node = FakeNode::new(vec![(2, 1_000), (4, 2_000), (9, 4_000)]);
+    node.scan_released.store(false, Ordering::SeqCst);
+    let bitcoin_service = start_node(node.clone());
+
+    tokio::task::spawn_blocking(move || {
+        let watch = watch_service(bitcoin_service, "watch_scan");
+        let registration = {
+            let watch = watch.clone();
+            std::thread::spawn(move || watch.register_wallet("cold", &[DESCRIPTOR.to_string()], Some(3)))
+        };
+        while !node.scan_started.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        // The scan must not hold up block processing
+        let (sender, receiver) = mpsc::channel();
+        {
+            let (watch, block) = (watch.clone(), node.chain[2].clone());
+            std::thread::spawn(move || sender.send(watch.process_block(&block, 2).is_ok()));
+        }
+        let processed = receiver.recv_timeout(Duration::from_secs(5));
+        node.scan_released.store(true, Ordering::SeqCst);
+        assert_eq!(processed, Ok(true));
+
+        // Block 2 came after the scanned UTXO set, so it is matched when the wallet is added
+        let wallet = registration.join().unwrap().unwrap();
+        assert_eq!((wallet.summary.balance_sats, wallet.summary.utxo_count), (3_000 + 50_0000_0000, 3));
+        assert_eq!(wallet.recent_events.len(), 1);
+        assert_eq!(wallet.recent_events[0].block_height, Some(2));
+    }).await.unwrap();
+}
@@ -0,0 +1,232 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use project_rust::services::events::{EventBus, FeeEstimate, IngestionEvent};
+use project_rust::services::webhooks::{
+    deliver_with_retries, http_client, run_webhook_dispatcher, send_once, sign_payload, AttemptOutcome,
+    DeliveryRequest, RetryPolicy, WebhookService,
+};
+use warp::http::StatusCode;
+use warp::Filter;
+
+mod common;
+
+use common::connect_postgres;
+
+// A request as seen by the local receiver
+#[derive(Debug, Clone)]
+struct Received {
+    event: Option<String>,
+    delivery: Option<String>,
+    timestamp: Option<String>,
+    signature: Option<String>,
+    body: String,
+}
+
+// Start an HTTP receiver on a free local port that answers with `statuses` in turn,
+// repeating the last one, and records every request it gets
+fn start_receiver(statuses: Vec<StatusCode>) -> (SocketAddr, Arc<Mutex<Vec<Received>>>) {
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let log = received.clone();
+    let route = warp::post()
+        .and(warp::header::optional::<String>("x-webhook-event"))
+        .and(warp::header::optional::<String>("x-webhook-delivery"))
+        .and(warp::header::optional::<String>("x-webhook-timestamp"))
+        .and(warp::header::optional::<String>("x-webhook-signature"))
+        .and(warp::body::bytes())
+        .map(move |event, delivery, timestamp, signature, body: warp::hyper::body::Bytes| {
+            log.lock().unwrap().push(Received {
+                event,
+                delivery,
+                timestamp,
+                signature,
+                body: String::from_utf8_lossy(&body).to_string(),
+            });
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            let status = statuses[call.min(statuses.len() - 1)];
+            warp::reply::with_status("", status)
+        });
+    let (addr, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    (addr, received)
+}
+
+fn request_to(addr: SocketAddr) -> DeliveryRequest {
+    DeliveryRequest {
+        delivery_id: 42,
+        url: format!("http://{}/hook", addr),
+        secret: "test-secret".to_string(),
+        event_type: "new_block".to_string(),
+        body: r#"{"event":"new_block","data":{"block_height":1}}"#.to_string(),
+    }
+}
+
+fn fast_policy(max_attempts: u32) -> RetryPolicy {
+    RetryPolicy {
+        max_attempts,
+        initial_backoff: Duration::from_millis(10),
+        max_backoff: Duration::from_millis(40),
+    }
+}
+
+#[test]
+fn signature_matches_reference_hmac() {
+    // HMAC-SHA256 of `1700000000.{"event":"new_block"}` keyed with `secret`
+    assert_eq!(
+        sign_payload("secret", 1_700_000_000, r#"{"event":"new_block"}"#),
+        "sha256=91dcbc41809a46a02fe4c3b7f90f6ffaae3875180349f580a39e8954863fdda9"
+    );
+}
+
+#[test]
+fn backoff_doubles_up_to_the_cap() {
+    let policy = RetryPolicy {
+        max_attempts: 10,
+        initial_backoff: Duration::from_secs(5),
+        max_backoff: Duration::from_secs(60),
+    };
+    let waits: Vec<u64> = (1..=6).map(|attempt| policy.backoff(attempt).as_secs()).collect();
+    assert_eq!(waits, vec![5, 10, 20, 40, 60, 60]);
+}
+
+#[tokio::test]
+async fn delivery_is_signed_and_carries_event_headers() {
+    let (addr, received) = start_receiver(vec![StatusCode::OK]);
+    let request = request_to(addr);
+
+    let outcome = send_once(&http_client(), &request).await;
+    assert_eq!(outcome, AttemptOutcome::Delivered { status_code: 200 });
+
+    let received = received.lock().unwrap();
+    assert_eq!(received.len(), 1);
+    let delivery = &received[0];
+    assert_eq!(delivery.body, request.body);
+    assert_eq!(delivery.event.as_deref(), Some("new_block"));
+    assert_eq!(delivery.delivery.as_deref(), Some("42"));
+    let timestamp: i64 = delivery.timestamp.as_deref().expect("timestamp header").parse().expect("numeric timestamp");
+    assert_eq!(delivery.signature, Some(sign_payload(&request.secret, timestamp, &delivery.body)));
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried_until_accepted() {
+    let (addr, received) = start_receiver(vec![
+        StatusCode::INTERNAL_SERVER_ERROR,
+        StatusCode::SERVICE_UNAVAILABLE,
+        StatusCode::NO_CONTENT,
+    ]);
+    let mut attempts = Vec::new();
+
+    let outcome = deliver_with_retries(&http_client(), &request_to(addr), fast_policy(5), 1, |attempt, outcome| {
+        attempts.push((attempt, outcome.clone()));
+    }).await;
+
+    assert_eq!(outcome, AttemptOutcome::Delivered { status_code: 204 });
+    assert_eq!(received.lock().unwrap().len(), 3);
+    let numbers: Vec<u32> = attempts.iter().map(|(attempt, _)| *attempt).collect();
+    assert_eq!(numbers, vec![1, 2, 3]);
+    assert!(matches!(attempts[0].1, AttemptOutcome::Failed { status_code: Some(500), .. }));
+    assert!(matches!(attempts[1].1, AttemptOutcome::Failed { status_code: Some(503), .. }));
+}
+
+#[tokio::test]
+async fn delivery_gives_up_after_the_last_attempt() {
+    let (addr, received) = start_receiver(vec![StatusCode::BAD_GATEWAY]);
+    let mut attempts = 0;
+
+    let outcome = deliver_with_retries(&http_client(), &request_to(addr), fast_policy(3), 1, |_, _| attempts += 1).await;
+
+    assert!(matches!(outcome, AttemptOutcome::Failed { status_code: Some(502), .. }));
+    assert_eq!(attempts, 3);
+    assert_eq!(received.lock().unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn resumed_delivery_only_uses_the_remaining_attempts() {
+    let (addr, received) = start_receiver(vec![StatusCode::BAD_GATEWAY]);
+    let mut attempts = Vec::new();
+
+    deliver_with_retries(&http_client(), &request_to(addr), fast_policy(4), 3, |attempt, _| attempts.push(attempt)).await;
+
+    assert_eq!(attempts, vec![3, 4]);
+    assert_eq!(received.lock().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn unreachable_receiver_is_a_failed_attempt() {
+    // Bind and drop a listener to get a local port nothing listens on
+    let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+    let outcome = deliver_with_retries(&http_client(), &request_to(addr), fast_policy(2), 1, |_, _| {}).await;
+
+    assert!(matches!(outcome, AttemptOutcome::Failed { status_code: None, .. }));
+}
+
+fn fees(fee_rate: f64) -> IngestionEvent {
+    IngestionEvent::FeeEstimates { estimates: vec![FeeEstimate { block_target: 6, fee_rate }] }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs TEST_POSTGRES_URL"]
+async fn the_fee_side_is_stored_so_a_restart_still_sees_the_crossing() {
+    let (addr, received) = start_receiver(vec![StatusCode::OK]);
+    let url = format!("http://{}/hook", addr);
+
+    let storage = tokio::task::spawn_blocking(move || {
+        let storage = connect_postgres("webhook_fees");
+        storage.ensure_webhook_tables().unwrap();
+        let service = WebhookService::new(storage.clone());
+        let id = service.register(&url, &["fee_threshold".to_string()], Some(6), Some(20.0)).unwrap().id;
+        service.dispatch(&fees(10.0)).unwrap();
+
+        // A new service, as after a restart, delivers the first estimate above the threshold once
+        let restarted = WebhookService::new(storage.clone());
+        restarted.dispatch(&fees(30.0)).unwrap();
+        restarted.dispatch(&fees(31.0)).unwrap();
+        assert_eq!(storage.list_webhook_deliveries(id, 10).unwrap().len(), 1);
+
+        restarted.delete(id).unwrap();
+        assert_eq!(storage.swap_webhook_fee_above(id, true).unwrap(), None);
+        storage
+    }).await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let received = received.lock().unwrap().clone();
+    assert_eq!(received.len(), 1);
+    assert!(received[0].body.contains(r#""direction":"above""#));
+    tokio::task::spawn_blocking(move || drop(storage)).await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+#[ignore = "needs TEST_POSTGRES_URL"]
+async fn events_a_lagging_dispatcher_dropped_are_logged_as_failed_deliveries() {
+    let (addr, received) = start_receiver(vec![StatusCode::OK]);
+    let url = format!("http://{}/hook", addr);
+    let (storage, service) = tokio::task::spawn_blocking(move || {
+        let storage = connect_postgres("webhook_lag");
+        storage.ensure_webhook_tables().unwrap();
+        let service = WebhookService::new(storage.clone());
+        service.register(&url, &["reorg".to_string()], None, None).unwrap();
+        (storage, service)
+    }).await.unwrap();
+
+    // Five events published at once into a channel of two
+    let event_bus = EventBus::new(2, 16);
+    let dispatcher = tokio::spawn(run_webhook_dispatcher(service, event_bus.clone()));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    for fork_height in 1..=5 {
+        event_bus.publish(IngestionEvent::Reorg { fork_height, orphaned_blocks: 1 });
+    }
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    dispatcher.abort();
+    let _ = dispatcher.await;
+
+    let deliveries = tokio::task::spawn_blocking(move || storage.list_webhook_deliveries(1, 10).unwrap()).await.unwrap();
+    let missed: Vec<_> = deliveries.iter().filter(|delivery| delivery.event_type == "missed_events").collect();
+    assert_eq!(missed.len(), 1);
+    assert_eq!((missed[0].status.as_str(), missed[0].attempts), ("failed", 0));
+    assert!(missed[0].payload.contains(r#""skipped":3"#));
+    assert_eq!(deliveries.len(), 3);
+    assert_eq!(received.lock().unwrap().len(), 2);
+}